@@ -2,6 +2,387 @@
 //!
 //! Custom patching pass that matches the output of one entrypoint, to the input parameters of another entrypoint.
 //! Heavily inspired by [spirv-location-injector](https://github.com/expenses/spirv-location-injector), used for testing the patching mechanism and ergonomics.
+//!
+//! ## Implementation details
+//!
+//! The patched module is one side of a stage interface (for instance the fragment stage), the reference module the other
+//! side (for instance the vertex stage). `Input` variables of a consumer are matched against the `Output` variables of
+//! its producer and vice versa:
+//!
+//! 1. Builtins are matched by their builtin. They have no location, so nothing is rewritten.
+//! 2. Other variables are matched by their `OpName`. Variables of both sides must have structurally equal types,
+//!    otherwise they are reported as mismatched.
+//! 3. The remaining variables are matched by type, if exactly one candidate of that type is left. Only unnamed variables
+//!    are matched this way, or variables whose counterpart is unnamed.
+//!
+//! The `Location` and `Component` decorations of each matched variable of the patched module are replaced by the ones
+//! of its counterpart. Variables without a counterpart keep their decorations and are reported.
 #![deny(warnings)]
 
-pub struct BindingLocationMatching {}
+use std::fmt::Display;
+
+use ahash::AHashSet;
+use spv_patcher::{
+    patch::{Patch, Patcher},
+    rspirv::{
+        dr::{Instruction, Module, Operand},
+        spirv::{BuiltIn, Decoration, Op, StorageClass, Word},
+    },
+    spirv_ext::SpirvExt,
+    type_tree::{TypeImporter, TypeTreeError},
+    PatcherError,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum MatchingError {
+    #[error("Could not parse reference module: {0}")]
+    Parse(#[from] spv_patcher::rspirv::binary::ParseState),
+    #[error("Could not select an entry point of the {module} module, it has {count}")]
+    EntryPoint { module: &'static str, count: usize },
+    #[error("Reference module has no entry point named \"{0}\"")]
+    NoEntryPoint(String),
+    #[error("Could not compare interface types: {0}")]
+    TypeTree(#[from] TypeTreeError),
+}
+
+///Which side of the stage interface the patched module is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    ///The patched module reads the reference module's outputs. Its `Input` variables are rewritten.
+    Consumer,
+    ///The patched module writes the reference module's inputs. Its `Output` variables are rewritten.
+    Producer,
+}
+
+///A variable whose counterpart has a different type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    ///Variable of the patched module.
+    pub variable: Word,
+    ///Variable of the reference module with the same name.
+    pub reference: Word,
+}
+
+///Result of a [BindingLocationMatching] run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MatchReport {
+    ///Matched `(variable, reference)` pairs.
+    pub matched: Vec<(Word, Word)>,
+    ///Number of matched variables whose decorations changed.
+    pub rewritten: usize,
+    ///Variables of the patched module without counterpart.
+    pub unmatched: Vec<Word>,
+    pub mismatched: Vec<Mismatch>,
+    ///Variables of the reference module nothing was matched to.
+    pub unused: Vec<Word>,
+}
+
+impl MatchReport {
+    ///True if every variable of the patched module found a counterpart of the same type.
+    pub fn is_complete(&self) -> bool {
+        self.unmatched.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+impl Display for MatchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "matched {} variables ({} rewritten), {} unmatched, {} type mismatches, {} unused",
+            self.matched.len(),
+            self.rewritten,
+            self.unmatched.len(),
+            self.mismatched.len(),
+            self.unused.len()
+        )
+    }
+}
+
+///Rewrites the interface locations of the patched module to line up with the `reference` module.
+#[derive(Debug, Clone)]
+pub struct BindingLocationMatching {
+    pub reference: Module,
+    pub side: Side,
+    ///Entry point of the reference module. Needed if it has more than one.
+    pub reference_entry: Option<String>,
+}
+
+//An `Input` or `Output` variable of an entry point's interface.
+struct InterfaceVar {
+    id: Word,
+    name: Option<String>,
+    builtin: Option<BuiltIn>,
+    //Pointee type
+    ty: Word,
+}
+
+impl BindingLocationMatching {
+    pub fn new(reference: Module, side: Side) -> Self {
+        BindingLocationMatching {
+            reference,
+            side,
+            reference_entry: None,
+        }
+    }
+
+    pub fn new_from_bytes(reference: &[u8], side: Side) -> Result<Self, MatchingError> {
+        Ok(Self::new(
+            spv_patcher::rspirv::dr::load_bytes(reference)?,
+            side,
+        ))
+    }
+
+    ///Selects the reference module's entry point by name.
+    pub fn with_reference_entry(mut self, name: &str) -> Self {
+        self.reference_entry = Some(name.to_owned());
+        self
+    }
+
+    ///Matches the interface of `module`'s only entry point.
+    pub fn run(&self, module: &mut Module) -> Result<MatchReport, MatchingError> {
+        self.match_interface(module, None)
+    }
+
+    //Matches the interface of the entry point function `entry`, or the module's only entry point.
+    fn match_interface(
+        &self,
+        module: &mut Module,
+        entry: Option<Word>,
+    ) -> Result<MatchReport, MatchingError> {
+        let reference = &self.reference;
+        let reference_entry = match &self.reference_entry {
+            Some(name) => reference
+                .find_entry_point(name, None)
+                .ok_or_else(|| MatchingError::NoEntryPoint(name.clone()))?,
+            None => single_entry_point(reference, "reference")?,
+        };
+        let entry = match entry {
+            Some(entry) => entry,
+            None => single_entry_point(module, "patched")?,
+        };
+        let (own_class, reference_class) = match self.side {
+            Side::Consumer => (StorageClass::Input, StorageClass::Output),
+            Side::Producer => (StorageClass::Output, StorageClass::Input),
+        };
+        let own = interface(module, entry, own_class);
+        let mut candidates = interface(reference, reference_entry, reference_class);
+
+        let mut importer = TypeImporter::new(reference, module)?;
+        let mut report = MatchReport::default();
+        let mut pairs = Vec::new();
+        let mut deferred = Vec::new();
+        for var in &own {
+            let position = match (var.builtin, &var.name) {
+                (Some(builtin), _) => candidates.iter().position(|c| c.builtin == Some(builtin)),
+                (None, Some(name)) => candidates
+                    .iter()
+                    .position(|c| c.builtin.is_none() && c.name.as_ref() == Some(name)),
+                (None, None) => None,
+            };
+            let Some(position) = position else {
+                if var.builtin.is_some() {
+                    report.unmatched.push(var.id);
+                } else {
+                    deferred.push(var);
+                }
+                continue;
+            };
+            let candidate = candidates.remove(position);
+            if importer.find(candidate.ty)? != Some(var.ty) {
+                report.mismatched.push(Mismatch {
+                    variable: var.id,
+                    reference: candidate.id,
+                });
+            } else if var.builtin.is_some() {
+                report.matched.push((var.id, candidate.id));
+            } else {
+                pairs.push((var.id, candidate.id));
+            }
+        }
+        //Differently named variables never match
+        for var in deferred {
+            let mut same_type = Vec::new();
+            for (idx, candidate) in candidates.iter().enumerate() {
+                if candidate.builtin.is_none()
+                    && (var.name.is_none() || candidate.name.is_none())
+                    && importer.find(candidate.ty)? == Some(var.ty)
+                {
+                    same_type.push(idx);
+                }
+            }
+            match same_type.as_slice() {
+                [idx] => pairs.push((var.id, candidates.remove(*idx).id)),
+                _ => report.unmatched.push(var.id),
+            }
+        }
+        report.unused = candidates.iter().map(|c| c.id).collect();
+
+        for (variable, counterpart) in pairs {
+            if copy_locations(reference, module, counterpart, variable) {
+                report.rewritten += 1;
+            }
+            report.matched.push((variable, counterpart));
+        }
+        warn_overlaps(module, &own);
+        Ok(report)
+    }
+}
+
+//Function id of the module's only entry point.
+fn single_entry_point(module: &Module, name: &'static str) -> Result<Word, MatchingError> {
+    match module.entry_points.as_slice() {
+        [ep] => {
+            ep.operands
+                .get(1)
+                .and_then(|op| op.id_ref_any())
+                .ok_or(MatchingError::EntryPoint {
+                    module: name,
+                    count: 1,
+                })
+        }
+        eps => Err(MatchingError::EntryPoint {
+            module: name,
+            count: eps.len(),
+        }),
+    }
+}
+
+//Variables of `class` used by the entry point `entry`, in declaration order.
+fn interface(module: &Module, entry: Word, class: StorageClass) -> Vec<InterfaceVar> {
+    let listed = module
+        .entry_points
+        .iter()
+        .filter(|ep| ep.operands.get(1) == Some(&Operand::IdRef(entry)))
+        .flat_map(|ep| ep.operands.iter().skip(3).filter_map(|op| op.id_ref_any()))
+        .collect::<AHashSet<_>>();
+    module
+        .types_global_values
+        .iter()
+        .filter(|inst| {
+            inst.class.opcode == Op::Variable
+                && inst.operands.first() == Some(&Operand::StorageClass(class))
+                && inst
+                    .result_id
+                    .map(|id| listed.contains(&id))
+                    .unwrap_or(false)
+        })
+        .filter_map(|inst| {
+            let id = inst.result_id?;
+            let ty = module.pointee(inst.result_type?)?;
+            let builtin = decorations(module, id)
+                .find(|dec| dec.operands[1] == Operand::Decoration(Decoration::BuiltIn))
+                .and_then(|dec| match dec.operands.get(2) {
+                    Some(Operand::BuiltIn(builtin)) => Some(*builtin),
+                    _ => None,
+                });
+            Some(InterfaceVar {
+                id,
+                name: module.get_name(id),
+                builtin,
+                ty,
+            })
+        })
+        .collect()
+}
+
+//`OpDecorate`s targeting `id`.
+fn decorations(module: &Module, id: Word) -> impl Iterator<Item = &Instruction> {
+    module.annotations.iter().filter(move |inst| {
+        inst.class.opcode == Op::Decorate && inst.operands.first() == Some(&Operand::IdRef(id))
+    })
+}
+
+fn is_location(inst: &Instruction) -> bool {
+    matches!(
+        inst.operands.get(1),
+        Some(Operand::Decoration(
+            Decoration::Location | Decoration::Component
+        ))
+    )
+}
+
+//Replaces the `Location` and `Component` decorations of `dst_id` with the ones of `src_id`. Returns true if they
+// changed.
+fn copy_locations(src: &Module, dst: &mut Module, src_id: Word, dst_id: Word) -> bool {
+    let key = |inst: &Instruction| inst.operands[1..].to_vec();
+    let mut wanted = decorations(src, src_id)
+        .filter(|inst| is_location(inst))
+        .map(key)
+        .collect::<Vec<_>>();
+    let mut existing = decorations(dst, dst_id)
+        .filter(|inst| is_location(inst))
+        .map(key)
+        .collect::<Vec<_>>();
+    wanted.sort_by_key(|operands| format!("{:?}", operands));
+    existing.sort_by_key(|operands| format!("{:?}", operands));
+    if wanted == existing {
+        return false;
+    }
+
+    dst.annotations.retain(|inst| {
+        !(inst.class.opcode == Op::Decorate
+            && inst.operands.first() == Some(&Operand::IdRef(dst_id))
+            && is_location(inst))
+    });
+    for operands in wanted {
+        dst.annotations.push(Instruction::new(
+            Op::Decorate,
+            None,
+            None,
+            std::iter::once(Operand::IdRef(dst_id))
+                .chain(operands)
+                .collect(),
+        ));
+    }
+    true
+}
+
+//Warns if two variables of `vars` ended up at the same location and component.
+fn warn_overlaps(module: &Module, vars: &[InterfaceVar]) {
+    let mut taken = AHashSet::default();
+    for var in vars {
+        let slot = |decoration: Decoration| {
+            decorations(module, var.id)
+                .find(|inst| inst.operands[1] == Operand::Decoration(decoration))
+                .and_then(|inst| inst.operands.get(2).cloned())
+        };
+        let Some(Operand::LiteralBit32(location)) = slot(Decoration::Location) else {
+            continue;
+        };
+        let component = match slot(Decoration::Component) {
+            Some(Operand::LiteralBit32(component)) => component,
+            _ => 0,
+        };
+        if !taken.insert((location, component)) {
+            log::warn!(
+                "Interface variable %{} overlaps location {} component {} after matching",
+                var.id,
+                location,
+                component
+            );
+        }
+    }
+}
+
+impl Patch for BindingLocationMatching {
+    fn apply<'a>(self, mut patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError> {
+        let entry = patcher.entry_function();
+        let report = self
+            .match_interface(patcher.ir_state.as_spirv(), entry)
+            .map_err(|e| PatcherError::Internal(e.into()))?;
+        log::info!("Binding location matching: {}", report);
+        for id in &report.unmatched {
+            log::warn!("Interface variable %{} has no counterpart", id);
+        }
+        for mismatch in &report.mismatched {
+            log::warn!(
+                "Interface variable %{} does not match the type of %{}",
+                mismatch.variable,
+                mismatch.reference
+            );
+        }
+        patcher.push_report(report);
+        Ok(patcher)
+    }
+}
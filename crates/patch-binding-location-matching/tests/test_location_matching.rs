@@ -0,0 +1,148 @@
+use patch_binding_location_matching::{BindingLocationMatching, Side};
+use spv_patcher::{
+    rspirv::{
+        dr::{Module as SpvModule, Operand},
+        spirv::{Decoration, Word},
+    },
+    spirv_ext::SpirvExt,
+    verify::Verifier,
+    Module,
+};
+
+const VERTEX: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint Vertex %main "main" %color %uv %normal %pos
+OpName %color "color"
+OpName %uv "uv"
+OpName %normal "normal"
+OpDecorate %color Location 0
+OpDecorate %uv Location 1
+OpDecorate %normal Location 2
+OpDecorate %pos BuiltIn Position
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%float = OpTypeFloat 32
+%v2float = OpTypeVector %float 2
+%v3float = OpTypeVector %float 3
+%v4float = OpTypeVector %float 4
+%ptr_v2 = OpTypePointer Output %v2float
+%ptr_v3 = OpTypePointer Output %v3float
+%ptr_v4 = OpTypePointer Output %v4float
+%color = OpVariable %ptr_v4 Output
+%uv = OpVariable %ptr_v2 Output
+%normal = OpVariable %ptr_v3 Output
+%pos = OpVariable %ptr_v4 Output
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+//`tex` has no name, so it is matched to `uv` by its type. `normal` has the wrong type.
+const FRAGMENT: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint Fragment %main "main" %color %tex %normal %extra
+OpExecutionMode %main OriginUpperLeft
+OpName %color "color"
+OpName %normal "normal"
+OpName %extra "extra"
+OpDecorate %color Location 3
+OpDecorate %tex Location 0
+OpDecorate %normal Location 2
+OpDecorate %extra Location 5
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%float = OpTypeFloat 32
+%v2float = OpTypeVector %float 2
+%v4float = OpTypeVector %float 4
+%ptr_v2 = OpTypePointer Input %v2float
+%ptr_v4 = OpTypePointer Input %v4float
+%ptr_float = OpTypePointer Input %float
+%color = OpVariable %ptr_v4 Input
+%tex = OpVariable %ptr_v2 Input
+%normal = OpVariable %ptr_v2 Input
+%extra = OpVariable %ptr_float Input
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+fn location(spv: &SpvModule, id: Word) -> Vec<u32> {
+    spv.annotations
+        .iter()
+        .filter(|inst| {
+            inst.operands[..2]
+                == [
+                    Operand::IdRef(id),
+                    Operand::Decoration(Decoration::Location),
+                ]
+        })
+        .filter_map(|inst| match inst.operands[2] {
+            Operand::LiteralBit32(location) => Some(location),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn match_consumer() {
+    let vertex = Module::from_assembly(VERTEX).unwrap().spirv().clone();
+    let mut fragment = Module::from_assembly(FRAGMENT).unwrap().spirv().clone();
+    let tex = fragment.entry_points[0].operands[4].unwrap_id_ref();
+    let (color, normal, extra) = (
+        fragment.get_by_name("color").unwrap().result_id.unwrap(),
+        fragment.get_by_name("normal").unwrap().result_id.unwrap(),
+        fragment.get_by_name("extra").unwrap().result_id.unwrap(),
+    );
+
+    let (vertex_color, vertex_uv) = (
+        vertex.get_by_name("color").unwrap().result_id.unwrap(),
+        vertex.get_by_name("uv").unwrap().result_id.unwrap(),
+    );
+
+    let report = BindingLocationMatching::new(vertex.clone(), Side::Consumer)
+        .run(&mut fragment)
+        .unwrap();
+    assert_eq!(report.matched, [(color, vertex_color), (tex, vertex_uv)]);
+    assert_eq!(report.rewritten, 2);
+    assert_eq!(report.unmatched, [extra]);
+    assert_eq!(report.mismatched.len(), 1);
+    assert_eq!(report.mismatched[0].variable, normal);
+    assert!(!report.is_complete());
+    //Normal was consumed by the mismatch, the position builtin is not read by the fragment stage
+    assert_eq!(report.unused.len(), 1);
+
+    assert_eq!(location(&fragment, color), [0]);
+    assert_eq!(location(&fragment, tex), [1]);
+    assert_eq!(location(&fragment, normal), [2]);
+    assert_eq!(location(&fragment, extra), [5]);
+    Verifier::verify(&fragment).unwrap();
+
+    //Matching again changes nothing
+    let report = BindingLocationMatching::new(vertex, Side::Consumer)
+        .run(&mut fragment)
+        .unwrap();
+    assert_eq!(report.rewritten, 0);
+}
+
+#[test]
+fn match_producer() {
+    let fragment = Module::from_assembly(FRAGMENT).unwrap().spirv().clone();
+    let mut vertex = Module::from_assembly(VERTEX).unwrap().spirv().clone();
+    let (color, uv) = (
+        vertex.get_by_name("color").unwrap().result_id.unwrap(),
+        vertex.get_by_name("uv").unwrap().result_id.unwrap(),
+    );
+    let report = BindingLocationMatching::new(fragment, Side::Producer)
+        .run(&mut vertex)
+        .unwrap();
+    assert_eq!(location(&vertex, color), [3]);
+    assert_eq!(location(&vertex, uv), [0]);
+    //The position builtin has no counterpart, just like the wrongly typed normal
+    assert_eq!(report.unmatched.len(), 1);
+    assert_eq!(report.mismatched.len(), 1);
+    Verifier::verify(&vertex).unwrap();
+}
@@ -0,0 +1,416 @@
+//! # Constant folding
+//!
+//! Folds instructions whose operands are all constant, and removes branches on constant conditions. Meant to be
+//! chained after patches that make values constant, for instance `MutateConstant` or freezing specialization
+//! constants.
+//!
+//! ## Implementation details
+//!
+//! Each function is folded until nothing changes:
+//!
+//! - Instructions the [ConstEvaluator] can evaluate (arithmetic, comparisons, conversions, composite extract and
+//!   construct, `GLSL.std.450`) are replaced by a constant. `OpPhi`s whose incoming values are all the same constant
+//!   are folded as well.
+//! - `OpBranchConditional` and `OpSwitch` on a constant become an `OpBranch` to the taken target. The selection merge is
+//!   removed with it, except for switches that do not branch to their merge, since cases may break to the merge. Those
+//!   become a switch with only a default target. Loop merges are kept.
+//! - Blocks that can no longer be reached are removed. Unreachable merge blocks and continue targets of live constructs
+//!   are kept, with their body replaced by `OpUnreachable` or a branch back to the loop header respectively.
+//!   `OpPhi`s lose incoming values of removed edges.
+
+#![deny(warnings)]
+
+use std::fmt::Display;
+
+use ahash::{AHashMap, AHashSet};
+use spv_patcher::{
+    analysis::{Cfg, ConstEvaluator, ConstValue},
+    patch::{Patch, Patcher},
+    rspirv::{
+        dr::{Instruction, Module, Operand},
+        spirv::{Op, Word},
+    },
+    spirv_ext::SpirvExt,
+    PatcherError,
+};
+
+///What a [ConstantFold] run changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FoldReport {
+    ///Number of instructions replaced by a constant.
+    pub instructions: usize,
+    ///Number of conditional branches and switches that were collapsed.
+    pub branches: usize,
+    ///Number of removed blocks.
+    pub blocks: usize,
+}
+
+impl FoldReport {
+    ///True if nothing was folded.
+    pub fn is_empty(&self) -> bool {
+        self.instructions == 0 && self.branches == 0 && self.blocks == 0
+    }
+}
+
+impl Display for FoldReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "folded {} instructions and {} branches, removed {} blocks",
+            self.instructions, self.branches, self.blocks
+        )
+    }
+}
+
+///Folds constant instructions and branches in all functions of a module.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConstantFold;
+
+impl ConstantFold {
+    ///Folds `module` and returns what was changed.
+    pub fn run(module: &mut Module) -> FoldReport {
+        let mut evaluator = ConstEvaluator::new(module);
+        let mut report = FoldReport::default();
+        //Ids that are no longer defined
+        let mut removed = AHashSet::default();
+        for function in 0..module.functions.len() {
+            loop {
+                let instructions =
+                    Self::fold_instructions(module, function, &mut evaluator, &mut removed);
+                let branches = Self::fold_branches(module, function, &evaluator);
+                report.instructions += instructions;
+                report.branches += branches;
+                if branches > 0 {
+                    report.blocks += Self::remove_unreachable(module, function, &mut removed);
+                }
+                if instructions == 0 && branches == 0 {
+                    break;
+                }
+            }
+        }
+
+        let targets_removed = |inst: &Instruction| {
+            inst.operands
+                .first()
+                .and_then(|op| op.id_ref_any())
+                .map(|id| removed.contains(&id))
+                .unwrap_or(false)
+        };
+        module.debug_names.retain(|inst| !targets_removed(inst));
+        module.annotations.retain(|inst| !targets_removed(inst));
+        report
+    }
+
+    //Replaces constant instructions of `function` by constants. Returns the number of folded instructions.
+    fn fold_instructions(
+        module: &mut Module,
+        function: usize,
+        evaluator: &mut ConstEvaluator,
+        removed: &mut AHashSet<Word>,
+    ) -> usize {
+        let mut replace = AHashMap::default();
+        for block in 0..module.functions[function].blocks.len() {
+            let mut idx = 0;
+            while idx < module.functions[function].blocks[block].instructions.len() {
+                let inst = &mut module.functions[function].blocks[block].instructions[idx];
+                replace_ids(inst, &replace);
+
+                let value = match (inst.result_id, inst.result_type) {
+                    (Some(id), Some(ty)) => {
+                        let value = if inst.class.opcode == Op::Phi {
+                            phi_value(inst, evaluator)
+                        } else {
+                            evaluator.eval_instruction(inst)
+                        };
+                        value.map(|value| (id, ty, value))
+                    }
+                    _ => None,
+                };
+                if let Some((id, ty, value)) = value {
+                    if let Some(constant) = evaluator.find_or_insert(module, ty, &value) {
+                        log::trace!("Fold %{} to %{} ({:?})", id, constant, value);
+                        module.functions[function].blocks[block]
+                            .instructions
+                            .remove(idx);
+                        replace.insert(id, constant);
+                        removed.insert(id);
+                        continue;
+                    }
+                }
+                idx += 1;
+            }
+        }
+
+        //Uses that come before the definition in block order, for instance in loop header phis
+        if !replace.is_empty() {
+            for block in module.functions[function].blocks.iter_mut() {
+                for inst in block.instructions.iter_mut() {
+                    replace_ids(inst, &replace);
+                }
+            }
+        }
+        replace.len()
+    }
+
+    //Collapses branches on constants. Returns the number of collapsed branches.
+    fn fold_branches(module: &mut Module, function: usize, evaluator: &ConstEvaluator) -> usize {
+        let id = |op: Option<&Operand>| op.and_then(|op| op.id_ref_any());
+        let loop_headers = module.functions[function]
+            .blocks
+            .iter()
+            .filter(|block| {
+                block
+                    .instructions
+                    .iter()
+                    .rev()
+                    .nth(1)
+                    .map(|inst| inst.class.opcode == Op::LoopMerge)
+                    .unwrap_or(false)
+            })
+            .filter_map(|block| block.label_id())
+            .collect::<AHashSet<_>>();
+        let mut folded = 0;
+        for block in module.functions[function].blocks.iter_mut() {
+            let Some(terminator) = block.instructions.last() else {
+                continue;
+            };
+            let operands = &terminator.operands;
+            let taken = match terminator.class.opcode {
+                Op::BranchConditional => match (id(operands.get(1)), id(operands.get(2))) {
+                    (Some(t), Some(f)) if t == f => Some(t),
+                    (Some(t), Some(f)) => id(operands.first())
+                        .and_then(|cond| evaluator.value(cond))
+                        .and_then(|cond| cond.as_bool())
+                        .map(|cond| if cond { t } else { f }),
+                    _ => None,
+                },
+                Op::Switch => id(operands.first())
+                    .and_then(|selector| evaluator.value(selector))
+                    .and_then(|selector| switch_target(selector, operands)),
+                _ => None,
+            };
+            let Some(taken) = taken else {
+                continue;
+            };
+            //Removing the back edge of a loop would break the loop construct
+            if operands
+                .iter()
+                .skip(1)
+                .filter_map(|op| op.id_ref_any())
+                .any(|target| target != taken && loop_headers.contains(&target))
+            {
+                continue;
+            }
+
+            let merge = block
+                .instructions
+                .iter()
+                .rev()
+                .nth(1)
+                .filter(|inst| matches!(inst.class.opcode, Op::SelectionMerge | Op::LoopMerge))
+                .map(|inst| (inst.class.opcode, id(inst.operands.first())));
+            let terminator = match (terminator.class.opcode, merge) {
+                //Cases may break to the merge block, so the construct needs to stay
+                (Op::Switch, Some((Op::SelectionMerge, Some(merge)))) if merge != taken => {
+                    if terminator.operands.len() == 2 && id(operands.get(1)) == Some(taken) {
+                        continue;
+                    }
+                    Instruction::new(
+                        Op::Switch,
+                        None,
+                        None,
+                        vec![terminator.operands[0].clone(), Operand::IdRef(taken)],
+                    )
+                }
+                (_, Some((Op::SelectionMerge, _))) => {
+                    let merge_idx = block.instructions.len() - 2;
+                    block.instructions.remove(merge_idx);
+                    Instruction::new(Op::Branch, None, None, vec![Operand::IdRef(taken)])
+                }
+                _ => Instruction::new(Op::Branch, None, None, vec![Operand::IdRef(taken)]),
+            };
+            log::trace!(
+                "Collapse branch of block %{} to %{}",
+                block.label_id().unwrap_or(0),
+                taken
+            );
+            *block.instructions.last_mut().unwrap() = terminator;
+            folded += 1;
+        }
+        folded
+    }
+
+    //Removes blocks that are not reachable anymore. Returns the number of removed blocks.
+    fn remove_unreachable(
+        module: &mut Module,
+        function: usize,
+        removed: &mut AHashSet<Word>,
+    ) -> usize {
+        let cfg = Cfg::new(&module.functions[function]);
+        let reachable = cfg
+            .reverse_post_order()
+            .into_iter()
+            .collect::<AHashSet<_>>();
+        if reachable.len() == cfg.len() {
+            return 0;
+        }
+
+        //Merge blocks and continue targets of live constructs need to stay
+        let mut merges = AHashSet::default();
+        let mut continues = AHashMap::default();
+        for construct in cfg.constructs() {
+            if !reachable.contains(&construct.header) {
+                continue;
+            }
+            if !reachable.contains(&construct.merge) {
+                merges.insert(construct.merge);
+            }
+            if let Some(continue_target) = construct.continue_target() {
+                if !reachable.contains(&continue_target) && !merges.contains(&continue_target) {
+                    continues.insert(continue_target, construct.header);
+                }
+            }
+        }
+
+        //Headers of loops whose continue target now branches back need a phi entry for it
+        let mut new_edges = Vec::new();
+        for (continue_target, header) in &continues {
+            let phis = module.functions[function].blocks[*header]
+                .instructions
+                .iter()
+                .filter(|inst| inst.class.opcode == Op::Phi)
+                .filter_map(|inst| inst.result_type.zip(inst.result_id))
+                .collect::<Vec<_>>();
+            for (ty, phi) in phis {
+                let undef = module.find_or_insert_undef(ty);
+                new_edges.push((phi, undef, cfg.label(*continue_target)));
+            }
+        }
+
+        let function = &mut module.functions[function];
+        let mut removed_blocks = 0;
+        let blocks = std::mem::take(&mut function.blocks);
+        for (idx, mut block) in blocks.into_iter().enumerate() {
+            if reachable.contains(&idx) {
+                function.blocks.push(block);
+                continue;
+            }
+            for inst in block.instructions.iter() {
+                removed.extend(inst.result_id);
+            }
+            if merges.contains(&idx) {
+                block.instructions =
+                    vec![Instruction::new(Op::Unreachable, None, None, Vec::new())];
+                function.blocks.push(block);
+            } else if let Some(header) = continues.get(&idx) {
+                block.instructions = vec![Instruction::new(
+                    Op::Branch,
+                    None,
+                    None,
+                    vec![Operand::IdRef(cfg.label(*header))],
+                )];
+                function.blocks.push(block);
+            } else {
+                removed.extend(block.label_id());
+                removed_blocks += 1;
+            }
+        }
+
+        //Only keep incoming values of edges that still exist
+        let cfg = Cfg::new(function);
+        for (idx, block) in function.blocks.iter_mut().enumerate() {
+            let predecessors = cfg
+                .predecessors(idx)
+                .iter()
+                .map(|pred| cfg.label(*pred))
+                .collect::<AHashSet<_>>();
+            for inst in block.instructions.iter_mut() {
+                if inst.class.opcode != Op::Phi {
+                    continue;
+                }
+                let added = new_edges
+                    .iter()
+                    .filter(|(phi, _, _)| inst.result_id == Some(*phi))
+                    .collect::<Vec<_>>();
+                let mut pairs = inst
+                    .operands
+                    .chunks(2)
+                    .filter(|pair| {
+                        pair.get(1)
+                            .and_then(|parent| parent.id_ref_any())
+                            .map(|parent| {
+                                predecessors.contains(&parent)
+                                    && !added.iter().any(|(_, _, new)| *new == parent)
+                            })
+                            .unwrap_or(false)
+                    })
+                    .flat_map(|pair| pair.iter().cloned())
+                    .collect::<Vec<_>>();
+                for (_, value, parent) in added {
+                    pairs.push(Operand::IdRef(*value));
+                    pairs.push(Operand::IdRef(*parent));
+                }
+                inst.operands = pairs;
+            }
+        }
+        removed_blocks
+    }
+}
+
+//Rewrites all ids of `inst` that are keys of `replace`.
+fn replace_ids(inst: &mut Instruction, replace: &AHashMap<Word, Word>) {
+    if replace.is_empty() {
+        return;
+    }
+    for operand in inst.operands.iter_mut() {
+        if let Some(id) = operand.id_ref_any_mut() {
+            if let Some(new) = replace.get(id) {
+                *id = *new;
+            }
+        }
+    }
+}
+
+//Value of a phi whose incoming values are all the same constant.
+fn phi_value(phi: &Instruction, evaluator: &ConstEvaluator) -> Option<ConstValue> {
+    let mut values = phi.operands.iter().step_by(2).map(|op| op.id_ref_any());
+    let first = values.next()??;
+    if values.all(|value| value == Some(first)) {
+        evaluator.value(first).cloned()
+    } else {
+        None
+    }
+}
+
+//Target of a switch on the constant `selector`.
+fn switch_target(selector: &ConstValue, operands: &[Operand]) -> Option<Word> {
+    let ConstValue::Int { bits, width, .. } = selector else {
+        return None;
+    };
+    let mask = if *width >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << width) - 1
+    };
+    let default = operands.get(1)?.id_ref_any()?;
+    for case in operands.get(2..)?.chunks(2) {
+        let literal = match case.first()? {
+            Operand::LiteralBit32(literal) => *literal as u64,
+            Operand::LiteralBit64(literal) => *literal,
+            _ => return None,
+        };
+        if literal & mask == *bits {
+            return case.get(1)?.id_ref_any();
+        }
+    }
+    Some(default)
+}
+
+impl Patch for ConstantFold {
+    fn apply<'a>(self, mut patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError> {
+        let report = Self::run(patcher.ir_state.as_spirv());
+        log::info!("Constant folding: {}", report);
+        patcher.push_report(report);
+        Ok(patcher)
+    }
+}
@@ -0,0 +1,165 @@
+use patch_constant_fold::ConstantFold;
+use spv_patcher::{
+    rspirv::{
+        dr::{Module as SpvModule, Operand},
+        spirv::Op,
+    },
+    spirv_ext::SpirvExt,
+    verify::Verifier,
+    Module,
+};
+
+const SHADER: &str = r#"
+OpCapability Shader
+%glsl = OpExtInstImport "GLSL.std.450"
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %out "out"
+OpName %root "root"
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%float = OpTypeFloat 32
+%bool = OpTypeBool
+%v2uint = OpTypeVector %uint 2
+%ptr_uint = OpTypePointer Private %uint
+%ptr_float = OpTypePointer Private %float
+%uint_2 = OpConstant %uint 2
+%uint_3 = OpConstant %uint 3
+%uint_4 = OpConstant %uint 4
+%uint_10 = OpConstant %uint 10
+%float_4 = OpConstant %float 4
+%out = OpVariable %ptr_uint Private
+%root = OpVariable %ptr_float Private
+%main = OpFunction %void None %fn
+%entry = OpLabel
+%a = OpIAdd %uint %uint_2 %uint_3
+%b = OpIMul %uint %a %uint_4
+%vec = OpCompositeConstruct %v2uint %a %b
+%second = OpCompositeExtract %uint %vec 1
+%c = OpULessThan %bool %second %uint_10
+%s = OpExtInst %float %glsl Sqrt %float_4
+OpStore %root %s
+OpSelectionMerge %merge None
+OpBranchConditional %c %then %else
+%then = OpLabel
+OpStore %out %uint_2
+OpBranch %merge
+%else = OpLabel
+OpStore %out %uint_3
+OpBranch %merge
+%merge = OpLabel
+%p = OpPhi %uint %uint_2 %then %b %else
+OpSelectionMerge %sw_merge None
+OpSwitch %a %sw_default 4 %case_4 5 %case_5
+%sw_default = OpLabel
+OpBranch %sw_merge
+%case_4 = OpLabel
+OpStore %out %uint_4
+OpBranch %sw_merge
+%case_5 = OpLabel
+OpStore %out %p
+OpBranch %sw_merge
+%sw_merge = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+fn count(spv: &SpvModule, op: Op) -> usize {
+    spv.all_inst_iter()
+        .filter(|inst| inst.class.opcode == op)
+        .count()
+}
+
+#[test]
+fn fold_instructions_and_branches() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let report = ConstantFold::run(&mut spv);
+
+    //a, b, vec, second, c, s and the phi
+    assert_eq!(report.instructions, 7);
+    assert_eq!(report.branches, 2);
+    for op in [
+        Op::IAdd,
+        Op::IMul,
+        Op::CompositeConstruct,
+        Op::CompositeExtract,
+        Op::ULessThan,
+        Op::ExtInst,
+        Op::Phi,
+        Op::BranchConditional,
+    ] {
+        assert_eq!(count(&spv, op), 0, "{:?} was not folded", op);
+    }
+    //b = 20 is not less than 10, so the then block is gone
+    assert_eq!(report.blocks, 3);
+    assert_eq!(count(&spv, Op::SelectionMerge), 1);
+
+    let constant_of = |value: Operand| {
+        spv.types_global_values
+            .iter()
+            .find(|inst| inst.class.opcode == Op::Constant && inst.operands == [value.clone()])
+            .and_then(|inst| inst.result_id)
+    };
+    let uint_20 = constant_of(Operand::LiteralBit32(20)).unwrap();
+    let float_2 = constant_of(Operand::LiteralBit32(2.0f32.to_bits())).unwrap();
+    let stores = spv
+        .all_inst_iter()
+        .filter(|inst| inst.class.opcode == Op::Store)
+        .map(|inst| inst.operands[1].unwrap_id_ref())
+        .collect::<Vec<_>>();
+    //sqrt(4), the else branch, and case 5 which stores the folded phi
+    assert_eq!(stores.len(), 3);
+    assert_eq!(stores[0], float_2);
+    assert_eq!(stores[2], uint_20);
+    assert!(spv.get_by_name("out").is_some());
+
+    Verifier::verify(&spv).unwrap();
+
+    //Folding is idempotent
+    assert!(ConstantFold::run(&mut spv).is_empty());
+}
+
+const LOOP: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%bool = OpTypeBool
+%false = OpConstantFalse %bool
+%uint_0 = OpConstant %uint 0
+%uint_1 = OpConstant %uint 1
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpBranch %header
+%header = OpLabel
+%i = OpPhi %uint %uint_0 %entry %next %continue
+OpLoopMerge %merge %continue None
+OpBranchConditional %false %body %merge
+%body = OpLabel
+OpBranch %continue
+%continue = OpLabel
+%next = OpIAdd %uint %i %uint_1
+OpBranch %header
+%merge = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn fold_dead_loop() {
+    let module = Module::from_assembly(LOOP).unwrap();
+    let mut spv = module.spirv().clone();
+    let report = ConstantFold::run(&mut spv);
+    assert_eq!(report.branches, 1);
+    //The body is removed, the continue target is kept to keep the loop construct intact
+    assert_eq!(report.blocks, 1);
+    assert_eq!(count(&spv, Op::LoopMerge), 1);
+    assert_eq!(count(&spv, Op::IAdd), 0);
+    Verifier::verify(&spv).unwrap();
+}
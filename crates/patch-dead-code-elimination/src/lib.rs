@@ -0,0 +1,302 @@
+//! # Dead code elimination
+//!
+//! Removes everything from a module that can not be reached from its entry points. This is meant to be chained after
+//! patches that leave unused code behind, for instance the old function after a
+//! [DynamicReplace](https://docs.rs/patch-function) or the empty bodies of a `StaticReplace`.
+//!
+//! ## Implementation details
+//!
+//! - Mark:
+//!   - Seed with the entry points (including their interface), execution modes, exported functions, constants decorated
+//!     as `BuiltIn WorkgroupSize`, global (non-semantic) `OpExtInst`s, and the operands of all other result-less global
+//!     instructions that are always kept, such as `OpSource` or a global `OpLine`.
+//!   - Every id referenced by a live global instruction is live. If a function is live, every id defined or referenced
+//!     within its body is live.
+//!   - Decorations of live ids keep ids they reference (`OpDecorateId`, decoration groups) alive. Repeat until nothing
+//!     changes.
+//! - Sweep: remove functions, global variables, types, constants, ext-inst imports and strings that are not live,
+//!   together with their decorations and names.
+//!
+//! Dead code *within* live functions is not touched.
+
+#![deny(warnings)]
+
+use std::fmt::Display;
+
+use ahash::{AHashMap, AHashSet};
+use spv_patcher::{
+    patch::{Invariant, NoDanglingCalls, Patch, Patcher},
+    rspirv::{
+        dr::{Instruction, Module, Operand},
+        grammar::reflect,
+        spirv::{BuiltIn, Decoration, LinkageType, Op, Word},
+    },
+    PatcherError,
+};
+
+///Everything a [DeadCodeElimination] run removed. Ids are the ids the instructions had before removal.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DceReport {
+    pub functions: Vec<Word>,
+    pub variables: Vec<Word>,
+    pub types: Vec<Word>,
+    pub constants: Vec<Word>,
+    pub ext_inst_imports: Vec<Word>,
+    ///Number of removed annotation instructions.
+    pub decorations: usize,
+    ///Number of removed `OpName`s and `OpMemberName`s.
+    pub names: usize,
+    ///Number of removed `OpString`s.
+    pub strings: usize,
+}
+
+impl DceReport {
+    ///True if nothing was removed.
+    pub fn is_empty(&self) -> bool {
+        self.removed_instructions() == 0
+    }
+
+    ///Number of removed instructions, not counting the instructions within removed function bodies.
+    pub fn removed_instructions(&self) -> usize {
+        self.functions.len()
+            + self.variables.len()
+            + self.types.len()
+            + self.constants.len()
+            + self.ext_inst_imports.len()
+            + self.decorations
+            + self.names
+            + self.strings
+    }
+}
+
+impl Display for DceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "removed {} functions, {} variables, {} types, {} constants, {} ext-inst imports, {} decorations, {} names, {} strings",
+            self.functions.len(),
+            self.variables.len(),
+            self.types.len(),
+            self.constants.len(),
+            self.ext_inst_imports.len(),
+            self.decorations,
+            self.names,
+            self.strings
+        )
+    }
+}
+
+//Where an id that is tracked for liveness is defined.
+#[derive(Clone, Copy)]
+enum Def {
+    Global(usize),
+    Function(usize),
+}
+
+///Removes functions that are unreachable from any entry point, as well as unused global variables, types, constants,
+/// decorations, names and ext-inst imports. Exported functions (`LinkageAttributes` with `Export`) are kept.
+///
+///Note that all entry points are kept, even if the [Patcher] is scoped to a single one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeadCodeElimination;
+
+impl DeadCodeElimination {
+    ///Eliminates dead code in `module` and returns what was removed.
+    pub fn run(module: &mut Module) -> DceReport {
+        let live = Self::mark(module);
+        Self::sweep(module, &live)
+    }
+
+    fn mark(module: &Module) -> AHashSet<Word> {
+        let mut defs = AHashMap::default();
+        for (idx, inst) in module.types_global_values.iter().enumerate() {
+            if let Some(id) = inst.result_id {
+                defs.insert(id, Def::Global(idx));
+            }
+        }
+        for (idx, f) in module.functions.iter().enumerate() {
+            if let Some(id) = f.def_id() {
+                defs.insert(id, Def::Function(idx));
+            }
+        }
+
+        let mut stack = Vec::new();
+        let push_refs = |inst: &Instruction, stack: &mut Vec<Word>| {
+            stack.extend(inst.result_type);
+            stack.extend(inst.operands.iter().filter_map(|op| op.id_ref_any()));
+        };
+
+        //Result-less global instructions are always kept, so everything they reference is live. Names and decorations
+        // are the exception, they are removed together with their target.
+        for inst in module
+            .entry_points
+            .iter()
+            .chain(module.execution_modes.iter())
+            .chain(module.debug_string_source.iter())
+            .chain(module.debug_module_processed.iter())
+            .chain(module.types_global_values.iter())
+            .filter(|inst| inst.result_id.is_none() && inst.class.opcode != Op::TypeForwardPointer)
+        {
+            push_refs(inst, &mut stack);
+        }
+        for inst in &module.annotations {
+            //Only exports are roots, imports are just declarations.
+            let is_root = match inst.operands.get(1..) {
+                Some(
+                    [Operand::Decoration(Decoration::LinkageAttributes), _, Operand::LinkageType(LinkageType::Export)]
+                    | [Operand::Decoration(Decoration::BuiltIn), Operand::BuiltIn(BuiltIn::WorkgroupSize)],
+                ) => inst.class.opcode == Op::Decorate,
+                _ => false,
+            };
+            if is_root {
+                stack.extend(inst.operands.get(0).and_then(|op| op.id_ref_any()));
+            }
+        }
+        for inst in &module.types_global_values {
+            if inst.class.opcode == Op::ExtInst {
+                stack.extend(inst.result_id);
+            }
+        }
+
+        let mut live = AHashSet::default();
+        loop {
+            while let Some(id) = stack.pop() {
+                if !live.insert(id) {
+                    continue;
+                }
+                match defs.get(&id) {
+                    Some(Def::Global(idx)) => {
+                        push_refs(&module.types_global_values[*idx], &mut stack)
+                    }
+                    Some(Def::Function(idx)) => {
+                        //Everything defined within a live function is kept, so its names and decorations are kept
+                        // as well, even if nothing references it (entry labels, unused parameters and results).
+                        for inst in module.functions[*idx].all_inst_iter() {
+                            live.extend(inst.result_id);
+                            push_refs(inst, &mut stack);
+                        }
+                    }
+                    None => {}
+                }
+            }
+
+            //Decorations of live ids might keep other ids alive.
+            for inst in &module.annotations {
+                let mut ids = inst.operands.iter().filter_map(|op| op.id_ref_any());
+                let first = ids.next();
+                match inst.class.opcode {
+                    //The group is live if any of its targets is.
+                    Op::GroupDecorate | Op::GroupMemberDecorate => {
+                        if ids.any(|target| live.contains(&target)) {
+                            stack.extend(first.filter(|group| !live.contains(group)));
+                        }
+                    }
+                    _ => {
+                        if first.map(|target| live.contains(&target)).unwrap_or(false) {
+                            stack.extend(ids.filter(|id| !live.contains(id)));
+                        }
+                    }
+                }
+            }
+            if stack.is_empty() {
+                break;
+            }
+        }
+
+        live
+    }
+
+    fn sweep(module: &mut Module, live: &AHashSet<Word>) -> DceReport {
+        let mut report = DceReport::default();
+        let is_live = |id: Option<Word>| id.map(|id| live.contains(&id)).unwrap_or(true);
+        let first_id = |inst: &Instruction| inst.operands.get(0).and_then(|op| op.id_ref_any());
+
+        module.functions.retain(|f| {
+            let keep = is_live(f.def_id());
+            if !keep {
+                report.functions.extend(f.def_id());
+            }
+            keep
+        });
+
+        module.types_global_values.retain(|inst| {
+            let keep = match inst.class.opcode {
+                Op::TypeForwardPointer => is_live(first_id(inst)),
+                _ => is_live(inst.result_id),
+            };
+            if !keep {
+                let op = inst.class.opcode;
+                let list = if op == Op::Variable {
+                    &mut report.variables
+                } else if reflect::is_type(op) {
+                    &mut report.types
+                } else {
+                    &mut report.constants
+                };
+                list.extend(inst.result_id);
+            }
+            keep
+        });
+
+        module.ext_inst_imports.retain(|inst| {
+            let keep = is_live(inst.result_id);
+            if !keep {
+                report.ext_inst_imports.extend(inst.result_id);
+            }
+            keep
+        });
+
+        let before = module.debug_string_source.len();
+        module
+            .debug_string_source
+            .retain(|inst| inst.class.opcode != Op::String || is_live(inst.result_id));
+        report.strings = before - module.debug_string_source.len();
+
+        let before = module.debug_names.len();
+        module.debug_names.retain(|inst| is_live(first_id(inst)));
+        report.names = before - module.debug_names.len();
+
+        let before = module.annotations.len();
+        module
+            .annotations
+            .retain_mut(|inst| match inst.class.opcode {
+                Op::DecorationGroup => is_live(inst.result_id),
+                Op::GroupDecorate => {
+                    let group = inst.operands.remove(0);
+                    inst.operands.retain(|op| is_live(op.id_ref_any()));
+                    inst.operands.insert(0, group);
+                    inst.operands.len() > 1
+                }
+                Op::GroupMemberDecorate => {
+                    //Group, followed by (target, member) pairs
+                    let mut pairs = inst.operands.split_off(1);
+                    let mut kept = Vec::with_capacity(pairs.len());
+                    while pairs.len() >= 2 {
+                        let pair: Vec<Operand> = pairs.drain(0..2).collect();
+                        if is_live(pair[0].id_ref_any()) {
+                            kept.extend(pair);
+                        }
+                    }
+                    inst.operands.extend(kept);
+                    inst.operands.len() > 1
+                }
+                _ => is_live(first_id(inst)),
+            });
+        report.decorations = before - module.annotations.len();
+
+        report
+    }
+}
+
+impl Patch for DeadCodeElimination {
+    fn apply<'a>(self, mut patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError> {
+        let report = Self::run(patcher.ir_state.as_spirv());
+        log::info!("Dead code elimination {}", report);
+        patcher.push_report(report);
+        Ok(patcher)
+    }
+
+    fn invariants(&self) -> Vec<Box<dyn Invariant>> {
+        vec![Box::new(NoDanglingCalls)]
+    }
+}
@@ -0,0 +1,157 @@
+use patch_dead_code_elimination::DeadCodeElimination;
+use spv_patcher::{rspirv::spirv::Op, Module};
+
+//`dead` is only called by `also_dead`, which no entry point reaches. `exported` is kept through its linkage.
+const SHADER: &str = r#"
+OpCapability Shader
+OpCapability Linkage
+%glsl = OpExtInstImport "GLSL.std.450"
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %dead "dead"
+OpName %unused_var "unused_var"
+OpDecorate %unused_var DescriptorSet 0
+OpDecorate %exported LinkageAttributes "exported" Export
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%float = OpTypeFloat 32
+%uint = OpTypeInt 32 0
+%float_1 = OpConstant %float 1
+%uint_7 = OpConstant %uint 7
+%ptr = OpTypePointer Private %uint
+%unused_var = OpVariable %ptr Private
+%dead = OpFunction %void None %fn
+%l0 = OpLabel
+%s = OpExtInst %float %glsl Sqrt %float_1
+OpReturn
+OpFunctionEnd
+%also_dead = OpFunction %void None %fn
+%l1 = OpLabel
+%c0 = OpFunctionCall %void %dead
+OpReturn
+OpFunctionEnd
+%exported = OpFunction %void None %fn
+%l2 = OpLabel
+OpReturn
+OpFunctionEnd
+%main = OpFunction %void None %fn
+%l3 = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn removes_unreachable_code() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let report = DeadCodeElimination::run(&mut spv);
+
+    assert_eq!(report.functions.len(), 2);
+    assert_eq!(report.variables.len(), 1);
+    //float, uint and the pointer type
+    assert_eq!(report.types.len(), 3);
+    assert_eq!(report.constants.len(), 2);
+    assert_eq!(report.ext_inst_imports.len(), 1);
+    assert_eq!(report.decorations, 1);
+    assert_eq!(report.names, 2);
+
+    assert_eq!(spv.functions.len(), 2);
+    assert!(spv.ext_inst_imports.is_empty());
+    assert!(spv.debug_names.is_empty());
+    assert_eq!(spv.annotations.len(), 1);
+    assert!(spv
+        .types_global_values
+        .iter()
+        .all(|inst| matches!(inst.class.opcode, Op::TypeVoid | Op::TypeFunction)));
+
+    //A second run has nothing left to do
+    assert!(DeadCodeElimination::run(&mut spv).is_empty());
+}
+
+#[test]
+fn as_patch() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let patched = module
+        .patch()
+        .patch(DeadCodeElimination)
+        .unwrap()
+        .unwrap_module();
+    assert_eq!(patched.functions.len(), 2);
+}
+
+//The global `OpLine` is the only user of `%file`.
+const GLOBAL_LINE: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+%file = OpString "shaders/line.comp"
+%unused = OpString "unused"
+%void = OpTypeVoid
+OpLine %file 3 0
+%fn = OpTypeFunction %void
+%main = OpFunction %void None %fn
+%l0 = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn keeps_strings_of_global_lines() {
+    let module = Module::from_assembly(GLOBAL_LINE).unwrap();
+    let mut spv = module.spirv().clone();
+    let report = DeadCodeElimination::run(&mut spv);
+    assert_eq!(report.strings, 1);
+
+    let file = spv
+        .types_global_values
+        .iter()
+        .find(|inst| inst.class.opcode == Op::Line)
+        .and_then(|inst| inst.operands[0].id_ref_any())
+        .unwrap();
+    assert!(spv
+        .debug_string_source
+        .iter()
+        .any(|inst| inst.result_id == Some(file)));
+}
+
+//Nothing references `%entry`, `%unused_param` or `%unused_result`, but they are defined within live functions.
+const LIVE_LOCALS: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %entry "entry"
+OpName %unused_param "unused_param"
+OpName %unused_result "unused_result"
+OpDecorate %unused_param RelaxedPrecision
+OpDecorate %unused_result RelaxedPrecision
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%float = OpTypeFloat 32
+%float_1 = OpConstant %float 1
+%fn_float = OpTypeFunction %void %float
+%helper = OpFunction %void None %fn_float
+%unused_param = OpFunctionParameter %float
+%l0 = OpLabel
+%unused_result = OpFAdd %float %float_1 %float_1
+OpReturn
+OpFunctionEnd
+%main = OpFunction %void None %fn
+%entry = OpLabel
+%c0 = OpFunctionCall %void %helper %float_1
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn keeps_names_and_decorations_within_live_functions() {
+    let module = Module::from_assembly(LIVE_LOCALS).unwrap();
+    let mut spv = module.spirv().clone();
+    let report = DeadCodeElimination::run(&mut spv);
+
+    assert!(report.is_empty(), "{}", report);
+    assert_eq!(spv.debug_names.len(), 3);
+    assert_eq!(spv.annotations.len(), 2);
+}
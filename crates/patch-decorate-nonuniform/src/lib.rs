@@ -19,12 +19,16 @@
 //! - Pass 3:
 //!   - If non-uniformity found, and ext not already there, add nonuniform ext to module.
 //!   - After analysis, if non-uniformity was found, add Capabilities if not already there.
+//!
+//! If the [Patcher](spv_patcher::patch::Patcher) is scoped to an entry point, only functions reachable from that
+//! entry point are traced and decorated.
 
 #![deny(warnings)]
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 use smallvec::SmallVec;
 use spv_patcher::{
+    analysis::{DefUse, InstLocation, UseSlot},
     patch::Patch,
     rspirv::{
         dr::{Instruction, Module, Operand},
@@ -64,6 +68,37 @@ pub struct NonUniformDecorate {
     to_decorate: SmallVec<[Instruction; 5]>,
     #[allow(dead_code)]
     indexed_type: IndexedType,
+    //Functions reachable from the selected entry point. `None` if all functions are considered.
+    scope: Option<AHashSet<u32>>,
+}
+
+//Iterates all global instructions, and all instructions of functions that are in `scope`.
+fn scoped_inst_iter<'a>(
+    spirv: &'a Module,
+    scope: &'a Option<AHashSet<u32>>,
+) -> impl Iterator<Item = &'a Instruction> {
+    spirv.global_inst_iter().chain(
+        spirv
+            .functions
+            .iter()
+            .filter(move |f| match (scope, f.def_id()) {
+                (Some(scope), Some(id)) => scope.contains(&id),
+                (Some(_), None) => false,
+                (None, _) => true,
+            })
+            .flat_map(|f| f.all_inst_iter()),
+    )
+}
+
+//True if `location` is global, or part of a function in `scope`.
+fn in_scope(spirv: &Module, scope: &Option<AHashSet<u32>>, location: InstLocation) -> bool {
+    match (scope, location.function()) {
+        (Some(scope), Some(function)) => spirv.functions[function]
+            .def_id()
+            .map(|id| scope.contains(&id))
+            .unwrap_or(false),
+        _ => true,
+    }
 }
 
 impl NonUniformDecorate {
@@ -73,10 +108,11 @@ impl NonUniformDecorate {
             runtime_arrays: SmallVec::new(),
             to_decorate: SmallVec::new(),
             indexed_type: IndexedType::default(),
+            scope: None,
         }
     }
 
-    fn seed_variables(&mut self, spirv: &Module) {
+    fn seed_variables(&mut self, spirv: &Module, def_use: &DefUse) {
         //Seeding pass. This is any input variable.
         for inst in spirv.global_inst_iter() {
             match inst.class.opcode {
@@ -118,63 +154,40 @@ impl NonUniformDecorate {
             }
         }
 
-        //now forward seed all ids that depend on a already seeded instruction.
-        //Do this in a loop until nothing changes, basically fix-point?
-
-        //NOTE thats currently kinda slow since it scales with the
-        // trace_queue length (at worst). Consider doing backwards tracing from the
-        // AccessChain
-        'forward_seeding: loop {
-            let mut changed = false;
-
-            for inst in spirv.all_inst_iter() {
-                if let Some(resid) = inst.result_id {
-                    //check if this instruction's operands are any already seeded variables
-                    'uses_search: for op in inst.operands.iter() {
-                        if let Some(refs) = op.id_ref_any() {
-                            if self.seeded_variables.contains_key(&refs) {
-                                log::info!("Forward seeding {}", resid);
-                                let was_present =
-                                    self.seeded_variables.insert(resid, inst.clone()).is_some();
-                                //Only mark if the id wasn't already in there
-                                if !was_present {
-                                    changed = true;
-                                }
-                                break 'uses_search;
-                            }
-                        }
-                    }
-                } else {
+        //now forward seed all ids that depend on a already seeded instruction. Each seeded id's users are visited
+        // exactly once via the def-use index.
+        let mut worklist: Vec<u32> = self.seeded_variables.keys().copied().collect();
+        while let Some(seeded) = worklist.pop() {
+            for user in def_use.users_of(seeded) {
+                //Only operands propagate, a seeded type doesn't make its values non-uniform.
+                let UseSlot::Operand(operand) = user.slot else {
+                    continue;
+                };
+                if !in_scope(spirv, &self.scope, user.location) {
+                    continue;
+                }
+                let Some(inst) = user.location.get(spirv) else {
+                    continue;
+                };
+
+                let target = match (inst.result_id, inst.class.opcode) {
+                    (Some(resid), _) => Some(resid),
                     //In this case we might have a  OpStore that could change a
                     // assignment. All the others (OpBranch, OpLine etc.) are not interesting afaik.
                     //
                     // TODO: Check that we are actually catching the branches and function calls
-                    match inst.class.opcode {
-                        Op::Store => {
-                            //Check if op_store's src id is in
-                            if let (Some(dst_id), Some(src_id)) =
-                                (inst.operands[0].id_ref_any(), inst.operands[1].id_ref_any())
-                            {
-                                if self.seeded_variables.contains_key(&src_id) {
-                                    let was_present = self
-                                        .seeded_variables
-                                        .insert(dst_id, inst.clone())
-                                        .is_some();
-                                    //Only mark if the id wasn't already in there
-                                    if !was_present {
-                                        changed = true;
-                                    }
-                                }
-                            }
-                        }
-                        _ => {}
+                    (None, Op::Store) if operand == 1 => inst.operands[0].id_ref_any(),
+                    _ => None,
+                };
+                if let Some(target) = target {
+                    //Only trace if the id wasn't already in there
+                    if !self.seeded_variables.contains_key(&target) {
+                        log::info!("Forward seeding {}", target);
+                        self.seeded_variables.insert(target, inst.clone());
+                        worklist.push(target);
                     }
                 }
             }
-
-            if !changed {
-                break 'forward_seeding;
-            }
         }
     }
 
@@ -239,7 +252,7 @@ impl NonUniformDecorate {
         // the intersection with non-uniform based
         // indices.
 
-        for inst in spirv.all_inst_iter() {
+        for inst in scoped_inst_iter(spirv, &self.scope) {
             match inst.class.opcode {
                 //TODO: test for AccessChainBound and PtrAccessChain as well?
                 Op::AccessChain => {
@@ -371,12 +384,16 @@ impl Patch for NonUniformDecorate {
         mut self,
         mut patcher: spv_patcher::patch::Patcher<'a>,
     ) -> Result<spv_patcher::patch::Patcher<'a>, spv_patcher::PatcherError> {
-        let spirv = patcher.ir_state.as_spirv();
+        self.scope = patcher.scope();
+        {
+            let (spirv, def_use) = patcher.ir_state.def_use();
+            //Pass 1: Seeding non-uniform ids
+            self.seed_variables(spirv, def_use);
+            //Pass 2:
+            self.trace_indices(spirv);
+        }
 
-        //Pass 1: Seeding non-uniform ids
-        self.seed_variables(spirv);
-        //Pass 2:
-        self.trace_indices(spirv);
+        let spirv = patcher.ir_state.as_spirv();
         self.decorate(spirv);
         //Pass 3: Fixing up capabilities and extensions
         self.fix_capabilities_and_extensions(spirv);
@@ -0,0 +1,151 @@
+use patch_decorate_nonuniform::NonUniformDecorate;
+use spv_patcher::{
+    rspirv::{
+        binary::Assemble,
+        dr::{Builder, Module as SpvModule, Operand},
+        spirv::{
+            self, AddressingModel, Capability, Decoration, Dim, ExecutionMode, ExecutionModel,
+            FunctionControl, ImageFormat, Op, StorageClass,
+        },
+    },
+    spirv_ext::SpirvExt,
+    Module,
+};
+
+//Both stages index `textures` with the non-uniform `index`, each in its own function.
+fn stages() -> Module {
+    let mut b = Builder::new();
+    b.capability(Capability::Shader);
+    b.memory_model(AddressingModel::Logical, spirv::MemoryModel::GLSL450);
+    let void = b.type_void();
+    let fn_ty = b.type_function(void, vec![]);
+    let float = b.type_float(32);
+    let uint = b.type_int(32, 0);
+    let img = b.type_image(float, Dim::Dim2D, 0, 0, 0, 1, ImageFormat::Unknown, None);
+    let img_arr = b.type_runtime_array(img);
+    let ptr_arr = b.type_pointer(None, StorageClass::UniformConstant, img_arr);
+    let ptr_img = b.type_pointer(None, StorageClass::UniformConstant, img);
+    let ptr_index = b.type_pointer(None, StorageClass::Input, uint);
+    let textures = b.variable(ptr_arr, None, StorageClass::UniformConstant, None);
+    let index = b.variable(ptr_index, None, StorageClass::Input, None);
+    b.decorate(index, Decoration::Flat, []);
+    b.decorate(index, Decoration::Location, [Operand::LiteralInt32(0)]);
+    b.decorate(
+        textures,
+        Decoration::DescriptorSet,
+        [Operand::LiteralInt32(0)],
+    );
+    b.decorate(textures, Decoration::Binding, [Operand::LiteralInt32(0)]);
+
+    let sample = |b: &mut Builder, access: &str| {
+        let f = b
+            .begin_function(void, None, FunctionControl::NONE, fn_ty)
+            .unwrap();
+        b.begin_block(None).unwrap();
+        let i = b.load(uint, None, index, None, []).unwrap();
+        let chain = b.access_chain(ptr_img, None, textures, [i]).unwrap();
+        b.load(img, None, chain, None, []).unwrap();
+        b.ret().unwrap();
+        b.end_function().unwrap();
+        b.name(chain, access);
+        f
+    };
+    let sample_frag = sample(&mut b, "frag_access");
+    let sample_comp = sample(&mut b, "comp_access");
+    let stage = |b: &mut Builder, callee: u32| {
+        let f = b
+            .begin_function(void, None, FunctionControl::NONE, fn_ty)
+            .unwrap();
+        b.begin_block(None).unwrap();
+        b.function_call(void, None, callee, []).unwrap();
+        b.ret().unwrap();
+        b.end_function().unwrap();
+        f
+    };
+    let frag = stage(&mut b, sample_frag);
+    let comp = stage(&mut b, sample_comp);
+
+    b.entry_point(ExecutionModel::Fragment, frag, "main", [index]);
+    b.entry_point(ExecutionModel::GLCompute, comp, "main", [index]);
+    b.execution_mode(frag, ExecutionMode::OriginUpperLeft, []);
+    b.execution_mode(comp, ExecutionMode::LocalSize, [1, 1, 1]);
+
+    let bytes = b
+        .module()
+        .assemble()
+        .into_iter()
+        .flat_map(|w| w.to_le_bytes())
+        .collect();
+    Module::new(bytes).unwrap()
+}
+
+fn non_uniform(spv: &SpvModule) -> Vec<u32> {
+    let mut ids = spv
+        .annotations
+        .iter()
+        .filter(|inst| {
+            inst.class.opcode == Op::Decorate
+                && inst.operands.get(1) == Some(&Operand::Decoration(Decoration::NonUniform))
+        })
+        .filter_map(|inst| inst.operands[0].id_ref_any())
+        .collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+//The access chain, its base, its index and the load through it.
+fn decorated(spv: &SpvModule, access: &str) -> Vec<u32> {
+    let chain = spv.get_by_name(access).unwrap().result_id.unwrap();
+    let mut ids = vec![chain];
+    for inst in spv.all_inst_iter() {
+        if inst.class.opcode == Op::AccessChain && inst.result_id == Some(chain) {
+            ids.extend(inst.operands.iter().filter_map(|op| op.id_ref_any()));
+        }
+        if inst.class.opcode == Op::Load && inst.operands[0].id_ref_any() == Some(chain) {
+            ids.push(inst.result_id.unwrap());
+        }
+    }
+    ids.sort();
+    ids
+}
+
+#[test]
+fn decorate_whole_module() {
+    let module = stages();
+    let patched = module
+        .patch()
+        .patch(NonUniformDecorate::new())
+        .unwrap()
+        .unwrap_module();
+
+    let mut expected = decorated(&patched, "frag_access");
+    expected.extend(decorated(&patched, "comp_access"));
+    expected.sort();
+    expected.dedup();
+    assert_eq!(non_uniform(&patched), expected);
+}
+
+#[test]
+fn decorate_scoped() {
+    let module = stages();
+    let patched = module
+        .patch_entry_point("main", Some(ExecutionModel::Fragment))
+        .unwrap()
+        .patch(NonUniformDecorate::new())
+        .unwrap()
+        .unwrap_module();
+    assert_eq!(non_uniform(&patched), decorated(&patched, "frag_access"));
+    assert!(patched
+        .capabilities
+        .iter()
+        .any(|inst| inst.operands[0] == Operand::Capability(Capability::ShaderNonUniform)));
+
+    let patched = module
+        .patch_entry_point("main", Some(ExecutionModel::GLCompute))
+        .unwrap()
+        .patch(NonUniformDecorate::new())
+        .unwrap()
+        .unwrap_module();
+    assert_eq!(non_uniform(&patched), decorated(&patched, "comp_access"));
+}
@@ -0,0 +1,313 @@
+//! # Descriptor remapping
+//!
+//! Rewrites the `DescriptorSet` and `Binding` decorations of a module's descriptors, so that modules written against
+//! different descriptor layouts fit a common pipeline layout without recompiling them.
+//!
+//! ## Implementation details
+//!
+//! All descriptors (`Uniform`, `UniformConstant` and `StorageBuffer` variables with a set and binding) are collected
+//! via [reflect::descriptors] first. The new binding of each descriptor is then chosen by, in this order of precedence:
+//!
+//! 1. an explicit mapping added with [DescriptorRemap::with_binding],
+//! 2. the mapper closure, see [DescriptorRemap::with_mapper],
+//! 3. collapsing into a single set, see [DescriptorRemap::collapse_into]. The target set keeps its bindings, all other
+//!    sets follow in ascending order, each starting behind the highest binding used so far.
+//!
+//! Descriptors that end up on the same binding as another descriptor are reported as collision, unless aliasing is
+//! allowed. Bindings that already aliased before remapping are left alone. `InputAttachmentIndex` decorations are
+//! rewritten with their own table.
+
+#![deny(warnings)]
+
+use std::fmt::Display;
+
+use ahash::AHashMap;
+use spv_patcher::{
+    patch::{Patch, Patcher},
+    reflect::{self, DescriptorBinding},
+    rspirv::{
+        dr::{Module, Operand},
+        spirv::{Decoration, Op, Word},
+    },
+    PatcherError,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RemapError {
+    #[error("Descriptors %{first} and %{second} would both be bound at {binding}")]
+    Collision {
+        binding: Binding,
+        first: Word,
+        second: Word,
+    },
+    #[error("Input attachments %{first} and %{second} would both use index {index}")]
+    InputAttachmentCollision {
+        index: u32,
+        first: Word,
+        second: Word,
+    },
+}
+
+///A descriptor set and binding pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Binding {
+    pub descriptor_set: u32,
+    pub binding: u32,
+}
+
+impl Binding {
+    pub fn new(descriptor_set: u32, binding: u32) -> Self {
+        Binding {
+            descriptor_set,
+            binding,
+        }
+    }
+}
+
+impl Display for Binding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "set {} binding {}", self.descriptor_set, self.binding)
+    }
+}
+
+impl From<&DescriptorBinding> for Binding {
+    fn from(descriptor: &DescriptorBinding) -> Self {
+        Binding::new(descriptor.set, descriptor.binding)
+    }
+}
+
+///A descriptor that was moved by a [DescriptorRemap].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Remapped {
+    pub variable: Word,
+    pub from: Binding,
+    pub to: Binding,
+}
+
+///Result of a [DescriptorRemap] run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemapReport {
+    pub remapped: Vec<Remapped>,
+    ///Number of rewritten `InputAttachmentIndex` decorations.
+    pub input_attachments: usize,
+}
+
+impl Display for RemapReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "remapped {} descriptors", self.remapped.len())?;
+        for remapped in &self.remapped {
+            write!(
+                f,
+                "\n    %{}: {} -> {}",
+                remapped.variable, remapped.from, remapped.to
+            )?;
+        }
+        if self.input_attachments > 0 {
+            write!(
+                f,
+                "\n    {} input attachment indices",
+                self.input_attachments
+            )?;
+        }
+        Ok(())
+    }
+}
+
+///Closure that chooses a descriptor's new binding. Returns `None` to keep the current one.
+pub type Mapper = Box<dyn Fn(&DescriptorBinding) -> Option<Binding>>;
+
+///Rewrites descriptor bindings of a module. Without any mapping, the module stays unchanged.
+#[derive(Default)]
+pub struct DescriptorRemap {
+    pub bindings: AHashMap<Binding, Binding>,
+    pub input_attachments: AHashMap<u32, u32>,
+    pub mapper: Option<Mapper>,
+    ///Set all descriptors are moved into.
+    pub collapse: Option<u32>,
+    ///If set, descriptors may share a binding after remapping.
+    pub allow_aliasing: bool,
+}
+
+impl DescriptorRemap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Moves the descriptor at `from` to `to`.
+    pub fn with_binding(mut self, from: Binding, to: Binding) -> Self {
+        self.bindings.insert(from, to);
+        self
+    }
+
+    ///Changes the input attachment index `from` to `to`.
+    pub fn with_input_attachment(mut self, from: u32, to: u32) -> Self {
+        self.input_attachments.insert(from, to);
+        self
+    }
+
+    ///Chooses the binding of every descriptor that has no explicit mapping.
+    pub fn with_mapper(
+        mut self,
+        mapper: impl Fn(&DescriptorBinding) -> Option<Binding> + 'static,
+    ) -> Self {
+        self.mapper = Some(Box::new(mapper));
+        self
+    }
+
+    ///Moves all descriptors into `descriptor_set`.
+    pub fn collapse_into(mut self, descriptor_set: u32) -> Self {
+        self.collapse = Some(descriptor_set);
+        self
+    }
+
+    pub fn allow_aliasing(mut self) -> Self {
+        self.allow_aliasing = true;
+        self
+    }
+
+    ///Remaps the descriptors of `module`.
+    pub fn run(&self, module: &mut Module) -> Result<RemapReport, RemapError> {
+        let descriptors = reflect::descriptors(module);
+        let collapsed = self.collapsed(&descriptors);
+
+        let mut targets = Vec::with_capacity(descriptors.len());
+        for descriptor in &descriptors {
+            let binding = Binding::from(descriptor);
+            let target = self
+                .bindings
+                .get(&binding)
+                .copied()
+                .or_else(|| self.mapper.as_ref().and_then(|mapper| mapper(descriptor)))
+                .or_else(|| collapsed.get(&binding).copied())
+                .unwrap_or(binding);
+            let index = descriptor
+                .input_attachment_index
+                .map(|index| *self.input_attachments.get(&index).unwrap_or(&index));
+            targets.push((target, index));
+        }
+        self.check_collisions(&descriptors, &targets)?;
+
+        let mut report = RemapReport::default();
+        for (descriptor, (target, index)) in descriptors.iter().zip(targets) {
+            let binding = Binding::from(descriptor);
+            if target != binding {
+                set_decoration(
+                    module,
+                    descriptor.variable,
+                    Decoration::DescriptorSet,
+                    target.descriptor_set,
+                );
+                set_decoration(
+                    module,
+                    descriptor.variable,
+                    Decoration::Binding,
+                    target.binding,
+                );
+                report.remapped.push(Remapped {
+                    variable: descriptor.variable,
+                    from: binding,
+                    to: target,
+                });
+            }
+            if let Some(index) = index {
+                if Some(index) != descriptor.input_attachment_index {
+                    set_decoration(
+                        module,
+                        descriptor.variable,
+                        Decoration::InputAttachmentIndex,
+                        index,
+                    );
+                    report.input_attachments += 1;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    //Bindings each binding moves to when collapsing all sets into one.
+    fn collapsed(&self, descriptors: &[DescriptorBinding]) -> AHashMap<Binding, Binding> {
+        let mut mapping = AHashMap::default();
+        let Some(set) = self.collapse else {
+            return mapping;
+        };
+        let mut bindings = descriptors.iter().map(Binding::from).collect::<Vec<_>>();
+        //The target set keeps its bindings, so it goes first
+        bindings.sort_by_key(|binding| (binding.descriptor_set != set, *binding));
+        bindings.dedup();
+
+        let mut offset = 0;
+        let mut next = 0;
+        let mut current = None;
+        for binding in bindings {
+            if current != Some(binding.descriptor_set) {
+                current = Some(binding.descriptor_set);
+                offset = next;
+            }
+            let target = Binding::new(set, binding.binding + offset);
+            next = next.max(target.binding + 1);
+            mapping.insert(binding, target);
+        }
+        mapping
+    }
+
+    fn check_collisions(
+        &self,
+        descriptors: &[DescriptorBinding],
+        targets: &[(Binding, Option<u32>)],
+    ) -> Result<(), RemapError> {
+        let mut taken: AHashMap<Binding, usize> = AHashMap::default();
+        let mut attachments: AHashMap<u32, usize> = AHashMap::default();
+        for (idx, (descriptor, (target, index))) in descriptors.iter().zip(targets).enumerate() {
+            //Descriptors that shared a binding before may keep sharing it
+            if let Some(other) = taken.insert(*target, idx) {
+                if !self.allow_aliasing
+                    && Binding::from(&descriptors[other]) != Binding::from(descriptor)
+                {
+                    return Err(RemapError::Collision {
+                        binding: *target,
+                        first: descriptors[other].variable,
+                        second: descriptor.variable,
+                    });
+                }
+            }
+            if let Some(index) = index {
+                if let Some(other) = attachments.insert(*index, idx) {
+                    if descriptors[other].input_attachment_index
+                        != descriptor.input_attachment_index
+                    {
+                        return Err(RemapError::InputAttachmentCollision {
+                            index: *index,
+                            first: descriptors[other].variable,
+                            second: descriptor.variable,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+//Sets the value of `decoration` on `id`.
+fn set_decoration(module: &mut Module, id: Word, decoration: Decoration, value: u32) {
+    for inst in module.annotations.iter_mut() {
+        if inst.class.opcode == Op::Decorate
+            && inst.operands.first() == Some(&Operand::IdRef(id))
+            && inst.operands.get(1) == Some(&Operand::Decoration(decoration))
+        {
+            inst.operands[2] = Operand::LiteralBit32(value);
+        }
+    }
+}
+
+impl Patch for DescriptorRemap {
+    fn apply<'a>(self, mut patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError> {
+        let report = self
+            .run(patcher.ir_state.as_spirv())
+            .map_err(|e| PatcherError::Internal(e.into()))?;
+        log::info!("Descriptor remap: {}", report);
+        patcher.push_report(report);
+        Ok(patcher)
+    }
+}
@@ -0,0 +1,193 @@
+use patch_descriptor_remap::{Binding, DescriptorRemap, RemapError};
+use spv_patcher::{
+    reflect::{descriptors, DescriptorType},
+    rspirv::{
+        dr::{Module as SpvModule, Operand},
+        spirv::Decoration,
+    },
+    spirv_ext::SpirvExt,
+    verify::Verifier,
+    Module,
+};
+
+const SHADER: &str = r#"
+OpCapability Shader
+OpCapability InputAttachment
+OpMemoryModel Logical GLSL450
+OpEntryPoint Fragment %main "main"
+OpExecutionMode %main OriginUpperLeft
+OpName %camera "camera"
+OpName %albedo "albedo"
+OpName %gbuffer "gbuffer"
+OpName %particles "particles"
+OpName %sampler "sampler"
+OpMemberDecorate %camera_block 0 Offset 0
+OpDecorate %camera_block Block
+OpDecorate %camera DescriptorSet 0
+OpDecorate %camera Binding 0
+OpDecorate %albedo DescriptorSet 0
+OpDecorate %albedo Binding 1
+OpDecorate %gbuffer DescriptorSet 0
+OpDecorate %gbuffer Binding 2
+OpDecorate %gbuffer InputAttachmentIndex 0
+OpDecorate %float_arr ArrayStride 4
+OpMemberDecorate %particle_block 0 Offset 0
+OpDecorate %particle_block BufferBlock
+OpDecorate %particles DescriptorSet 1
+OpDecorate %particles Binding 0
+OpDecorate %sampler DescriptorSet 1
+OpDecorate %sampler Binding 1
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%float = OpTypeFloat 32
+%uint = OpTypeInt 32 0
+%uint_4 = OpConstant %uint 4
+%v4float = OpTypeVector %float 4
+%camera_block = OpTypeStruct %v4float
+%ptr_camera = OpTypePointer Uniform %camera_block
+%image = OpTypeImage %float 2D 0 0 0 1 Unknown
+%image_arr = OpTypeArray %image %uint_4
+%ptr_images = OpTypePointer UniformConstant %image_arr
+%subpass = OpTypeImage %float SubpassData 0 0 0 2 Unknown
+%ptr_subpass = OpTypePointer UniformConstant %subpass
+%float_arr = OpTypeRuntimeArray %float
+%particle_block = OpTypeStruct %float_arr
+%ptr_particles = OpTypePointer Uniform %particle_block
+%sampler_ty = OpTypeSampler
+%ptr_sampler = OpTypePointer UniformConstant %sampler_ty
+%camera = OpVariable %ptr_camera Uniform
+%albedo = OpVariable %ptr_images UniformConstant
+%gbuffer = OpVariable %ptr_subpass UniformConstant
+%particles = OpVariable %ptr_particles Uniform
+%sampler = OpVariable %ptr_sampler UniformConstant
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+fn decoration(spv: &SpvModule, name: &str, decoration: Decoration) -> u32 {
+    let id = spv.get_by_name(name).unwrap().result_id.unwrap();
+    spv.annotations
+        .iter()
+        .find_map(|inst| match inst.operands.as_slice() {
+            [Operand::IdRef(target), Operand::Decoration(dec), Operand::LiteralBit32(value)]
+                if *target == id && *dec == decoration =>
+            {
+                Some(*value)
+            }
+            _ => None,
+        })
+        .unwrap()
+}
+
+fn binding(spv: &SpvModule, name: &str) -> Binding {
+    Binding::new(
+        decoration(spv, name, Decoration::DescriptorSet),
+        decoration(spv, name, Decoration::Binding),
+    )
+}
+
+#[test]
+fn reflect_descriptors() {
+    let spv = Module::from_assembly(SHADER).unwrap().spirv().clone();
+    let found = descriptors(&spv)
+        .into_iter()
+        .map(|d| {
+            (
+                d.name.unwrap(),
+                d.descriptor_type,
+                d.count,
+                d.input_attachment_index,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            ("camera".to_owned(), DescriptorType::UniformBuffer, 1, None),
+            ("albedo".to_owned(), DescriptorType::SampledImage, 4, None),
+            (
+                "gbuffer".to_owned(),
+                DescriptorType::InputAttachment,
+                1,
+                Some(0)
+            ),
+            (
+                "particles".to_owned(),
+                DescriptorType::StorageBuffer,
+                1,
+                None
+            ),
+            ("sampler".to_owned(), DescriptorType::Sampler, 1, None),
+        ]
+    );
+}
+
+#[test]
+fn table_and_mapper() {
+    let mut spv = Module::from_assembly(SHADER).unwrap().spirv().clone();
+    let report = DescriptorRemap::new()
+        .with_binding(Binding::new(1, 0), Binding::new(2, 5))
+        .with_binding(Binding::new(1, 1), Binding::new(1, 3))
+        .with_mapper(|d| match d.descriptor_type {
+            //Overruled by the table
+            DescriptorType::Sampler => Some(Binding::new(7, 7)),
+            DescriptorType::UniformBuffer => Some(Binding::new(3, 0)),
+            _ => None,
+        })
+        .with_input_attachment(0, 2)
+        .run(&mut spv)
+        .unwrap();
+    assert_eq!(report.remapped.len(), 3);
+    assert_eq!(report.input_attachments, 1);
+
+    assert_eq!(binding(&spv, "particles"), Binding::new(2, 5));
+    assert_eq!(binding(&spv, "sampler"), Binding::new(1, 3));
+    assert_eq!(binding(&spv, "camera"), Binding::new(3, 0));
+    assert_eq!(binding(&spv, "albedo"), Binding::new(0, 1));
+    assert_eq!(
+        decoration(&spv, "gbuffer", Decoration::InputAttachmentIndex),
+        2
+    );
+    Verifier::verify(&spv).unwrap();
+}
+
+#[test]
+fn collapse_sets() {
+    let mut spv = Module::from_assembly(SHADER).unwrap().spirv().clone();
+    let report = DescriptorRemap::new()
+        .collapse_into(0)
+        .run(&mut spv)
+        .unwrap();
+    assert_eq!(report.remapped.len(), 2);
+    assert_eq!(binding(&spv, "camera"), Binding::new(0, 0));
+    assert_eq!(binding(&spv, "gbuffer"), Binding::new(0, 2));
+    assert_eq!(binding(&spv, "particles"), Binding::new(0, 3));
+    assert_eq!(binding(&spv, "sampler"), Binding::new(0, 4));
+
+    //Collapsing into another set moves set 0 behind it
+    let mut spv = Module::from_assembly(SHADER).unwrap().spirv().clone();
+    DescriptorRemap::new()
+        .collapse_into(1)
+        .run(&mut spv)
+        .unwrap();
+    assert_eq!(binding(&spv, "sampler"), Binding::new(1, 1));
+    assert_eq!(binding(&spv, "camera"), Binding::new(1, 2));
+}
+
+#[test]
+fn collisions() {
+    let mut spv = Module::from_assembly(SHADER).unwrap().spirv().clone();
+    let remap = DescriptorRemap::new().with_binding(Binding::new(1, 0), Binding::new(0, 1));
+    assert!(matches!(
+        remap.run(&mut spv),
+        Err(RemapError::Collision { .. })
+    ));
+    //Nothing was changed
+    assert_eq!(binding(&spv, "particles"), Binding::new(1, 0));
+
+    let report = remap.allow_aliasing().run(&mut spv).unwrap();
+    assert_eq!(report.remapped.len(), 1);
+    assert_eq!(binding(&spv, "particles"), Binding::new(0, 1));
+}
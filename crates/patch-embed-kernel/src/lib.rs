@@ -0,0 +1,923 @@
+//! # Kernel embedding
+//!
+//! Embeds a compute kernel into another one. The embedded kernel's entry point becomes a callable function, which is
+//! called from the host at a chosen [CallSite]. This fuses small (for instance post-processing) kernels into a main
+//! kernel without a second dispatch.
+//!
+//! ## Implementation details
+//!
+//! - All functions reachable from the kernel's entry point are copied into the host. Types and constants are mapped
+//!   structurally, so equal types of both modules are shared.
+//! - `Input` variables (builtins) the kernel uses become parameters, which are appended to the parameters of every
+//!   copied function and passed along on every call. Each function stores the value into a local variable that
+//!   replaces the global one. At each call site the host passes the arguments given by the user. Builtins without an
+//!   argument are wired to the host's builtin of the same kind, which is declared if needed.
+//! - Descriptors and push constants (`Uniform`, `StorageBuffer`, `UniformConstant` and `PushConstant` variables) are
+//!   resolved to host globals, since logical addressing does not allow passing them as pointers. In this order, the
+//!   kernel's variable is replaced by the host variable given as argument, by the host's descriptor at the same set and
+//!   binding (or the host's push constant block), or is copied into the host with its decorations. A host variable of
+//!   a different type is reported as [EmbedError::ArgumentMismatch].
+//! - Other global variables of the kernel (`Private`, `Workgroup`) are copied into the host.
+//!
+//! Execution modes of the kernel are ignored, so both kernels should agree on their workgroup size. Non-semantic debug
+//! instructions of the kernel are dropped.
+
+#![deny(warnings)]
+
+use ahash::{AHashMap, AHashSet};
+use spv_patcher::{
+    analysis::CallGraph,
+    import::{FunctionImporter, ImportError},
+    patch::{Patch, Patcher},
+    rspirv::{
+        self,
+        dr::{Block, Function, Instruction, Module, Operand},
+        spirv::{BuiltIn, Decoration, ExecutionModel, Op, StorageClass, Word},
+    },
+    spirv_ext::SpirvExt,
+    type_tree::TypeTreeError,
+    PatcherError,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum EmbedError {
+    #[error("Could not parse kernel: {0}")]
+    Parse(#[from] rspirv::binary::ParseState),
+    #[error("Kernel must have exactly one entry point, it has {0}")]
+    KernelEntryPoints(usize),
+    #[error("Host has {0} entry points, select the one to embed into")]
+    AmbiguousHost(usize),
+    #[error("Only compute kernels can be embedded, got {0:?}")]
+    NotCompute(ExecutionModel),
+    #[error("Call site %{0} does not exist, or no call can be placed there")]
+    InvalidCallSite(Word),
+    #[error("Kernel input %{id} ({name}) has no argument")]
+    UnmappedInput { id: Word, name: String },
+    #[error("Argument %{argument} does not fit kernel input %{input}")]
+    ArgumentMismatch { input: Word, argument: Word },
+    #[error("Kernel references %{0}, which can not be mapped into the host")]
+    UnknownId(Word),
+    #[error("Could not import kernel types: {0}")]
+    TypeImport(#[from] TypeTreeError),
+    #[error("Could not import kernel code: {0}")]
+    Import(#[from] ImportError),
+}
+
+///Where the embedded kernel is called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallSite {
+    ///At the start of the host's entry point.
+    EntryStart,
+    ///Before every return of the host's entry point.
+    EntryEnd,
+    ///Before the instruction with this result id.
+    Before(Word),
+    ///After the instruction with this result id.
+    After(Word),
+}
+
+///Selects an input variable of the embedded kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KernelInput {
+    ///The `Input` variable decorated with this builtin.
+    BuiltIn(BuiltIn),
+    ///The descriptor bound at this set and binding.
+    Binding { descriptor_set: u32, binding: u32 },
+    ///The push constant block.
+    PushConstant,
+    ///The variable with this `OpName`.
+    Name(String),
+}
+
+///Result of an [EmbedKernel] run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmbedReport {
+    ///Id of the function the kernel's entry point became.
+    pub function: Word,
+    ///Kernel variables that became parameters, in parameter order. Ids are the kernel's ids.
+    pub inputs: Vec<Word>,
+    ///Kernel descriptors and push constants together with the host variable that replaces them.
+    pub resources: Vec<(Word, Word)>,
+    ///Number of inserted calls.
+    pub calls: usize,
+}
+
+///Embeds `kernel`, a module with a single compute entry point, into the patched module and calls it at `call_site`.
+///
+///`arguments` maps the kernel's inputs to host ids. Builtin inputs take a value of the builtin's type, or a host
+/// variable, which is loaded at the call site. Descriptors and push constants take a host variable of the same type,
+/// which replaces the kernel's variable. Without an argument they are matched by binding, see the
+/// [crate documentation](crate).
+///
+///The patch embeds into the patcher's entry point if it is scoped to one. [EmbedKernel::run] needs a host with a single
+/// entry point, unless the call site is given by id.
+#[derive(Debug, Clone)]
+pub struct EmbedKernel {
+    pub kernel: Module,
+    pub call_site: CallSite,
+    pub arguments: Vec<(KernelInput, Word)>,
+}
+
+//A kernel builtin that became a parameter.
+struct Input {
+    variable: Word,
+    //Parameter type in the host
+    ty: Word,
+}
+
+impl EmbedKernel {
+    pub fn new(kernel: Module, call_site: CallSite) -> Self {
+        EmbedKernel {
+            kernel,
+            call_site,
+            arguments: Vec::new(),
+        }
+    }
+
+    pub fn new_from_bytes(kernel: &[u8], call_site: CallSite) -> Result<Self, EmbedError> {
+        Ok(Self::new(rspirv::dr::load_bytes(kernel)?, call_site))
+    }
+
+    ///Passes the host's `argument` to the kernel's `input`.
+    pub fn with_argument(mut self, input: KernelInput, argument: Word) -> Self {
+        self.arguments.push((input, argument));
+        self
+    }
+
+    ///Embeds the kernel into `host`.
+    pub fn run(&self, host: &mut Module) -> Result<EmbedReport, EmbedError> {
+        self.embed(host, None)
+    }
+
+    //Embeds into the entry point function `entry`, or the host's only entry point.
+    fn embed(&self, host: &mut Module, entry: Option<Word>) -> Result<EmbedReport, EmbedError> {
+        let kernel = &self.kernel;
+        let [kernel_entry] = kernel.entry_points.as_slice() else {
+            return Err(EmbedError::KernelEntryPoints(kernel.entry_points.len()));
+        };
+        let model = match kernel_entry.operands.first() {
+            Some(Operand::ExecutionModel(model)) => *model,
+            _ => ExecutionModel::GLCompute,
+        };
+        if model != ExecutionModel::GLCompute {
+            return Err(EmbedError::NotCompute(model));
+        }
+        let kernel_function = kernel_entry.operands[1].unwrap_id_ref();
+        let kernel_name = match kernel_entry.operands.get(2) {
+            Some(Operand::LiteralString(name)) => name.clone(),
+            _ => String::from("kernel"),
+        };
+
+        //Resolve the call sites before anything is changed
+        let entry = match (entry, self.call_site) {
+            (Some(entry), _) => Some(entry),
+            (None, CallSite::EntryStart | CallSite::EntryEnd) => match host.entry_points.as_slice()
+            {
+                [ep] => ep.operands.get(1).and_then(|op| op.id_ref_any()),
+                eps => return Err(EmbedError::AmbiguousHost(eps.len())),
+            },
+            _ => None,
+        };
+        let sites = call_sites(host, self.call_site, entry)?;
+        let entry_points = calling_entry_points(host, &sites);
+
+        merge_header(kernel, host);
+        let mut embedder = Embedder {
+            kernel,
+            import: FunctionImporter::new(kernel, host)?,
+            entry_points,
+            globals: AHashMap::default(),
+        };
+
+        let mut functions = kernel
+            .reachable_functions(kernel_function)
+            .into_iter()
+            .filter_map(|id| kernel.functions.iter().position(|f| f.def_id() == Some(id)))
+            .collect::<Vec<_>>();
+        functions.sort_unstable();
+        let inputs = embedder.inputs(host, &functions)?;
+        let resources = embedder.resources(host, &functions, &self.arguments)?;
+        let function = embedder.copy_functions(host, &functions, &inputs)?;
+        if kernel.get_name(kernel_function).is_none() {
+            host.debug_names.push(Instruction::new(
+                Op::Name,
+                None,
+                None,
+                vec![
+                    Operand::IdRef(function),
+                    Operand::LiteralString(kernel_name),
+                ],
+            ));
+        }
+
+        //Gather the arguments, loads of host variables are emitted at each call site
+        let mut arguments = Vec::with_capacity(inputs.len());
+        for input in &inputs {
+            arguments.push(self.argument(host, &mut embedder, input)?);
+        }
+        let void = host.find_or_insert_type(Op::TypeVoid, &[], &[]);
+        //Insert back to front, so that the locations of earlier sites stay valid
+        for (fidx, bidx, at) in sites.iter().rev().copied() {
+            let mut call = Vec::new();
+            let mut operands = vec![Operand::IdRef(function)];
+            for (argument, input) in arguments.iter().zip(&inputs) {
+                let value = match argument {
+                    Argument::Direct(id) => *id,
+                    Argument::Load(variable) => {
+                        let id = host.allocate_id();
+                        call.push(Instruction::new(
+                            Op::Load,
+                            Some(input.ty),
+                            Some(id),
+                            vec![Operand::IdRef(*variable)],
+                        ));
+                        id
+                    }
+                };
+                operands.push(Operand::IdRef(value));
+            }
+            let result = host.allocate_id();
+            call.push(Instruction::new(
+                Op::FunctionCall,
+                Some(void),
+                Some(result),
+                operands,
+            ));
+            host.functions[fidx].blocks[bidx]
+                .instructions
+                .splice(at..at, call);
+        }
+
+        Ok(EmbedReport {
+            function,
+            inputs: inputs.iter().map(|input| input.variable).collect(),
+            resources,
+            calls: sites.len(),
+        })
+    }
+
+    //Host argument passed to `input`.
+    fn argument(
+        &self,
+        host: &mut Module,
+        embedder: &mut Embedder,
+        input: &Input,
+    ) -> Result<Argument, EmbedError> {
+        let kernel = &self.kernel;
+        let mapped = self
+            .arguments
+            .iter()
+            .find(|(selector, _)| selects(kernel, input.variable, selector))
+            .map(|(_, argument)| *argument);
+        let builtin = decoration_operand(kernel, input.variable, Decoration::BuiltIn);
+
+        let argument = match (mapped, builtin) {
+            (Some(argument), _) => argument,
+            (None, Some(Operand::BuiltIn(builtin))) => match host_builtin(host, builtin) {
+                Some(variable) => variable,
+                None => embedder.declare_builtin(host, input.variable)?,
+            },
+            _ => {
+                return Err(EmbedError::UnmappedInput {
+                    id: input.variable,
+                    name: kernel
+                        .get_name(input.variable)
+                        .unwrap_or_else(|| String::from("unnamed")),
+                })
+            }
+        };
+
+        let def = host
+            .all_inst_iter()
+            .find(|inst| inst.result_id == Some(argument));
+        let ty = def.and_then(|inst| inst.result_type);
+        let is_variable = def
+            .map(|inst| inst.class.opcode == Op::Variable)
+            .unwrap_or(false);
+        if ty == Some(input.ty) {
+            return Ok(Argument::Direct(argument));
+        }
+        //Builtin values may be given by their variable
+        if is_variable {
+            if ty.and_then(|ty| host.pointee(ty)) == Some(input.ty) {
+                //The call sites' entry points use the variable now
+                add_to_interface(host, &embedder.entry_points, argument);
+                return Ok(Argument::Load(argument));
+            }
+        }
+        Err(EmbedError::ArgumentMismatch {
+            input: input.variable,
+            argument,
+        })
+    }
+}
+
+enum Argument {
+    Direct(Word),
+    ///A variable that is loaded at the call site.
+    Load(Word),
+}
+
+//Kernel globals that are not types or constants.
+enum Global {
+    ///Index into the inputs.
+    Input(usize),
+    Imported(Word),
+}
+
+//State of copying the kernel into the host.
+struct Embedder<'k> {
+    kernel: &'k Module,
+    import: FunctionImporter<'k>,
+    //Functions of the host's entry points that reach a call site
+    entry_points: AHashSet<Word>,
+    globals: AHashMap<Word, Global>,
+}
+
+impl<'k> Embedder<'k> {
+    //Collects the input variables `functions` use, and declares their parameter types.
+    fn inputs(&mut self, host: &mut Module, functions: &[usize]) -> Result<Vec<Input>, EmbedError> {
+        let kernel = self.kernel;
+        let used = functions
+            .iter()
+            .flat_map(|f| kernel.functions[*f].all_inst_iter())
+            .flat_map(|inst| inst.operands.iter().filter_map(|op| op.id_ref_any()))
+            .collect::<AHashSet<_>>();
+
+        let mut inputs = Vec::new();
+        for inst in &kernel.types_global_values {
+            let (Op::Variable, Some(variable), Some(ptr)) =
+                (inst.class.opcode, inst.result_id, inst.result_type)
+            else {
+                continue;
+            };
+            if !used.contains(&variable) {
+                continue;
+            }
+            if inst.operands.first() != Some(&Operand::StorageClass(StorageClass::Input)) {
+                continue;
+            }
+            let pointee = kernel.pointee(ptr).ok_or(EmbedError::UnknownId(ptr))?;
+            let ty = self.import.types().import(host, pointee)?;
+            self.globals.insert(variable, Global::Input(inputs.len()));
+            inputs.push(Input { variable, ty });
+        }
+        Ok(inputs)
+    }
+
+    //Resolves the descriptors and push constants `functions` use to host variables. Returns pairs of kernel and host
+    // variable.
+    fn resources(
+        &mut self,
+        host: &mut Module,
+        functions: &[usize],
+        arguments: &[(KernelInput, Word)],
+    ) -> Result<Vec<(Word, Word)>, EmbedError> {
+        let kernel = self.kernel;
+        let used = functions
+            .iter()
+            .flat_map(|f| kernel.functions[*f].all_inst_iter())
+            .flat_map(|inst| inst.operands.iter().filter_map(|op| op.id_ref_any()))
+            .collect::<AHashSet<_>>();
+
+        let mut resources = Vec::new();
+        for inst in &kernel.types_global_values {
+            let (Op::Variable, Some(variable), Some(ptr), Some(Operand::StorageClass(class))) = (
+                inst.class.opcode,
+                inst.result_id,
+                inst.result_type,
+                inst.operands.first(),
+            ) else {
+                continue;
+            };
+            if !used.contains(&variable)
+                || !matches!(
+                    class,
+                    StorageClass::Uniform
+                        | StorageClass::StorageBuffer
+                        | StorageClass::UniformConstant
+                        | StorageClass::PushConstant
+                )
+            {
+                continue;
+            }
+
+            let given = arguments
+                .iter()
+                .find(|(selector, _)| selects(kernel, variable, selector))
+                .map(|(_, argument)| *argument);
+            let counterpart = given.or_else(|| {
+                if *class == StorageClass::PushConstant {
+                    host_variables(host, StorageClass::PushConstant).next()
+                } else {
+                    let set = decoration_operand(kernel, variable, Decoration::DescriptorSet)?;
+                    let binding = decoration_operand(kernel, variable, Decoration::Binding)?;
+                    host_variables(host, *class).find(|id| {
+                        decoration_operand(host, *id, Decoration::DescriptorSet).as_ref()
+                            == Some(&set)
+                            && decoration_operand(host, *id, Decoration::Binding).as_ref()
+                                == Some(&binding)
+                    })
+                }
+            });
+
+            let Some(counterpart) = counterpart else {
+                resources.push((variable, self.import_variable(host, variable)?));
+                continue;
+            };
+            let host_ty = host
+                .types_global_values
+                .iter()
+                .find(|inst| {
+                    inst.result_id == Some(counterpart) && inst.class.opcode == Op::Variable
+                })
+                .and_then(|inst| inst.result_type);
+            if host_ty.is_none() || self.import.types().find(ptr)? != host_ty {
+                return Err(EmbedError::ArgumentMismatch {
+                    input: variable,
+                    argument: counterpart,
+                });
+            }
+            self.globals.insert(variable, Global::Imported(counterpart));
+            add_to_interface(host, &self.entry_points, counterpart);
+            resources.push((variable, counterpart));
+        }
+        Ok(resources)
+    }
+
+    //Copies `functions` into the host, appending a parameter for each input. Returns the id of the function the
+    // kernel's entry point became.
+    fn copy_functions(
+        &mut self,
+        host: &mut Module,
+        functions: &[usize],
+        inputs: &[Input],
+    ) -> Result<Word, EmbedError> {
+        let kernel = self.kernel;
+        let entry = kernel.entry_points[0].operands[1].unwrap_id_ref();
+        self.import.reserve_locals(host, functions);
+
+        let mut copied = Vec::with_capacity(functions.len());
+        for f in functions.iter().copied() {
+            let function = &kernel.functions[f];
+            let def = function.def.as_ref().unwrap();
+            let return_type = self.map(host, def.result_type.unwrap())?;
+
+            let mut parameters = Vec::new();
+            for param in &function.parameters {
+                let ty = self.local_type(host, param.result_type.unwrap())?;
+                parameters.push(Instruction::new(
+                    Op::FunctionParameter,
+                    Some(ty),
+                    Some(self.import.locals()[&param.result_id.unwrap()]),
+                    Vec::new(),
+                ));
+            }
+            //Kernel input to the id that replaces it within this function
+            let mut replace = AHashMap::default();
+            let mut prologue = Vec::new();
+            let mut stores = Vec::new();
+            let mut input_params = Vec::new();
+            for input in inputs {
+                let param = host.allocate_id();
+                parameters.push(Instruction::new(
+                    Op::FunctionParameter,
+                    Some(input.ty),
+                    Some(param),
+                    Vec::new(),
+                ));
+                input_params.push(param);
+                let ptr = host.find_or_insert_type(
+                    Op::TypePointer,
+                    &[
+                        Operand::StorageClass(StorageClass::Function),
+                        Operand::IdRef(input.ty),
+                    ],
+                    &[],
+                );
+                let local = host.allocate_id();
+                prologue.push(Instruction::new(
+                    Op::Variable,
+                    Some(ptr),
+                    Some(local),
+                    vec![Operand::StorageClass(StorageClass::Function)],
+                ));
+                stores.push(Instruction::new(
+                    Op::Store,
+                    None,
+                    None,
+                    vec![Operand::IdRef(local), Operand::IdRef(param)],
+                ));
+                replace.insert(input.variable, local);
+            }
+
+            let param_types = parameters
+                .iter()
+                .map(|param| Operand::IdRef(param.result_type.unwrap()))
+                .collect::<Vec<_>>();
+            let function_type = host.find_or_insert_type(
+                Op::TypeFunction,
+                &std::iter::once(Operand::IdRef(return_type))
+                    .chain(param_types)
+                    .collect::<Vec<_>>(),
+                &[],
+            );
+            let id = self.import.locals()[&def.result_id.unwrap()];
+            let mut new_def = def.clone();
+            new_def.result_id = Some(id);
+            new_def.result_type = Some(return_type);
+            new_def.operands[1] = Operand::IdRef(function_type);
+
+            let mut blocks = Vec::with_capacity(function.blocks.len());
+            for block in &function.blocks {
+                let mut instructions = Vec::with_capacity(block.instructions.len());
+                for inst in &block.instructions {
+                    if self.import.is_non_semantic(inst) {
+                        continue;
+                    }
+                    let mut inst = inst.clone();
+                    if let Some(ty) = inst.result_type {
+                        inst.result_type = Some(self.local_type(host, ty)?);
+                    }
+                    if let Some(id) = inst.result_id {
+                        inst.result_id = Some(self.import.locals()[&id]);
+                    }
+                    for operand in inst.operands.iter_mut() {
+                        if let Some(id) = operand.id_ref_any_mut() {
+                            *id = match replace.get(id) {
+                                Some(new) => *new,
+                                None => self.map(host, *id)?,
+                            };
+                        }
+                    }
+                    //Pass the inputs along
+                    if inst.class.opcode == Op::FunctionCall {
+                        inst.operands
+                            .extend(input_params.iter().map(|param| Operand::IdRef(*param)));
+                    }
+                    instructions.push(inst);
+                }
+                let label = block.label_id().map(|label| {
+                    Instruction::new(
+                        Op::Label,
+                        None,
+                        Some(self.import.locals()[&label]),
+                        Vec::new(),
+                    )
+                });
+                blocks.push(Block {
+                    label,
+                    instructions,
+                });
+            }
+            if let Some(first) = blocks.first_mut() {
+                let variables = first
+                    .instructions
+                    .iter()
+                    .take_while(|inst| inst.class.opcode == Op::Variable)
+                    .count();
+                let stores_at = variables + prologue.len();
+                first.instructions.splice(0..0, prologue);
+                first.instructions.splice(stores_at..stores_at, stores);
+            }
+
+            copied.push(Function {
+                def: Some(new_def),
+                end: Some(Instruction::new(Op::FunctionEnd, None, None, Vec::new())),
+                parameters,
+                blocks,
+            });
+        }
+
+        self.import.import_local_annotations(host)?;
+        let entry_id = self.import.locals()[&entry];
+        host.functions.extend(copied);
+        Ok(entry_id)
+    }
+
+    //Maps a type used within a function body. Pointers to `Input` become pointers to `Function`, since inputs are
+    // copied to local variables.
+    fn local_type(&mut self, host: &mut Module, ty: Word) -> Result<Word, EmbedError> {
+        let kernel = self.kernel;
+        let is_input_ptr = kernel
+            .types_global_values
+            .iter()
+            .find(|inst| inst.result_id == Some(ty))
+            .map(|inst| {
+                inst.class.opcode == Op::TypePointer
+                    && inst.operands.first() == Some(&Operand::StorageClass(StorageClass::Input))
+            })
+            .unwrap_or(false);
+        if !is_input_ptr {
+            return self.map(host, ty);
+        }
+        let pointee = kernel.pointee(ty).ok_or(EmbedError::UnknownId(ty))?;
+        let pointee = self.import.types().import(host, pointee)?;
+        Ok(host.find_or_insert_type(
+            Op::TypePointer,
+            &[
+                Operand::StorageClass(StorageClass::Function),
+                Operand::IdRef(pointee),
+            ],
+            &[],
+        ))
+    }
+
+    //Maps a kernel id into the host, importing global declarations if needed.
+    fn map(&mut self, host: &mut Module, id: Word) -> Result<Word, EmbedError> {
+        match self.globals.get(&id) {
+            Some(Global::Imported(new)) => return Ok(*new),
+            //Inputs are replaced before mapping
+            Some(Global::Input(_)) => return Err(EmbedError::UnknownId(id)),
+            None => {}
+        }
+        match self.import.map(host, id) {
+            Ok(new) => Ok(new),
+            Err(ImportError::Variable(id)) => self.import_variable(host, id),
+            Err(ImportError::UnknownId(id)) => Err(EmbedError::UnknownId(id)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    //Copies the kernel's global variable `id` into the host, together with its decorations and name.
+    fn import_variable(&mut self, host: &mut Module, id: Word) -> Result<Word, EmbedError> {
+        let kernel = self.kernel;
+        let mut variable = kernel
+            .types_global_values
+            .iter()
+            .find(|inst| inst.result_id == Some(id))
+            .cloned()
+            .ok_or(EmbedError::UnknownId(id))?;
+        let new = host.allocate_id();
+        self.globals.insert(id, Global::Imported(new));
+        variable.result_id = Some(new);
+        variable.result_type = Some(self.map(host, variable.result_type.unwrap())?);
+        for operand in variable.operands.iter_mut() {
+            if let Some(id) = operand.id_ref_any_mut() {
+                *id = self.map(host, *id)?;
+            }
+        }
+        host.types_global_values.push(variable);
+
+        let targets = |inst: &&Instruction| inst.operands.first() == Some(&Operand::IdRef(id));
+        for mut name in kernel.debug_names.iter().filter(targets).cloned() {
+            name.operands[0] = Operand::IdRef(new);
+            host.debug_names.push(name);
+        }
+        for mut decoration in kernel
+            .annotations
+            .iter()
+            .filter(targets)
+            .cloned()
+            .collect::<Vec<_>>()
+        {
+            for operand in decoration.operands.iter_mut() {
+                if let Some(id) = operand.id_ref_any_mut() {
+                    *id = self.map(host, *id)?;
+                }
+            }
+            host.annotations.push(decoration);
+        }
+
+        add_to_interface(host, &self.entry_points, new);
+        Ok(new)
+    }
+
+    //Declares the builtin input variable `variable` of the kernel in the host.
+    fn declare_builtin(&mut self, host: &mut Module, variable: Word) -> Result<Word, EmbedError> {
+        //The variable is an input, which is not mapped otherwise
+        self.globals.remove(&variable);
+        let new = self.import_variable(host, variable)?;
+        self.globals.insert(variable, Global::Imported(new));
+        Ok(new)
+    }
+}
+
+//Copies capabilities, extensions and the memory model requirements of the kernel into the host.
+fn merge_header(kernel: &Module, host: &mut Module) {
+    for capability in &kernel.capabilities {
+        if let Some(Operand::Capability(capability)) = capability.operands.first() {
+            host.add_capability(*capability);
+        }
+    }
+    for extension in &kernel.extensions {
+        if let Some(Operand::LiteralString(name)) = extension.operands.first() {
+            if !host.has_extension(name) {
+                host.extensions.push(extension.clone());
+            }
+        }
+    }
+    let model = |module: &Module| {
+        module
+            .memory_model
+            .as_ref()
+            .map(|inst| inst.operands.clone())
+    };
+    if model(kernel) != model(host) {
+        log::warn!("Kernel and host use different memory models, keeping the host's");
+    }
+}
+
+//Locations (function, block, instruction index) calls are inserted at, in ascending order.
+fn call_sites(
+    host: &Module,
+    site: CallSite,
+    entry: Option<Word>,
+) -> Result<Vec<(usize, usize, usize)>, EmbedError> {
+    let entry_index = entry.and_then(|entry| {
+        host.functions
+            .iter()
+            .position(|f| f.def_id() == Some(entry))
+    });
+    let is_header = |inst: &Instruction| matches!(inst.class.opcode, Op::Variable | Op::Phi);
+    match site {
+        CallSite::EntryStart => {
+            let fidx = entry_index.ok_or(EmbedError::InvalidCallSite(entry.unwrap_or(0)))?;
+            let block = host.functions[fidx]
+                .blocks
+                .first()
+                .ok_or(EmbedError::InvalidCallSite(entry.unwrap_or(0)))?;
+            let at = block
+                .instructions
+                .iter()
+                .take_while(|inst| is_header(inst) || inst.class.opcode == Op::Line)
+                .count();
+            Ok(vec![(fidx, 0, at)])
+        }
+        CallSite::EntryEnd => {
+            let fidx = entry_index.ok_or(EmbedError::InvalidCallSite(entry.unwrap_or(0)))?;
+            Ok(host.functions[fidx]
+                .blocks
+                .iter()
+                .enumerate()
+                .filter(|(_, block)| {
+                    block
+                        .instructions
+                        .last()
+                        .map(|inst| inst.class.opcode == Op::Return)
+                        .unwrap_or(false)
+                })
+                .map(|(bidx, block)| (fidx, bidx, block.instructions.len() - 1))
+                .collect())
+        }
+        CallSite::Before(id) | CallSite::After(id) => {
+            for (fidx, function) in host.functions.iter().enumerate() {
+                for (bidx, block) in function.blocks.iter().enumerate() {
+                    let Some(idx) = block
+                        .instructions
+                        .iter()
+                        .position(|inst| inst.result_id == Some(id))
+                    else {
+                        continue;
+                    };
+                    let at = match site {
+                        //Variables and phis have to stay at the start of the block
+                        CallSite::Before(_) if is_header(&block.instructions[idx]) => {
+                            return Err(EmbedError::InvalidCallSite(id))
+                        }
+                        CallSite::Before(_) => idx,
+                        _ => {
+                            block
+                                .instructions
+                                .iter()
+                                .skip(idx + 1)
+                                .take_while(|inst| is_header(inst))
+                                .count()
+                                + idx
+                                + 1
+                        }
+                    };
+                    return Ok(vec![(fidx, bidx, at)]);
+                }
+            }
+            Err(EmbedError::InvalidCallSite(id))
+        }
+    }
+}
+
+//True if `selector` selects the kernel variable `variable`.
+fn selects(kernel: &Module, variable: Word, selector: &KernelInput) -> bool {
+    match selector {
+        KernelInput::BuiltIn(builtin) => {
+            decoration_operand(kernel, variable, Decoration::BuiltIn)
+                == Some(Operand::BuiltIn(*builtin))
+        }
+        KernelInput::Binding {
+            descriptor_set,
+            binding,
+        } => {
+            decoration_operand(kernel, variable, Decoration::DescriptorSet)
+                == Some(Operand::LiteralBit32(*descriptor_set))
+                && decoration_operand(kernel, variable, Decoration::Binding)
+                    == Some(Operand::LiteralBit32(*binding))
+        }
+        KernelInput::PushConstant => kernel
+            .types_global_values
+            .iter()
+            .find(|inst| inst.result_id == Some(variable))
+            .map(|inst| {
+                inst.operands.first() == Some(&Operand::StorageClass(StorageClass::PushConstant))
+            })
+            .unwrap_or(false),
+        KernelInput::Name(name) => kernel.get_name(variable).as_ref() == Some(name),
+    }
+}
+
+//First operand of `decoration` applied to `id`.
+fn decoration_operand(module: &Module, id: Word, decoration: Decoration) -> Option<Operand> {
+    module
+        .annotations
+        .iter()
+        .find(|inst| {
+            inst.class.opcode == Op::Decorate
+                && inst.operands.first() == Some(&Operand::IdRef(id))
+                && inst.operands.get(1) == Some(&Operand::Decoration(decoration))
+        })
+        .and_then(|inst| inst.operands.get(2).cloned())
+}
+
+//Functions of the host's entry points that reach any of the call `sites`.
+fn calling_entry_points(host: &Module, sites: &[(usize, usize, usize)]) -> AHashSet<Word> {
+    let call_graph = CallGraph::new(host);
+    sites
+        .iter()
+        .filter_map(|(fidx, _, _)| host.functions[*fidx].def_id())
+        .flat_map(|function| call_graph.entry_points_reaching(function))
+        .collect()
+}
+
+//Lists the host variable `variable` in the interface of the `entry_points` that call the kernel. Since SPIR-V 1.4 the
+// interface lists all global variables an entry point uses, before only inputs and outputs.
+fn add_to_interface(host: &mut Module, entry_points: &AHashSet<Word>, variable: Word) {
+    let version = host
+        .header
+        .as_ref()
+        .map(|header| header.version())
+        .unwrap_or((1, 0));
+    let storage = host
+        .types_global_values
+        .iter()
+        .find(|inst| inst.result_id == Some(variable))
+        .and_then(|inst| inst.operands.first().cloned());
+    if version < (1, 4)
+        && storage != Some(Operand::StorageClass(StorageClass::Input))
+        && storage != Some(Operand::StorageClass(StorageClass::Output))
+    {
+        return;
+    }
+    for entry_point in host.entry_points.iter_mut() {
+        let function = entry_point.operands.get(1).and_then(|op| op.id_ref_any());
+        if !function
+            .map(|function| entry_points.contains(&function))
+            .unwrap_or(false)
+        {
+            continue;
+        }
+        if !entry_point.operands[3..].contains(&Operand::IdRef(variable)) {
+            entry_point.operands.push(Operand::IdRef(variable));
+        }
+    }
+}
+
+//The host's global variables of storage class `class`.
+fn host_variables(host: &Module, class: StorageClass) -> impl Iterator<Item = Word> + '_ {
+    host.types_global_values
+        .iter()
+        .filter(move |inst| {
+            inst.class.opcode == Op::Variable
+                && inst.operands.first() == Some(&Operand::StorageClass(class))
+        })
+        .filter_map(|inst| inst.result_id)
+}
+
+//The host's input variable decorated with `builtin`.
+fn host_builtin(host: &Module, builtin: BuiltIn) -> Option<Word> {
+    host.types_global_values
+        .iter()
+        .filter(|inst| {
+            inst.class.opcode == Op::Variable
+                && inst.operands.first() == Some(&Operand::StorageClass(StorageClass::Input))
+        })
+        .filter_map(|inst| inst.result_id)
+        .find(|id| {
+            decoration_operand(host, *id, Decoration::BuiltIn) == Some(Operand::BuiltIn(builtin))
+        })
+}
+
+impl Patch for EmbedKernel {
+    fn apply<'a>(self, mut patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError> {
+        let entry = patcher.entry_function();
+        let report = self
+            .embed(patcher.ir_state.as_spirv(), entry)
+            .map_err(|e| PatcherError::Internal(e.into()))?;
+        log::info!(
+            "Embedded kernel as %{} with {} inputs, called {} times",
+            report.function,
+            report.inputs.len(),
+            report.calls
+        );
+        patcher.push_report(report);
+        Ok(patcher)
+    }
+}
@@ -0,0 +1,366 @@
+use patch_embed_kernel::{CallSite, EmbedError, EmbedKernel, KernelInput};
+use spv_patcher::{
+    rspirv::{
+        dr::{Module as SpvModule, Operand},
+        spirv::{Decoration, Op, Word},
+    },
+    spirv_ext::SpirvExt,
+    verify::Verifier,
+    Module,
+};
+
+const HOST: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %buffer "buffer"
+OpDecorate %arr ArrayStride 4
+OpMemberDecorate %block 0 Offset 0
+OpDecorate %block BufferBlock
+OpDecorate %buffer DescriptorSet 0
+OpDecorate %buffer Binding 0
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%uint_0 = OpConstant %uint 0
+%uint_7 = OpConstant %uint 7
+%arr = OpTypeRuntimeArray %uint
+%block = OpTypeStruct %arr
+%ptr_block = OpTypePointer Uniform %block
+%ptr_uint = OpTypePointer Uniform %uint
+%buffer = OpVariable %ptr_block Uniform
+%main = OpFunction %void None %fn
+%entry = OpLabel
+%elem = OpAccessChain %ptr_uint %buffer %uint_0
+OpStore %elem %uint_7
+OpReturn
+OpFunctionEnd
+"#;
+
+//Doubles the element at the invocation's index, using a helper function
+const KERNEL: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %double "double" %gid
+OpExecutionMode %double LocalSize 1 1 1
+OpName %data "data"
+OpName %scale "scale"
+OpDecorate %gid BuiltIn GlobalInvocationId
+OpDecorate %arr ArrayStride 4
+OpMemberDecorate %block 0 Offset 0
+OpDecorate %block BufferBlock
+OpDecorate %data DescriptorSet 1
+OpDecorate %data Binding 2
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%v3uint = OpTypeVector %uint 3
+%uint_0 = OpConstant %uint 0
+%uint_2 = OpConstant %uint 2
+%scale_fn = OpTypeFunction %uint %uint
+%ptr_in = OpTypePointer Input %v3uint
+%ptr_in_uint = OpTypePointer Input %uint
+%arr = OpTypeRuntimeArray %uint
+%block = OpTypeStruct %arr
+%ptr_block = OpTypePointer Uniform %block
+%ptr_uint = OpTypePointer Uniform %uint
+%ptr_priv = OpTypePointer Private %uint
+%gid = OpVariable %ptr_in Input
+%data = OpVariable %ptr_block Uniform
+%factor = OpVariable %ptr_priv Private %uint_2
+%double = OpFunction %void None %fn
+%entry = OpLabel
+%gid_x = OpAccessChain %ptr_in_uint %gid %uint_0
+%x = OpLoad %uint %gid_x
+%elem = OpAccessChain %ptr_uint %data %uint_0 %x
+%value = OpLoad %uint %elem
+%scaled = OpFunctionCall %uint %scale %value
+OpStore %elem %scaled
+OpReturn
+OpFunctionEnd
+%scale = OpFunction %uint None %scale_fn
+%v = OpFunctionParameter %uint
+%body = OpLabel
+%f = OpLoad %uint %factor
+%r = OpIMul %uint %v %f
+OpReturnValue %r
+OpFunctionEnd
+"#;
+
+fn calls(spv: &SpvModule, function: Word) -> Vec<&spv_patcher::rspirv::dr::Instruction> {
+    spv.all_inst_iter()
+        .filter(|inst| {
+            inst.class.opcode == Op::FunctionCall
+                && inst.operands.first() == Some(&Operand::IdRef(function))
+        })
+        .collect()
+}
+
+#[test]
+fn embed_at_end() {
+    let mut host = Module::from_assembly(HOST).unwrap().spirv().clone();
+    let buffer = host.get_by_name("buffer").unwrap().result_id.unwrap();
+    let kernel = Module::from_assembly(KERNEL).unwrap().spirv().clone();
+    let embed = EmbedKernel::new(kernel, CallSite::EntryEnd).with_argument(
+        KernelInput::Binding {
+            descriptor_set: 1,
+            binding: 2,
+        },
+        buffer,
+    );
+    let report = embed.run(&mut host).unwrap();
+    assert_eq!(report.calls, 1);
+    assert_eq!(report.inputs.len(), 1);
+    assert_eq!(report.resources.len(), 1);
+    assert_eq!(report.resources[0].1, buffer);
+    assert_eq!(host.functions.len(), 3);
+    assert_eq!(host.get_name(report.function).as_deref(), Some("double"));
+
+    //The host declared the builtin it did not have and passes it, the buffer is used directly
+    let call = calls(&host, report.function)[0];
+    assert_eq!(call.operands.len(), 2);
+    let entry_point = &host.entry_points[0];
+    assert_eq!(entry_point.operands.len(), 4);
+
+    //The helper gets the builtin as well, the private variable is moved into the host
+    let function = host
+        .functions
+        .iter()
+        .find(|f| f.def_id() == Some(report.function))
+        .unwrap();
+    let inner = function
+        .all_inst_iter()
+        .find(|inst| inst.class.opcode == Op::FunctionCall)
+        .unwrap();
+    assert_eq!(inner.operands.len(), 3);
+    assert!(function.all_inst_iter().any(|inst| {
+        inst.class.opcode == Op::AccessChain
+            && inst.operands.first() == Some(&Operand::IdRef(buffer))
+    }));
+    assert!(host.get_by_name("data").is_none());
+    assert!(host.types_global_values.iter().any(|inst| {
+        inst.class.opcode == Op::Variable
+            && inst.operands.first()
+                == Some(&Operand::StorageClass(
+                    spv_patcher::rspirv::spirv::StorageClass::Private,
+                ))
+    }));
+
+    Verifier::verify(&host).unwrap();
+}
+
+#[test]
+fn embed_at_start() {
+    let mut host = Module::from_assembly(HOST).unwrap().spirv().clone();
+    let buffer = host.get_by_name("buffer").unwrap().result_id.unwrap();
+    let kernel = Module::from_assembly(KERNEL).unwrap().spirv().clone();
+    let report = EmbedKernel::new(kernel, CallSite::EntryStart)
+        .with_argument(KernelInput::Name("data".to_owned()), buffer)
+        .run(&mut host)
+        .unwrap();
+    //The builtin is loaded right before the call
+    let main = &host.functions[0];
+    let opcodes = main.blocks[0].instructions[..2]
+        .iter()
+        .map(|inst| inst.class.opcode)
+        .collect::<Vec<_>>();
+    assert_eq!(opcodes, [Op::Load, Op::FunctionCall]);
+    assert_eq!(calls(&host, report.function).len(), 1);
+    Verifier::verify(&host).unwrap();
+}
+
+#[test]
+fn resolve_descriptors() {
+    //Without an argument the kernel's descriptor is copied into the host, keeping its binding
+    let mut host = Module::from_assembly(HOST).unwrap().spirv().clone();
+    let kernel = Module::from_assembly(KERNEL).unwrap().spirv().clone();
+    let report = EmbedKernel::new(kernel, CallSite::EntryEnd)
+        .run(&mut host)
+        .unwrap();
+    let data = host.get_by_name("data").unwrap().result_id.unwrap();
+    assert_eq!(report.resources[0].1, data);
+    assert!(host.annotations.iter().any(|inst| {
+        inst.operands
+            == [
+                Operand::IdRef(data),
+                Operand::Decoration(Decoration::DescriptorSet),
+                Operand::LiteralBit32(1),
+            ]
+    }));
+    Verifier::verify(&host).unwrap();
+
+    //A host descriptor at the same binding is used instead
+    let mut host = Module::from_assembly(HOST).unwrap().spirv().clone();
+    let buffer = host.get_by_name("buffer").unwrap().result_id.unwrap();
+    let asm = KERNEL
+        .replace("%data DescriptorSet 1", "%data DescriptorSet 0")
+        .replace("%data Binding 2", "%data Binding 0");
+    let kernel = Module::from_assembly(&asm).unwrap().spirv().clone();
+    let report = EmbedKernel::new(kernel, CallSite::EntryEnd)
+        .run(&mut host)
+        .unwrap();
+    assert_eq!(report.resources[0].1, buffer);
+    assert!(host.get_by_name("data").is_none());
+    Verifier::verify(&host).unwrap();
+}
+
+#[test]
+fn errors() {
+    let kernel = Module::from_assembly(KERNEL).unwrap().spirv().clone();
+    let mut host = Module::from_assembly(HOST).unwrap().spirv().clone();
+    let uint_7 = host
+        .types_global_values
+        .iter()
+        .find(|inst| inst.operands == [Operand::LiteralBit32(7)])
+        .and_then(|inst| inst.result_id)
+        .unwrap();
+    let mismatch = EmbedKernel::new(kernel.clone(), CallSite::EntryEnd)
+        .with_argument(KernelInput::Name("data".to_owned()), uint_7)
+        .run(&mut host);
+    assert!(matches!(mismatch, Err(EmbedError::ArgumentMismatch { .. })));
+
+    let mut host = Module::from_assembly(HOST).unwrap().spirv().clone();
+    let missing = EmbedKernel::new(kernel, CallSite::After(9999)).run(&mut host);
+    assert!(matches!(missing, Err(EmbedError::InvalidCallSite(9999))));
+}
+
+//`HOST` with an additional fragment entry point, which does not call the kernel
+const MULTI_HOST: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpEntryPoint Fragment %frag "frag"
+OpExecutionMode %main LocalSize 1 1 1
+OpExecutionMode %frag OriginUpperLeft
+OpName %buffer "buffer"
+OpDecorate %arr ArrayStride 4
+OpMemberDecorate %block 0 Offset 0
+OpDecorate %block BufferBlock
+OpDecorate %buffer DescriptorSet 0
+OpDecorate %buffer Binding 0
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%arr = OpTypeRuntimeArray %uint
+%block = OpTypeStruct %arr
+%ptr_block = OpTypePointer Uniform %block
+%buffer = OpVariable %ptr_block Uniform
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+%frag = OpFunction %void None %fn
+%frag_entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn interface_of_calling_entry_point_only() {
+    let module = Module::from_assembly(MULTI_HOST).unwrap();
+    let buffer = module
+        .spirv()
+        .get_by_name("buffer")
+        .unwrap()
+        .result_id
+        .unwrap();
+    let kernel = Module::from_assembly(KERNEL).unwrap().spirv().clone();
+    let patched = module
+        .patch_entry_point("main", None)
+        .unwrap()
+        .patch(
+            EmbedKernel::new(kernel, CallSite::EntryEnd)
+                .with_argument(KernelInput::Name("data".to_owned()), buffer),
+        )
+        .unwrap()
+        .unwrap_module();
+
+    //The declared GlobalInvocationId input is only listed by the compute entry point
+    let interface = |name: &str| {
+        patched
+            .entry_points
+            .iter()
+            .find(|ep| ep.operands[2] == Operand::LiteralString(name.to_owned()))
+            .unwrap()
+            .operands[3..]
+            .to_vec()
+    };
+    assert_eq!(interface("main").len(), 1);
+    assert!(interface("frag").is_empty());
+    Verifier::verify(&patched).unwrap();
+}
+
+//Both entry points could call the kernel, the host already declares the builtin it reads
+const BUILTIN_HOST: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpEntryPoint GLCompute %other "other"
+OpExecutionMode %main LocalSize 1 1 1
+OpExecutionMode %other LocalSize 1 1 1
+OpName %buffer "buffer"
+OpName %host_gid "host_gid"
+OpDecorate %host_gid BuiltIn GlobalInvocationId
+OpDecorate %arr ArrayStride 4
+OpMemberDecorate %block 0 Offset 0
+OpDecorate %block BufferBlock
+OpDecorate %buffer DescriptorSet 0
+OpDecorate %buffer Binding 0
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%v3uint = OpTypeVector %uint 3
+%ptr_in = OpTypePointer Input %v3uint
+%arr = OpTypeRuntimeArray %uint
+%block = OpTypeStruct %arr
+%ptr_block = OpTypePointer Uniform %block
+%buffer = OpVariable %ptr_block Uniform
+%host_gid = OpVariable %ptr_in Input
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+%other = OpFunction %void None %fn
+%other_entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn interface_lists_existing_builtin() {
+    let module = Module::from_assembly(BUILTIN_HOST).unwrap();
+    let spv = module.spirv();
+    let buffer = spv.get_by_name("buffer").unwrap().result_id.unwrap();
+    let host_gid = spv.get_by_name("host_gid").unwrap().result_id.unwrap();
+    let kernel = Module::from_assembly(KERNEL).unwrap().spirv().clone();
+    let patched = module
+        .patch_entry_point("other", None)
+        .unwrap()
+        .patch(
+            EmbedKernel::new(kernel, CallSite::EntryEnd)
+                .with_argument(KernelInput::Name("data".to_owned()), buffer),
+        )
+        .unwrap()
+        .unwrap_module();
+
+    //The host's builtin is loaded and passed, so the calling entry point lists it
+    let interface = |name: &str| {
+        patched
+            .entry_points
+            .iter()
+            .find(|ep| ep.operands[2] == Operand::LiteralString(name.to_owned()))
+            .unwrap()
+            .operands[3..]
+            .to_vec()
+    };
+    assert_eq!(interface("other"), [Operand::IdRef(host_gid)]);
+    assert!(interface("main").is_empty());
+    let builtins = patched
+        .annotations
+        .iter()
+        .filter(|inst| inst.operands.get(1) == Some(&Operand::Decoration(Decoration::BuiltIn)))
+        .count();
+    assert_eq!(builtins, 1);
+    Verifier::verify(&patched).unwrap();
+}
@@ -1 +1,515 @@
-pub struct AssignmentRewrite {}
+use ahash::{AHashMap, AHashSet};
+use spv_patcher::{
+    analysis::{Cfg, DominatorTree},
+    import::{FunctionImporter, ImportError},
+    patch::{Invariant, NoDanglingCalls, Patch, ReturnsDeclaredType},
+    rspirv::{
+        dr::{Instruction, Module, Operand},
+        spirv::{Op, Word},
+    },
+    spirv_ext::{LineMatcher, SpirvExt},
+    type_tree::{TypeImporter, TypeTreeError},
+};
+use thiserror::Error;
+
+use crate::dynamic_replace::{
+    build_function, verify_return, DynamicReplaceError, RuntimeFunctionSignature, RuntimeReplace,
+};
+
+#[derive(Error, Debug)]
+pub enum AssignmentRewriteError {
+    #[error("No store matches {0:?}")]
+    NoMatch(StoreSelector),
+    #[error("Name \"{0}\" is used by more than one variable")]
+    Ambiguous(String),
+    #[error("%{id} is not a variable, but {op:?}")]
+    NotAVariable { id: Word, op: Op },
+    #[error("No function found for index {0} in the replacement module")]
+    InvalidReplacementIndex(usize),
+    #[error("SPIRV parsing error: {0}")]
+    SPIRVParseError(#[from] spv_patcher::rspirv::binary::ParseState),
+    #[error("Parameter {0} has no routed argument")]
+    MissingArgument(usize),
+    #[error("Argument routed to parameter {index}, but the function has only {count} parameters")]
+    UnknownParameter { index: usize, count: usize },
+    #[error("Argument %{id} is not in scope of the store in function %{function}")]
+    OutOfScope { id: Word, function: Word },
+    #[error("Argument %{id} does not fit parameter {parameter}")]
+    ArgumentMismatch { parameter: usize, id: Word },
+    #[error("Function returns %{found}, but the rewritten variable stores %{expected}")]
+    ReturnTypeMismatch { expected: Word, found: Word },
+    #[error("Replacement code uses the global variable %{0}, which can not be imported")]
+    UnsupportedGlobal(Word),
+    #[error("Could not import replacement types: {0}")]
+    TypeTree(#[from] TypeTreeError),
+    #[error("Could not import replacement function: {0}")]
+    Import(#[from] ImportError),
+    #[error("Could not build replacement function: {0}")]
+    Builder(#[from] DynamicReplaceError),
+}
+
+///Selects the `OpStore`s an [AssignmentRewrite] rewrites.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreSelector {
+    ///All stores to the variable with this `OpName`.
+    Name(String),
+    ///All stores to the variable with this id.
+    Id(Word),
+    ///All stores following an `OpLine` with this line, in a file whose name ends with `file`.
+    Line { file: String, line: u32 },
+}
+
+///Source of the function that computes the new value.
+pub enum Replacement {
+    ///The function at `index` of `module`. The function, and all functions it calls, are copied into the patched module.
+    Module { module: Module, index: usize },
+    ///A function written by the closure. Its parameters have the types of the routed arguments, and its return type is
+    /// the type of the rewritten variable.
+    Builder(Box<dyn RuntimeReplace>),
+}
+
+///Result of an [AssignmentRewrite] run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewriteReport {
+    ///Id of the called function in the patched module.
+    pub function: Word,
+    ///Number of rewritten stores.
+    pub stores: usize,
+}
+
+pub struct AssignmentRewrite {
+    pub selector: StoreSelector,
+    pub replacement: Replacement,
+    ///Routing table of `(parameter index, id)` pairs. Each parameter needs exactly one id that is in scope at every
+    /// selected store. Variables can be routed to parameters of their pointee type, in which case they are loaded before
+    /// the call.
+    pub arguments: Vec<(usize, Word)>,
+}
+
+//An `OpStore` location (function, block, instruction).
+type StoreLocation = (usize, usize, usize);
+
+impl AssignmentRewrite {
+    pub fn new_from_module(selector: StoreSelector, module: Module, index: usize) -> Self {
+        AssignmentRewrite {
+            selector,
+            replacement: Replacement::Module { module, index },
+            arguments: Vec::new(),
+        }
+    }
+
+    pub fn new_from_bytes(
+        selector: StoreSelector,
+        module: &[u8],
+        index: usize,
+    ) -> Result<Self, AssignmentRewriteError> {
+        let module = spv_patcher::rspirv::dr::load_bytes(module)?;
+        Ok(Self::new_from_module(selector, module, index))
+    }
+
+    pub fn new_from_builder(selector: StoreSelector, replacement: impl RuntimeReplace) -> Self {
+        AssignmentRewrite {
+            selector,
+            replacement: Replacement::Builder(Box::new(replacement)),
+            arguments: Vec::new(),
+        }
+    }
+
+    ///Routes `id` to the function's parameter at `parameter`.
+    pub fn with_argument(mut self, parameter: usize, id: Word) -> Self {
+        self.arguments.push((parameter, id));
+        self
+    }
+
+    ///Rewrites the selected stores of `module`.
+    pub fn run(&self, module: &mut Module) -> Result<RewriteReport, AssignmentRewriteError> {
+        let stores = self.select(module)?;
+        let parameter_count = match &self.replacement {
+            Replacement::Module { module: src, index } => src
+                .functions
+                .get(*index)
+                .ok_or(AssignmentRewriteError::InvalidReplacementIndex(*index))?
+                .parameters
+                .len(),
+            Replacement::Builder(_) => self
+                .arguments
+                .iter()
+                .map(|(index, _)| index + 1)
+                .max()
+                .unwrap_or(0),
+        };
+        let routed = self.route(parameter_count)?;
+        check_scope(module, &stores, &routed)?;
+
+        //Validate the signature before anything is declared, so a mismatch leaves the module untouched. Errors while
+        // importing or building the replacement may still leave declarations behind.
+        let return_type = stored_type(module, stores[0])?;
+        for store in &stores[1..] {
+            let found = stored_type(module, *store)?;
+            if found != return_type {
+                return Err(AssignmentRewriteError::ReturnTypeMismatch {
+                    expected: return_type,
+                    found,
+                });
+            }
+        }
+        let parameter_types = match &self.replacement {
+            Replacement::Module { module: src, index } => {
+                //A type the host does not declare can not match, the mismatch then names the replacement's id
+                let function = &src.functions[*index];
+                let mut importer = TypeImporter::new(src, module)?;
+                let src_return = function
+                    .def
+                    .as_ref()
+                    .and_then(|def| def.result_type)
+                    .unwrap_or(0);
+                if importer.find(src_return)? != Some(return_type) {
+                    return Err(AssignmentRewriteError::ReturnTypeMismatch {
+                        expected: return_type,
+                        found: src_return,
+                    });
+                }
+                let mut parameter_types = Vec::with_capacity(function.parameters.len());
+                for (parameter, param) in function.parameters.iter().enumerate() {
+                    let src_ty = param.result_type.unwrap_or(0);
+                    let ty =
+                        importer
+                            .find(src_ty)?
+                            .ok_or(AssignmentRewriteError::ArgumentMismatch {
+                                parameter,
+                                id: routed[parameter],
+                            })?;
+                    parameter_types.push(ty);
+                }
+                check_globals(src, *index)?;
+                parameter_types
+            }
+            Replacement::Builder(_) => routed
+                .iter()
+                .enumerate()
+                .map(|(parameter, id)| {
+                    type_of(module, *id)
+                        .ok_or(AssignmentRewriteError::ArgumentMismatch { parameter, id: *id })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+
+        //Resolve the arguments, variables are loaded at the call
+        let mut arguments = Vec::with_capacity(routed.len());
+        for (parameter, (id, ty)) in routed.iter().zip(&parameter_types).enumerate() {
+            let found = type_of(module, *id);
+            if found == Some(*ty) {
+                arguments.push((*id, false));
+            } else if found.and_then(|ptr| module.pointee(ptr)) == Some(*ty) {
+                arguments.push((*id, true));
+            } else {
+                return Err(AssignmentRewriteError::ArgumentMismatch { parameter, id: *id });
+            }
+        }
+
+        //Declare the function
+        let function = match &self.replacement {
+            Replacement::Module { module: src, index } => import_function(module, src, *index)?,
+            Replacement::Builder(replace) => {
+                let function_type = module.find_or_insert_type(
+                    Op::TypeFunction,
+                    &std::iter::once(return_type)
+                        .chain(parameter_types.iter().copied())
+                        .map(Operand::IdRef)
+                        .collect::<Vec<_>>(),
+                    &[],
+                );
+                let mut sig =
+                    RuntimeFunctionSignature::new(return_type, &parameter_types, function_type);
+                let function = build_function(module, &mut sig, &**replace)?;
+                if let Err(e) = verify_return(module, function, &sig) {
+                    module.functions.retain(|f| f.def_id() != Some(function));
+                    return Err(e.into());
+                }
+                function
+            }
+        };
+
+        //Back to front, so that the locations of earlier stores stay valid
+        for (fidx, bidx, iidx) in stores.iter().rev().copied() {
+            let mut call = Vec::new();
+            let mut operands = vec![Operand::IdRef(function)];
+            for ((id, load), ty) in arguments.iter().zip(&parameter_types) {
+                if *load {
+                    let value = module.allocate_id();
+                    call.push(Instruction::new(
+                        Op::Load,
+                        Some(*ty),
+                        Some(value),
+                        vec![Operand::IdRef(*id)],
+                    ));
+                    operands.push(Operand::IdRef(value));
+                } else {
+                    operands.push(Operand::IdRef(*id));
+                }
+            }
+            let result = module.allocate_id();
+            call.push(Instruction::new(
+                Op::FunctionCall,
+                Some(return_type),
+                Some(result),
+                operands,
+            ));
+            let instructions = &mut module.functions[fidx].blocks[bidx].instructions;
+            instructions[iidx].operands[1] = Operand::IdRef(result);
+            instructions.splice(iidx..iidx, call);
+        }
+
+        Ok(RewriteReport {
+            function,
+            stores: stores.len(),
+        })
+    }
+
+    //Locations of the selected stores, in module order.
+    fn select(&self, module: &Module) -> Result<Vec<StoreLocation>, AssignmentRewriteError> {
+        let variable = match &self.selector {
+            StoreSelector::Name(name) => {
+                //Only variables are considered, a name several of them share is ambiguous
+                let is_variable = |id: Word| {
+                    module
+                        .all_inst_iter()
+                        .find(|inst| inst.result_id == Some(id))
+                        .filter(|inst| inst.class.opcode == Op::Variable)
+                };
+                let mut variables = module
+                    .debug_names
+                    .iter()
+                    .filter(|inst| {
+                        inst.class.opcode == Op::Name
+                            && inst.operands.get(1) == Some(&Operand::LiteralString(name.clone()))
+                    })
+                    .filter_map(|inst| inst.operands.first().and_then(|op| op.id_ref_any()))
+                    .filter_map(is_variable);
+                let variable = variables
+                    .next()
+                    .ok_or_else(|| AssignmentRewriteError::NoMatch(self.selector.clone()))?;
+                if variables.next().is_some() {
+                    return Err(AssignmentRewriteError::Ambiguous(name.clone()));
+                }
+                Some(variable)
+            }
+            StoreSelector::Id(id) => Some(
+                module
+                    .all_inst_iter()
+                    .find(|inst| inst.result_id == Some(*id))
+                    .ok_or_else(|| AssignmentRewriteError::NoMatch(self.selector.clone()))?,
+            ),
+            StoreSelector::Line { .. } => None,
+        };
+        let variable = match variable {
+            Some(inst) if inst.class.opcode != Op::Variable => {
+                return Err(AssignmentRewriteError::NotAVariable {
+                    id: inst.result_id.unwrap_or(0),
+                    op: inst.class.opcode,
+                })
+            }
+            Some(inst) => inst.result_id,
+            None => None,
+        };
+
+        let lines = match &self.selector {
+            StoreSelector::Line { file, line } => Some(LineMatcher::new(module, file, *line)),
+            _ => None,
+        };
+
+        let mut stores = Vec::new();
+        for (fidx, function) in module.functions.iter().enumerate() {
+            for (bidx, block) in function.blocks.iter().enumerate() {
+                //An OpLine applies until the next OpLine, OpNoLine or the end of the block
+                let mut on_line = false;
+                for (iidx, inst) in block.instructions.iter().enumerate() {
+                    match (inst.class.opcode, &self.selector) {
+                        (Op::Line, _) => {
+                            on_line = lines.as_ref().map(|l| l.matches(inst)).unwrap_or(false)
+                        }
+                        (Op::NoLine, _) => on_line = false,
+                        (Op::Store, StoreSelector::Line { .. }) if on_line => {
+                            stores.push((fidx, bidx, iidx))
+                        }
+                        (Op::Store, _)
+                            if variable.is_some()
+                                && inst.operands.first().and_then(|op| op.id_ref_any())
+                                    == variable =>
+                        {
+                            stores.push((fidx, bidx, iidx))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        if stores.is_empty() {
+            return Err(AssignmentRewriteError::NoMatch(self.selector.clone()));
+        }
+        Ok(stores)
+    }
+
+    //Orders the routing table by parameter.
+    fn route(&self, count: usize) -> Result<Vec<Word>, AssignmentRewriteError> {
+        let mut routed = vec![None; count];
+        for (index, id) in &self.arguments {
+            let slot = routed
+                .get_mut(*index)
+                .ok_or(AssignmentRewriteError::UnknownParameter {
+                    index: *index,
+                    count,
+                })?;
+            if slot.is_some() {
+                log::warn!("Parameter {} is routed twice, using %{}", index, id);
+            }
+            *slot = Some(*id);
+        }
+        routed
+            .into_iter()
+            .enumerate()
+            .map(|(index, id)| id.ok_or(AssignmentRewriteError::MissingArgument(index)))
+            .collect()
+    }
+}
+
+//Checks that all routed ids are available at every store, i.e. they are global, parameters of the store's function, or
+// defined in a dominating position.
+fn check_scope(
+    module: &Module,
+    stores: &[StoreLocation],
+    routed: &[Word],
+) -> Result<(), AssignmentRewriteError> {
+    let globals = module
+        .types_global_values
+        .iter()
+        .filter_map(|inst| inst.result_id)
+        .collect::<AHashSet<_>>();
+    let mut dominators = AHashMap::default();
+    for (fidx, bidx, iidx) in stores.iter().copied() {
+        let function = &module.functions[fidx];
+        let doms = dominators
+            .entry(fidx)
+            .or_insert_with(|| DominatorTree::dominators(&Cfg::new(function)));
+        for id in routed {
+            let in_scope = globals.contains(id)
+                || function.parameters.iter().any(|p| p.result_id == Some(*id))
+                || function
+                    .blocks
+                    .iter()
+                    .enumerate()
+                    .any(|(def_block, block)| {
+                        block
+                            .instructions
+                            .iter()
+                            .position(|inst| inst.result_id == Some(*id))
+                            .map(|def_idx| {
+                                (def_block == bidx && def_idx < iidx)
+                                    || doms.strictly_dominates(def_block, bidx)
+                            })
+                            .unwrap_or(false)
+                    });
+            if !in_scope {
+                return Err(AssignmentRewriteError::OutOfScope {
+                    id: *id,
+                    function: function.def_id().unwrap_or(0),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+//Fails if the function at `index` of `src`, or a function it calls, uses a global variable. Those can not be imported.
+fn check_globals(src: &Module, index: usize) -> Result<(), AssignmentRewriteError> {
+    let root = src.functions[index]
+        .def_id()
+        .ok_or(AssignmentRewriteError::InvalidReplacementIndex(index))?;
+    let reachable = src.reachable_functions(root);
+    let variables = src
+        .types_global_values
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::Variable)
+        .filter_map(|inst| inst.result_id)
+        .collect::<AHashSet<_>>();
+    let used = src
+        .functions
+        .iter()
+        .filter(|f| {
+            f.def_id()
+                .map(|id| reachable.contains(&id))
+                .unwrap_or(false)
+        })
+        .flat_map(|f| f.all_inst_iter())
+        .flat_map(|inst| inst.operands.iter().filter_map(|op| op.id_ref_any()))
+        .find(|id| variables.contains(id));
+    match used {
+        Some(id) => Err(AssignmentRewriteError::UnsupportedGlobal(id)),
+        None => Ok(()),
+    }
+}
+
+//Copies the function at `index` of `src`, and every function it calls, into `dst`. Returns the new function's id.
+fn import_function(
+    dst: &mut Module,
+    src: &Module,
+    index: usize,
+) -> Result<Word, AssignmentRewriteError> {
+    let root = src.functions[index]
+        .def_id()
+        .ok_or(AssignmentRewriteError::InvalidReplacementIndex(index))?;
+    FunctionImporter::new(src, dst)?
+        .import_functions(dst, root)
+        .map_err(|e| match e {
+            ImportError::Variable(id) => AssignmentRewriteError::UnsupportedGlobal(id),
+            e => e.into(),
+        })
+}
+
+//Type of the value `id`.
+fn type_of(module: &Module, id: Word) -> Option<Word> {
+    module
+        .all_inst_iter()
+        .find(|inst| inst.result_id == Some(id))
+        .and_then(|inst| inst.result_type)
+}
+
+//Type of the value the store at `location` writes.
+fn stored_type(module: &Module, location: StoreLocation) -> Result<Word, AssignmentRewriteError> {
+    let (fidx, bidx, iidx) = location;
+    let store = &module.functions[fidx].blocks[bidx].instructions[iidx];
+    let target = store.operands[0].unwrap_id_ref();
+    let def = module
+        .all_inst_iter()
+        .find(|inst| inst.result_id == Some(target))
+        .unwrap();
+    def.result_type.and_then(|ptr| module.pointee(ptr)).ok_or(
+        AssignmentRewriteError::NotAVariable {
+            id: target,
+            op: def.class.opcode,
+        },
+    )
+}
+
+impl Patch for AssignmentRewrite {
+    fn apply<'a>(
+        self,
+        mut patcher: spv_patcher::patch::Patcher<'a>,
+    ) -> Result<spv_patcher::patch::Patcher<'a>, spv_patcher::PatcherError> {
+        let report = self
+            .run(patcher.ir_state.as_spirv())
+            .map_err(|e| spv_patcher::PatcherError::Internal(e.into()))?;
+        log::info!(
+            "Rewrote {} stores to call %{}",
+            report.stores,
+            report.function
+        );
+        patcher.push_report(report);
+        Ok(patcher)
+    }
+
+    fn invariants(&self) -> Vec<Box<dyn Invariant>> {
+        vec![Box::new(NoDanglingCalls), Box::new(ReturnsDeclaredType)]
+    }
+}
@@ -1,8 +1,10 @@
 use smallvec::SmallVec;
 use spv_patcher::{
-    patch::Patch,
+    analysis::{DefUse, Use, UseSlot},
+    patch::{Invariant, NoDanglingCalls, Patch, ReturnsDeclaredType},
     rspirv::{
         dr::{Builder, Instruction, Module},
+        grammar,
         spirv::{FunctionControl, Op},
     },
 };
@@ -18,6 +20,10 @@ pub enum DynamicReplaceError {
     BuilderError(#[from] spv_patcher::rspirv::dr::Error),
     #[error("Last instruction added to the builder must be OpReturn or OpReturnValue, depending on the supplied signature return-type")]
     InvalidLastInstruction,
+    #[error(
+        "Replacement function returns value of type {found:?}, but signature expects %{expected}"
+    )]
+    ReturnTypeMismatch { expected: u32, found: Option<u32> },
 }
 
 #[derive(Debug, Clone)]
@@ -34,6 +40,18 @@ pub struct RuntimeFunctionSignature {
     function_id: u32,
 }
 
+impl RuntimeFunctionSignature {
+    ///Signature of a function that is not declared yet. Parameter ids are assigned once the function is built.
+    pub(crate) fn new(return_type: u32, parameter_types: &[u32], function_type: u32) -> Self {
+        RuntimeFunctionSignature {
+            return_type,
+            parameter: parameter_types.iter().map(|ty| (0, *ty)).collect(),
+            function_type,
+            function_id: 0,
+        }
+    }
+}
+
 ///Trait alias for the replace function that is executed when using [new_dyn](LinkReplace::dyn_new).
 pub trait RuntimeReplace =
     Fn(&mut Builder, &RuntimeFunctionSignature) -> Result<(), DynamicReplaceError> + 'static;
@@ -117,91 +135,135 @@ impl DynamicReplace {
         module: &mut Module,
         sig: &mut RuntimeFunctionSignature,
     ) -> Result<u32, DynamicReplaceError> {
-        //creates the builder on the module, starts a new function, then executes the clojure on that
-        // function.
-        // Afterwards we end the function and rewrite all calls to the newly written one.
-
-        let mut tmp_module = Module::new();
-        core::mem::swap(&mut tmp_module, module);
-        let mut builder = Builder::new_from_module(tmp_module);
-
-        //start a new function based on `sig`
-        let function_id = builder.begin_function(
-            sig.return_type,
-            None,
-            FunctionControl::empty(),
-            sig.function_type,
-        )?;
-        //add all parameters
-        for p in &mut sig.parameter {
-            //add parameter and rewrite id
-            let param_id = builder.function_parameter(p.1)?;
-            p.0 = param_id;
-        }
-        //now start basic block
-        let _block_id = builder.begin_block(None)?;
-
-        //let the closure take over
-        (self.replace_function)(&mut builder, &sig)?;
-
+        let function_id = build_function(module, sig, &*self.replace_function)?;
         log::info!("Successfully replaced function!");
-
-        //Now check if the caller has already terminated the block (via OpReturnValue) if not, throw an error
-        //as we can't know what should be returned
-        // TODO: reimplement, by checking whole block
-        /*
-                let last_op = builder.pop_instruction().unwrap();
-                match last_op.class.opcode {
-                    Op::Return | Op::ReturnValue => {}
-                    _ => return Err(DynamicReplaceError::InvalidLastInstruction),
-                }
-
-                //push back the instruction
-                builder.insert_into_block(spv_patcher::rspirv::dr::InsertPoint::End, last_op)?;
-        */
-        //Now end function
-        builder.end_function()?;
-
-        //swap back modules
-        let mut tmp_module = builder.module();
-        core::mem::swap(&mut tmp_module, module);
-
         Ok(function_id)
     }
 
     fn rewrite_function_ids(
         &self,
         module: &mut Module,
+        def_use: &mut DefUse,
         new_id: u32,
         sig: &RuntimeFunctionSignature,
     ) -> Result<(), DynamicReplaceError> {
-        //rewrite all function calls to the old function with the new id
-
-        for inst in module.all_inst_iter_mut() {
-            match inst.class.opcode {
-                Op::FunctionCall => {
-                    if let Some(call_id) = inst
-                        .operands
-                        .get_mut(0)
-                        .map(|op_ty| op_ty.id_ref_any_mut())
-                        .flatten()
-                    {
-                        if *call_id == sig.function_id {
-                            log::info!("Mutate call {} -> {}", call_id, sig.function_id);
-                            *call_id = new_id;
-                        }
-                    }
-                }
-                _ => {}
-            }
+        //rewrite all function calls to the old function with the new id. Names, decorations and entry points keep
+        // referencing the old function.
+        let calls: SmallVec<[Use; 8]> = def_use
+            .users_of(sig.function_id)
+            .iter()
+            .filter(|u| {
+                u.slot == UseSlot::Operand(0)
+                    && u.location.get(module).map(|inst| inst.class.opcode)
+                        == Some(Op::FunctionCall)
+            })
+            .copied()
+            .collect();
+        for call in calls {
+            log::info!("Mutate call {} -> {}", sig.function_id, new_id);
+            def_use.replace_use(module, call, new_id);
         }
 
         Ok(())
     }
+}
 
-    fn verify_return(&self, _module: &Module) -> Result<(), DynamicReplaceError> {
-        Ok(())
+//Starts a new function for `sig` and lets `replace` write its body. The parameter ids of `sig` are rewritten to the new
+// function's parameters. Returns the new function's ID.
+pub(crate) fn build_function(
+    module: &mut Module,
+    sig: &mut RuntimeFunctionSignature,
+    replace: &dyn RuntimeReplace,
+) -> Result<u32, DynamicReplaceError> {
+    //creates the builder on the module, starts a new function, then executes the clojure on that
+    // function.
+    let mut tmp_module = Module::new();
+    core::mem::swap(&mut tmp_module, module);
+    let mut builder = Builder::new_from_module(tmp_module);
+
+    //start a new function based on `sig`
+    let function_id = builder.begin_function(
+        sig.return_type,
+        None,
+        FunctionControl::empty(),
+        sig.function_type,
+    )?;
+    //add all parameters
+    for p in &mut sig.parameter {
+        //add parameter and rewrite id
+        let param_id = builder.function_parameter(p.1)?;
+        p.0 = param_id;
     }
+    //now start basic block
+    let _block_id = builder.begin_block(None)?;
+
+    //let the closure take over
+    let result = replace(&mut builder, sig);
+    if result.is_ok() {
+        builder.end_function()?;
+    }
+
+    //swap back modules
+    let mut tmp_module = builder.module();
+    core::mem::swap(&mut tmp_module, module);
+    result.map(|_| function_id)
+}
+
+//Checks that every block of the replacement function is terminated, and that all returns match the signature's return type.
+pub(crate) fn verify_return(
+    module: &Module,
+    new_id: u32,
+    sig: &RuntimeFunctionSignature,
+) -> Result<(), DynamicReplaceError> {
+    let function = module
+        .functions
+        .iter()
+        .find(|f| f.def_id() == Some(new_id))
+        .ok_or(DynamicReplaceError::NoFunctionIndex)?;
+
+    let is_void = module
+        .types_global_values
+        .iter()
+        .any(|inst| inst.result_id == Some(sig.return_type) && inst.class.opcode == Op::TypeVoid);
+    let type_of = |id: u32| {
+        module
+            .all_inst_iter()
+            .find(|inst| inst.result_id == Some(id))
+            .and_then(|inst| inst.result_type)
+    };
+
+    if function.blocks.is_empty() {
+        return Err(DynamicReplaceError::InvalidLastInstruction);
+    }
+    for block in &function.blocks {
+        let last = block
+            .instructions
+            .last()
+            .ok_or(DynamicReplaceError::InvalidLastInstruction)?;
+        match last.class.opcode {
+            Op::Return if is_void => {}
+            Op::ReturnValue if !is_void => {
+                let found = last
+                    .operands
+                    .get(0)
+                    .and_then(|op| op.id_ref_any())
+                    .and_then(&type_of);
+                if found != Some(sig.return_type) {
+                    return Err(DynamicReplaceError::ReturnTypeMismatch {
+                        expected: sig.return_type,
+                        found,
+                    });
+                }
+            }
+            Op::Return | Op::ReturnValue => {
+                return Err(DynamicReplaceError::InvalidLastInstruction)
+            }
+            _ if grammar::reflect::is_block_terminator(last.class.opcode) => {}
+            _ => return Err(DynamicReplaceError::InvalidLastInstruction),
+        }
+    }
+
+    Ok(())
 }
 
 impl Patch for DynamicReplace {
@@ -236,11 +298,17 @@ impl Patch for DynamicReplace {
         let new_function_id = self
             .write_new_function(spv_mod, &mut sig)
             .map_err(|e| spv_patcher::PatcherError::Internal(e.into()))?;
-        self.rewrite_function_ids(spv_mod, new_function_id, &sig)
+
+        let (spv_mod, def_use) = patcher.ir_state.def_use_mut();
+        self.rewrite_function_ids(spv_mod, def_use, new_function_id, &sig)
             .map_err(|e| spv_patcher::PatcherError::Internal(e.into()))?;
-        self.verify_return(&spv_mod)
+        verify_return(&spv_mod, new_function_id, &sig)
             .map_err(|e| spv_patcher::PatcherError::Internal(e.into()))?;
-        //TODO: At this point we could DCE the old function...
+        //NOTE: The old function is kept, chain `patch_dead_code_elimination::DeadCodeElimination` to remove it.
         Ok(patcher)
     }
+
+    fn invariants(&self) -> Vec<Box<dyn Invariant>> {
+        vec![Box::new(NoDanglingCalls), Box::new(ReturnsDeclaredType)]
+    }
 }
@@ -1,12 +1,30 @@
 use smallvec::SmallVec;
-use spv_patcher::{rspirv::dr::Module, spirv_ext::SpirvExt};
+use spv_patcher::{analysis::CallGraph, rspirv::dr::Module, spirv_ext::SpirvExt, EntryPoint};
 
 use crate::function_finder::FuncSignature;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct FuncDeclaration {
+    ///Result id of the function's `OpFunction`.
+    pub function_id: u32,
     pub debug_name: Option<String>,
     pub signature: FuncSignature,
+    ///Functions calling this function directly.
+    pub callers: SmallVec<[u32; 3]>,
+    ///Functions called directly by this function.
+    pub callees: SmallVec<[u32; 3]>,
+    ///True if the function can (indirectly) call itself.
+    pub recursive: bool,
+    ///Function ids of all entry points from which this function is reachable.
+    pub entry_points: SmallVec<[u32; 1]>,
+}
+
+impl FuncDeclaration {
+    ///True if the function is reachable from `entry_point`, i.e. replacing the function changes the entry point's
+    /// behaviour.
+    pub fn affects(&self, entry_point: &EntryPoint) -> bool {
+        self.entry_points.contains(&entry_point.function_id)
+    }
 }
 
 ///Reusable function enumerator. Searches for all available functions, and enumerates their
@@ -20,12 +38,14 @@ impl FuncEnumerator {
     pub fn enumerate(spirv: &Module) -> SmallVec<[FuncDeclaration; 3]> {
         //Walk the module, for each OpFunction, collect the return type-id,
         // as well as all argument type-ids.
-        // Then check if we have a debug name.
+        // Then check if we have a debug name, and where the function sits in the call graph.
+        let call_graph = CallGraph::new(spirv);
         spirv
             .functions
             .iter()
             .map(|f| {
-                let debug_name = spirv.get_name(f.def.as_ref().unwrap().result_id.unwrap());
+                let function_id = f.def.as_ref().unwrap().result_id.unwrap();
+                let debug_name = spirv.get_name(function_id);
                 let signature = FuncSignature {
                     return_type: f.def.as_ref().unwrap().result_type.unwrap(),
                     argument_types: f
@@ -35,8 +55,13 @@ impl FuncEnumerator {
                         .collect(),
                 };
                 FuncDeclaration {
+                    function_id,
                     debug_name,
                     signature,
+                    callers: call_graph.callers(function_id).iter().copied().collect(),
+                    callees: call_graph.callees(function_id).iter().copied().collect(),
+                    recursive: call_graph.is_recursive(function_id),
+                    entry_points: call_graph.entry_points_reaching(function_id),
                 }
             })
             .collect()
@@ -15,8 +15,12 @@ mod enumerate;
 mod function_finder;
 mod static_replace;
 
-pub use assignment_rewrite::AssignmentRewrite;
-pub use dynamic_replace::{DynamicReplace, RuntimeFunctionSignature, RuntimeReplace};
+pub use assignment_rewrite::{
+    AssignmentRewrite, AssignmentRewriteError, Replacement, RewriteReport, StoreSelector,
+};
+pub use dynamic_replace::{
+    DynamicReplace, DynamicReplaceError, RuntimeFunctionSignature, RuntimeReplace,
+};
 pub use enumerate::{FuncDeclaration, FuncEnumerator};
 pub use function_finder::{FuncIdent, FunctionFinder};
 pub use spv_patcher::rspirv;
@@ -1,8 +1,7 @@
 use std::rc::Rc;
 
-use ahash::AHashMap;
 use spv_patcher::{
-    patch::Patch,
+    patch::{Invariant, NoDanglingCalls, Patch},
     rspirv::{
         binary::Assemble,
         dr::{Instruction, Module, Operand},
@@ -10,6 +9,7 @@ use spv_patcher::{
     },
     spirt::{self, Context},
     spirv_ext::SpirvExt,
+    type_tree::{TypeImporter, TypeTreeError},
 };
 
 use crate::function_finder::FuncSignature;
@@ -36,6 +36,8 @@ pub enum StaticReplaceError {
     SignatureMatchError,
     #[error("There was no function marked as \"export\" with the name \"{0}\" ")]
     NoFunctionWithName(String),
+    #[error("Could not compare types of both modules: {0}")]
+    TypeTree(#[from] TypeTreeError),
 }
 
 ///Declares a *whole* spirv module and a function index into the module that will replace a function with the same identification
@@ -177,60 +179,41 @@ impl StaticReplace {
             ));
         };
 
-        //Pass2: find a function with the same-ish signature, and add the linkage decoration needed for the linker to
-        //       find it.
-        //NOTE: We currently just test for a similar-ish signature by building both type trees and then
-        //      comparing the parameter types of both.
-        //
-        //      This is currently not *perfect*. For that we'd merge the patch function into the `dst` module
-        //      before linking. That's what the spirv-linker does. However, in our case the target function is not
-        //      decorated with linkage attributes which makes that harder.
-        //
-        //      In the future there might be a SPIR-T based merge pass that would allow that.
-
-        let mut src_arg_ty_table = self
-            .ident
-            .argument_types
-            .iter()
-            .map(|i| (*i, None))
-            .collect::<AHashMap<u32, Option<u32>>>();
-
-        let mut match_list = Vec::with_capacity(1);
-
-        'function_def_iter: for (fidx, f) in dst.functions.iter().enumerate() {
-            if f.parameters.len() == self.ident.argument_types.len() {
-                //we do that by pattern matching for now 👀
-                // check if type pattern is the same.
-                for (dst_f_ty, ident_ty) in
-                    f.parameters.iter().zip(self.ident.argument_types.iter())
-                {
-                    let dst_f_ty = *dst_f_ty.result_type.as_ref().unwrap();
-                    if let Some(seen_ty) = src_arg_ty_table.get_mut(ident_ty) {
-                        //don't know the mapping atm. push into table
-                        if let Some(seen_dst_ty) = seen_ty {
-                            if dst_f_ty != *seen_dst_ty {
-                                log::error!("We know type={} in the patch module as type={} in the destination module already. However, we tried mapping as type={}.", ident_ty, seen_dst_ty, dst_f_ty);
-                                continue 'function_def_iter;
-                            }
-                        } else {
-                            //need to insert seen type
-                            *seen_ty = Some(dst_f_ty);
-                        }
-                    } else {
-                        log::error!("src_arg_ty_table was incomplete, Linking will fail!");
-                        return Err(StaticReplaceError::InvalidReplacementIndex(
-                            self.replacement_index,
-                        ));
-                    }
+        //Pass2: find a function with the same signature, and add the linkage decoration needed for the linker to
+        //       find it. Types are compared structurally (including their decorations), so the ids of both modules
+        //       do not need to match.
+        let mut importer = TypeImporter::new(&self.replacement_module, dst)?;
+        let mut signature = Vec::with_capacity(self.ident.argument_types.len() + 1);
+        for src_ty in
+            std::iter::once(&self.ident.return_type).chain(self.ident.argument_types.iter())
+        {
+            match importer.find(*src_ty)? {
+                Some(dst_ty) => signature.push(dst_ty),
+                None => {
+                    log::error!(
+                        "Type %{} of the replacement function is not declared in the destination module",
+                        src_ty
+                    );
+                    return Err(StaticReplaceError::SignatureMatchError);
                 }
-
-                //if no continue was called up to this point, decorate this function with the correct call
-                log::error!("Found matching function in dst as {fidx}");
-
-                match_list.push(fidx);
             }
         }
 
+        let match_list = dst
+            .functions
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| {
+                f.def.as_ref().and_then(|def| def.result_type) == Some(signature[0])
+                    && f.parameters.len() == signature.len() - 1
+                    && f.parameters
+                        .iter()
+                        .zip(signature[1..].iter())
+                        .all(|(param, ty)| param.result_type == Some(*ty))
+            })
+            .map(|(fidx, _)| fidx)
+            .collect::<Vec<_>>();
+
         if match_list.len() == 0 {
             log::error!(
                 "Did not find any matching function that could be patched in the source module!"
@@ -387,4 +370,9 @@ impl Patch for StaticReplace {
         }
         Ok(patcher)
     }
+
+    fn invariants(&self) -> Vec<Box<dyn Invariant>> {
+        //Linking must resolve every call to the replaced function
+        vec![Box::new(NoDanglingCalls)]
+    }
 }
@@ -0,0 +1,256 @@
+use patch_function::{
+    rspirv::{
+        binary::Assemble,
+        dr::{Builder, Module as SpvModule, Operand},
+        spirv::{Op, Word},
+    },
+    AssignmentRewrite, AssignmentRewriteError, DynamicReplaceError, RuntimeFunctionSignature,
+    StoreSelector,
+};
+use spv_patcher::{spirv_ext::SpirvExt, verify::Verifier, Module};
+
+const HOST: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+%file = OpString "shaders/rewrite.comp"
+OpName %x "x"
+OpName %p "p"
+OpName %late "late"
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%ptr_fn = OpTypePointer Function %uint
+%ptr_priv = OpTypePointer Private %uint
+%uint_1 = OpConstant %uint 1
+%uint_3 = OpConstant %uint 3
+%p = OpVariable %ptr_priv Private %uint_1
+%main = OpFunction %void None %fn
+%entry = OpLabel
+%x = OpVariable %ptr_fn Function
+OpLine %file 7 0
+OpStore %x %uint_1
+OpNoLine
+%late = OpLoad %uint %x
+OpReturn
+OpFunctionEnd
+"#;
+
+const REPLACEMENT: &str = r#"
+OpCapability Shader
+OpCapability Linkage
+OpMemoryModel Logical GLSL450
+%uint = OpTypeInt 32 0
+%float = OpTypeFloat 32
+%add_fn = OpTypeFunction %uint %uint %uint
+%float_fn = OpTypeFunction %float
+%float_2 = OpConstant %float 2
+%add = OpFunction %uint None %add_fn
+%a = OpFunctionParameter %uint
+%b = OpFunctionParameter %uint
+%add_entry = OpLabel
+%sum = OpIAdd %uint %a %b
+OpReturnValue %sum
+OpFunctionEnd
+%two = OpFunction %float None %float_fn
+%two_entry = OpLabel
+OpReturnValue %float_2
+OpFunctionEnd
+"#;
+
+//Replacement with line information and non-semantic debug instructions
+const DEBUG_REPLACEMENT: &str = r#"
+OpCapability Shader
+OpCapability Linkage
+OpExtension "SPV_KHR_non_semantic_info"
+%info = OpExtInstImport "NonSemantic.DebugPrintf"
+OpMemoryModel Logical GLSL450
+%file = OpString "replacement.comp"
+%fmt = OpString "called"
+%uint = OpTypeInt 32 0
+%void = OpTypeVoid
+%fn = OpTypeFunction %uint
+%uint_5 = OpConstant %uint 5
+%five = OpFunction %uint None %fn
+%five_entry = OpLabel
+OpLine %file 3 0
+%print = OpExtInst %void %info 1 %fmt
+OpReturnValue %uint_5
+OpFunctionEnd
+"#;
+
+fn constant(spv: &SpvModule, value: u32) -> Word {
+    spv.types_global_values
+        .iter()
+        .find(|inst| {
+            inst.class.opcode == Op::Constant && inst.operands == [Operand::LiteralBit32(value)]
+        })
+        .and_then(|inst| inst.result_id)
+        .unwrap()
+}
+
+fn stored_value(spv: &SpvModule) -> Word {
+    spv.functions[0]
+        .all_inst_iter()
+        .find(|inst| inst.class.opcode == Op::Store)
+        .unwrap()
+        .operands[1]
+        .unwrap_id_ref()
+}
+
+#[test]
+fn rewrite_from_module() {
+    let mut host = Module::from_assembly(HOST).unwrap().spirv().clone();
+    let p = host.get_by_name("p").unwrap().result_id.unwrap();
+    let uint_3 = constant(&host, 3);
+    let replacement = Module::from_assembly(REPLACEMENT).unwrap().spirv().clone();
+    let report =
+        AssignmentRewrite::new_from_module(StoreSelector::Name("x".to_owned()), replacement, 0)
+            .with_argument(1, uint_3)
+            .with_argument(0, p)
+            .run(&mut host)
+            .unwrap();
+    assert_eq!(report.stores, 1);
+    assert_eq!(host.functions.len(), 2);
+
+    //The private variable is loaded, and the call's result is stored instead
+    let call = host.functions[0]
+        .all_inst_iter()
+        .find(|inst| inst.class.opcode == Op::FunctionCall)
+        .unwrap();
+    assert_eq!(call.operands[0], Operand::IdRef(report.function));
+    assert_eq!(call.operands[2], Operand::IdRef(uint_3));
+    assert_eq!(stored_value(&host), call.result_id.unwrap());
+    let load = host.functions[0]
+        .all_inst_iter()
+        .find(|inst| inst.result_id == call.operands[1].id_ref_any())
+        .unwrap();
+    assert_eq!(load.operands, [Operand::IdRef(p)]);
+
+    Verifier::verify(&host).unwrap();
+}
+
+#[test]
+fn rewrite_from_builder() {
+    let mut host = Module::from_assembly(HOST).unwrap().spirv().clone();
+    let uint_3 = constant(&host, 3);
+    let square = |builder: &mut Builder,
+                  sig: &RuntimeFunctionSignature|
+     -> Result<(), DynamicReplaceError> {
+        let (value, _) = sig.parameter[0];
+        let squared = builder.i_mul(sig.return_type, None, value, value)?;
+        builder.ret_value(squared)?;
+        Ok(())
+    };
+    let report = AssignmentRewrite::new_from_builder(
+        StoreSelector::Line {
+            file: "rewrite.comp".to_owned(),
+            line: 7,
+        },
+        square,
+    )
+    .with_argument(0, uint_3)
+    .run(&mut host)
+    .unwrap();
+    assert_eq!(report.stores, 1);
+    let function = host
+        .functions
+        .iter()
+        .find(|f| f.def_id() == Some(report.function))
+        .unwrap();
+    assert_eq!(function.parameters.len(), 1);
+    assert_ne!(stored_value(&host), constant(&host, 1));
+
+    Verifier::verify(&host).unwrap();
+}
+
+#[test]
+fn errors() {
+    let host = Module::from_assembly(HOST).unwrap().spirv().clone();
+    let late = host.get_by_name("late").unwrap().result_id.unwrap();
+    let uint_3 = constant(&host, 3);
+    let replacement = Module::from_assembly(REPLACEMENT).unwrap().spirv().clone();
+    let rewrite = |index: usize| {
+        AssignmentRewrite::new_from_module(
+            StoreSelector::Name("x".to_owned()),
+            replacement.clone(),
+            index,
+        )
+    };
+
+    let err = rewrite(0).with_argument(0, uint_3).run(&mut host.clone());
+    assert!(matches!(
+        err,
+        Err(AssignmentRewriteError::MissingArgument(1))
+    ));
+    let err = rewrite(0)
+        .with_argument(0, uint_3)
+        .with_argument(1, late)
+        .run(&mut host.clone());
+    assert!(matches!(
+        err,
+        Err(AssignmentRewriteError::OutOfScope { .. })
+    ));
+    //A mismatching signature leaves the module untouched
+    let mut module = host.clone();
+    let err = rewrite(1).run(&mut module);
+    assert!(matches!(
+        err,
+        Err(AssignmentRewriteError::ReturnTypeMismatch { .. })
+    ));
+    assert_eq!(module.assemble(), host.assemble());
+
+    let err = AssignmentRewrite::new_from_module(StoreSelector::Id(late), replacement.clone(), 0)
+        .run(&mut host.clone());
+    assert!(matches!(
+        err,
+        Err(AssignmentRewriteError::NotAVariable { op: Op::Load, .. })
+    ));
+
+    //Both variables are named "x"
+    let asm = HOST.replace(r#"OpName %p "p""#, r#"OpName %p "x""#);
+    let mut shared = Module::from_assembly(&asm).unwrap().spirv().clone();
+    let err = rewrite(0)
+        .with_argument(0, uint_3)
+        .with_argument(1, uint_3)
+        .run(&mut shared);
+    assert!(matches!(err, Err(AssignmentRewriteError::Ambiguous(name)) if name == "x"));
+}
+
+#[test]
+fn rewrite_imports_debug_info() {
+    let mut host = Module::from_assembly(HOST).unwrap().spirv().clone();
+    let replacement = Module::from_assembly(DEBUG_REPLACEMENT)
+        .unwrap()
+        .spirv()
+        .clone();
+    AssignmentRewrite::new_from_module(StoreSelector::Name("x".to_owned()), replacement, 0)
+        .run(&mut host)
+        .unwrap();
+
+    //The OpLine is kept and refers to an imported string, the printf is dropped
+    let strings = host
+        .debug_string_source
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::String)
+        .collect::<Vec<_>>();
+    let line = host.functions[1]
+        .all_inst_iter()
+        .find(|inst| inst.class.opcode == Op::Line)
+        .unwrap();
+    let file = strings
+        .iter()
+        .find(|inst| inst.result_id == line.operands[0].id_ref_any())
+        .unwrap();
+    assert_eq!(
+        file.operands,
+        [Operand::LiteralString("replacement.comp".to_owned())]
+    );
+    assert!(!host.functions[1]
+        .all_inst_iter()
+        .any(|inst| inst.class.opcode == Op::ExtInst));
+    assert!(host.ext_inst_imports.is_empty());
+
+    Verifier::verify(&host).unwrap();
+}
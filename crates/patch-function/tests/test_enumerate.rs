@@ -0,0 +1,112 @@
+use patch_function::{FuncDeclaration, FuncEnumerator};
+use spv_patcher::{rspirv::spirv::ExecutionModel, spirv_ext::SpirvExt, Module};
+
+//`add` is shared by both entry points, `ping` and `pong` call each other and are only reachable from `compute`.
+const SHADER: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint Fragment %frag "main"
+OpEntryPoint GLCompute %comp "compute"
+OpExecutionMode %frag OriginUpperLeft
+OpExecutionMode %comp LocalSize 1 1 1
+OpName %add "add"
+OpName %ping "ping"
+OpName %frag "frag"
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%float = OpTypeFloat 32
+%add_fn = OpTypeFunction %float %float %float
+%float_1 = OpConstant %float 1
+%add = OpFunction %float None %add_fn
+%a = OpFunctionParameter %float
+%b = OpFunctionParameter %float
+%l0 = OpLabel
+%sum = OpFAdd %float %a %b
+OpReturnValue %sum
+OpFunctionEnd
+%ping = OpFunction %void None %fn
+%l1 = OpLabel
+%c0 = OpFunctionCall %void %pong
+OpReturn
+OpFunctionEnd
+%pong = OpFunction %void None %fn
+%l2 = OpLabel
+%c1 = OpFunctionCall %void %ping
+OpReturn
+OpFunctionEnd
+%frag = OpFunction %void None %fn
+%l3 = OpLabel
+%c2 = OpFunctionCall %float %add %float_1 %float_1
+OpReturn
+OpFunctionEnd
+%comp = OpFunction %void None %fn
+%l4 = OpLabel
+%c3 = OpFunctionCall %float %add %float_1 %float_1
+%c4 = OpFunctionCall %void %ping
+OpReturn
+OpFunctionEnd
+"#;
+
+fn declaration(declarations: &[FuncDeclaration], function: u32) -> &FuncDeclaration {
+    declarations
+        .iter()
+        .find(|decl| decl.function_id == function)
+        .unwrap()
+}
+
+#[test]
+fn enumerate_declarations() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let spv = module.spirv();
+    let declarations = FuncEnumerator::enumerate(spv);
+    assert_eq!(declarations.len(), 5);
+    let [add, ping, pong, frag, comp] = [0, 1, 2, 3, 4].map(|i| spv.functions[i].def_id().unwrap());
+
+    let add_decl = declaration(&declarations, add);
+    assert_eq!(add_decl.debug_name.as_deref(), Some("add"));
+    let float = spv.functions[0].parameters[0].result_type.unwrap();
+    assert_eq!(add_decl.signature.return_type, float);
+    assert_eq!(
+        add_decl.signature.argument_types.as_slice(),
+        &[float, float]
+    );
+    assert_eq!(add_decl.callers.as_slice(), &[frag, comp]);
+    assert!(add_decl.callees.is_empty());
+    assert!(!add_decl.recursive);
+    assert_eq!(add_decl.entry_points.as_slice(), &[frag, comp]);
+
+    let pong_decl = declaration(&declarations, pong);
+    assert_eq!(pong_decl.debug_name, None);
+    assert!(pong_decl.recursive);
+    assert!(declaration(&declarations, ping).recursive);
+    assert_eq!(pong_decl.callers.as_slice(), &[ping]);
+    assert_eq!(pong_decl.callees.as_slice(), &[ping]);
+    assert_eq!(pong_decl.entry_points.as_slice(), &[comp]);
+
+    let comp_decl = declaration(&declarations, comp);
+    assert_eq!(comp_decl.callees.as_slice(), &[add, ping]);
+    assert!(comp_decl.callers.is_empty());
+    assert!(!comp_decl.recursive);
+    assert_eq!(comp_decl.entry_points.as_slice(), &[comp]);
+    assert_eq!(spv.get_by_name("frag").unwrap().result_id.unwrap(), frag);
+}
+
+#[test]
+fn affected_entry_points() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let spv = module.spirv();
+    let declarations = FuncEnumerator::enumerate(spv);
+    let fragment = module
+        .entry_point("main", Some(ExecutionModel::Fragment))
+        .unwrap();
+    let compute = module.entry_point("compute", None).unwrap();
+
+    let add = spv.get_by_name("add").unwrap().result_id.unwrap();
+    let add = declaration(&declarations, add);
+    assert!(add.affects(fragment));
+    assert!(add.affects(compute));
+    let ping = spv.get_by_name("ping").unwrap().result_id.unwrap();
+    let ping = declaration(&declarations, ping);
+    assert!(!ping.affects(fragment));
+    assert!(ping.affects(compute));
+}
@@ -0,0 +1,750 @@
+//! # Loop guard
+//!
+//! Limits the number of iterations of selected loops, so that a kernel stuck in an infinite loop terminates instead of
+//! hanging the GPU. Optionally, a flag in a debug storage buffer is set whenever a guard triggered, which makes the hang
+//! visible to the host.
+//!
+//! ## Implementation details
+//!
+//! Only structured loops (headers with an `OpLoopMerge`) are guarded. For each of them:
+//!
+//! - An `OpPhi` counter is added to the header. It starts at 0 when the loop is entered and is incremented in each
+//!   block with a back edge (latch).
+//! - Each latch exits to the loop's merge block once the counter reaches the limit. An `OpBranch` to the header becomes
+//!   an `OpBranchConditional`, a conditional branch between header and merge block gets its condition extended. Loops
+//!   with latches that branch anywhere else are skipped. `OpPhi`s of the merge block get an `OpUndef` for new edges.
+//! - If a [DebugFlag] is requested, the loop exits through a new merge block, which branches to the old one. It
+//!   `OpAtomicOr`s 1 into the flag if the loop exited since the limit was reached, so the atomic runs at most once per
+//!   loop exit instead of in every iteration. `OpPhi`s of the old merge block receive their values from the loop through
+//!   the new block.
+//!
+//! The loop body runs at most `max_iterations` times.
+
+#![deny(warnings)]
+
+use std::fmt::Display;
+
+use ahash::AHashSet;
+use spv_patcher::{
+    analysis::{Cfg, DominatorTree, LoopForest},
+    patch::{Patch, Patcher},
+    rspirv::{
+        dr::{Block, Instruction, Module, Operand},
+        spirv::{Capability, Decoration, Op, SelectionControl, StorageClass, Word},
+    },
+    spirv_ext::{ConstantValue, LineMatcher, SpirvExt, TypeDecoration},
+    PatcherError,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum LoopGuardError {
+    #[error("No loop matches {0:?}")]
+    NoMatch(LoopSelector),
+    #[error("Loops need to be allowed at least one iteration")]
+    ZeroIterations,
+    #[error("Descriptor set {descriptor_set} binding {binding} is already in use")]
+    BindingInUse { descriptor_set: u32, binding: u32 },
+    #[error("Debug flags need storage buffers, which are only available to shaders")]
+    FlagRequiresShader,
+}
+
+///Selects the loops a [LoopGuard] guards.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoopSelector {
+    ///All loops of the module.
+    All,
+    ///All loops of the function with this id.
+    Function(Word),
+    ///The loops whose header block carries an `OpLine` with this line, in a file whose name ends with `file`.
+    Line { file: String, line: u32 },
+}
+
+///Storage buffer binding of the flag a [LoopGuard] sets. The buffer holds a single `uint`, which is set to 1 once any
+/// guard triggered. The host is responsible for clearing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebugFlag {
+    pub descriptor_set: u32,
+    pub binding: u32,
+}
+
+///Identifies a loop by its function and header block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LoopId {
+    pub function: Word,
+    ///Label of the loop's header block.
+    pub header: Word,
+}
+
+///Loops a [LoopGuard] run touched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GuardReport {
+    pub guarded: Vec<LoopId>,
+    ///Selected loops that could not be guarded, for instance since they are unstructured.
+    pub skipped: Vec<LoopId>,
+    ///Variable of the debug flag, if one was requested.
+    pub flag: Option<Word>,
+}
+
+impl Display for GuardReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "guarded {} loops, skipped {}",
+            self.guarded.len(),
+            self.skipped.len()
+        )
+    }
+}
+
+///Breaks out of all loops selected by `selector` after `max_iterations` iterations.
+#[derive(Debug, Clone)]
+pub struct LoopGuard {
+    pub selector: LoopSelector,
+    pub max_iterations: u32,
+    pub flag: Option<DebugFlag>,
+}
+
+//A selected loop. Blocks are given by their labels, since guards insert blocks.
+struct Selected {
+    function: usize,
+    id: LoopId,
+    //None for unstructured loops
+    merge: Option<Word>,
+    latches: Vec<Word>,
+}
+
+//Ids shared by all guards.
+struct Shared {
+    uint: Word,
+    bool_ty: Word,
+    zero: Word,
+    one: Word,
+    max: Word,
+    //Variable and member pointer type of the debug flag
+    flag: Option<(Word, Word)>,
+}
+
+impl LoopGuard {
+    pub fn new(selector: LoopSelector, max_iterations: u32) -> Self {
+        LoopGuard {
+            selector,
+            max_iterations,
+            flag: None,
+        }
+    }
+
+    ///Sets a flag in the storage buffer at `descriptor_set` and `binding` if any guard triggered.
+    pub fn with_flag(mut self, descriptor_set: u32, binding: u32) -> Self {
+        self.flag = Some(DebugFlag {
+            descriptor_set,
+            binding,
+        });
+        self
+    }
+
+    ///Guards the selected loops of `module`.
+    pub fn run(&self, module: &mut Module) -> Result<GuardReport, LoopGuardError> {
+        if self.max_iterations == 0 {
+            return Err(LoopGuardError::ZeroIterations);
+        }
+        let selected = self.select(module);
+        if selected.is_empty() {
+            return Err(LoopGuardError::NoMatch(self.selector.clone()));
+        }
+
+        let mut report = GuardReport::default();
+        let (guardable, skipped): (Vec<_>, Vec<_>) = selected
+            .into_iter()
+            .partition(|selected| is_guardable(module, selected));
+        for selected in &skipped {
+            log::warn!(
+                "Can not guard loop %{} in function %{}, it has unsupported back edges",
+                selected.id.header,
+                selected.id.function
+            );
+        }
+        report.skipped = skipped.into_iter().map(|selected| selected.id).collect();
+        if guardable.is_empty() {
+            return Ok(report);
+        }
+
+        let shared = self.declare(module)?;
+        report.flag = shared.flag.map(|(variable, _)| variable);
+        let mut counters = AHashSet::default();
+        for selected in guardable {
+            guard(module, &selected, &shared, &mut counters);
+            report.guarded.push(selected.id);
+        }
+        Ok(report)
+    }
+
+    fn select(&self, module: &Module) -> Vec<Selected> {
+        let lines = match &self.selector {
+            LoopSelector::Line { file, line } => Some(LineMatcher::new(module, file, *line)),
+            _ => None,
+        };
+
+        let mut selected = Vec::new();
+        for (fidx, function) in module.functions.iter().enumerate() {
+            let Some(function_id) = function.def.as_ref().and_then(|def| def.result_id) else {
+                continue;
+            };
+            if let LoopSelector::Function(id) = self.selector {
+                if id != function_id {
+                    continue;
+                }
+            }
+
+            let cfg = Cfg::new(function);
+            let dominators = DominatorTree::dominators(&cfg);
+            let forest = LoopForest::new(&cfg, &dominators);
+            for lp in forest.loops() {
+                if let Some(lines) = &lines {
+                    let has_line = function.blocks[lp.header]
+                        .instructions
+                        .iter()
+                        .any(|inst| lines.matches(inst));
+                    if !has_line {
+                        continue;
+                    }
+                }
+                selected.push(Selected {
+                    function: fidx,
+                    id: LoopId {
+                        function: function_id,
+                        header: cfg.label(lp.header),
+                    },
+                    //Unstructured loops are skipped as unguardable
+                    merge: lp.merge.map(|merge| cfg.label(merge)),
+                    latches: lp.latches.iter().map(|latch| cfg.label(*latch)).collect(),
+                });
+            }
+        }
+        selected
+    }
+
+    //Declares the constants, and the debug flag if requested.
+    fn declare(&self, module: &mut Module) -> Result<Shared, LoopGuardError> {
+        let uint = module.find_or_insert_type(
+            Op::TypeInt,
+            &[Operand::LiteralBit32(32), Operand::LiteralBit32(0)],
+            &[],
+        );
+        let bool_ty = module.find_or_insert_type(Op::TypeBool, &[], &[]);
+        let zero = module.find_or_insert_constant(ConstantValue::U32(0));
+        let one = module.find_or_insert_constant(ConstantValue::U32(1));
+        let max = module.find_or_insert_constant(ConstantValue::U32(self.max_iterations));
+        let flag = match self.flag {
+            Some(flag) => Some(declare_flag(module, flag, uint)?),
+            None => None,
+        };
+        Ok(Shared {
+            uint,
+            bool_ty,
+            zero,
+            one,
+            max,
+            flag,
+        })
+    }
+}
+
+//True if all latches of `selected` either branch to the header, or branch between header and merge block.
+fn is_guardable(module: &Module, selected: &Selected) -> bool {
+    let Some(merge) = selected.merge else {
+        return false;
+    };
+    let function = &module.functions[selected.function];
+    let (header, merge) = (Some(selected.id.header), Some(merge));
+    selected.latches.iter().all(|latch| {
+        let Some(terminator) = function
+            .blocks
+            .iter()
+            .find(|block| block.label_id() == Some(*latch))
+            .and_then(|block| block.instructions.last())
+        else {
+            return false;
+        };
+        let target = |idx: usize| terminator.operands.get(idx).and_then(|op| op.id_ref_any());
+        match terminator.class.opcode {
+            Op::Branch => target(0) == header,
+            Op::BranchConditional => {
+                (target(1) == header && target(2) == merge)
+                    || (target(1) == merge && target(2) == header)
+            }
+            _ => false,
+        }
+    })
+}
+
+//Declares the storage buffer of the debug flag. Returns the variable and the pointer type of its member.
+fn declare_flag(
+    module: &mut Module,
+    flag: DebugFlag,
+    uint: Word,
+) -> Result<(Word, Word), LoopGuardError> {
+    let is_shader = module
+        .capabilities
+        .iter()
+        .any(|inst| inst.operands.first() == Some(&Operand::Capability(Capability::Shader)));
+    if !is_shader {
+        return Err(LoopGuardError::FlagRequiresShader);
+    }
+    let decorated = |decoration: Decoration, value: u32| {
+        module
+            .annotations
+            .iter()
+            .filter(|inst| {
+                inst.class.opcode == Op::Decorate
+                    && inst.operands.get(1) == Some(&Operand::Decoration(decoration))
+                    && inst.operands.get(2) == Some(&Operand::LiteralBit32(value))
+            })
+            .filter_map(|inst| inst.operands.first().and_then(|op| op.id_ref_any()))
+            .collect::<AHashSet<_>>()
+    };
+    let in_set = decorated(Decoration::DescriptorSet, flag.descriptor_set);
+    if decorated(Decoration::Binding, flag.binding)
+        .iter()
+        .any(|id| in_set.contains(id))
+    {
+        return Err(LoopGuardError::BindingInUse {
+            descriptor_set: flag.descriptor_set,
+            binding: flag.binding,
+        });
+    }
+
+    let version = module
+        .header
+        .as_ref()
+        .map(|header| header.version())
+        .unwrap_or((1, 0));
+    if version < (1, 3) && !module.has_extension("SPV_KHR_storage_buffer_storage_class") {
+        module.extensions.push(Instruction::new(
+            Op::Extension,
+            None,
+            None,
+            vec![Operand::LiteralString(String::from(
+                "SPV_KHR_storage_buffer_storage_class",
+            ))],
+        ));
+    }
+
+    let block = module.find_or_insert_type(
+        Op::TypeStruct,
+        &[Operand::IdRef(uint)],
+        &[
+            TypeDecoration::new(Decoration::Block, Vec::new()),
+            TypeDecoration::member(0, Decoration::Offset, vec![Operand::LiteralBit32(0)]),
+        ],
+    );
+    let block_ptr = module.find_or_insert_type(
+        Op::TypePointer,
+        &[
+            Operand::StorageClass(StorageClass::StorageBuffer),
+            Operand::IdRef(block),
+        ],
+        &[],
+    );
+    let member_ptr = module.find_or_insert_type(
+        Op::TypePointer,
+        &[
+            Operand::StorageClass(StorageClass::StorageBuffer),
+            Operand::IdRef(uint),
+        ],
+        &[],
+    );
+
+    let variable = module.allocate_id();
+    module.types_global_values.push(Instruction::new(
+        Op::Variable,
+        Some(block_ptr),
+        Some(variable),
+        vec![Operand::StorageClass(StorageClass::StorageBuffer)],
+    ));
+    module.debug_names.push(Instruction::new(
+        Op::Name,
+        None,
+        None,
+        vec![
+            Operand::IdRef(variable),
+            Operand::LiteralString(String::from("loop_guard_flag")),
+        ],
+    ));
+    for (decoration, value) in [
+        (Decoration::DescriptorSet, flag.descriptor_set),
+        (Decoration::Binding, flag.binding),
+    ] {
+        module.annotations.push(Instruction::new(
+            Op::Decorate,
+            None,
+            None,
+            vec![
+                Operand::IdRef(variable),
+                Operand::Decoration(decoration),
+                Operand::LiteralBit32(value),
+            ],
+        ));
+    }
+    //Since SPIR-V 1.4 the interface lists all global variables an entry point uses
+    if version >= (1, 4) {
+        for entry_point in module.entry_points.iter_mut() {
+            entry_point.operands.push(Operand::IdRef(variable));
+        }
+    }
+    Ok((variable, member_ptr))
+}
+
+//Adds the counter to a guardable loop. `counters` are the counters of loops guarded so far.
+fn guard(module: &mut Module, selected: &Selected, shared: &Shared, counters: &mut AHashSet<Word>) {
+    let cfg = Cfg::new(&module.functions[selected.function]);
+    let header = selected.id.header;
+    let merge = selected.merge.unwrap();
+    let block_of = |label: Word| cfg.block_of(label).unwrap();
+    //With a debug flag, the loop exits through a new merge block that sets the flag
+    let exit = match shared.flag {
+        Some(_) => {
+            let dominators = DominatorTree::dominators(&cfg);
+            let merge = block_of(merge);
+            let breaks = cfg
+                .predecessors(merge)
+                .iter()
+                .filter(|pred| !dominators.dominates(merge, **pred))
+                .map(|pred| cfg.label(*pred))
+                .collect::<Vec<_>>();
+            Some((module.allocate_id(), breaks))
+        }
+        None => None,
+    };
+    let exit_target = exit.as_ref().map(|(exit, _)| *exit).unwrap_or(merge);
+
+    let counter = module.allocate_id();
+    counters.insert(counter);
+    let mut incoming = Vec::new();
+    let mut new_exit_edges = Vec::new();
+    //Whether the guard triggered, for each latch
+    let mut triggered = Vec::new();
+    for pred in cfg.predecessors(block_of(header)) {
+        if !selected.latches.contains(&cfg.label(*pred)) {
+            incoming.push(Operand::IdRef(shared.zero));
+            incoming.push(Operand::IdRef(cfg.label(*pred)));
+        }
+    }
+
+    for latch in selected.latches.iter().copied() {
+        let next = module.allocate_id();
+        let over = module.allocate_id();
+        incoming.push(Operand::IdRef(next));
+        incoming.push(Operand::IdRef(latch));
+        triggered.push((over, latch));
+
+        let mut check = vec![
+            Instruction::new(
+                Op::IAdd,
+                Some(shared.uint),
+                Some(next),
+                vec![Operand::IdRef(counter), Operand::IdRef(shared.one)],
+            ),
+            Instruction::new(
+                Op::UGreaterThanEqual,
+                Some(shared.bool_ty),
+                Some(over),
+                vec![Operand::IdRef(next), Operand::IdRef(shared.max)],
+            ),
+        ];
+
+        let block = &mut module.functions[selected.function].blocks[block_of(latch)];
+        let terminator = block.instructions.pop().unwrap();
+        //Exit condition of the rewritten branch
+        let condition = if terminator.class.opcode == Op::Branch {
+            new_exit_edges.push(latch);
+            over
+        } else {
+            let condition = terminator.operands[0].clone();
+            let exit = module.allocate_id();
+            if terminator.operands[1] == Operand::IdRef(merge) {
+                check.push(Instruction::new(
+                    Op::LogicalOr,
+                    Some(shared.bool_ty),
+                    Some(exit),
+                    vec![condition, Operand::IdRef(over)],
+                ));
+            } else {
+                let inverted = module.allocate_id();
+                check.push(Instruction::new(
+                    Op::LogicalNot,
+                    Some(shared.bool_ty),
+                    Some(inverted),
+                    vec![condition],
+                ));
+                check.push(Instruction::new(
+                    Op::LogicalOr,
+                    Some(shared.bool_ty),
+                    Some(exit),
+                    vec![Operand::IdRef(inverted), Operand::IdRef(over)],
+                ));
+            }
+            exit
+        };
+
+        let block = &mut module.functions[selected.function].blocks[block_of(latch)];
+        //A header that is its own latch ends with its merge instruction, which has to stay in front of the branch
+        let at = match block.instructions.last() {
+            Some(inst) if matches!(inst.class.opcode, Op::LoopMerge | Op::SelectionMerge) => {
+                block.instructions.len() - 1
+            }
+            _ => block.instructions.len(),
+        };
+        block.instructions.splice(at..at, check);
+        block.instructions.push(Instruction::new(
+            Op::BranchConditional,
+            None,
+            None,
+            vec![
+                Operand::IdRef(condition),
+                Operand::IdRef(exit_target),
+                Operand::IdRef(header),
+            ],
+        ));
+    }
+
+    module.functions[selected.function].blocks[block_of(header)]
+        .instructions
+        .insert(
+            0,
+            Instruction::new(Op::Phi, Some(shared.uint), Some(counter), incoming),
+        );
+
+    if let Some((exit, breaks)) = exit {
+        flag_exit(
+            module, selected, shared, counters, exit, &breaks, &triggered,
+        );
+        return;
+    }
+    if new_exit_edges.is_empty() {
+        return;
+    }
+    let phis = module.functions[selected.function].blocks[block_of(merge)]
+        .instructions
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::Phi)
+        .map(|inst| (inst.result_id, inst.result_type))
+        .collect::<Vec<_>>();
+    let undefs = phis
+        .into_iter()
+        .map(|phi| new_edge_value(module, shared, counters, phi))
+        .collect::<Vec<_>>();
+    let phis = module.functions[selected.function].blocks[block_of(merge)]
+        .instructions
+        .iter_mut()
+        .filter(|inst| inst.class.opcode == Op::Phi);
+    for (phi, undef) in phis.zip(undefs) {
+        for latch in &new_exit_edges {
+            phi.operands.push(Operand::IdRef(undef));
+            phi.operands.push(Operand::IdRef(*latch));
+        }
+    }
+}
+
+//Value of an `OpPhi` with the given result id and type on a new edge from a latch. The merge block might be the header
+// of a guarded loop, whose counter has to start at 0.
+fn new_edge_value(
+    module: &mut Module,
+    shared: &Shared,
+    counters: &AHashSet<Word>,
+    phi: (Option<Word>, Option<Word>),
+) -> Word {
+    match phi {
+        (Some(id), _) if counters.contains(&id) => shared.zero,
+        (_, Some(ty)) => module.find_or_insert_undef(ty),
+        _ => shared.zero,
+    }
+}
+
+//Inserts `exit` as the new merge block of a loop whose latches already branch to it. `breaks` are the other blocks
+// that branched to the old merge block, and `triggered` holds whether the guard triggered for each latch. `exit` sets
+// the debug flag only if a guard triggered, and then branches to the old merge block.
+fn flag_exit(
+    module: &mut Module,
+    selected: &Selected,
+    shared: &Shared,
+    counters: &AHashSet<Word>,
+    exit: Word,
+    breaks: &[Word],
+    triggered: &[(Word, Word)],
+) {
+    let (variable, member_ptr) = shared.flag.unwrap();
+    let merge = selected.merge.unwrap();
+    let no = module.find_or_insert_constant(ConstantValue::Bool(false));
+    let (set_flag, any, member, old) = (
+        module.allocate_id(),
+        module.allocate_id(),
+        module.allocate_id(),
+        module.allocate_id(),
+    );
+
+    //Breaks and the loop's merge instruction target the new block
+    for block in module.functions[selected.function].blocks.iter_mut() {
+        let Some(label) = block.label_id() else {
+            continue;
+        };
+        if label == selected.id.header {
+            for inst in block.instructions.iter_mut() {
+                if inst.class.opcode == Op::LoopMerge {
+                    inst.operands[0] = Operand::IdRef(exit);
+                }
+            }
+        }
+        if breaks.contains(&label) {
+            if let Some(terminator) = block.instructions.last_mut() {
+                for operand in terminator.operands.iter_mut() {
+                    if *operand == Operand::IdRef(merge) {
+                        *operand = Operand::IdRef(exit);
+                    }
+                }
+            }
+        }
+    }
+
+    //Values of the old merge block's phis coming from the loop move to the new block
+    let at = module.functions[selected.function]
+        .blocks
+        .iter()
+        .position(|block| block.label_id() == Some(merge))
+        .unwrap();
+    let phis = module.functions[selected.function].blocks[at]
+        .instructions
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::Phi)
+        .map(|inst| (inst.result_id, inst.result_type, inst.operands.clone()))
+        .collect::<Vec<_>>();
+    let mut exit_instructions = vec![Instruction::new(
+        Op::Phi,
+        Some(shared.bool_ty),
+        Some(any),
+        triggered
+            .iter()
+            .copied()
+            .chain(
+                breaks
+                    .iter()
+                    .filter(|pred| triggered.iter().all(|(_, latch)| latch != *pred))
+                    .map(|pred| (no, *pred)),
+            )
+            .flat_map(|(value, pred)| [Operand::IdRef(value), Operand::IdRef(pred)])
+            .collect(),
+    )];
+    let mut kept = Vec::with_capacity(phis.len());
+    for (id, ty, operands) in phis {
+        let (from_loop, others): (Vec<_>, Vec<_>) = operands.chunks(2).partition(|pair| {
+            pair[1]
+                .id_ref_any()
+                .map(|pred| breaks.contains(&pred))
+                .unwrap_or(false)
+        });
+        let mut moved = from_loop.concat();
+        //Latches that only branched to the header before
+        let new_edges = triggered
+            .iter()
+            .filter(|(_, latch)| {
+                from_loop
+                    .iter()
+                    .all(|pair| pair[1] != Operand::IdRef(*latch))
+            })
+            .map(|(_, latch)| *latch)
+            .collect::<Vec<_>>();
+        if !new_edges.is_empty() {
+            let value = new_edge_value(module, shared, counters, (id, ty));
+            for latch in new_edges {
+                moved.push(Operand::IdRef(value));
+                moved.push(Operand::IdRef(latch));
+            }
+        }
+        let new = module.allocate_id();
+        exit_instructions.push(Instruction::new(Op::Phi, ty, Some(new), moved));
+        let mut operands = others.concat();
+        operands.extend([
+            Operand::IdRef(new),
+            Operand::IdRef(exit),
+            Operand::IdRef(new),
+            Operand::IdRef(set_flag),
+        ]);
+        kept.push(operands);
+    }
+    let old_phis = module.functions[selected.function].blocks[at]
+        .instructions
+        .iter_mut()
+        .filter(|inst| inst.class.opcode == Op::Phi);
+    for (phi, operands) in old_phis.zip(kept) {
+        phi.operands = operands;
+    }
+
+    exit_instructions.extend([
+        Instruction::new(
+            Op::SelectionMerge,
+            None,
+            None,
+            vec![
+                Operand::IdRef(merge),
+                Operand::SelectionControl(SelectionControl::NONE),
+            ],
+        ),
+        Instruction::new(
+            Op::BranchConditional,
+            None,
+            None,
+            vec![
+                Operand::IdRef(any),
+                Operand::IdRef(set_flag),
+                Operand::IdRef(merge),
+            ],
+        ),
+    ]);
+    let set_flag = Block {
+        label: Some(Instruction::new(
+            Op::Label,
+            None,
+            Some(set_flag),
+            Vec::new(),
+        )),
+        instructions: vec![
+            Instruction::new(
+                Op::AccessChain,
+                Some(member_ptr),
+                Some(member),
+                vec![Operand::IdRef(variable), Operand::IdRef(shared.zero)],
+            ),
+            //Device scope and relaxed semantics, which happen to be the constants 1 and 0
+            Instruction::new(
+                Op::AtomicOr,
+                Some(shared.uint),
+                Some(old),
+                vec![
+                    Operand::IdRef(member),
+                    Operand::IdScope(shared.one),
+                    Operand::IdMemorySemantics(shared.zero),
+                    Operand::IdRef(shared.one),
+                ],
+            ),
+            Instruction::new(Op::Branch, None, None, vec![Operand::IdRef(merge)]),
+        ],
+    };
+    let exit = Block {
+        label: Some(Instruction::new(Op::Label, None, Some(exit), Vec::new())),
+        instructions: exit_instructions,
+    };
+    module.functions[selected.function]
+        .blocks
+        .splice(at..at, [exit, set_flag]);
+}
+
+impl Patch for LoopGuard {
+    fn apply<'a>(self, mut patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError> {
+        let report = self
+            .run(patcher.ir_state.as_spirv())
+            .map_err(|e| PatcherError::Internal(e.into()))?;
+        log::info!("Loop guard: {}", report);
+        patcher.push_report(report);
+        Ok(patcher)
+    }
+}
@@ -0,0 +1,239 @@
+use patch_loop_guard::{LoopGuard, LoopGuardError, LoopSelector};
+use spv_patcher::{
+    rspirv::{
+        dr::{Module as SpvModule, Operand},
+        spirv::{Decoration, Op, Word},
+    },
+    verify::Verifier,
+    Module,
+};
+
+//A counting loop whose merge block is the header of a do-while loop, which is its own continue target
+const SHADER: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+%file = OpString "shaders/loops.comp"
+OpName %a_header "a_header"
+OpName %a_continue "a_continue"
+OpName %b_header "b_header"
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%bool = OpTypeBool
+%uint_0 = OpConstant %uint 0
+%uint_1 = OpConstant %uint 1
+%uint_8 = OpConstant %uint 8
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpBranch %a_header
+%a_header = OpLabel
+%i = OpPhi %uint %uint_0 %entry %i_next %a_continue
+%cond = OpULessThan %bool %i %uint_8
+OpLine %file 4 0
+OpLoopMerge %b_header %a_continue None
+OpBranchConditional %cond %a_body %b_header
+%a_body = OpLabel
+OpBranch %a_continue
+%a_continue = OpLabel
+%i_next = OpIAdd %uint %i %uint_1
+OpBranch %a_header
+%b_header = OpLabel
+%j = OpPhi %uint %i %a_header %j_next %b_header
+%j_next = OpIAdd %uint %j %uint_1
+%again = OpINotEqual %bool %j_next %uint_0
+OpLoopMerge %end %b_header None
+OpBranchConditional %again %b_header %end
+%end = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+fn named(spv: &SpvModule, name: &str) -> Word {
+    spv.debug_names
+        .iter()
+        .find(|inst| inst.operands.get(1) == Some(&Operand::LiteralString(name.to_owned())))
+        .and_then(|inst| inst.operands[0].id_ref_any())
+        .unwrap()
+}
+
+fn count(spv: &SpvModule, op: Op) -> usize {
+    spv.all_inst_iter()
+        .filter(|inst| inst.class.opcode == op)
+        .count()
+}
+
+fn block<'a>(spv: &'a SpvModule, label: Word) -> &'a spv_patcher::rspirv::dr::Block {
+    spv.functions[0]
+        .blocks
+        .iter()
+        .find(|block| block.label_id() == Some(label))
+        .unwrap()
+}
+
+#[test]
+fn guard_all_loops() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let report = LoopGuard::new(LoopSelector::All, 64).run(&mut spv).unwrap();
+    assert_eq!(report.guarded.len(), 2);
+    assert!(report.skipped.is_empty());
+    assert_eq!(report.flag, None);
+    assert_eq!(count(&spv, Op::UGreaterThanEqual), 2);
+    assert_eq!(count(&spv, Op::Phi), 4);
+
+    let (a_header, a_continue, b_header) = (
+        named(&spv, "a_header"),
+        named(&spv, "a_continue"),
+        named(&spv, "b_header"),
+    );
+    //The latch of the first loop now exits to its merge block
+    let terminator = block(&spv, a_continue).instructions.last().unwrap();
+    assert_eq!(terminator.class.opcode, Op::BranchConditional);
+    assert_eq!(
+        terminator.operands[1..],
+        [Operand::IdRef(b_header), Operand::IdRef(a_header)]
+    );
+
+    //Both counters start at 0 when entering their loop, including the new edge from the first loop's latch
+    let uint_0 = named_constant(&spv, 0);
+    let b_phis = block(&spv, b_header)
+        .instructions
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::Phi)
+        .collect::<Vec<_>>();
+    assert_eq!(b_phis.len(), 2);
+    assert!(b_phis.iter().all(|phi| phi.operands.len() == 6));
+    let counter = b_phis
+        .iter()
+        .find(|phi| {
+            phi.operands
+                .chunks(2)
+                .filter(|pair| pair[1] != Operand::IdRef(b_header))
+                .all(|pair| pair[0] == Operand::IdRef(uint_0))
+        })
+        .unwrap();
+    assert!(counter.operands.contains(&Operand::IdRef(a_continue)));
+
+    Verifier::verify(&spv).unwrap();
+}
+
+fn named_constant(spv: &SpvModule, value: u32) -> Word {
+    spv.types_global_values
+        .iter()
+        .find(|inst| {
+            inst.class.opcode == Op::Constant && inst.operands == [Operand::LiteralBit32(value)]
+        })
+        .and_then(|inst| inst.result_id)
+        .unwrap()
+}
+
+#[test]
+fn debug_flag() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let guard = LoopGuard::new(LoopSelector::All, 64).with_flag(0, 3);
+    let report = guard.run(&mut spv).unwrap();
+    let flag = report.flag.unwrap();
+    assert_eq!(count(&spv, Op::AtomicOr), 2);
+    let atomic = spv
+        .all_inst_iter()
+        .find(|inst| inst.class.opcode == Op::AtomicOr)
+        .unwrap();
+    assert!(matches!(
+        atomic.operands[1..3],
+        [Operand::IdScope(_), Operand::IdMemorySemantics(_)]
+    ));
+    //The do-while condition is inverted and combined with the limit
+    assert_eq!(count(&spv, Op::LogicalNot), 1);
+    assert_eq!(count(&spv, Op::LogicalOr), 1);
+    let binding = spv.annotations.iter().any(|inst| {
+        inst.operands
+            == [
+                Operand::IdRef(flag),
+                Operand::Decoration(Decoration::Binding),
+                Operand::LiteralBit32(3),
+            ]
+    });
+    assert!(binding);
+
+    //The flag is set on the exit path only, the latches and loop bodies stay free of atomics
+    let has_atomic = |label: Word| {
+        block(&spv, label)
+            .instructions
+            .iter()
+            .any(|inst| inst.class.opcode == Op::AtomicOr)
+    };
+    let (a_header, a_continue, b_header) = (
+        named(&spv, "a_header"),
+        named(&spv, "a_continue"),
+        named(&spv, "b_header"),
+    );
+    assert!(!has_atomic(a_header) && !has_atomic(a_continue) && !has_atomic(b_header));
+    //The first loop exits through a new merge block, which branches to the old one directly or after setting the flag
+    let loop_merge = block(&spv, a_header)
+        .instructions
+        .iter()
+        .find(|inst| inst.class.opcode == Op::LoopMerge)
+        .unwrap();
+    let exit = loop_merge.operands[0].unwrap_id_ref();
+    assert_ne!(exit, b_header);
+    let terminator = block(&spv, exit).instructions.last().unwrap();
+    assert_eq!(terminator.class.opcode, Op::BranchConditional);
+    assert_eq!(terminator.operands[2], Operand::IdRef(b_header));
+    assert!(has_atomic(terminator.operands[1].unwrap_id_ref()));
+    //The do-while's phis receive the values of the first loop through both new blocks
+    let b_phis = block(&spv, b_header)
+        .instructions
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::Phi)
+        .collect::<Vec<_>>();
+    assert_eq!(b_phis.len(), 2);
+    assert!(b_phis
+        .iter()
+        .all(|phi| phi.operands.len() == 6 && phi.operands.contains(&Operand::IdRef(exit))));
+    Verifier::verify(&spv).unwrap();
+
+    assert!(matches!(
+        guard.run(&mut spv),
+        Err(LoopGuardError::BindingInUse {
+            descriptor_set: 0,
+            binding: 3
+        })
+    ));
+}
+
+#[test]
+fn select_by_line() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let report = LoopGuard::new(
+        LoopSelector::Line {
+            file: "loops.comp".to_owned(),
+            line: 4,
+        },
+        64,
+    )
+    .run(&mut spv)
+    .unwrap();
+    assert_eq!(report.guarded.len(), 1);
+    assert_eq!(report.guarded[0].header, named(&spv, "a_header"));
+    Verifier::verify(&spv).unwrap();
+
+    let missing = LoopGuard::new(
+        LoopSelector::Line {
+            file: "loops.comp".to_owned(),
+            line: 5,
+        },
+        64,
+    );
+    assert!(matches!(
+        missing.run(&mut spv),
+        Err(LoopGuardError::NoMatch(_))
+    ));
+    assert!(matches!(
+        LoopGuard::new(LoopSelector::All, 0).run(&mut spv),
+        Err(LoopGuardError::ZeroIterations)
+    ));
+}
@@ -0,0 +1,402 @@
+//! # Specialization
+//!
+//! Patches that move values between specialization constants and regular constants.
+//!
+//! [FreezeSpecialization] bakes `VkSpecializationInfo`-style values into a module, so that drivers never see the
+//! specialization constants. [PromoteToSpecialization] goes the other way and makes compiled-in constants adjustable
+//! at pipeline creation time.
+//!
+//! ## Implementation details
+//!
+//! Freezing walks the global declarations in order. Spec constants with a provided value become `OpConstant*`s with
+//! the same id. `OpSpecConstantComposite`s and `OpSpecConstantOp`s whose operands are constant by then are evaluated
+//! with the [ConstEvaluator] and replaced as well. Constituents of composite results are reused if declared already,
+//! or declared right before the composite otherwise.
+//!
+//! Afterwards `SpecId` decorations of frozen constants are removed, `LocalSizeId` execution modes with constant sizes
+//! are rewritten to `LocalSize`, and `LocalSize` modes are updated to match a frozen `WorkgroupSize` builtin.
+
+#![deny(warnings)]
+
+use std::fmt::Display;
+
+use ahash::{AHashMap, AHashSet};
+use spv_patcher::{
+    analysis::{ConstEvaluator, ConstValue},
+    patch::{ConstantSelector, MutateConstantError, Patch, Patcher},
+    rspirv::{
+        dr::{Instruction, Module, Operand},
+        spirv::{BuiltIn, Decoration, ExecutionMode, Op, Word},
+    },
+    spirv_ext::SpirvExt,
+    PatcherError,
+};
+use thiserror::Error;
+
+mod promote;
+pub use promote::{PromoteToSpecialization, PromotedConstant};
+
+#[derive(Error, Debug)]
+pub enum SpecializationError {
+    #[error("Value {value:?} does not fit specialization constant {spec_id} (%{id})")]
+    TypeMismatch {
+        spec_id: u32,
+        id: Word,
+        value: SpecValue,
+    },
+    #[error("Could not select constants: {0}")]
+    Select(#[from] MutateConstantError),
+    #[error("No constant matches {0:?}")]
+    NoMatch(ConstantSelector),
+    #[error("%{id} is a {op:?}, only scalar constants can be promoted")]
+    NotPromotable { id: Word, op: Op },
+    #[error("%{id} can not be promoted, %{user} needs it to be a constant")]
+    RequiresConstant { id: Word, user: Word },
+    #[error("%{id} can not be promoted, {op:?} uses it as scope or memory semantics")]
+    ScopeOperand { id: Word, op: Op },
+    #[error("SpecId {0} is already in use")]
+    SpecIdInUse(u32),
+}
+
+///Value of a single specialization constant, given like the data of a `VkSpecializationMapEntry`. The type of the
+/// constant decides how the bits are interpreted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpecValue {
+    Bool(bool),
+    ///Bits of a value of at most 32 bit. For boolean constants, any non-zero value is `true`.
+    Bits32(u32),
+    ///Bits of a 64 bit value.
+    Bits64(u64),
+}
+
+impl From<bool> for SpecValue {
+    fn from(value: bool) -> Self {
+        SpecValue::Bool(value)
+    }
+}
+
+impl From<u32> for SpecValue {
+    fn from(value: u32) -> Self {
+        SpecValue::Bits32(value)
+    }
+}
+
+impl From<i32> for SpecValue {
+    fn from(value: i32) -> Self {
+        SpecValue::Bits32(value as u32)
+    }
+}
+
+impl From<f32> for SpecValue {
+    fn from(value: f32) -> Self {
+        SpecValue::Bits32(value.to_bits())
+    }
+}
+
+impl From<u64> for SpecValue {
+    fn from(value: u64) -> Self {
+        SpecValue::Bits64(value)
+    }
+}
+
+impl From<i64> for SpecValue {
+    fn from(value: i64) -> Self {
+        SpecValue::Bits64(value as u64)
+    }
+}
+
+impl From<f64> for SpecValue {
+    fn from(value: f64) -> Self {
+        SpecValue::Bits64(value.to_bits())
+    }
+}
+
+///Result ids of everything a [FreezeSpecialization] run touched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FreezeReport {
+    ///Spec constants that were given a value.
+    pub frozen: Vec<Word>,
+    ///`OpSpecConstantComposite`s and `OpSpecConstantOp`s that were evaluated to constants.
+    pub evaluated: Vec<Word>,
+    ///Spec constants that are left as they are, because they were not given a value, or depend on one that was not.
+    pub remaining: Vec<Word>,
+}
+
+impl Display for FreezeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "froze {} spec constants, evaluated {} spec operations, {} spec constants remain",
+            self.frozen.len(),
+            self.evaluated.len(),
+            self.remaining.len()
+        )
+    }
+}
+
+///Replaces specialization constants by constants, given a map from `SpecId` to value. Specialization ids that are not
+/// used by the module are ignored, like Vulkan does.
+#[derive(Debug, Clone, Default)]
+pub struct FreezeSpecialization {
+    pub values: AHashMap<u32, SpecValue>,
+}
+
+impl FreezeSpecialization {
+    pub fn new(values: impl IntoIterator<Item = (u32, SpecValue)>) -> Self {
+        FreezeSpecialization {
+            values: values.into_iter().collect(),
+        }
+    }
+
+    ///Freezes the specialization constants of `module`.
+    pub fn run(&self, module: &mut Module) -> Result<FreezeReport, SpecializationError> {
+        let spec_ids = module
+            .annotations
+            .iter()
+            .filter_map(|inst| match (inst.class.opcode, inst.operands.as_slice()) {
+                (
+                    Op::Decorate,
+                    [Operand::IdRef(target), Operand::Decoration(Decoration::SpecId), Operand::LiteralBit32(spec_id)],
+                ) => Some((*target, *spec_id)),
+                _ => None,
+            })
+            .collect::<AHashMap<_, _>>();
+
+        let mut evaluator = ConstEvaluator::new(module);
+        let mut report = FreezeReport::default();
+        let mut idx = 0;
+        while idx < module.types_global_values.len() {
+            let inst = &module.types_global_values[idx];
+            let (Some(id), Some(ty)) = (inst.result_id, inst.result_type) else {
+                evaluator.declare(inst);
+                idx += 1;
+                continue;
+            };
+            let value =
+                match inst.class.opcode {
+                    Op::SpecConstantTrue | Op::SpecConstantFalse | Op::SpecConstant => {
+                        let given = spec_ids.get(&id).and_then(|spec_id| {
+                            self.values.get(spec_id).map(|value| (*spec_id, *value))
+                        });
+                        match given {
+                            Some((spec_id, value)) => {
+                                report.frozen.push(id);
+                                Some(Self::spec_value(&evaluator, ty, value).ok_or(
+                                    SpecializationError::TypeMismatch { spec_id, id, value },
+                                )?)
+                            }
+                            None => None,
+                        }
+                    }
+                    Op::SpecConstantComposite => inst
+                        .operands
+                        .iter()
+                        .map(|op| op.id_ref_any().and_then(|id| evaluator.value(id).cloned()))
+                        .collect::<Option<Vec<_>>>()
+                        .map(ConstValue::Composite),
+                    Op::SpecConstantOp => evaluator.eval_instruction(inst),
+                    _ => None,
+                };
+
+            match value {
+                Some(value) if Self::declarable(&evaluator, ty, &value) => {
+                    if inst.class.opcode == Op::SpecConstantComposite
+                        || inst.class.opcode == Op::SpecConstantOp
+                    {
+                        report.evaluated.push(id);
+                    }
+                    idx = Self::declare_constant(module, &mut evaluator, idx, ty, id, &value);
+                    evaluator.set_value(id, value);
+                }
+                _ => {
+                    if is_spec_constant(inst.class.opcode) {
+                        report.remaining.push(id);
+                    }
+                    evaluator.declare(inst);
+                }
+            }
+            idx += 1;
+        }
+
+        let frozen = report.frozen.iter().copied().collect::<AHashSet<_>>();
+        module.annotations.retain(|inst| {
+            !matches!(
+                (inst.class.opcode, inst.operands.as_slice()),
+                (Op::Decorate, [Operand::IdRef(target), Operand::Decoration(Decoration::SpecId), ..])
+                    if frozen.contains(target)
+            )
+        });
+
+        Self::freeze_local_size(module, &evaluator, &report);
+        Ok(report)
+    }
+
+    //Interprets `value` as value of type `ty`.
+    fn spec_value(evaluator: &ConstEvaluator, ty: Word, value: SpecValue) -> Option<ConstValue> {
+        match (value, evaluator.zero(ty)?) {
+            (SpecValue::Bool(b), ConstValue::Bool(_)) => Some(ConstValue::Bool(b)),
+            (SpecValue::Bits32(bits), ConstValue::Bool(_)) => Some(ConstValue::Bool(bits != 0)),
+            (
+                SpecValue::Bits32(bits),
+                ConstValue::Int { width, .. } | ConstValue::Float { width, .. },
+            ) if width <= 32 => evaluator.literal_value(ty, &[Operand::LiteralBit32(bits)]),
+            (
+                SpecValue::Bits64(bits),
+                ConstValue::Int { width, .. } | ConstValue::Float { width, .. },
+            ) if width > 32 => evaluator.literal_value(ty, &[Operand::LiteralBit64(bits)]),
+            _ => None,
+        }
+    }
+
+    //True if `value` can be declared as constant of type `ty`.
+    fn declarable(evaluator: &ConstEvaluator, ty: Word, value: &ConstValue) -> bool {
+        match value {
+            ConstValue::Composite(members) => {
+                evaluator.member_count(ty) == Some(members.len() as u64)
+                    && members.iter().enumerate().all(|(idx, member)| {
+                        evaluator
+                            .member_type(ty, idx)
+                            .is_some_and(|ty| Self::declarable(evaluator, ty, member))
+                    })
+            }
+            _ => true,
+        }
+    }
+
+    //Replaces the instruction at `idx` by a declaration of `value`. Constituents of composites that are not declared
+    // yet are inserted in front of it. Returns the new index of the declaration.
+    fn declare_constant(
+        module: &mut Module,
+        evaluator: &mut ConstEvaluator,
+        mut idx: usize,
+        ty: Word,
+        id: Word,
+        value: &ConstValue,
+    ) -> usize {
+        let declaration = match (value, value.scalar_parts()) {
+            (_, Some((op, operands))) => Instruction::new(op, Some(ty), Some(id), operands),
+            (ConstValue::Composite(members), None) => {
+                let member_types = (0..members.len())
+                    .map_while(|idx| evaluator.member_type(ty, idx))
+                    .collect::<Vec<_>>();
+                let mut constituents = Vec::with_capacity(members.len());
+                for (member, member_ty) in members.iter().zip(member_types) {
+                    let existing = module.types_global_values[..idx].iter().find(|inst| {
+                        inst.result_type == Some(member_ty)
+                            && !is_spec_constant(inst.class.opcode)
+                            && inst.result_id.and_then(|id| evaluator.value(id)) == Some(member)
+                    });
+                    let member_id = match existing.and_then(|inst| inst.result_id) {
+                        Some(member_id) => member_id,
+                        None => {
+                            let member_id = module.allocate_id();
+                            module
+                                .types_global_values
+                                .insert(idx, Instruction::new(Op::Nop, None, None, Vec::new()));
+                            idx = Self::declare_constant(
+                                module, evaluator, idx, member_ty, member_id, member,
+                            ) + 1;
+                            evaluator.set_value(member_id, member.clone());
+                            member_id
+                        }
+                    };
+                    constituents.push(Operand::IdRef(member_id));
+                }
+                Instruction::new(Op::ConstantComposite, Some(ty), Some(id), constituents)
+            }
+            //Scalars always have parts
+            (_, None) => return idx,
+        };
+        module.types_global_values[idx] = declaration;
+        idx
+    }
+
+    fn freeze_local_size(module: &mut Module, evaluator: &ConstEvaluator, report: &FreezeReport) {
+        let size = |ids: &[Operand]| {
+            ids.iter()
+                .map(|op| {
+                    op.id_ref_any()
+                        .and_then(|id| evaluator.value(id))
+                        .and_then(|value| value.as_u64())
+                        .map(|value| Operand::LiteralBit32(value as u32))
+                })
+                .collect::<Option<Vec<_>>>()
+        };
+
+        for inst in module.execution_modes.iter_mut() {
+            let literal_mode = match inst.operands.get(1) {
+                Some(Operand::ExecutionMode(ExecutionMode::LocalSizeId)) => {
+                    ExecutionMode::LocalSize
+                }
+                Some(Operand::ExecutionMode(ExecutionMode::LocalSizeHintId)) => {
+                    ExecutionMode::LocalSizeHint
+                }
+                _ => continue,
+            };
+            if let Some(literals) = size(&inst.operands[2..]) {
+                let mut operands = vec![
+                    inst.operands[0].clone(),
+                    Operand::ExecutionMode(literal_mode),
+                ];
+                operands.extend(literals);
+                *inst = Instruction::new(Op::ExecutionMode, None, None, operands);
+            }
+        }
+
+        //The WorkgroupSize builtin overrides LocalSize. Keep both in sync for drivers that only look at the latter.
+        let changed = |id: &Word| report.frozen.contains(id) || report.evaluated.contains(id);
+        let workgroup_size = module.annotations.iter().find_map(|inst| {
+            match (inst.class.opcode, inst.operands.as_slice()) {
+                (
+                    Op::Decorate,
+                    [Operand::IdRef(target), Operand::Decoration(Decoration::BuiltIn), Operand::BuiltIn(BuiltIn::WorkgroupSize)],
+                ) if changed(target) => Some(*target),
+                _ => None,
+            }
+        });
+        let Some(ConstValue::Composite(members)) =
+            workgroup_size.and_then(|id| evaluator.value(id))
+        else {
+            return;
+        };
+        let Some(literals) = members
+            .iter()
+            .map(|member| {
+                member
+                    .as_u64()
+                    .map(|value| Operand::LiteralBit32(value as u32))
+            })
+            .collect::<Option<Vec<_>>>()
+        else {
+            return;
+        };
+        for inst in module.execution_modes.iter_mut() {
+            if let Some(Operand::ExecutionMode(ExecutionMode::LocalSize)) = inst.operands.get(1) {
+                inst.operands.truncate(2);
+                inst.operands.extend(literals.iter().cloned());
+            }
+        }
+    }
+}
+
+fn is_spec_constant(op: Op) -> bool {
+    matches!(
+        op,
+        Op::SpecConstantTrue
+            | Op::SpecConstantFalse
+            | Op::SpecConstant
+            | Op::SpecConstantComposite
+            | Op::SpecConstantOp
+    )
+}
+
+impl Patch for FreezeSpecialization {
+    fn apply<'a>(self, mut patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError> {
+        let report = self
+            .run(patcher.ir_state.as_spirv())
+            .map_err(|e| PatcherError::Internal(e.into()))?;
+        log::info!("Specialization: {}", report);
+        patcher.push_report(report);
+        Ok(patcher)
+    }
+}
@@ -0,0 +1,243 @@
+use ahash::AHashSet;
+use spv_patcher::{
+    analysis::{DefUse, InstLocation, Section, UseSlot},
+    patch::{ConstantSelector, Patch, Patcher},
+    rspirv::{
+        dr::{Instruction, Module, Operand},
+        spirv::{Decoration, Op, Word},
+    },
+    spirv_ext::SpirvExt,
+    PatcherError,
+};
+
+use crate::{SpecValue, SpecializationError};
+
+///A constant that was promoted by [PromoteToSpecialization].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromotedConstant {
+    pub id: Word,
+    pub spec_id: u32,
+    ///Debug name of the constant, if it has one.
+    pub name: Option<String>,
+    ///The value the constant had, which is now the default of the specialization constant.
+    pub default: SpecValue,
+}
+
+///Turns the scalar constants selected by `selector` into specialization constants, so they can be changed at pipeline
+/// creation time.
+///
+///Selected constants get consecutive `SpecId`s. If `spec_id` is `None`, the first one is the one after the highest
+/// `SpecId` the module uses. Note that the constant is promoted, not a single use of it, so all users of a constant
+/// share the `SpecId`. A [Use](ConstantSelector::Use) therefore promotes the constant it uses.
+///
+///Constant composites built from a promoted constant become specialization constant composites. Constants used as
+/// struct index, scope or memory semantics can not be promoted.
+#[derive(Debug, Clone)]
+pub struct PromoteToSpecialization {
+    pub selector: ConstantSelector,
+    pub spec_id: Option<u32>,
+}
+
+impl PromoteToSpecialization {
+    pub fn new(selector: ConstantSelector) -> Self {
+        PromoteToSpecialization {
+            selector,
+            spec_id: None,
+        }
+    }
+
+    ///Assigns `SpecId`s starting at `spec_id`, instead of after the highest one in use.
+    pub fn with_spec_id(mut self, spec_id: u32) -> Self {
+        self.spec_id = Some(spec_id);
+        self
+    }
+
+    ///Promotes the selected constants of `module`, and returns the assigned `SpecId`s.
+    pub fn run(&self, module: &mut Module) -> Result<Vec<PromotedConstant>, SpecializationError> {
+        let selected = self.selector.select(module)?;
+        if selected.is_empty() {
+            return Err(SpecializationError::NoMatch(self.selector.clone()));
+        }
+
+        let used = module
+            .annotations
+            .iter()
+            .filter_map(|inst| match inst.operands.as_slice() {
+                [_, Operand::Decoration(Decoration::SpecId), Operand::LiteralBit32(spec_id)] => {
+                    Some(*spec_id)
+                }
+                _ => None,
+            })
+            .collect::<AHashSet<_>>();
+        let mut next_spec_id = match self.spec_id {
+            Some(spec_id) => spec_id,
+            None => used.iter().max().map(|max| max + 1).unwrap_or(0),
+        };
+
+        let def_use = DefUse::new(module);
+        let mut promoted = Vec::with_capacity(selected.len());
+        for idx in selected.iter().copied() {
+            let inst = &module.types_global_values[idx];
+            let id = inst.result_id.unwrap_or(0);
+            let default = match (inst.class.opcode, inst.operands.as_slice()) {
+                (Op::Constant, [Operand::LiteralBit32(bits)]) => SpecValue::Bits32(*bits),
+                (Op::Constant, [Operand::LiteralBit64(bits)]) => SpecValue::Bits64(*bits),
+                (Op::ConstantTrue, _) => SpecValue::Bool(true),
+                (Op::ConstantFalse, _) => SpecValue::Bool(false),
+                (op, _) => return Err(SpecializationError::NotPromotable { id, op }),
+            };
+            if let Some(user) = constant_user(module, &def_use, id) {
+                return Err(SpecializationError::RequiresConstant { id, user });
+            }
+            if let Some(op) = scope_user(module, &def_use, id) {
+                return Err(SpecializationError::ScopeOperand { id, op });
+            }
+            if used.contains(&next_spec_id) {
+                return Err(SpecializationError::SpecIdInUse(next_spec_id));
+            }
+
+            promoted.push(PromotedConstant {
+                id,
+                spec_id: next_spec_id,
+                name: module.get_name(id),
+                default,
+            });
+            next_spec_id += 1;
+        }
+
+        //Only mutate once all constants are known to be promotable
+        let composites = dependent_composites(module, &def_use, &promoted);
+        for idx in composites {
+            let inst = &mut module.types_global_values[idx];
+            *inst = Instruction::new(
+                Op::SpecConstantComposite,
+                inst.result_type,
+                inst.result_id,
+                inst.operands.clone(),
+            );
+        }
+        for (idx, constant) in selected.into_iter().zip(&promoted) {
+            let inst = &mut module.types_global_values[idx];
+            let (ty, id, operands) = (inst.result_type, inst.result_id, inst.operands.clone());
+            let spec_op = match inst.class.opcode {
+                Op::ConstantTrue => Op::SpecConstantTrue,
+                Op::ConstantFalse => Op::SpecConstantFalse,
+                _ => Op::SpecConstant,
+            };
+            *inst = Instruction::new(spec_op, ty, id, operands);
+            module.annotations.push(Instruction::new(
+                Op::Decorate,
+                None,
+                None,
+                vec![
+                    Operand::IdRef(constant.id),
+                    Operand::Decoration(Decoration::SpecId),
+                    Operand::LiteralBit32(constant.spec_id),
+                ],
+            ));
+        }
+        Ok(promoted)
+    }
+}
+
+//Returns an instruction that needs `id` to be a real constant, if there is one. That is the case for indices into
+// structs in access chains.
+fn constant_user(module: &Module, def_use: &DefUse, id: Word) -> Option<Word> {
+    let type_of = |id: Word| {
+        def_use
+            .def_inst(module, id)
+            .and_then(|inst| inst.result_type)
+    };
+    let decl = |ty: Word| def_use.def_inst(module, ty);
+
+    def_use.users_of(id).iter().find_map(|u| {
+        let inst = u.location.get(module)?;
+        let first_index = match inst.class.opcode {
+            Op::AccessChain | Op::InBoundsAccessChain => 1,
+            Op::PtrAccessChain | Op::InBoundsPtrAccessChain => 2,
+            _ => return None,
+        };
+        //Walk the indices, starting at the base pointer's pointee type
+        let mut ty = type_of(inst.operands.first()?.id_ref_any()?)
+            .and_then(decl)
+            .and_then(|ptr| ptr.operands.get(1))
+            .and_then(|op| op.id_ref_any())?;
+        for operand in &inst.operands[first_index.min(inst.operands.len())..] {
+            let index = operand.id_ref_any()?;
+            let ty_inst = decl(ty)?;
+            ty = match ty_inst.class.opcode {
+                Op::TypeStruct if index == id => return inst.result_id,
+                Op::TypeStruct => {
+                    let member = match decl(index)?.operands.first()? {
+                        Operand::LiteralBit32(member) => *member as usize,
+                        _ => return None,
+                    };
+                    ty_inst.operands.get(member)?.id_ref_any()?
+                }
+                _ => ty_inst.operands.first()?.id_ref_any()?,
+            };
+        }
+        None
+    })
+}
+
+//Returns the opcode of an instruction that uses `id` as scope or memory semantics, if there is one.
+fn scope_user(module: &Module, def_use: &DefUse, id: Word) -> Option<Op> {
+    def_use.users_of(id).iter().find_map(|u| {
+        let UseSlot::Operand(slot) = u.slot else {
+            return None;
+        };
+        let inst = u.location.get(module)?;
+        match inst.operands.get(slot)? {
+            Operand::IdScope(_) | Operand::IdMemorySemantics(_) => Some(inst.class.opcode),
+            _ => None,
+        }
+    })
+}
+
+//Indices of all constant composites that contain one of the `promoted` constants, directly or through other composites.
+fn dependent_composites(
+    module: &Module,
+    def_use: &DefUse,
+    promoted: &[PromotedConstant],
+) -> Vec<usize> {
+    let mut composites = Vec::new();
+    let mut visited = AHashSet::new();
+    let mut stack = promoted.iter().map(|c| c.id).collect::<Vec<_>>();
+    while let Some(id) = stack.pop() {
+        for u in def_use.users_of(id) {
+            let InstLocation::Global {
+                section: Section::TypesGlobalValues,
+                index,
+            } = u.location
+            else {
+                continue;
+            };
+            let inst = &module.types_global_values[index];
+            if inst.class.opcode == Op::ConstantComposite && visited.insert(index) {
+                composites.push(index);
+                stack.extend(inst.result_id);
+            }
+        }
+    }
+    composites
+}
+
+impl Patch for PromoteToSpecialization {
+    fn apply<'a>(self, mut patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError> {
+        let promoted = self
+            .run(patcher.ir_state.as_spirv())
+            .map_err(|e| PatcherError::Internal(e.into()))?;
+        for constant in &promoted {
+            log::info!(
+                "Promoted %{} ({}) to SpecId {} with default {:?}",
+                constant.id,
+                constant.name.as_deref().unwrap_or("unnamed"),
+                constant.spec_id,
+                constant.default
+            );
+        }
+        patcher.push_report(promoted);
+        Ok(patcher)
+    }
+}
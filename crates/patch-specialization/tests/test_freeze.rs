@@ -0,0 +1,131 @@
+use patch_specialization::{FreezeSpecialization, SpecValue};
+use spv_patcher::{
+    rspirv::{
+        dr::{Module as SpvModule, Operand},
+        spirv::{Decoration, ExecutionMode, Op},
+    },
+    spirv_ext::SpirvExt,
+    verify::Verifier,
+    Module,
+};
+
+const SHADER: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %count "count"
+OpName %scale "scale"
+OpName %enabled "enabled"
+OpName %doubled "doubled"
+OpName %pair "pair"
+OpName %wgs "wgs"
+OpName %untouched "untouched"
+OpDecorate %count SpecId 0
+OpDecorate %scale SpecId 1
+OpDecorate %enabled SpecId 2
+OpDecorate %size_x SpecId 3
+OpDecorate %untouched SpecId 4
+OpDecorate %wgs BuiltIn WorkgroupSize
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%float = OpTypeFloat 32
+%bool = OpTypeBool
+%v2uint = OpTypeVector %uint 2
+%v3uint = OpTypeVector %uint 3
+%uint_1 = OpConstant %uint 1
+%count = OpSpecConstant %uint 4
+%scale = OpSpecConstant %float 1
+%enabled = OpSpecConstantFalse %bool
+%size_x = OpSpecConstant %uint 1
+%untouched = OpSpecConstant %uint 9
+%doubled = OpSpecConstantOp %uint IAdd %count %count
+%pair = OpSpecConstantComposite %v2uint %count %doubled
+%shuffled = OpSpecConstantOp %v2uint VectorShuffle %pair %pair 1 0
+%mixed = OpSpecConstantOp %uint IAdd %count %untouched
+%wgs = OpSpecConstantComposite %v3uint %size_x %uint_1 %uint_1
+%main = OpFunction %void None %fn
+%entry = OpLabel
+%x = OpIMul %uint %doubled %count
+%y = OpFMul %float %scale %scale
+%z = OpLogicalNot %bool %enabled
+%w = OpCompositeExtract %uint %shuffled 0
+OpReturn
+OpFunctionEnd
+"#;
+
+fn decl(spv: &SpvModule, name: &str) -> (Op, Vec<Operand>) {
+    let id = spv.get_by_name(name).unwrap().result_id;
+    let inst = spv
+        .types_global_values
+        .iter()
+        .find(|inst| inst.result_id == id)
+        .unwrap();
+    (inst.class.opcode, inst.operands.clone())
+}
+
+#[test]
+fn freeze_values_and_operations() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let freeze = FreezeSpecialization::new([
+        (0, SpecValue::from(3u32)),
+        (1, SpecValue::from(0.5f32)),
+        (2, SpecValue::from(true)),
+        (3, SpecValue::from(8u32)),
+        //Unused spec ids are ignored
+        (42, SpecValue::from(1u32)),
+    ]);
+    let report = freeze.run(&mut spv).unwrap();
+    assert_eq!(report.frozen.len(), 4);
+    //doubled, pair, shuffled and wgs
+    assert_eq!(report.evaluated.len(), 4);
+    //untouched and mixed
+    assert_eq!(report.remaining.len(), 2);
+
+    assert_eq!(
+        decl(&spv, "count"),
+        (Op::Constant, vec![Operand::LiteralBit32(3)])
+    );
+    assert_eq!(
+        decl(&spv, "scale"),
+        (Op::Constant, vec![Operand::LiteralBit32(0.5f32.to_bits())])
+    );
+    assert_eq!(decl(&spv, "enabled").0, Op::ConstantTrue);
+    assert_eq!(
+        decl(&spv, "doubled"),
+        (Op::Constant, vec![Operand::LiteralBit32(6)])
+    );
+    assert_eq!(decl(&spv, "pair").0, Op::ConstantComposite);
+    assert_eq!(decl(&spv, "untouched").0, Op::SpecConstant);
+
+    //Only the SpecId of the untouched constant is left
+    let spec_ids = spv
+        .annotations
+        .iter()
+        .filter(|inst| inst.operands.get(1) == Some(&Operand::Decoration(Decoration::SpecId)))
+        .count();
+    assert_eq!(spec_ids, 1);
+
+    //LocalSize follows the frozen WorkgroupSize builtin
+    assert_eq!(decl(&spv, "wgs").0, Op::ConstantComposite);
+    assert_eq!(
+        spv.execution_modes[0].operands[1..],
+        [
+            Operand::ExecutionMode(ExecutionMode::LocalSize),
+            Operand::LiteralBit32(8),
+            Operand::LiteralBit32(1),
+            Operand::LiteralBit32(1),
+        ]
+    );
+    Verifier::verify(&spv).unwrap();
+}
+
+#[test]
+fn type_mismatch() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let freeze = FreezeSpecialization::new([(0, SpecValue::from(3u64))]);
+    assert!(freeze.run(&mut spv).is_err());
+}
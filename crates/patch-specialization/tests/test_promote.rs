@@ -0,0 +1,211 @@
+use patch_specialization::{
+    FreezeSpecialization, PromoteToSpecialization, PromotedConstant, SpecValue, SpecializationError,
+};
+use spv_patcher::{
+    patch::ConstantSelector,
+    rspirv::{
+        dr::{Module as SpvModule, Operand},
+        spirv::{Decoration, Op, Word},
+    },
+    spirv_ext::{ConstantValue, SpirvExt},
+    verify::Verifier,
+    Module,
+};
+
+const SHADER: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %iterations "iterations"
+OpName %threshold "threshold"
+OpName %member "member"
+OpName %existing "existing"
+OpDecorate %existing SpecId 3
+OpMemberDecorate %data 0 Offset 0
+OpMemberDecorate %data 1 Offset 4
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%float = OpTypeFloat 32
+%data = OpTypeStruct %uint %float
+%ptr_data = OpTypePointer Private %data
+%ptr_float = OpTypePointer Private %float
+%iterations = OpConstant %uint 16
+%threshold = OpConstant %float 0.25
+%member = OpConstant %uint 1
+%existing = OpSpecConstant %uint 0
+%var = OpVariable %ptr_data Private
+%main = OpFunction %void None %fn
+%entry = OpLabel
+%x = OpIMul %uint %iterations %iterations
+%y = OpFMul %float %threshold %threshold
+%p = OpAccessChain %ptr_float %var %member
+OpReturn
+OpFunctionEnd
+"#;
+
+fn spec_id_of(spv: &SpvModule, id: Word) -> Option<u32> {
+    spv.annotations
+        .iter()
+        .find_map(|inst| match inst.operands.as_slice() {
+            [Operand::IdRef(target), Operand::Decoration(Decoration::SpecId), Operand::LiteralBit32(spec_id)]
+                if *target == id =>
+            {
+                Some(*spec_id)
+            }
+            _ => None,
+        })
+}
+
+fn op_of(spv: &SpvModule, id: Word) -> Op {
+    spv.types_global_values
+        .iter()
+        .find(|inst| inst.result_id == Some(id))
+        .unwrap()
+        .class
+        .opcode
+}
+
+#[test]
+fn promote_and_freeze_again() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let iterations = spv.get_by_name("iterations").unwrap().result_id.unwrap();
+    let threshold = spv.get_by_name("threshold").unwrap().result_id.unwrap();
+
+    let promoted = PromoteToSpecialization::new(ConstantSelector::Name("iterations".to_string()))
+        .run(&mut spv)
+        .unwrap();
+    assert_eq!(promoted.len(), 1);
+    //Auto-assigned after the highest SpecId in use
+    assert_eq!(promoted[0].spec_id, 4);
+    assert_eq!(promoted[0].name.as_deref(), Some("iterations"));
+    assert_eq!(promoted[0].default, SpecValue::Bits32(16));
+    assert_eq!(spec_id_of(&spv, iterations), Some(4));
+
+    let promoted = PromoteToSpecialization::new(ConstantSelector::Value(ConstantValue::F32(0.25)))
+        .with_spec_id(10)
+        .run(&mut spv)
+        .unwrap();
+    assert_eq!(promoted[0].id, threshold);
+    assert_eq!(spec_id_of(&spv, threshold), Some(10));
+    assert_eq!(op_of(&spv, iterations), Op::SpecConstant);
+    assert_eq!(op_of(&spv, threshold), Op::SpecConstant);
+    Verifier::verify(&spv).unwrap();
+
+    //Freezing the default values restores the constants
+    let defaults = FreezeSpecialization::new([(4, SpecValue::Bits32(16))]);
+    defaults.run(&mut spv).unwrap();
+    assert_eq!(op_of(&spv, iterations), Op::Constant);
+    assert_eq!(spec_id_of(&spv, iterations), None);
+}
+
+#[test]
+fn promote_as_patch() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut patcher = module
+        .patch()
+        .patch(PromoteToSpecialization::new(ConstantSelector::Name(
+            "iterations".to_string(),
+        )))
+        .unwrap();
+    let promoted = patcher.report::<Vec<PromotedConstant>>().unwrap().clone();
+    assert_eq!(promoted.len(), 1);
+    assert_eq!(promoted[0].spec_id, 4);
+
+    let spv = patcher.unwrap_module();
+    assert_eq!(spec_id_of(&spv, promoted[0].id), Some(4));
+}
+
+#[test]
+fn refuse_invalid_promotions() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let existing = spv.get_by_name("existing").unwrap().result_id;
+    //Struct indices must stay constant
+    assert!(
+        PromoteToSpecialization::new(ConstantSelector::Name("member".to_string()))
+            .run(&mut spv)
+            .is_err()
+    );
+    //SpecId 3 is already taken
+    assert!(
+        PromoteToSpecialization::new(ConstantSelector::Name("iterations".to_string()))
+            .with_spec_id(3)
+            .run(&mut spv)
+            .is_err()
+    );
+    //Nothing was changed
+    assert!(spv
+        .types_global_values
+        .iter()
+        .all(|inst| inst.class.opcode != Op::SpecConstant || inst.result_id == existing));
+}
+
+const COMPOSITES: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %width "width"
+OpName %size "size"
+OpName %sizes "sizes"
+OpName %scope "scope"
+OpName %semantics "semantics"
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%v2uint = OpTypeVector %uint 2
+%uint_2 = OpConstant %uint 2
+%arr = OpTypeArray %v2uint %uint_2
+%width = OpConstant %uint 8
+%size = OpConstantComposite %v2uint %width %width
+%sizes = OpConstantComposite %arr %size %size
+%scope = OpConstant %uint 2
+%semantics = OpConstant %uint 264
+%main = OpFunction %void None %fn
+%entry = OpLabel
+%v = OpIAdd %v2uint %size %size
+OpControlBarrier %scope %scope %semantics
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn promote_composite_constituent() {
+    let mut spv = Module::from_assembly(COMPOSITES).unwrap().spirv().clone();
+    PromoteToSpecialization::new(ConstantSelector::Name("width".to_string()))
+        .run(&mut spv)
+        .unwrap();
+    //Both the direct and the nested composite depend on the specialization constant now
+    assert_eq!(
+        op_of(&spv, spv.get_by_name("size").unwrap().result_id.unwrap()),
+        Op::SpecConstantComposite
+    );
+    assert_eq!(
+        op_of(&spv, spv.get_by_name("sizes").unwrap().result_id.unwrap()),
+        Op::SpecConstantComposite
+    );
+    Verifier::verify(&spv).unwrap();
+}
+
+#[test]
+fn refuse_scope_operands() {
+    let mut spv = Module::from_assembly(COMPOSITES).unwrap().spirv().clone();
+    for name in ["scope", "semantics"] {
+        let result =
+            PromoteToSpecialization::new(ConstantSelector::Name(name.to_string())).run(&mut spv);
+        assert!(matches!(
+            result,
+            Err(SpecializationError::ScopeOperand {
+                op: Op::ControlBarrier,
+                ..
+            })
+        ));
+    }
+    assert_eq!(
+        op_of(&spv, spv.get_by_name("scope").unwrap().result_id.unwrap()),
+        Op::Constant
+    );
+}
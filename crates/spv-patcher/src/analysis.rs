@@ -0,0 +1,14 @@
+//! # Analysis
+//!
+//! Reusable analyses of a SPIR-V module. Patches should prefer those over re-scanning the module. The [DefUse] index is
+//! cached by [IrState](crate::patch::IrState) as long as the module is not mutated behind its back.
+
+mod call_graph;
+mod cfg;
+mod const_eval;
+mod def_use;
+
+pub use call_graph::CallGraph;
+pub use cfg::{Cfg, Construct, ConstructKind, DominatorTree, Loop, LoopForest};
+pub use const_eval::{ConstEvaluator, ConstValue};
+pub use def_use::{DefUse, InstLocation, Section, Use, UseSlot};
@@ -0,0 +1,201 @@
+use ahash::{AHashMap, AHashSet};
+use rspirv::{
+    dr::Module,
+    spirv::{Op, Word},
+};
+use smallvec::SmallVec;
+
+///Static call graph of a module, built from all `OpFunctionCall`s. Functions are identified by the result id of their
+/// `OpFunction`.
+#[derive(Debug, Clone, Default)]
+pub struct CallGraph {
+    //All defined functions in module order.
+    functions: Vec<Word>,
+    //Callees in order of their first call. Might contain ids of functions that are not defined in the module.
+    callees: AHashMap<Word, SmallVec<[Word; 4]>>,
+    callers: AHashMap<Word, SmallVec<[Word; 4]>>,
+    //Function ids of all entry points.
+    entry_points: SmallVec<[Word; 1]>,
+    recursive: AHashSet<Word>,
+}
+
+impl CallGraph {
+    pub fn new(module: &Module) -> Self {
+        let mut graph = CallGraph::default();
+        for function in &module.functions {
+            let Some(caller) = function.def_id() else {
+                continue;
+            };
+            graph.functions.push(caller);
+            let callees = graph.callees.entry(caller).or_default();
+            for inst in function.blocks.iter().flat_map(|b| b.instructions.iter()) {
+                if inst.class.opcode != Op::FunctionCall {
+                    continue;
+                }
+                if let Some(callee) = inst.operands.get(0).and_then(|o| o.id_ref_any()) {
+                    if !callees.contains(&callee) {
+                        callees.push(callee);
+                    }
+                }
+            }
+        }
+        for (caller, callees) in &graph.callees {
+            for callee in callees {
+                graph.callers.entry(*callee).or_default().push(*caller);
+            }
+        }
+        //Keep callers in module order, independent of the map's iteration order
+        let order: AHashMap<Word, usize> = graph
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(idx, f)| (*f, idx))
+            .collect();
+        for callers in graph.callers.values_mut() {
+            callers.sort_by_key(|c| order.get(c).copied().unwrap_or(usize::MAX));
+        }
+
+        graph.entry_points = module
+            .entry_points
+            .iter()
+            .filter_map(|ep| ep.operands.get(1).and_then(|o| o.id_ref_any()))
+            .collect();
+
+        graph.recursive = graph.recursive_functions();
+        graph
+    }
+
+    //Functions that are part of a call cycle. Uses Tarjan's strongly connected components, iteratively, so that deep call
+    // chains don't overflow the stack.
+    fn recursive_functions(&self) -> AHashSet<Word> {
+        let mut index: AHashMap<Word, usize> = AHashMap::default();
+        let mut lowlink: AHashMap<Word, usize> = AHashMap::default();
+        let mut on_stack = AHashSet::default();
+        let mut stack = Vec::new();
+        let mut recursive = AHashSet::default();
+        let mut next_index = 0;
+
+        for root in &self.functions {
+            if index.contains_key(root) {
+                continue;
+            }
+            //Function and index of its next callee for each level of the depth first search
+            let mut frames = vec![(*root, 0)];
+            index.insert(*root, next_index);
+            lowlink.insert(*root, next_index);
+            next_index += 1;
+            stack.push(*root);
+            on_stack.insert(*root);
+
+            while let Some((function, next)) = frames.last_mut() {
+                let function = *function;
+                if let Some(callee) = self.callees(function).get(*next).copied() {
+                    *next += 1;
+                    match index.get(&callee).copied() {
+                        None => {
+                            index.insert(callee, next_index);
+                            lowlink.insert(callee, next_index);
+                            next_index += 1;
+                            stack.push(callee);
+                            on_stack.insert(callee);
+                            frames.push((callee, 0));
+                        }
+                        Some(callee_index) if on_stack.contains(&callee) => {
+                            let low = lowlink[&function].min(callee_index);
+                            lowlink.insert(function, low);
+                        }
+                        Some(_) => {}
+                    }
+                    continue;
+                }
+
+                frames.pop();
+                let low = lowlink[&function];
+                if let Some((caller, _)) = frames.last() {
+                    let caller_low = lowlink[caller].min(low);
+                    lowlink.insert(*caller, caller_low);
+                }
+                if low == index[&function] {
+                    //`function` is the root of a component, which is recursive if it has more than one member or calls itself
+                    let mut component = SmallVec::<[Word; 4]>::new();
+                    while let Some(member) = stack.pop() {
+                        on_stack.remove(&member);
+                        component.push(member);
+                        if member == function {
+                            break;
+                        }
+                    }
+                    if component.len() > 1 || self.callees(function).contains(&function) {
+                        recursive.extend(component);
+                    }
+                }
+            }
+        }
+        recursive
+    }
+
+    ///All functions defined in the module, in module order.
+    pub fn functions(&self) -> &[Word] {
+        &self.functions
+    }
+
+    ///Function ids of all entry points.
+    pub fn entry_points(&self) -> &[Word] {
+        &self.entry_points
+    }
+
+    ///Functions directly called by `function`, in order of their first call.
+    pub fn callees(&self, function: Word) -> &[Word] {
+        self.callees
+            .get(&function)
+            .map(|c| c.as_slice())
+            .unwrap_or(&[])
+    }
+
+    ///Functions directly calling `function`, in module order.
+    pub fn callers(&self, function: Word) -> &[Word] {
+        self.callers
+            .get(&function)
+            .map(|c| c.as_slice())
+            .unwrap_or(&[])
+    }
+
+    ///All functions that can be reached via `OpFunctionCall` from `root`, including `root` itself.
+    pub fn reachable_from(&self, root: Word) -> AHashSet<Word> {
+        let mut reachable = AHashSet::default();
+        let mut stack = vec![root];
+        while let Some(function) = stack.pop() {
+            if reachable.insert(function) {
+                stack.extend_from_slice(self.callees(function));
+            }
+        }
+        reachable
+    }
+
+    ///Function ids of all entry points from which `function` can be reached.
+    pub fn entry_points_reaching(&self, function: Word) -> SmallVec<[Word; 1]> {
+        //Walk callers upwards, instead of computing the reachable set of each entry point
+        let mut reaching = AHashSet::default();
+        let mut stack = vec![function];
+        while let Some(f) = stack.pop() {
+            if reaching.insert(f) {
+                stack.extend_from_slice(self.callers(f));
+            }
+        }
+        self.entry_points
+            .iter()
+            .copied()
+            .filter(|ep| reaching.contains(ep))
+            .collect()
+    }
+
+    ///True if `function` can (indirectly) call itself. Note that recursion is not allowed in shaders.
+    pub fn is_recursive(&self, function: Word) -> bool {
+        self.recursive.contains(&function)
+    }
+
+    ///True if any function is recursive.
+    pub fn has_recursion(&self) -> bool {
+        !self.recursive.is_empty()
+    }
+}
@@ -0,0 +1,554 @@
+use ahash::AHashMap;
+use rspirv::{
+    dr::{Block, Function, Operand},
+    grammar::reflect,
+    spirv::{Op, Word},
+};
+use smallvec::SmallVec;
+
+///Kind of a structured control flow construct.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstructKind {
+    ///Header declares an `OpSelectionMerge`.
+    Selection,
+    ///Header declares an `OpLoopMerge`.
+    Loop { continue_target: usize },
+}
+
+///A structured construct, declared by a merge instruction in its header block. All blocks are given as index into the
+/// function's blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Construct {
+    pub header: usize,
+    pub merge: usize,
+    pub kind: ConstructKind,
+}
+
+impl Construct {
+    pub fn continue_target(&self) -> Option<usize> {
+        match self.kind {
+            ConstructKind::Loop { continue_target } => Some(continue_target),
+            ConstructKind::Selection => None,
+        }
+    }
+
+    ///True if `block` is part of this construct, which are all blocks dominated by the header, but not by the merge
+    /// block.
+    pub fn contains(&self, block: usize, dominators: &DominatorTree) -> bool {
+        dominators.dominates(self.header, block) && !dominators.dominates(self.merge, block)
+    }
+}
+
+///Control flow graph of a single function. Blocks are addressed by their index in [Function::blocks], the first block
+/// is the entry.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    labels: Vec<Word>,
+    index: AHashMap<Word, usize>,
+    successors: Vec<SmallVec<[usize; 2]>>,
+    predecessors: Vec<SmallVec<[usize; 2]>>,
+    constructs: Vec<Construct>,
+}
+
+impl Cfg {
+    pub fn new(function: &Function) -> Self {
+        let labels: Vec<Word> = function
+            .blocks
+            .iter()
+            .map(|b| b.label_id().unwrap_or(0))
+            .collect();
+        let index: AHashMap<Word, usize> = labels
+            .iter()
+            .enumerate()
+            .map(|(idx, label)| (*label, idx))
+            .collect();
+
+        let mut successors = vec![SmallVec::new(); labels.len()];
+        let mut predecessors = vec![SmallVec::<[usize; 2]>::new(); labels.len()];
+        let mut constructs = Vec::new();
+        for (idx, block) in function.blocks.iter().enumerate() {
+            for target in branch_targets(block) {
+                if let Some(target) = index.get(&target).copied() {
+                    if !successors[idx].contains(&target) {
+                        successors[idx].push(target);
+                        predecessors[target].push(idx);
+                    }
+                }
+            }
+
+            let Some(merge_inst) = block.instructions.iter().rev().nth(1) else {
+                continue;
+            };
+            let label = |op: Option<&Operand>| {
+                op.and_then(|op| op.id_ref_any())
+                    .and_then(|id| index.get(&id).copied())
+            };
+            let kind = match merge_inst.class.opcode {
+                Op::SelectionMerge => Some(ConstructKind::Selection),
+                Op::LoopMerge => label(merge_inst.operands.get(1))
+                    .map(|continue_target| ConstructKind::Loop { continue_target }),
+                _ => None,
+            };
+            if let (Some(kind), Some(merge)) = (kind, label(merge_inst.operands.get(0))) {
+                constructs.push(Construct {
+                    header: idx,
+                    merge,
+                    kind,
+                });
+            }
+        }
+
+        Cfg {
+            labels,
+            index,
+            successors,
+            predecessors,
+            constructs,
+        }
+    }
+
+    ///Number of blocks.
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    ///Label id of `block`.
+    pub fn label(&self, block: usize) -> Word {
+        self.labels[block]
+    }
+
+    ///Index of the block with the given label.
+    pub fn block_of(&self, label: Word) -> Option<usize> {
+        self.index.get(&label).copied()
+    }
+
+    pub fn successors(&self, block: usize) -> &[usize] {
+        &self.successors[block]
+    }
+
+    pub fn predecessors(&self, block: usize) -> &[usize] {
+        &self.predecessors[block]
+    }
+
+    ///Blocks that leave the function, i.e. end in `OpReturn`, `OpReturnValue`, `OpKill`, `OpUnreachable` or similar.
+    pub fn exits(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len()).filter(|b| self.successors[*b].is_empty())
+    }
+
+    ///All structured constructs of the function, in block order of their headers.
+    pub fn constructs(&self) -> &[Construct] {
+        &self.constructs
+    }
+
+    ///The construct declared by `header`, if any.
+    pub fn construct_of_header(&self, header: usize) -> Option<&Construct> {
+        self.constructs.iter().find(|c| c.header == header)
+    }
+
+    ///Blocks reachable from the entry in reverse post order.
+    pub fn reverse_post_order(&self) -> Vec<usize> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        post_order(0, |b| self.successors[b].to_vec(), self.len())
+            .into_iter()
+            .rev()
+            .collect()
+    }
+}
+
+//Label ids a block's terminator branches to.
+fn branch_targets(block: &Block) -> SmallVec<[Word; 2]> {
+    let Some(terminator) = block.instructions.last() else {
+        return SmallVec::new();
+    };
+    if !reflect::is_block_terminator(terminator.class.opcode) {
+        return SmallVec::new();
+    }
+    let first_target = match terminator.class.opcode {
+        Op::Branch => 0,
+        //Skip condition, or selector
+        Op::BranchConditional | Op::Switch => 1,
+        _ => return SmallVec::new(),
+    };
+    terminator
+        .operands
+        .iter()
+        .skip(first_target)
+        .filter_map(|op| match op {
+            Operand::IdRef(id) => Some(*id),
+            _ => None,
+        })
+        .collect()
+}
+
+//Iterative depth first post order of all nodes reachable from `root`.
+fn post_order(root: usize, successors: impl Fn(usize) -> Vec<usize>, count: usize) -> Vec<usize> {
+    let mut visited = vec![false; count];
+    let mut order = Vec::with_capacity(count);
+    let mut stack = vec![(root, successors(root), 0)];
+    visited[root] = true;
+    while let Some((node, succ, next)) = stack.last_mut() {
+        if let Some(s) = succ.get(*next).copied() {
+            *next += 1;
+            if !visited[s] {
+                visited[s] = true;
+                stack.push((s, successors(s), 0));
+            }
+        } else {
+            order.push(*node);
+            stack.pop();
+        }
+    }
+    order
+}
+
+///Dominator or post-dominator tree of a [Cfg].
+///
+/// Post-dominators are computed relative to a virtual exit node that succeeds all [exits](Cfg::exits). Blocks that
+/// can't reach an exit (infinite loops), or that are unreachable from the entry in case of dominators, are not part of
+/// the tree.
+#[derive(Debug, Clone)]
+pub struct DominatorTree {
+    //Immediate dominator per block. The virtual exit node of post-dominator trees is not stored.
+    idom: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    roots: Vec<usize>,
+    //Pre- and post-order numbers in the tree for constant time dominance queries.
+    pre: Vec<usize>,
+    post: Vec<usize>,
+    in_tree: Vec<bool>,
+}
+
+impl DominatorTree {
+    ///Dominator tree rooted at the entry block.
+    pub fn dominators(cfg: &Cfg) -> Self {
+        if cfg.is_empty() {
+            return Self::from_idom(Vec::new(), Vec::new());
+        }
+        let idom = compute_idom(
+            cfg.len(),
+            0,
+            |b| cfg.successors(b).to_vec(),
+            |b| cfg.predecessors(b).to_vec(),
+        );
+        let in_tree = idom.iter().map(|d| d.is_some()).collect();
+        let idom = idom
+            .iter()
+            .enumerate()
+            .map(|(b, d)| d.filter(|d| *d != b))
+            .collect();
+        Self::from_idom(idom, in_tree)
+    }
+
+    ///Post-dominator tree. Blocks that are only post-dominated by the virtual exit, like all exit blocks, are the roots
+    /// of the tree.
+    pub fn post_dominators(cfg: &Cfg) -> Self {
+        let exit = cfg.len();
+        let exits: Vec<usize> = cfg.exits().collect();
+        //Reverse graph, with the virtual exit as root
+        let idom = compute_idom(
+            cfg.len() + 1,
+            exit,
+            |b| {
+                if b == exit {
+                    exits.clone()
+                } else {
+                    cfg.predecessors(b).to_vec()
+                }
+            },
+            |b| {
+                if b == exit {
+                    Vec::new()
+                } else {
+                    let mut preds = cfg.successors(b).to_vec();
+                    if preds.is_empty() {
+                        preds.push(exit);
+                    }
+                    preds
+                }
+            },
+        );
+        let in_tree = idom[..exit].iter().map(|d| d.is_some()).collect();
+        let idom = idom[..exit]
+            .iter()
+            .map(|d| d.filter(|d| *d != exit))
+            .collect();
+        Self::from_idom(idom, in_tree)
+    }
+
+    fn from_idom(idom: Vec<Option<usize>>, in_tree: Vec<bool>) -> Self {
+        let count = idom.len();
+        let mut children = vec![Vec::new(); count];
+        let mut roots = Vec::new();
+        for (b, d) in idom.iter().enumerate() {
+            match d {
+                Some(d) => children[*d].push(b),
+                None if in_tree[b] => roots.push(b),
+                None => {}
+            }
+        }
+
+        let mut pre = vec![usize::MAX; count];
+        let mut post = vec![usize::MAX; count];
+        let (mut pre_counter, mut post_counter) = (0, 0);
+        for root in &roots {
+            let mut stack = vec![(*root, 0)];
+            pre[*root] = pre_counter;
+            pre_counter += 1;
+            while let Some((node, next)) = stack.last_mut() {
+                if let Some(child) = children[*node].get(*next).copied() {
+                    *next += 1;
+                    pre[child] = pre_counter;
+                    pre_counter += 1;
+                    stack.push((child, 0));
+                } else {
+                    post[*node] = post_counter;
+                    post_counter += 1;
+                    stack.pop();
+                }
+            }
+        }
+
+        DominatorTree {
+            idom,
+            children,
+            roots,
+            pre,
+            post,
+            in_tree,
+        }
+    }
+
+    ///Immediate (post-)dominator of `block`. `None` for roots and blocks that are not part of the tree.
+    pub fn idom(&self, block: usize) -> Option<usize> {
+        self.idom[block]
+    }
+
+    ///Blocks immediately (post-)dominated by `block`.
+    pub fn children(&self, block: usize) -> &[usize] {
+        &self.children[block]
+    }
+
+    ///The entry block for dominator trees, blocks directly post-dominated by the virtual exit for post-dominator trees.
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    pub fn contains(&self, block: usize) -> bool {
+        self.in_tree[block]
+    }
+
+    ///True if `a` (post-)dominates `b`. Every block dominates itself.
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        self.in_tree[a]
+            && self.in_tree[b]
+            && self.pre[a] <= self.pre[b]
+            && self.post[b] <= self.post[a]
+    }
+
+    ///True if `a` (post-)dominates `b` and `a != b`.
+    pub fn strictly_dominates(&self, a: usize, b: usize) -> bool {
+        a != b && self.dominates(a, b)
+    }
+}
+
+//Cooper, Harvey, Kennedy: "A Simple, Fast Dominance Algorithm". Returns the immediate dominator of each node, the root
+// dominates itself, unreachable nodes have none.
+fn compute_idom(
+    count: usize,
+    root: usize,
+    successors: impl Fn(usize) -> Vec<usize>,
+    predecessors: impl Fn(usize) -> Vec<usize>,
+) -> Vec<Option<usize>> {
+    let order = post_order(root, successors, count);
+    let mut number = vec![usize::MAX; count];
+    for (n, node) in order.iter().enumerate() {
+        number[*node] = n;
+    }
+
+    let mut idom = vec![None; count];
+    idom[root] = Some(root);
+    let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+        while a != b {
+            while number[a] < number[b] {
+                a = idom[a].unwrap();
+            }
+            while number[b] < number[a] {
+                b = idom[b].unwrap();
+            }
+        }
+        a
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for node in order.iter().rev().copied().filter(|n| *n != root) {
+            let mut new_idom = None;
+            for pred in predecessors(node) {
+                if idom[pred].is_none() {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    None => pred,
+                    Some(current) => intersect(&idom, pred, current),
+                });
+            }
+            if new_idom.is_some() && idom[node] != new_idom {
+                idom[node] = new_idom;
+                changed = true;
+            }
+        }
+    }
+    idom
+}
+
+///A natural loop.
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub header: usize,
+    ///All blocks of the loop, including the header and nested loops, in ascending order.
+    pub blocks: Vec<usize>,
+    ///Blocks with a back edge to the header.
+    pub latches: SmallVec<[usize; 1]>,
+    ///Blocks outside the loop that are branched to from inside.
+    pub exits: SmallVec<[usize; 2]>,
+    ///Merge block, if the header declares an `OpLoopMerge`.
+    pub merge: Option<usize>,
+    ///Continue target, if the header declares an `OpLoopMerge`.
+    pub continue_target: Option<usize>,
+    ///Index of the innermost enclosing loop in [LoopForest::loops].
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    ///Nesting depth, outermost loops have depth 1.
+    pub depth: usize,
+}
+
+impl Loop {
+    pub fn contains(&self, block: usize) -> bool {
+        self.blocks.binary_search(&block).is_ok()
+    }
+}
+
+///Loop nesting forest of a function, built from the natural loops of all back edges.
+#[derive(Debug, Clone)]
+pub struct LoopForest {
+    loops: Vec<Loop>,
+    //Innermost loop per block
+    innermost: Vec<Option<usize>>,
+}
+
+impl LoopForest {
+    pub fn new(cfg: &Cfg, dominators: &DominatorTree) -> Self {
+        //Back edges are edges to a dominating block. Group them by header.
+        let mut latches: Vec<(usize, SmallVec<[usize; 1]>)> = Vec::new();
+        for block in 0..cfg.len() {
+            for succ in cfg.successors(block) {
+                if dominators.dominates(*succ, block) {
+                    match latches.iter_mut().find(|(h, _)| h == succ) {
+                        Some((_, l)) => l.push(block),
+                        None => latches.push((*succ, SmallVec::from_slice(&[block]))),
+                    }
+                }
+            }
+        }
+
+        let mut loops: Vec<Loop> = latches
+            .into_iter()
+            .map(|(header, latches)| {
+                //Walk backwards from each latch until the header is reached
+                let mut in_loop = vec![false; cfg.len()];
+                in_loop[header] = true;
+                let mut stack: Vec<usize> = latches.to_vec();
+                while let Some(b) = stack.pop() {
+                    if in_loop[b] || !dominators.contains(b) {
+                        continue;
+                    }
+                    in_loop[b] = true;
+                    stack.extend_from_slice(cfg.predecessors(b));
+                }
+                let blocks: Vec<usize> = (0..cfg.len()).filter(|b| in_loop[*b]).collect();
+
+                let mut exits = SmallVec::new();
+                for b in &blocks {
+                    for succ in cfg.successors(*b) {
+                        if !in_loop[*succ] && !exits.contains(succ) {
+                            exits.push(*succ);
+                        }
+                    }
+                }
+
+                let construct = cfg.construct_of_header(header);
+                Loop {
+                    header,
+                    blocks,
+                    latches,
+                    exits,
+                    merge: construct
+                        .filter(|c| c.continue_target().is_some())
+                        .map(|c| c.merge),
+                    continue_target: construct.and_then(|c| c.continue_target()),
+                    parent: None,
+                    children: Vec::new(),
+                    depth: 0,
+                }
+            })
+            .collect();
+
+        //Outer loops first, so parents are assigned before their children
+        loops.sort_by(|a, b| {
+            b.blocks
+                .len()
+                .cmp(&a.blocks.len())
+                .then(a.header.cmp(&b.header))
+        });
+        let mut innermost = vec![None; cfg.len()];
+        for idx in 0..loops.len() {
+            let parent = (0..idx)
+                .rev()
+                .find(|p| loops[*p].contains(loops[idx].header));
+            loops[idx].parent = parent;
+            loops[idx].depth = parent.map(|p| loops[p].depth).unwrap_or(0) + 1;
+            if let Some(parent) = parent {
+                loops[parent].children.push(idx);
+            }
+            for b in &loops[idx].blocks {
+                innermost[*b] = Some(idx);
+            }
+        }
+
+        LoopForest { loops, innermost }
+    }
+
+    ///All loops, outer loops before the loops nested in them.
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+
+    ///Loops that are not nested in any other loop.
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.loops.len()).filter(|l| self.loops[*l].parent.is_none())
+    }
+
+    ///Innermost loop containing `block`.
+    pub fn innermost_loop_of(&self, block: usize) -> Option<usize> {
+        self.innermost[block]
+    }
+
+    ///Loop with the given header block.
+    pub fn loop_of_header(&self, header: usize) -> Option<&Loop> {
+        self.loops.iter().find(|l| l.header == header)
+    }
+
+    ///Number of loops `block` is nested in.
+    pub fn depth(&self, block: usize) -> usize {
+        self.innermost[block]
+            .map(|l| self.loops[l].depth)
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,866 @@
+use ahash::AHashMap;
+use rspirv::{
+    dr::{Instruction, Module, Operand},
+    spirv::{Op, Word},
+};
+
+use crate::spirv_ext::SpirvExt;
+
+///Value of a constant, as seen by the [ConstEvaluator].
+///
+///Scalars are stored as bit patterns of their declared width, so values of different widths or signedness never
+/// compare equal. `OpConstantNull` is expanded to the zero value of its type, see [zero](ConstEvaluator::zero).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ConstValue {
+    Bool(bool),
+    ///Integer, stored zero-extended. Use [as_i64](ConstValue::as_i64) for the signed interpretation.
+    Int {
+        bits: u64,
+        width: u32,
+        signed: bool,
+    },
+    Float {
+        bits: u64,
+        width: u32,
+    },
+    ///Vector, matrix, array or struct, in member order.
+    Composite(Vec<ConstValue>),
+}
+
+impl ConstValue {
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            ConstValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    ///Unsigned interpretation of an integer.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            ConstValue::Int { bits, .. } => Some(*bits),
+            _ => None,
+        }
+    }
+
+    ///Signed interpretation of an integer.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            ConstValue::Int { bits, width, .. } => Some(sign_extend(*bits, *width)),
+            _ => None,
+        }
+    }
+
+    ///Value of a 32 or 64 bit float. Half floats are not interpreted.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            ConstValue::Float { bits, width: 32 } => Some(f32::from_bits(*bits as u32) as f64),
+            ConstValue::Float { bits, width: 64 } => Some(f64::from_bits(*bits)),
+            _ => None,
+        }
+    }
+
+    ///Opcode and operands of an `OpConstant*` declaring this value. `None` for composites, whose constituents need to
+    /// be declared first.
+    pub fn scalar_parts(&self) -> Option<(Op, Vec<Operand>)> {
+        match self {
+            ConstValue::Bool(true) => Some((Op::ConstantTrue, Vec::new())),
+            ConstValue::Bool(false) => Some((Op::ConstantFalse, Vec::new())),
+            ConstValue::Int {
+                bits,
+                width,
+                signed,
+            } => {
+                let operand = if *width > 32 {
+                    Operand::LiteralBit64(*bits)
+                } else if *signed {
+                    //Narrow signed literals are sign-extended to a full word
+                    Operand::LiteralBit32(sign_extend(*bits, *width) as u32)
+                } else {
+                    Operand::LiteralBit32(*bits as u32)
+                };
+                Some((Op::Constant, vec![operand]))
+            }
+            ConstValue::Float { bits, width } => {
+                let operand = if *width > 32 {
+                    Operand::LiteralBit64(*bits)
+                } else {
+                    Operand::LiteralBit32(*bits as u32)
+                };
+                Some((Op::Constant, vec![operand]))
+            }
+            ConstValue::Composite(_) => None,
+        }
+    }
+}
+
+//Type of a constant as far as the evaluator is concerned.
+#[derive(Debug, Clone, PartialEq)]
+enum ConstType {
+    Bool,
+    Int {
+        width: u32,
+        signed: bool,
+    },
+    Float {
+        width: u32,
+    },
+    ///Member types of a vector, matrix or struct, in order.
+    Composite(Vec<Word>),
+    ///Arrays are not expanded, since their length can be huge.
+    Array {
+        elem: Word,
+        len: u64,
+    },
+}
+
+//Maximum number of scalars in a zero value. `OpConstantNull` of larger types is not evaluated.
+const MAX_ZERO_SCALARS: u64 = 1 << 16;
+
+fn mask(bits: u64, width: u32) -> u64 {
+    if width >= 64 {
+        bits
+    } else {
+        bits & ((1u64 << width) - 1)
+    }
+}
+
+fn sign_extend(bits: u64, width: u32) -> i64 {
+    if width >= 64 || width == 0 {
+        bits as i64
+    } else {
+        let shift = 64 - width;
+        ((bits << shift) as i64) >> shift
+    }
+}
+
+///Evaluates instructions on constant operands.
+///
+///The evaluator knows all types and (non-specialization) constants of the module it was created from. Constants that
+/// are computed or declared later can be added via [declare](ConstEvaluator::declare) or
+/// [set_value](ConstEvaluator::set_value). Evaluation never guesses: if an operation is not supported, an operand is not
+/// constant, or the result is undefined (for instance a division by zero), the result is `None`.
+#[derive(Debug, Clone, Default)]
+pub struct ConstEvaluator {
+    types: AHashMap<Word, ConstType>,
+    values: AHashMap<Word, ConstValue>,
+    //Id of the `GLSL.std.450` import, if any.
+    glsl_std_450: Option<Word>,
+}
+
+impl ConstEvaluator {
+    pub fn new(module: &Module) -> Self {
+        let mut evaluator = ConstEvaluator {
+            glsl_std_450: module
+                .ext_inst_imports
+                .iter()
+                .find(|inst| {
+                    matches!(inst.operands.first(), Some(Operand::LiteralString(s)) if s == "GLSL.std.450")
+                })
+                .and_then(|inst| inst.result_id),
+            ..Default::default()
+        };
+        for inst in &module.types_global_values {
+            evaluator.declare(inst);
+        }
+        evaluator
+    }
+
+    ///Registers the type or constant declared by `inst`. Anything else is ignored.
+    pub fn declare(&mut self, inst: &Instruction) {
+        let Some(id) = inst.result_id else {
+            return;
+        };
+        let literal = |idx: usize| match inst.operands.get(idx) {
+            Some(Operand::LiteralBit32(v)) => Some(*v),
+            _ => None,
+        };
+        let ty = match inst.class.opcode {
+            Op::TypeBool => Some(ConstType::Bool),
+            Op::TypeInt => literal(0)
+                .zip(literal(1))
+                .map(|(width, signed)| ConstType::Int {
+                    width,
+                    signed: signed == 1,
+                }),
+            Op::TypeFloat => literal(0).map(|width| ConstType::Float { width }),
+            Op::TypeVector | Op::TypeMatrix => {
+                match (
+                    inst.operands.get(0).and_then(|op| op.id_ref_any()),
+                    literal(1),
+                ) {
+                    (Some(elem), Some(count)) => {
+                        Some(ConstType::Composite(vec![elem; count as usize]))
+                    }
+                    _ => None,
+                }
+            }
+            Op::TypeArray => {
+                let elem = inst.operands.get(0).and_then(|op| op.id_ref_any());
+                let len = inst
+                    .operands
+                    .get(1)
+                    .and_then(|op| op.id_ref_any())
+                    .and_then(|len| self.values.get(&len))
+                    .and_then(|len| len.as_u64());
+                elem.zip(len)
+                    .map(|(elem, len)| ConstType::Array { elem, len })
+            }
+            Op::TypeStruct => Some(ConstType::Composite(
+                inst.operands
+                    .iter()
+                    .filter_map(|op| op.id_ref_any())
+                    .collect(),
+            )),
+            _ => None,
+        };
+        if let Some(ty) = ty {
+            self.types.insert(id, ty);
+            return;
+        }
+
+        let Some(ty) = inst.result_type else {
+            return;
+        };
+        let value = match inst.class.opcode {
+            Op::ConstantTrue => Some(ConstValue::Bool(true)),
+            Op::ConstantFalse => Some(ConstValue::Bool(false)),
+            Op::Constant => self.literal_value(ty, &inst.operands),
+            Op::ConstantNull => self.zero(ty),
+            Op::ConstantComposite => inst
+                .operands
+                .iter()
+                .map(|op| op.id_ref_any().and_then(|id| self.values.get(&id).cloned()))
+                .collect::<Option<Vec<_>>>()
+                .map(ConstValue::Composite),
+            _ => None,
+        };
+        if let Some(value) = value {
+            self.values.insert(id, value);
+        }
+    }
+
+    ///Value of the constant `id`, if it is known.
+    pub fn value(&self, id: Word) -> Option<&ConstValue> {
+        self.values.get(&id)
+    }
+
+    ///Sets the value of `id`, for instance after evaluating an instruction that defines it.
+    pub fn set_value(&mut self, id: Word, value: ConstValue) {
+        self.values.insert(id, value);
+    }
+
+    ///Id of a constant of type `ty` with the given `value`. If the module declares none, the constant (and its
+    /// constituents) are declared at the end of the global declarations. Returns `None` if the type is not known.
+    pub fn find_or_insert(
+        &mut self,
+        module: &mut Module,
+        ty: Word,
+        value: &ConstValue,
+    ) -> Option<Word> {
+        let existing = module.types_global_values.iter().find(|inst| {
+            inst.result_type == Some(ty)
+                && is_constant(inst.class.opcode)
+                && inst.result_id.and_then(|id| self.values.get(&id)) == Some(value)
+        });
+        if let Some(id) = existing.and_then(|inst| inst.result_id) {
+            return Some(id);
+        }
+
+        let (op, operands) = match (value, value.scalar_parts()) {
+            (_, Some(parts)) => parts,
+            (ConstValue::Composite(members), None) => {
+                if self.member_count(ty)? != members.len() as u64 {
+                    return None;
+                }
+                let mut constituents = Vec::with_capacity(members.len());
+                for (idx, member) in members.iter().enumerate() {
+                    let member_ty = self.member_type(ty, idx)?;
+                    constituents.push(Operand::IdRef(
+                        self.find_or_insert(module, member_ty, member)?,
+                    ));
+                }
+                (Op::ConstantComposite, constituents)
+            }
+            (_, None) => return None,
+        };
+        let id = module.allocate_id();
+        module
+            .types_global_values
+            .push(Instruction::new(op, Some(ty), Some(id), operands));
+        self.values.insert(id, value.clone());
+        Some(id)
+    }
+
+    ///Zero value of type `ty`, as declared by `OpConstantNull`. `None` for types with more than 65536 scalars.
+    pub fn zero(&self, ty: Word) -> Option<ConstValue> {
+        if self.scalar_count(ty)? > MAX_ZERO_SCALARS {
+            return None;
+        }
+        match self.types.get(&ty)? {
+            ConstType::Bool => Some(ConstValue::Bool(false)),
+            ConstType::Int { width, signed } => Some(ConstValue::Int {
+                bits: 0,
+                width: *width,
+                signed: *signed,
+            }),
+            ConstType::Float { width } => Some(ConstValue::Float {
+                bits: 0,
+                width: *width,
+            }),
+            ConstType::Composite(members) => members
+                .iter()
+                .map(|member| self.zero(*member))
+                .collect::<Option<Vec<_>>>()
+                .map(ConstValue::Composite),
+            ConstType::Array { elem, len } => Some(ConstValue::Composite(vec![
+                self.zero(*elem)?;
+                *len as usize
+            ])),
+        }
+    }
+
+    ///Type of member `idx` of the composite type `ty`.
+    pub fn member_type(&self, ty: Word, idx: usize) -> Option<Word> {
+        match self.types.get(&ty)? {
+            ConstType::Composite(members) => members.get(idx).copied(),
+            ConstType::Array { elem, len } => ((idx as u64) < *len).then_some(*elem),
+            _ => None,
+        }
+    }
+
+    ///Number of members of the composite type `ty`.
+    pub fn member_count(&self, ty: Word) -> Option<u64> {
+        match self.types.get(&ty)? {
+            ConstType::Composite(members) => Some(members.len() as u64),
+            ConstType::Array { len, .. } => Some(*len),
+            _ => None,
+        }
+    }
+
+    //Number of scalars in a value of type `ty`, saturating.
+    fn scalar_count(&self, ty: Word) -> Option<u64> {
+        match self.types.get(&ty)? {
+            ConstType::Composite(members) => members.iter().try_fold(0u64, |sum, member| {
+                Some(sum.saturating_add(self.scalar_count(*member)?))
+            }),
+            ConstType::Array { elem, len } => Some(self.scalar_count(*elem)?.saturating_mul(*len)),
+            _ => Some(1),
+        }
+    }
+
+    ///Interprets the literal `operands` of an `OpConstant` or `OpSpecConstant` of type `ty`.
+    pub fn literal_value(&self, ty: Word, operands: &[Operand]) -> Option<ConstValue> {
+        let bits = match operands {
+            [Operand::LiteralBit32(v)] => *v as u64,
+            [Operand::LiteralBit64(v)] => *v,
+            _ => return None,
+        };
+        match self.types.get(&ty)? {
+            ConstType::Int { width, signed } => Some(ConstValue::Int {
+                bits: mask(bits, *width),
+                width: *width,
+                signed: *signed,
+            }),
+            ConstType::Float { width } => Some(ConstValue::Float {
+                bits: mask(bits, *width),
+                width: *width,
+            }),
+            _ => None,
+        }
+    }
+
+    ///Evaluates `inst` if all its operands are known constants. `OpSpecConstantOp` is evaluated as the operation it
+    /// wraps.
+    pub fn eval_instruction(&self, inst: &Instruction) -> Option<ConstValue> {
+        let ty = inst.result_type?;
+        match (inst.class.opcode, inst.operands.split_first()) {
+            (Op::SpecConstantOp, Some((Operand::LiteralSpecConstantOpInteger(op), operands))) => {
+                self.eval(*op, ty, operands)
+            }
+            (op, _) => self.eval(op, ty, &inst.operands),
+        }
+    }
+
+    ///Evaluates `op` with the given `operands` and result type `result_type`.
+    pub fn eval(&self, op: Op, result_type: Word, operands: &[Operand]) -> Option<ConstValue> {
+        let arg = |idx: usize| {
+            operands
+                .get(idx)
+                .and_then(|op| op.id_ref_any())
+                .and_then(|id| self.values.get(&id))
+        };
+        let literal = |idx: usize| match operands.get(idx) {
+            Some(Operand::LiteralBit32(v)) => Some(*v),
+            _ => None,
+        };
+
+        match op {
+            Op::CopyObject => arg(0).cloned(),
+            Op::Select => {
+                let (cond, a, b) = (arg(0)?, arg(1)?, arg(2)?);
+                match cond {
+                    ConstValue::Bool(c) => Some(if *c { a.clone() } else { b.clone() }),
+                    //Component-wise selection
+                    ConstValue::Composite(conds) => match (a, b) {
+                        (ConstValue::Composite(a), ConstValue::Composite(b))
+                            if a.len() == conds.len() && b.len() == conds.len() =>
+                        {
+                            conds
+                                .iter()
+                                .zip(a.iter().zip(b.iter()))
+                                .map(|(c, (a, b))| {
+                                    c.as_bool().map(|c| if c { a.clone() } else { b.clone() })
+                                })
+                                .collect::<Option<Vec<_>>>()
+                                .map(ConstValue::Composite)
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+            Op::CompositeConstruct => {
+                let count = self.member_count(result_type)?;
+                let mut constituents = Vec::with_capacity(operands.len());
+                for idx in 0..operands.len() {
+                    let member_is_composite = matches!(
+                        self.member_type(result_type, constituents.len())
+                            .and_then(|ty| self.types.get(&ty)),
+                        Some(ConstType::Composite(_) | ConstType::Array { .. })
+                    );
+                    match arg(idx)? {
+                        //Vectors may be constructed from smaller vectors
+                        ConstValue::Composite(parts) if !member_is_composite => {
+                            constituents.extend(parts.iter().cloned())
+                        }
+                        value => constituents.push(value.clone()),
+                    }
+                }
+                (constituents.len() as u64 == count).then_some(ConstValue::Composite(constituents))
+            }
+            Op::CompositeExtract => {
+                let mut value = arg(0)?;
+                for idx in 1..operands.len() {
+                    match value {
+                        ConstValue::Composite(members) => {
+                            value = members.get(literal(idx)? as usize)?;
+                        }
+                        _ => return None,
+                    }
+                }
+                Some(value.clone())
+            }
+            Op::CompositeInsert => {
+                let object = arg(0)?.clone();
+                let mut composite = arg(1)?.clone();
+                let mut target = &mut composite;
+                for idx in 2..operands.len() {
+                    match target {
+                        ConstValue::Composite(members) => {
+                            target = members.get_mut(literal(idx)? as usize)?;
+                        }
+                        _ => return None,
+                    }
+                }
+                *target = object;
+                Some(composite)
+            }
+            Op::VectorShuffle => {
+                let (ConstValue::Composite(a), ConstValue::Composite(b)) = (arg(0)?, arg(1)?)
+                else {
+                    return None;
+                };
+                (2..operands.len())
+                    .map(|idx| {
+                        let component = literal(idx)? as usize;
+                        //0xFFFFFFFF selects an undefined component, which is not evaluated
+                        if component < a.len() {
+                            a.get(component).cloned()
+                        } else {
+                            b.get(component - a.len()).cloned()
+                        }
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .map(ConstValue::Composite)
+            }
+            Op::ExtInst => {
+                let (Some(Operand::IdRef(set)), Some(Operand::LiteralExtInstInteger(inst))) =
+                    (operands.first(), operands.get(1))
+                else {
+                    return None;
+                };
+                if Some(*set) != self.glsl_std_450 {
+                    return None;
+                }
+                let args = (2..operands.len()).map(arg).collect::<Option<Vec<_>>>()?;
+                self.eval_componentwise(result_type, &args, &|ty, args| eval_glsl(*inst, ty, args))
+            }
+            _ => {
+                let args = (0..operands.len()).map(arg).collect::<Option<Vec<_>>>()?;
+                self.eval_componentwise(result_type, &args, &|ty, args| {
+                    self.eval_scalar(op, ty, args)
+                })
+            }
+        }
+    }
+
+    //Evaluates scalar operations, or vector operations component by component. Scalar arguments are used for all
+    // components.
+    fn eval_componentwise(
+        &self,
+        result_type: Word,
+        args: &[&ConstValue],
+        scalar: &dyn Fn(&ConstType, &[&ConstValue]) -> Option<ConstValue>,
+    ) -> Option<ConstValue> {
+        if let Some(ConstType::Composite(members)) = self.types.get(&result_type) {
+            return members
+                .iter()
+                .enumerate()
+                .map(|(idx, member)| {
+                    let component = args
+                        .iter()
+                        .map(|arg| match arg {
+                            ConstValue::Composite(parts) => parts.get(idx),
+                            scalar => Some(*scalar),
+                        })
+                        .collect::<Option<Vec<_>>>()?;
+                    self.eval_componentwise(*member, &component, scalar)
+                })
+                .collect::<Option<Vec<_>>>()
+                .map(ConstValue::Composite);
+        }
+        //Scalar results of vector operands are not component-wise (for instance OpDot).
+        if args
+            .iter()
+            .any(|arg| matches!(arg, ConstValue::Composite(_)))
+        {
+            return None;
+        }
+        scalar(self.types.get(&result_type)?, args)
+    }
+
+    fn eval_scalar(&self, op: Op, ty: &ConstType, args: &[&ConstValue]) -> Option<ConstValue> {
+        let int = |bits: u64| int_value(ty, bits);
+        let float = |value: f64| float_value(ty, value);
+        let b = |value: bool| Some(ConstValue::Bool(value));
+
+        match (op, args) {
+            //Integer arithmetic
+            (Op::SNegate, [a]) => int(a.as_i64()?.wrapping_neg() as u64),
+            (Op::Not, [a]) => int(!a.as_u64()?),
+            (Op::IAdd, [a, c]) => int(a.as_u64()?.wrapping_add(c.as_u64()?)),
+            (Op::ISub, [a, c]) => int(a.as_u64()?.wrapping_sub(c.as_u64()?)),
+            (Op::IMul, [a, c]) => int(a.as_u64()?.wrapping_mul(c.as_u64()?)),
+            (Op::UDiv, [a, c]) => int(a.as_u64()?.checked_div(c.as_u64()?)?),
+            (Op::UMod, [a, c]) => int(a.as_u64()?.checked_rem(c.as_u64()?)?),
+            (Op::SDiv, [a, c]) => int(a.as_i64()?.checked_div(c.as_i64()?)? as u64),
+            (Op::SRem, [a, c]) => int(a.as_i64()?.checked_rem(c.as_i64()?)? as u64),
+            (Op::SMod, [a, c]) => {
+                let (a, c) = (a.as_i64()?, c.as_i64()?);
+                let rem = a.checked_rem(c)?;
+                //The result takes the sign of the divisor
+                int(if rem != 0 && (rem < 0) != (c < 0) {
+                    rem + c
+                } else {
+                    rem
+                } as u64)
+            }
+            (Op::ShiftLeftLogical | Op::ShiftRightLogical | Op::ShiftRightArithmetic, [a, s]) => {
+                let s = s.as_u64()?;
+                //Shifting by the width or more is undefined
+                if s >= int_width(a)? as u64 {
+                    return None;
+                }
+                int(match op {
+                    Op::ShiftLeftLogical => a.as_u64()? << s,
+                    Op::ShiftRightLogical => a.as_u64()? >> s,
+                    _ => (a.as_i64()? >> s) as u64,
+                })
+            }
+            (Op::BitwiseOr, [a, c]) => int(a.as_u64()? | c.as_u64()?),
+            (Op::BitwiseXor, [a, c]) => int(a.as_u64()? ^ c.as_u64()?),
+            (Op::BitwiseAnd, [a, c]) => int(a.as_u64()? & c.as_u64()?),
+
+            //Integer comparison
+            (Op::IEqual, [a, c]) => b(a.as_u64()? == c.as_u64()?),
+            (Op::INotEqual, [a, c]) => b(a.as_u64()? != c.as_u64()?),
+            (Op::ULessThan, [a, c]) => b(a.as_u64()? < c.as_u64()?),
+            (Op::ULessThanEqual, [a, c]) => b(a.as_u64()? <= c.as_u64()?),
+            (Op::UGreaterThan, [a, c]) => b(a.as_u64()? > c.as_u64()?),
+            (Op::UGreaterThanEqual, [a, c]) => b(a.as_u64()? >= c.as_u64()?),
+            (Op::SLessThan, [a, c]) => b(a.as_i64()? < c.as_i64()?),
+            (Op::SLessThanEqual, [a, c]) => b(a.as_i64()? <= c.as_i64()?),
+            (Op::SGreaterThan, [a, c]) => b(a.as_i64()? > c.as_i64()?),
+            (Op::SGreaterThanEqual, [a, c]) => b(a.as_i64()? >= c.as_i64()?),
+
+            //Logical
+            (Op::LogicalNot, [a]) => b(!a.as_bool()?),
+            (Op::LogicalOr, [a, c]) => b(a.as_bool()? || c.as_bool()?),
+            (Op::LogicalAnd, [a, c]) => b(a.as_bool()? && c.as_bool()?),
+            (Op::LogicalEqual, [a, c]) => b(a.as_bool()? == c.as_bool()?),
+            (Op::LogicalNotEqual, [a, c]) => b(a.as_bool()? != c.as_bool()?),
+
+            //Float arithmetic. 32 bit floats are computed in double precision, which rounds to the same result for
+            // those operations.
+            (Op::FNegate, [a]) => float(-a.as_f64()?),
+            (Op::FAdd, [a, c]) => float(a.as_f64()? + c.as_f64()?),
+            (Op::FSub, [a, c]) => float(a.as_f64()? - c.as_f64()?),
+            (Op::FMul | Op::VectorTimesScalar, [a, c]) => float(a.as_f64()? * c.as_f64()?),
+            (Op::FDiv, [a, c]) => float(a.as_f64()? / c.as_f64()?),
+            (Op::FRem, [a, c]) => float(a.as_f64()? % c.as_f64()?),
+            (Op::FMod, [a, c]) => {
+                let (a, c) = (a.as_f64()?, c.as_f64()?);
+                //The result takes the sign of the divisor
+                let rem = a % c;
+                float(if rem != 0.0 && (rem < 0.0) != (c < 0.0) {
+                    rem + c
+                } else {
+                    rem
+                })
+            }
+
+            //Float comparison. Ordered comparisons are false if either operand is NaN, unordered ones true.
+            (Op::FOrdEqual, [a, c]) => b(a.as_f64()? == c.as_f64()?),
+            (Op::FOrdNotEqual, [a, c]) => {
+                let (a, c) = (a.as_f64()?, c.as_f64()?);
+                b(!a.is_nan() && !c.is_nan() && a != c)
+            }
+            (Op::FOrdLessThan, [a, c]) => b(a.as_f64()? < c.as_f64()?),
+            (Op::FOrdLessThanEqual, [a, c]) => b(a.as_f64()? <= c.as_f64()?),
+            (Op::FOrdGreaterThan, [a, c]) => b(a.as_f64()? > c.as_f64()?),
+            (Op::FOrdGreaterThanEqual, [a, c]) => b(a.as_f64()? >= c.as_f64()?),
+            (Op::FUnordEqual, [a, c]) => {
+                let (a, c) = (a.as_f64()?, c.as_f64()?);
+                b(a.is_nan() || c.is_nan() || a == c)
+            }
+            (Op::FUnordNotEqual, [a, c]) => b(a.as_f64()? != c.as_f64()?),
+            (Op::FUnordLessThan, [a, c]) => b(unordered(a, c, |a, c| a < c)?),
+            (Op::FUnordLessThanEqual, [a, c]) => b(unordered(a, c, |a, c| a <= c)?),
+            (Op::FUnordGreaterThan, [a, c]) => b(unordered(a, c, |a, c| a > c)?),
+            (Op::FUnordGreaterThanEqual, [a, c]) => b(unordered(a, c, |a, c| a >= c)?),
+
+            //Conversions
+            (Op::UConvert, [a]) => int(a.as_u64()?),
+            (Op::SConvert, [a]) => int(a.as_i64()? as u64),
+            (Op::FConvert, [a]) => float(a.as_f64()?),
+            (Op::ConvertUToF, [a]) => float(a.as_u64()? as f64),
+            (Op::ConvertSToF, [a]) => float(a.as_i64()? as f64),
+            (Op::ConvertFToU, [a]) => {
+                let value = a.as_f64()?;
+                let ConstType::Int { width, .. } = ty else {
+                    return None;
+                };
+                //Out of range conversions are undefined
+                if !(value.is_finite() && value > -1.0 && value < 2f64.powi(*width as i32)) {
+                    return None;
+                }
+                int(value as u64)
+            }
+            (Op::ConvertFToS, [a]) => {
+                let value = a.as_f64()?;
+                let ConstType::Int { width, .. } = ty else {
+                    return None;
+                };
+                let limit = 2f64.powi(*width as i32 - 1);
+                if !(value.is_finite() && value >= -limit && value < limit) {
+                    return None;
+                }
+                int(value as i64 as u64)
+            }
+            (Op::Bitcast, [a]) => {
+                let (bits, width) = match a {
+                    ConstValue::Int { bits, width, .. } | ConstValue::Float { bits, width } => {
+                        (*bits, *width)
+                    }
+                    _ => return None,
+                };
+                match ty {
+                    ConstType::Int { width: w, signed } if *w == width => Some(ConstValue::Int {
+                        bits,
+                        width,
+                        signed: *signed,
+                    }),
+                    ConstType::Float { width: w } if *w == width => {
+                        Some(ConstValue::Float { bits, width })
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn int_value(ty: &ConstType, bits: u64) -> Option<ConstValue> {
+    match ty {
+        ConstType::Int { width, signed } => Some(ConstValue::Int {
+            bits: mask(bits, *width),
+            width: *width,
+            signed: *signed,
+        }),
+        _ => None,
+    }
+}
+
+fn float_value(ty: &ConstType, value: f64) -> Option<ConstValue> {
+    match ty {
+        ConstType::Float { width: 32 } => Some(ConstValue::Float {
+            bits: (value as f32).to_bits() as u64,
+            width: 32,
+        }),
+        ConstType::Float { width: 64 } => Some(ConstValue::Float {
+            bits: value.to_bits(),
+            width: 64,
+        }),
+        _ => None,
+    }
+}
+
+fn is_constant(op: Op) -> bool {
+    matches!(
+        op,
+        Op::Constant
+            | Op::ConstantTrue
+            | Op::ConstantFalse
+            | Op::ConstantNull
+            | Op::ConstantComposite
+    )
+}
+
+//Evaluates the scalar `GLSL.std.450` instruction `inst`. Float results are computed with the host's math library,
+// which is at least as precise as Vulkan requires. Results that are not finite are not folded, since most of them are
+// undefined (for instance `Sqrt` of a negative number).
+fn eval_glsl(inst: u32, ty: &ConstType, args: &[&ConstValue]) -> Option<ConstValue> {
+    let float = |value: f64| {
+        if value.is_finite() {
+            float_value(ty, value)
+        } else {
+            None
+        }
+    };
+    let int = |bits: u64| int_value(ty, bits);
+
+    match (inst, args) {
+        (1, [x]) => float(x.as_f64()?.round()),
+        (2, [x]) => {
+            let x = x.as_f64()?;
+            //Ties to the even neighbour
+            if (x - x.trunc()).abs() == 0.5 {
+                float(2.0 * (x / 2.0).round())
+            } else {
+                float(x.round())
+            }
+        }
+        (3, [x]) => float(x.as_f64()?.trunc()),
+        (4, [x]) => float(x.as_f64()?.abs()),
+        (5, [x]) => int(x.as_i64()?.wrapping_abs() as u64),
+        (6, [x]) => {
+            let x = x.as_f64()?;
+            float(if x > 0.0 {
+                1.0
+            } else if x < 0.0 {
+                -1.0
+            } else {
+                0.0
+            })
+        }
+        (7, [x]) => int(x.as_i64()?.signum() as u64),
+        (8, [x]) => float(x.as_f64()?.floor()),
+        (9, [x]) => float(x.as_f64()?.ceil()),
+        (10, [x]) => {
+            let x = x.as_f64()?;
+            float(x - x.floor())
+        }
+        (11, [x]) => float(x.as_f64()?.to_radians()),
+        (12, [x]) => float(x.as_f64()?.to_degrees()),
+        (13, [x]) => float(x.as_f64()?.sin()),
+        (14, [x]) => float(x.as_f64()?.cos()),
+        (15, [x]) => float(x.as_f64()?.tan()),
+        (16, [x]) => float(x.as_f64()?.asin()),
+        (17, [x]) => float(x.as_f64()?.acos()),
+        (18, [x]) => float(x.as_f64()?.atan()),
+        (19, [x]) => float(x.as_f64()?.sinh()),
+        (20, [x]) => float(x.as_f64()?.cosh()),
+        (21, [x]) => float(x.as_f64()?.tanh()),
+        (22, [x]) => float(x.as_f64()?.asinh()),
+        (23, [x]) => float(x.as_f64()?.acosh()),
+        (24, [x]) => float(x.as_f64()?.atanh()),
+        (25, [y, x]) => float(y.as_f64()?.atan2(x.as_f64()?)),
+        (26, [x, y]) => {
+            let (x, y) = (x.as_f64()?, y.as_f64()?);
+            //Undefined for x < 0, or x == 0 and y <= 0
+            if x < 0.0 || (x == 0.0 && y <= 0.0) {
+                return None;
+            }
+            float(x.powf(y))
+        }
+        (27, [x]) => float(x.as_f64()?.exp()),
+        (28, [x]) => float(x.as_f64()?.ln()),
+        (29, [x]) => float(x.as_f64()?.exp2()),
+        (30, [x]) => float(x.as_f64()?.log2()),
+        (31, [x]) => float(x.as_f64()?.sqrt()),
+        (32, [x]) => float(1.0 / x.as_f64()?.sqrt()),
+        (37, [x, y]) => float(x.as_f64()?.min(y.as_f64()?)),
+        (38, [x, y]) => int(x.as_u64()?.min(y.as_u64()?)),
+        (39, [x, y]) => int(x.as_i64()?.min(y.as_i64()?) as u64),
+        (40, [x, y]) => float(x.as_f64()?.max(y.as_f64()?)),
+        (41, [x, y]) => int(x.as_u64()?.max(y.as_u64()?)),
+        (42, [x, y]) => int(x.as_i64()?.max(y.as_i64()?) as u64),
+        (43, [x, lo, hi]) => {
+            let (x, lo, hi) = (x.as_f64()?, lo.as_f64()?, hi.as_f64()?);
+            //Undefined if lo > hi
+            if lo > hi {
+                return None;
+            }
+            float(x.max(lo).min(hi))
+        }
+        (44, [x, lo, hi]) => {
+            let (x, lo, hi) = (x.as_u64()?, lo.as_u64()?, hi.as_u64()?);
+            if lo > hi {
+                return None;
+            }
+            int(x.clamp(lo, hi))
+        }
+        (45, [x, lo, hi]) => {
+            let (x, lo, hi) = (x.as_i64()?, lo.as_i64()?, hi.as_i64()?);
+            if lo > hi {
+                return None;
+            }
+            int(x.clamp(lo, hi) as u64)
+        }
+        (46, [x, y, a]) => {
+            let (x, y, a) = (x.as_f64()?, y.as_f64()?, a.as_f64()?);
+            float(x * (1.0 - a) + y * a)
+        }
+        (48, [edge, x]) => float(if x.as_f64()? < edge.as_f64()? {
+            0.0
+        } else {
+            1.0
+        }),
+        (49, [edge0, edge1, x]) => {
+            let (edge0, edge1, x) = (edge0.as_f64()?, edge1.as_f64()?, x.as_f64()?);
+            //Undefined if edge0 >= edge1
+            if edge0 >= edge1 {
+                return None;
+            }
+            let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+            float(t * t * (3.0 - 2.0 * t))
+        }
+        (50, [a, b, c]) => float(a.as_f64()?.mul_add(b.as_f64()?, c.as_f64()?)),
+        _ => None,
+    }
+}
+
+fn int_width(value: &ConstValue) -> Option<u32> {
+    match value {
+        ConstValue::Int { width, .. } => Some(*width),
+        _ => None,
+    }
+}
+
+//Unordered comparison, true if either operand is NaN.
+fn unordered(a: &ConstValue, c: &ConstValue, cmp: fn(f64, f64) -> bool) -> Option<bool> {
+    let (a, c) = (a.as_f64()?, c.as_f64()?);
+    Some(a.is_nan() || c.is_nan() || cmp(a, c))
+}
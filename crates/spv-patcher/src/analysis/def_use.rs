@@ -0,0 +1,548 @@
+use ahash::AHashMap;
+use rspirv::{
+    dr::{Instruction, Module},
+    spirv::{Op, Word},
+};
+
+///Global section of a module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Section {
+    Capabilities,
+    Extensions,
+    ExtInstImports,
+    MemoryModel,
+    EntryPoints,
+    ExecutionModes,
+    DebugStringSource,
+    DebugNames,
+    DebugModuleProcessed,
+    Annotations,
+    TypesGlobalValues,
+}
+
+impl Section {
+    const ALL: [Section; 11] = [
+        Section::Capabilities,
+        Section::Extensions,
+        Section::ExtInstImports,
+        Section::MemoryModel,
+        Section::EntryPoints,
+        Section::ExecutionModes,
+        Section::DebugStringSource,
+        Section::DebugNames,
+        Section::DebugModuleProcessed,
+        Section::Annotations,
+        Section::TypesGlobalValues,
+    ];
+
+    fn instructions<'m>(&self, module: &'m Module) -> &'m [Instruction] {
+        match self {
+            Section::Capabilities => &module.capabilities,
+            Section::Extensions => &module.extensions,
+            Section::ExtInstImports => &module.ext_inst_imports,
+            Section::MemoryModel => match &module.memory_model {
+                Some(inst) => std::slice::from_ref(inst),
+                None => &[],
+            },
+            Section::EntryPoints => &module.entry_points,
+            Section::ExecutionModes => &module.execution_modes,
+            Section::DebugStringSource => &module.debug_string_source,
+            Section::DebugNames => &module.debug_names,
+            Section::DebugModuleProcessed => &module.debug_module_processed,
+            Section::Annotations => &module.annotations,
+            Section::TypesGlobalValues => &module.types_global_values,
+        }
+    }
+
+    //`None` for the memory model, which is not a list.
+    fn instructions_mut<'m>(&self, module: &'m mut Module) -> Option<&'m mut Vec<Instruction>> {
+        Some(match self {
+            Section::Capabilities => &mut module.capabilities,
+            Section::Extensions => &mut module.extensions,
+            Section::ExtInstImports => &mut module.ext_inst_imports,
+            Section::MemoryModel => return None,
+            Section::EntryPoints => &mut module.entry_points,
+            Section::ExecutionModes => &mut module.execution_modes,
+            Section::DebugStringSource => &mut module.debug_string_source,
+            Section::DebugNames => &mut module.debug_names,
+            Section::DebugModuleProcessed => &mut module.debug_module_processed,
+            Section::Annotations => &mut module.annotations,
+            Section::TypesGlobalValues => &mut module.types_global_values,
+        })
+    }
+}
+
+///Location of an instruction within a module. Functions and blocks are addressed by index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstLocation {
+    Global {
+        section: Section,
+        index: usize,
+    },
+    ///The `OpFunction` instruction of a function.
+    FunctionDef {
+        function: usize,
+    },
+    Parameter {
+        function: usize,
+        index: usize,
+    },
+    ///The `OpLabel` of a block.
+    Label {
+        function: usize,
+        block: usize,
+    },
+    ///An instruction within a block.
+    Block {
+        function: usize,
+        block: usize,
+        index: usize,
+    },
+}
+
+impl InstLocation {
+    ///Index of the function this location is part of, or `None` for global instructions.
+    pub fn function(&self) -> Option<usize> {
+        match self {
+            InstLocation::Global { .. } => None,
+            InstLocation::FunctionDef { function }
+            | InstLocation::Parameter { function, .. }
+            | InstLocation::Label { function, .. }
+            | InstLocation::Block { function, .. } => Some(*function),
+        }
+    }
+
+    ///Returns the instruction at this location.
+    pub fn get<'m>(&self, module: &'m Module) -> Option<&'m Instruction> {
+        match *self {
+            InstLocation::Global { section, index } => section.instructions(module).get(index),
+            InstLocation::FunctionDef { function } => module.functions.get(function)?.def.as_ref(),
+            InstLocation::Parameter { function, index } => {
+                module.functions.get(function)?.parameters.get(index)
+            }
+            InstLocation::Label { function, block } => module
+                .functions
+                .get(function)?
+                .blocks
+                .get(block)?
+                .label
+                .as_ref(),
+            InstLocation::Block {
+                function,
+                block,
+                index,
+            } => module
+                .functions
+                .get(function)?
+                .blocks
+                .get(block)?
+                .instructions
+                .get(index),
+        }
+    }
+
+    ///Returns the instruction at this location mutably.
+    pub fn get_mut<'m>(&self, module: &'m mut Module) -> Option<&'m mut Instruction> {
+        match *self {
+            InstLocation::Global {
+                section: Section::MemoryModel,
+                index: 0,
+            } => module.memory_model.as_mut(),
+            InstLocation::Global { section, index } => {
+                section.instructions_mut(module)?.get_mut(index)
+            }
+            InstLocation::FunctionDef { function } => {
+                module.functions.get_mut(function)?.def.as_mut()
+            }
+            InstLocation::Parameter { function, index } => module
+                .functions
+                .get_mut(function)?
+                .parameters
+                .get_mut(index),
+            InstLocation::Label { function, block } => module
+                .functions
+                .get_mut(function)?
+                .blocks
+                .get_mut(block)?
+                .label
+                .as_mut(),
+            InstLocation::Block {
+                function,
+                block,
+                index,
+            } => module
+                .functions
+                .get_mut(function)?
+                .blocks
+                .get_mut(block)?
+                .instructions
+                .get_mut(index),
+        }
+    }
+
+    //Returns the list and index for locations that allow inserting instructions.
+    fn container(&self) -> Option<(Container, usize)> {
+        match *self {
+            InstLocation::Global { section, index } if section != Section::MemoryModel => {
+                Some((Container::Section(section), index))
+            }
+            InstLocation::Block {
+                function,
+                block,
+                index,
+            } => Some((Container::Block { function, block }, index)),
+            _ => None,
+        }
+    }
+
+    fn with_index(&self, new_index: usize) -> Self {
+        match *self {
+            InstLocation::Global { section, .. } => InstLocation::Global {
+                section,
+                index: new_index,
+            },
+            InstLocation::Block {
+                function, block, ..
+            } => InstLocation::Block {
+                function,
+                block,
+                index: new_index,
+            },
+            other => other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Section(Section),
+    Block { function: usize, block: usize },
+}
+
+///Which part of an instruction uses an id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UseSlot {
+    ResultType,
+    ///Index into the instruction's operands.
+    Operand(usize),
+}
+
+///A single use of an id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Use {
+    pub location: InstLocation,
+    pub slot: UseSlot,
+}
+
+///Def-use and use-def index of a module.
+///
+/// The index is only valid as long as the module is changed through it, or not at all. [IrState](crate::patch::IrState)
+/// caches the index of its SPIR-V representation, use [IrState::def_use_mut](crate::patch::IrState::def_use_mut) to
+/// mutate the module while keeping the index up to date.
+///
+/// [kill](DefUse::kill) replaces instructions by `OpNop` instead of removing them, so that locations stay stable.
+/// [compact](DefUse::compact) removes them again, which `IrState` does automatically before the module is handed out
+/// in any other way.
+#[derive(Debug, Clone, Default)]
+pub struct DefUse {
+    defs: AHashMap<Word, InstLocation>,
+    uses: AHashMap<Word, Vec<Use>>,
+    killed: usize,
+}
+
+impl DefUse {
+    pub fn new(module: &Module) -> Self {
+        let mut def_use = DefUse::default();
+        for section in Section::ALL {
+            for (index, inst) in section.instructions(module).iter().enumerate() {
+                def_use.record(InstLocation::Global { section, index }, inst);
+            }
+        }
+        for (fidx, function) in module.functions.iter().enumerate() {
+            if let Some(def) = &function.def {
+                def_use.record(InstLocation::FunctionDef { function: fidx }, def);
+            }
+            for (index, param) in function.parameters.iter().enumerate() {
+                def_use.record(
+                    InstLocation::Parameter {
+                        function: fidx,
+                        index,
+                    },
+                    param,
+                );
+            }
+            for (bidx, block) in function.blocks.iter().enumerate() {
+                if let Some(label) = &block.label {
+                    def_use.record(
+                        InstLocation::Label {
+                            function: fidx,
+                            block: bidx,
+                        },
+                        label,
+                    );
+                }
+                for (index, inst) in block.instructions.iter().enumerate() {
+                    def_use.record(
+                        InstLocation::Block {
+                            function: fidx,
+                            block: bidx,
+                            index,
+                        },
+                        inst,
+                    );
+                }
+            }
+        }
+        def_use
+    }
+
+    fn record(&mut self, location: InstLocation, inst: &Instruction) {
+        if let Some(id) = inst.result_id {
+            self.defs.insert(id, location);
+        }
+        for (id, slot) in inst_uses(inst) {
+            self.uses
+                .entry(id)
+                .or_default()
+                .push(Use { location, slot });
+        }
+    }
+
+    fn forget(&mut self, location: InstLocation, inst: &Instruction) {
+        if let Some(id) = inst.result_id {
+            if self.defs.get(&id) == Some(&location) {
+                self.defs.remove(&id);
+            }
+        }
+        for (id, _) in inst_uses(inst) {
+            if let Some(uses) = self.uses.get_mut(&id) {
+                uses.retain(|u| u.location != location);
+            }
+        }
+    }
+
+    ///Location of the instruction defining `id`.
+    pub fn definition_of(&self, id: Word) -> Option<InstLocation> {
+        self.defs.get(&id).copied()
+    }
+
+    ///The instruction defining `id`.
+    pub fn def_inst<'m>(&self, module: &'m Module, id: Word) -> Option<&'m Instruction> {
+        self.definition_of(id)?.get(module)
+    }
+
+    ///All uses of `id`, including decorations and debug names.
+    pub fn users_of(&self, id: Word) -> &[Use] {
+        self.uses.get(&id).map(|u| u.as_slice()).unwrap_or(&[])
+    }
+
+    ///Replaces the id referenced by `to_replace` with `new`.
+    pub fn replace_use(&mut self, module: &mut Module, to_replace: Use, new: Word) {
+        let Some(inst) = to_replace.location.get_mut(module) else {
+            return;
+        };
+        let slot = match to_replace.slot {
+            UseSlot::ResultType => inst.result_type.as_mut(),
+            UseSlot::Operand(idx) => inst
+                .operands
+                .get_mut(idx)
+                .and_then(|op| op.id_ref_any_mut()),
+        };
+        let Some(slot) = slot else {
+            return;
+        };
+        let old = std::mem::replace(slot, new);
+        if old == new {
+            return;
+        }
+        if let Some(uses) = self.uses.get_mut(&old) {
+            uses.retain(|u| *u != to_replace);
+        }
+        self.uses.entry(new).or_default().push(to_replace);
+    }
+
+    ///Replaces all uses of `old` with `new`. Returns the number of replaced uses.
+    ///
+    ///Note that the definition of `old` is kept, use [kill](DefUse::kill) to remove it.
+    pub fn replace_all_uses(&mut self, module: &mut Module, old: Word, new: Word) -> usize {
+        if old == new {
+            return 0;
+        }
+        let uses = self.uses.remove(&old).unwrap_or_default();
+        for to_replace in &uses {
+            if let Some(inst) = to_replace.location.get_mut(module) {
+                match to_replace.slot {
+                    UseSlot::ResultType => inst.result_type = Some(new),
+                    UseSlot::Operand(idx) => {
+                        if let Some(id) = inst
+                            .operands
+                            .get_mut(idx)
+                            .and_then(|op| op.id_ref_any_mut())
+                        {
+                            *id = new;
+                        }
+                    }
+                }
+            }
+        }
+        let count = uses.len();
+        self.uses.entry(new).or_default().extend(uses);
+        count
+    }
+
+    ///Kills the definition of `id`, as well as all decorations and debug names targeting it. Other uses of `id`
+    /// are kept and have to be handled by the caller.
+    pub fn kill(&mut self, module: &mut Module, id: Word) {
+        let mut to_kill: Vec<InstLocation> = self
+            .users_of(id)
+            .iter()
+            .filter(|u| {
+                matches!(
+                    u.location,
+                    InstLocation::Global {
+                        section: Section::Annotations | Section::DebugNames,
+                        ..
+                    }
+                )
+            })
+            .map(|u| u.location)
+            .collect();
+        to_kill.extend(self.definition_of(id));
+        for location in to_kill {
+            self.kill_at(module, location);
+        }
+    }
+
+    ///Kills the instruction at `location`.
+    ///
+    ///Killing an `OpFunction` kills the whole function, killing a block's `OpLabel` kills the whole block. Both are
+    /// removed by [compact](DefUse::compact), just like a killed `OpFunctionParameter`. Updating call sites, branches
+    /// and function types is up to the caller.
+    pub fn kill_at(&mut self, module: &mut Module, location: InstLocation) {
+        let mut to_kill = Vec::new();
+        match location {
+            InstLocation::FunctionDef { function } => {
+                let Some(f) = module.functions.get(function) else {
+                    return;
+                };
+                to_kill.extend(
+                    (0..f.parameters.len())
+                        .map(|index| InstLocation::Parameter { function, index }),
+                );
+                for block in 0..f.blocks.len() {
+                    to_kill.push(InstLocation::Label { function, block });
+                }
+            }
+            InstLocation::Label { function, block } => {
+                let Some(b) = module
+                    .functions
+                    .get(function)
+                    .and_then(|f| f.blocks.get(block))
+                else {
+                    return;
+                };
+                to_kill.extend((0..b.instructions.len()).map(|index| InstLocation::Block {
+                    function,
+                    block,
+                    index,
+                }));
+            }
+            _ => {}
+        }
+        for inner in to_kill {
+            self.kill_at(module, inner);
+        }
+        self.kill_one(module, location);
+    }
+
+    fn kill_one(&mut self, module: &mut Module, location: InstLocation) {
+        let Some(inst) = location.get_mut(module) else {
+            return;
+        };
+        if inst.class.opcode == Op::Nop {
+            return;
+        }
+        let killed = std::mem::replace(inst, Instruction::new(Op::Nop, None, None, Vec::new()));
+        self.forget(location, &killed);
+        self.killed += 1;
+    }
+
+    ///Inserts `inst` at `location`, moving following instructions back. Only global sections (except for the memory
+    /// model) and block instructions can be inserted into.
+    ///
+    ///Note that this has to update all following locations, prefer appending where possible.
+    pub fn insert(&mut self, module: &mut Module, location: InstLocation, inst: Instruction) {
+        let (container, index) = location
+            .container()
+            .expect("Instructions can only be inserted into global sections or blocks");
+        let list = match container {
+            Container::Section(section) => section.instructions_mut(module),
+            Container::Block { function, block } => module
+                .functions
+                .get_mut(function)
+                .and_then(|f| f.blocks.get_mut(block))
+                .map(|b| &mut b.instructions),
+        }
+        .expect("Insert location does not exist");
+
+        let shifts = index < list.len();
+        list.insert(index, inst.clone());
+        if shifts {
+            let shift = |loc: &mut InstLocation| {
+                if let Some((c, i)) = loc.container() {
+                    if c == container && i >= index {
+                        *loc = loc.with_index(i + 1);
+                    }
+                }
+            };
+            self.defs.values_mut().for_each(shift);
+            self.uses
+                .values_mut()
+                .flat_map(|uses| uses.iter_mut())
+                .for_each(|u| shift(&mut u.location));
+        }
+        self.record(location, &inst);
+    }
+
+    ///True if instructions were killed since the index was built.
+    pub fn has_killed(&self) -> bool {
+        self.killed > 0
+    }
+
+    ///Removes all `OpNop`s left by [kill](DefUse::kill) from `module` and rebuilds the index.
+    pub fn compact(&mut self, module: &mut Module) {
+        let is_live = |inst: &Instruction| inst.class.opcode != Op::Nop;
+        for section in Section::ALL {
+            if let Some(list) = section.instructions_mut(module) {
+                list.retain(is_live);
+            }
+        }
+        //Killed functions, parameters and blocks are removed as a whole
+        module
+            .functions
+            .retain(|f| f.def.as_ref().map(is_live).unwrap_or(true));
+        for function in &mut module.functions {
+            function.parameters.retain(is_live);
+            function
+                .blocks
+                .retain(|b| b.label.as_ref().map(is_live).unwrap_or(true));
+            for block in &mut function.blocks {
+                block.instructions.retain(is_live);
+            }
+        }
+        *self = DefUse::new(module);
+    }
+}
+
+//All ids `inst` uses, together with the slot they are used in.
+fn inst_uses(inst: &Instruction) -> impl Iterator<Item = (Word, UseSlot)> + '_ {
+    inst.result_type
+        .map(|ty| (ty, UseSlot::ResultType))
+        .into_iter()
+        .chain(
+            inst.operands
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, op)| Some((op.id_ref_any()?, UseSlot::Operand(idx)))),
+        )
+}
@@ -0,0 +1,179 @@
+//! # SPIR-V assembly
+//!
+//! Native Rust implementation of the SPIR-V assembly text format, as used by `spirv-dis` and `spirv-as`. The
+//! [Disassembler] prints friendly names derived from `OpName`s and types, the [assemble] function reads the same format
+//! back into a module. Both work without SPIRV-Tools being installed.
+//!
+//! Use [Module::from_assembly](crate::Module::from_assembly) to load a patchable module from assembly text.
+
+mod assembler;
+mod disassembler;
+
+pub use assembler::{assemble, AssemblerError};
+pub use disassembler::{disassemble, Disassembler};
+
+use rspirv::{
+    dr::{Instruction, Operand},
+    spirv::Op,
+};
+
+const GLSL_STD_450: &str = "GLSL.std.450";
+const OPENCL_STD: &str = "OpenCL.std";
+
+//Numeric scalar type, used to format and parse context dependent literals of `OpConstant`, `OpSpecConstant` and `OpSwitch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumericType {
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+}
+
+impl NumericType {
+    fn from_instruction(inst: &Instruction) -> Option<Self> {
+        match (
+            inst.class.opcode,
+            inst.operands.get(0),
+            inst.operands.get(1),
+        ) {
+            (
+                Op::TypeInt,
+                Some(Operand::LiteralBit32(width)),
+                Some(Operand::LiteralBit32(signed)),
+            ) => Some(NumericType::Int {
+                width: *width,
+                signed: *signed == 1,
+            }),
+            (Op::TypeFloat, Some(Operand::LiteralBit32(width)), _) => {
+                Some(NumericType::Float { width: *width })
+            }
+            _ => None,
+        }
+    }
+
+    fn width(&self) -> u32 {
+        match self {
+            NumericType::Int { width, .. } | NumericType::Float { width } => *width,
+        }
+    }
+
+    ///Formats the literal `bits` of this type.
+    fn format(&self, bits: u64) -> String {
+        match *self {
+            NumericType::Int {
+                width,
+                signed: true,
+            } if width <= 32 => (bits as u32 as i32).to_string(),
+            NumericType::Int { signed: true, .. } => (bits as i64).to_string(),
+            NumericType::Int { .. } => bits.to_string(),
+            NumericType::Float { width: 32 } => {
+                format_float(f32::from_bits(bits as u32) as f64, bits, 32)
+            }
+            NumericType::Float { width: 64 } => format_float(f64::from_bits(bits), bits, 64),
+            //Half floats and other widths are printed as raw bit pattern
+            NumericType::Float { .. } => format!("0x{:x}", bits),
+        }
+    }
+
+    ///Parses `token` as literal of this type.
+    fn parse(&self, token: &str) -> Option<Operand> {
+        match *self {
+            NumericType::Int { width, .. } => {
+                let value = parse_integer(token)?;
+                if width > 32 {
+                    Some(Operand::LiteralBit64(value as u64))
+                } else {
+                    //Literals of narrow types are stored sign- or zero-extended in a single word
+                    Some(Operand::LiteralBit32(value as u32))
+                }
+            }
+            NumericType::Float { width: 32 } => {
+                let bits = match parse_nonfinite(token, 32) {
+                    Some(bits) => bits as u32,
+                    None => token.parse::<f32>().ok()?.to_bits(),
+                };
+                Some(Operand::LiteralBit32(bits))
+            }
+            NumericType::Float { width: 64 } => {
+                let bits = match parse_nonfinite(token, 64) {
+                    Some(bits) => bits,
+                    None => token.parse::<f64>().ok()?.to_bits(),
+                };
+                Some(Operand::LiteralBit64(bits))
+            }
+            NumericType::Float { .. } => {
+                let value = parse_integer(token)?;
+                Some(Operand::LiteralBit32(value as u32))
+            }
+        }
+    }
+}
+
+//Formats finite floats in decimal notation, infinities and NaNs as hex float like spirv-dis, so that NaN payloads survive.
+fn format_float(value: f64, bits: u64, width: u32) -> String {
+    if value.is_finite() {
+        return if width == 32 {
+            format!("{}", value as f32)
+        } else {
+            format!("{}", value)
+        };
+    }
+
+    let (sign, fraction, fraction_digits, exponent) = if width == 32 {
+        (bits >> 31 != 0, (bits & 0x7f_ffff) << 1, 6, 128)
+    } else {
+        (bits >> 63 != 0, bits & 0xf_ffff_ffff_ffff, 13, 1024)
+    };
+    let sign = if sign { "-" } else { "" };
+    if fraction == 0 {
+        format!("{}0x1p+{}", sign, exponent)
+    } else {
+        format!(
+            "{}0x1.{:0width$x}p+{}",
+            sign,
+            fraction,
+            exponent,
+            width = fraction_digits
+        )
+    }
+}
+
+//Parses the hex float form of infinities and NaNs emitted by [format_float].
+fn parse_nonfinite(token: &str, width: u32) -> Option<u64> {
+    let (sign, rest) = match token.strip_prefix('-') {
+        Some(rest) => (1u64, rest),
+        None => (0u64, token),
+    };
+    let (exponent, fraction_digits, fraction_bits) = if width == 32 {
+        ("p+128", 6, 23)
+    } else {
+        ("p+1024", 13, 52)
+    };
+    let mantissa = rest.strip_prefix("0x1")?.strip_suffix(exponent)?;
+    let fraction = match mantissa.strip_prefix('.') {
+        Some(digits) if digits.len() == fraction_digits => u64::from_str_radix(digits, 16).ok()?,
+        Some(_) => return None,
+        None if mantissa.is_empty() => 0,
+        None => return None,
+    };
+
+    if width == 32 {
+        Some((sign << 31) | (0xff << 23) | ((fraction >> 1) & ((1 << fraction_bits) - 1)))
+    } else {
+        Some((sign << 63) | (0x7ff << 52) | (fraction & ((1 << fraction_bits) - 1)))
+    }
+}
+
+//Parses decimal, negative decimal or `0x` prefixed hex integers. Negative values are returned in two's complement.
+fn parse_integer(token: &str) -> Option<i128> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, token),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i128>().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
@@ -0,0 +1,677 @@
+use ahash::{AHashMap, AHashSet};
+use rspirv::{
+    binary::Assemble,
+    dr::{Instruction, ModuleHeader, Operand},
+    grammar::{
+        CoreInstructionTable, GlslStd450InstructionTable, LogicalOperand,
+        OpenCLStd100InstructionTable, OperandKind, OperandQuantifier,
+    },
+    spirv::{self, Op},
+};
+use thiserror::Error;
+
+use super::{parse_integer, NumericType, GLSL_STD_450, OPENCL_STD};
+
+#[derive(Error, Debug)]
+pub enum AssemblerError {
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    #[error("line {line}: unknown instruction \"{name}\"")]
+    UnknownInstruction { line: usize, name: String },
+    #[error("line {line}: could not parse \"{token}\" as {expected}")]
+    InvalidOperand {
+        line: usize,
+        token: String,
+        expected: String,
+    },
+    #[error("line {line}: missing operand {expected} of Op{opname}")]
+    MissingOperand {
+        line: usize,
+        opname: String,
+        expected: String,
+    },
+    #[error("Id %{0} is used, but never defined")]
+    UndefinedId(String),
+    #[error("Assembled module could not be loaded: {0}")]
+    Load(#[from] rspirv::binary::ParseState),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    //`%name`, without the `%`
+    Id(String),
+    //Quoted and unescaped string
+    String(String),
+    Equals,
+    //Everything else: opcodes, enumerants and numbers
+    Word(String),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+}
+
+impl Token {
+    fn text(&self) -> String {
+        match &self.kind {
+            TokenKind::Id(name) => format!("%{}", name),
+            TokenKind::String(s) => format!("\"{}\"", s),
+            TokenKind::Equals => String::from("="),
+            TokenKind::Word(w) => w.clone(),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, AssemblerError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            //Comments run until the end of the line
+            ';' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '=' => {
+                chars.next();
+                tokens.push(Token {
+                    kind: TokenKind::Equals,
+                    line,
+                });
+            }
+            //Strings may span multiple lines, for instance the source text of `OpSource`
+            '"' => {
+                chars.next();
+                let start_line = line;
+                let mut s = String::new();
+                let mut terminated = false;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            if let Some(escaped) = chars.next() {
+                                s.push(escaped);
+                            }
+                        }
+                        '"' => {
+                            terminated = true;
+                            break;
+                        }
+                        c => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            s.push(c)
+                        }
+                    }
+                }
+                if !terminated {
+                    return Err(AssemblerError::Syntax {
+                        line: start_line,
+                        message: String::from("unterminated string literal"),
+                    });
+                }
+                tokens.push(Token {
+                    kind: TokenKind::String(s),
+                    line: start_line,
+                });
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) =
+                    chars.next_if(|c| !(c.is_whitespace() || *c == ';' || *c == '"' || *c == '='))
+                {
+                    word.push(c);
+                }
+                let kind = match word.strip_prefix('%') {
+                    Some(name) if !name.is_empty() => TokenKind::Id(name.to_owned()),
+                    _ => TokenKind::Word(word),
+                };
+                tokens.push(Token { kind, line });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+//Reads the `; Version: x.y` header comment written by the disassembler.
+fn header_version(text: &str) -> Option<(u8, u8)> {
+    text.lines()
+        .map(|l| l.trim())
+        .take_while(|l| l.is_empty() || l.starts_with(';'))
+        .find_map(|l| l.strip_prefix(';')?.trim().strip_prefix("Version:"))
+        .and_then(|v| {
+            let (major, minor) = v.trim().split_once('.')?;
+            Some((major.parse().ok()?, minor.parse().ok()?))
+        })
+}
+
+///Assembles SPIR-V assembly `text`, as produced by `spirv-dis` or the [Disassembler](super::Disassembler), into a module.
+///
+/// Ids are written as `%name`. Numeric ids (`%42`) keep their value, named ids are numbered after them in order of first
+/// appearance. The SPIR-V version is read from a `; Version: x.y` header comment and defaults to 1.0.
+pub fn assemble(text: &str) -> Result<rspirv::dr::Module, AssemblerError> {
+    let tokens = tokenize(text)?;
+    let mut assembler = Assembler::new(&tokens);
+    let mut instructions = Vec::new();
+    while assembler.pos < tokens.len() {
+        instructions.push(assembler.instruction()?);
+    }
+
+    if let Some(undefined) = assembler
+        .used
+        .iter()
+        .filter(|name| !assembler.defined.contains(*name))
+        .min()
+    {
+        return Err(AssemblerError::UndefinedId(undefined.clone()));
+    }
+
+    let mut header = ModuleHeader::new(assembler.next_id.max(assembler.max_id + 1));
+    let (major, minor) = header_version(text).unwrap_or((1, 0));
+    header.set_version(major, minor);
+
+    let mut words = header.assemble();
+    for inst in &instructions {
+        inst.assemble_into(&mut words);
+    }
+    Ok(rspirv::dr::load_words(words)?)
+}
+
+struct Assembler<'t> {
+    tokens: &'t [Token],
+    pos: usize,
+    ids: AHashMap<String, u32>,
+    //Numeric ids that are used verbatim, and therefore can't be allocated to named ids.
+    reserved: AHashSet<u32>,
+    next_id: u32,
+    max_id: u32,
+    used: AHashSet<String>,
+    defined: AHashSet<String>,
+    numeric_types: AHashMap<u32, NumericType>,
+    value_types: AHashMap<u32, u32>,
+    ext_sets: AHashMap<u32, String>,
+    opcodes: AHashMap<&'static str, Op>,
+}
+
+impl<'t> Assembler<'t> {
+    fn new(tokens: &'t [Token]) -> Self {
+        let reserved: AHashSet<u32> = tokens
+            .iter()
+            .filter_map(|t| match &t.kind {
+                TokenKind::Id(name) => name.parse::<u32>().ok(),
+                _ => None,
+            })
+            .collect();
+        let max_id = reserved.iter().copied().max().unwrap_or(0);
+
+        Assembler {
+            tokens,
+            pos: 0,
+            ids: AHashMap::default(),
+            reserved,
+            next_id: 1,
+            max_id,
+            used: AHashSet::default(),
+            defined: AHashSet::default(),
+            numeric_types: AHashMap::default(),
+            value_types: AHashMap::default(),
+            ext_sets: AHashMap::default(),
+            opcodes: CoreInstructionTable::iter()
+                .map(|inst| (inst.opname, inst.opcode))
+                .collect(),
+        }
+    }
+
+    fn id(&mut self, name: &str) -> u32 {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = match name.parse::<u32>() {
+            Ok(id) => id,
+            Err(_) => {
+                while self.reserved.contains(&self.next_id) {
+                    self.next_id += 1;
+                }
+                let id = self.next_id;
+                self.next_id += 1;
+                self.max_id = self.max_id.max(id);
+                id
+            }
+        };
+        self.ids.insert(name.to_owned(), id);
+        id
+    }
+
+    fn peek(&self) -> Option<&'t Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn line(&self) -> usize {
+        self.peek()
+            .or_else(|| self.tokens.last())
+            .map(|t| t.line)
+            .unwrap_or(0)
+    }
+
+    //True if the next token starts a new instruction, i.e. is `%x =` or `OpXyz`.
+    fn at_instruction_start(&self) -> bool {
+        match self.peek().map(|t| &t.kind) {
+            None => true,
+            Some(TokenKind::Id(_)) => {
+                matches!(
+                    self.tokens.get(self.pos + 1).map(|t| &t.kind),
+                    Some(TokenKind::Equals)
+                )
+            }
+            Some(TokenKind::Word(w)) => w
+                .strip_prefix("Op")
+                .map(|name| self.opcodes.contains_key(name))
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, AssemblerError> {
+        let line = self.line();
+        //Optional `%result =`
+        let result_id = match (
+            self.peek().map(|t| &t.kind),
+            self.tokens.get(self.pos + 1).map(|t| &t.kind),
+        ) {
+            (Some(TokenKind::Id(name)), Some(TokenKind::Equals)) => {
+                self.pos += 2;
+                self.defined.insert(name.clone());
+                Some(self.id(name))
+            }
+            _ => None,
+        };
+
+        let token = self.peek().ok_or_else(|| AssemblerError::Syntax {
+            line,
+            message: String::from("expected instruction after \"=\""),
+        })?;
+        let opcode = match &token.kind {
+            TokenKind::Word(w) => w
+                .strip_prefix("Op")
+                .and_then(|name| self.opcodes.get(name).copied())
+                .ok_or_else(|| AssemblerError::UnknownInstruction {
+                    line,
+                    name: w.clone(),
+                })?,
+            _ => {
+                return Err(AssemblerError::Syntax {
+                    line,
+                    message: format!("expected instruction, found {}", token.text()),
+                })
+            }
+        };
+        self.pos += 1;
+
+        let grammar = CoreInstructionTable::get(opcode);
+        let mut inst = Instruction::new(opcode, None, result_id, Vec::new());
+        let mut has_result = false;
+        for logical in grammar.operands {
+            match logical.kind {
+                OperandKind::IdResultType => {
+                    let ty = self.expect_id(grammar.opname, "result type")?;
+                    inst.result_type = Some(ty);
+                }
+                OperandKind::IdResult => has_result = true,
+                _ => self.logical_operand(&mut inst, logical)?,
+            }
+        }
+
+        if has_result != result_id.is_some() {
+            return Err(AssemblerError::Syntax {
+                line,
+                message: if has_result {
+                    format!("Op{} requires a result id", grammar.opname)
+                } else {
+                    format!("Op{} has no result id", grammar.opname)
+                },
+            });
+        }
+        if !self.at_instruction_start() {
+            let token = self.peek().map(|t| t.text()).unwrap_or_default();
+            return Err(AssemblerError::Syntax {
+                line: self.line(),
+                message: format!("unexpected operand {} for Op{}", token, grammar.opname),
+            });
+        }
+
+        self.track(&inst);
+        Ok(inst)
+    }
+
+    //Records type information needed to parse later literals.
+    fn track(&mut self, inst: &Instruction) {
+        let Some(id) = inst.result_id else {
+            return;
+        };
+        if let Some(ty) = inst.result_type {
+            self.value_types.insert(id, ty);
+        }
+        if let Some(nt) = NumericType::from_instruction(inst) {
+            self.numeric_types.insert(id, nt);
+        }
+        if inst.class.opcode == Op::ExtInstImport {
+            if let Some(Operand::LiteralString(set)) = inst.operands.get(0) {
+                self.ext_sets.insert(id, set.clone());
+            }
+        }
+    }
+
+    fn expect_id(&mut self, opname: &str, expected: &str) -> Result<u32, AssemblerError> {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Id(name),
+                ..
+            }) if !self.at_instruction_start() => {
+                self.pos += 1;
+                self.used.insert(name.clone());
+                Ok(self.id(name))
+            }
+            Some(token) if !self.at_instruction_start() => Err(AssemblerError::InvalidOperand {
+                line: token.line,
+                token: token.text(),
+                expected: expected.to_owned(),
+            }),
+            _ => Err(AssemblerError::MissingOperand {
+                line: self.line(),
+                opname: opname.to_owned(),
+                expected: expected.to_owned(),
+            }),
+        }
+    }
+
+    fn logical_operand(
+        &mut self,
+        inst: &mut Instruction,
+        logical: &LogicalOperand,
+    ) -> Result<(), AssemblerError> {
+        match logical.quantifier {
+            OperandQuantifier::One => self.operand(inst, logical.kind),
+            OperandQuantifier::ZeroOrOne => {
+                if !self.at_instruction_start() {
+                    self.operand(inst, logical.kind)?;
+                }
+                Ok(())
+            }
+            OperandQuantifier::ZeroOrMore => {
+                while !self.at_instruction_start() {
+                    self.operand(inst, logical.kind)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn operand(&mut self, inst: &mut Instruction, kind: OperandKind) -> Result<(), AssemblerError> {
+        let opname = inst.class.opname;
+        let expected = format!("{:?}", kind);
+        match kind {
+            OperandKind::IdRef => {
+                let id = self.expect_id(opname, &expected)?;
+                inst.operands.push(Operand::IdRef(id));
+            }
+            OperandKind::IdScope => {
+                let id = self.expect_id(opname, &expected)?;
+                inst.operands.push(Operand::IdScope(id));
+            }
+            OperandKind::IdMemorySemantics => {
+                let id = self.expect_id(opname, &expected)?;
+                inst.operands.push(Operand::IdMemorySemantics(id));
+            }
+            OperandKind::PairIdRefIdRef => {
+                self.operand(inst, OperandKind::IdRef)?;
+                self.operand(inst, OperandKind::IdRef)?;
+            }
+            OperandKind::PairIdRefLiteralInteger => {
+                self.operand(inst, OperandKind::IdRef)?;
+                self.operand(inst, OperandKind::LiteralInteger)?;
+            }
+            OperandKind::PairLiteralIntegerIdRef => {
+                self.operand(inst, OperandKind::LiteralContextDependentNumber)?;
+                self.operand(inst, OperandKind::IdRef)?;
+            }
+            OperandKind::LiteralString => {
+                let token = self.next_word(opname, &expected)?;
+                match &token.kind {
+                    TokenKind::String(s) => inst.operands.push(Operand::LiteralString(s.clone())),
+                    _ => return Err(invalid(token, &expected)),
+                }
+            }
+            OperandKind::LiteralInteger => {
+                let token = self.next_word(opname, &expected)?;
+                let value = word(token)
+                    .and_then(parse_integer)
+                    .ok_or_else(|| invalid(token, &expected))?;
+                inst.operands.push(Operand::LiteralBit32(value as u32));
+            }
+            OperandKind::LiteralContextDependentNumber => {
+                let token = self.next_word(opname, &expected)?;
+                let ty = match inst.class.opcode {
+                    Op::Switch => inst
+                        .operands
+                        .get(0)
+                        .and_then(|op| op.id_ref_any())
+                        .and_then(|selector| self.value_types.get(&selector).copied()),
+                    _ => inst.result_type,
+                };
+                let numeric = ty
+                    .and_then(|ty| self.numeric_types.get(&ty).copied())
+                    .unwrap_or(NumericType::Int {
+                        width: 32,
+                        signed: false,
+                    });
+                let operand = word(token)
+                    .and_then(|w| numeric.parse(w))
+                    .ok_or_else(|| invalid(token, &format!("{}-bit literal", numeric.width())))?;
+                inst.operands.push(operand);
+            }
+            OperandKind::LiteralExtInstInteger => {
+                let token = self.next_word(opname, &expected)?;
+                let set = inst
+                    .operands
+                    .get(0)
+                    .and_then(|op| op.id_ref_any())
+                    .and_then(|set| self.ext_sets.get(&set))
+                    .map(|s| s.as_str());
+                let w = word(token).ok_or_else(|| invalid(token, &expected))?;
+                let (number, ext_grammar) =
+                    ext_instruction(set, w).ok_or_else(|| invalid(token, &expected))?;
+                inst.operands.push(Operand::LiteralExtInstInteger(number));
+                //Known extended instructions define their own operands. Otherwise the core grammar's trailing
+                // `IdRef*` takes over.
+                if let Some(ext_grammar) = ext_grammar {
+                    for logical in ext_grammar {
+                        self.logical_operand(inst, logical)?;
+                    }
+                }
+            }
+            OperandKind::LiteralSpecConstantOpInteger => {
+                let token = self.next_word(opname, &expected)?;
+                let op = word(token)
+                    .map(|w| w.strip_prefix("Op").unwrap_or(w))
+                    .and_then(|w| self.opcodes.get(w).copied())
+                    .ok_or_else(|| invalid(token, &expected))?;
+                inst.operands
+                    .push(Operand::LiteralSpecConstantOpInteger(op));
+                //Operands of the wrapped operation, without result type and id
+                for logical in CoreInstructionTable::get(op).operands.iter().filter(|l| {
+                    !matches!(l.kind, OperandKind::IdResultType | OperandKind::IdResult)
+                }) {
+                    self.logical_operand(inst, logical)?;
+                }
+            }
+            _ => {
+                let token = self.next_word(opname, &expected)?;
+                let operand = word(token)
+                    .and_then(|w| parse_enum(kind, w))
+                    .ok_or_else(|| invalid(token, &expected))?;
+                let additional = operand.additional_operands();
+                inst.operands.push(operand);
+                for logical in &additional {
+                    self.logical_operand(inst, logical)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn next_word(&mut self, opname: &str, expected: &str) -> Result<&'t Token, AssemblerError> {
+        if self.at_instruction_start() {
+            return Err(AssemblerError::MissingOperand {
+                line: self.line(),
+                opname: opname.to_owned(),
+                expected: expected.to_owned(),
+            });
+        }
+        let token = &self.tokens[self.pos];
+        self.pos += 1;
+        Ok(token)
+    }
+}
+
+fn word(token: &Token) -> Option<&str> {
+    match &token.kind {
+        TokenKind::Word(w) => Some(w.as_str()),
+        _ => None,
+    }
+}
+
+fn invalid(token: &Token, expected: &str) -> AssemblerError {
+    AssemblerError::InvalidOperand {
+        line: token.line,
+        token: token.text(),
+        expected: expected.to_owned(),
+    }
+}
+
+//Resolves an extended instruction by name or number. Returns its grammar operands if the set is known.
+fn ext_instruction(
+    set: Option<&str>,
+    token: &str,
+) -> Option<(u32, Option<&'static [LogicalOperand]>)> {
+    let known = match set {
+        Some(GLSL_STD_450) => GlslStd450InstructionTable::iter()
+            .find(|inst| inst.opname == token || token.parse::<u32>().ok() == Some(inst.opcode))
+            .map(|inst| (inst.opcode, inst.operands)),
+        Some(OPENCL_STD) => OpenCLStd100InstructionTable::iter()
+            .find(|inst| inst.opname == token || token.parse::<u32>().ok() == Some(inst.opcode))
+            .map(|inst| (inst.opcode, inst.operands)),
+        _ => None,
+    };
+    match known {
+        Some((number, operands)) => Some((number, Some(operands))),
+        None => parse_integer(token).map(|n| (n as u32, None)),
+    }
+}
+
+//Compares flag names ignoring case and underscores, so both `DontInline` and `DONT_INLINE` are accepted.
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '_')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+//Parses a value enumerant by name or number, or a `|` separated list of flags.
+fn parse_enum(kind: OperandKind, token: &str) -> Option<Operand> {
+    macro_rules! value_enums {
+        ($($name:ident),*) => {
+            match kind {
+                $(OperandKind::$name => {
+                    let value = token
+                        .parse::<spirv::$name>()
+                        .ok()
+                        .or_else(|| parse_integer(token).and_then(|n| spirv::$name::from_u32(n as u32)));
+                    return value.map(Operand::$name);
+                })*
+                _ => {}
+            }
+        };
+    }
+    macro_rules! flag_enums {
+        ($($name:ident),*) => {
+            match kind {
+                $(OperandKind::$name => {
+                    let mut flags = spirv::$name::empty();
+                    for part in token.split('|') {
+                        let normalized = normalize(part);
+                        if normalized == "NONE" {
+                            continue;
+                        }
+                        let flag = spirv::$name::all()
+                            .iter_names()
+                            .find(|(name, _)| normalize(name) == normalized)
+                            .map(|(_, flag)| flag)
+                            .or_else(|| parse_integer(part).map(|n| spirv::$name::from_bits_retain(n as u32)))?;
+                        flags |= flag;
+                    }
+                    return Some(Operand::$name(flags));
+                })*
+                _ => {}
+            }
+        };
+    }
+
+    value_enums!(
+        SourceLanguage,
+        ExecutionModel,
+        AddressingModel,
+        MemoryModel,
+        ExecutionMode,
+        StorageClass,
+        SamplerAddressingMode,
+        SamplerFilterMode,
+        ImageFormat,
+        ImageChannelOrder,
+        ImageChannelDataType,
+        FPRoundingMode,
+        LinkageType,
+        AccessQualifier,
+        FunctionParameterAttribute,
+        Decoration,
+        BuiltIn,
+        Scope,
+        GroupOperation,
+        KernelEnqueueFlags,
+        Capability,
+        RayQueryIntersection,
+        RayQueryCommittedIntersectionType,
+        RayQueryCandidateIntersectionType
+    );
+    flag_enums!(
+        ImageOperands,
+        FPFastMathMode,
+        SelectionControl,
+        LoopControl,
+        FunctionControl,
+        MemorySemantics,
+        MemoryAccess,
+        KernelProfilingInfo,
+        RayFlags,
+        FragmentShadingRate
+    );
+
+    //`spirv-dis` prints dimensions without their `Dim` prefix
+    if let OperandKind::Dim = kind {
+        let prefixed = format!("Dim{}", token);
+        return token
+            .parse::<spirv::Dim>()
+            .or_else(|_| prefixed.parse::<spirv::Dim>())
+            .ok()
+            .or_else(|| parse_integer(token).and_then(|n| spirv::Dim::from_u32(n as u32)))
+            .map(Operand::Dim);
+    }
+
+    None
+}
@@ -0,0 +1,469 @@
+use std::fmt::Write;
+
+use ahash::{AHashMap, AHashSet};
+use rspirv::{
+    dr::{Instruction, Module, Operand},
+    grammar::{CoreInstructionTable, GlslStd450InstructionTable, OpenCLStd100InstructionTable},
+    spirv::Op,
+};
+
+use super::{NumericType, GLSL_STD_450, OPENCL_STD};
+
+//Column at which the opcode starts, same as `spirv-dis`.
+const OPCODE_COLUMN: usize = 15;
+
+//Formats bitflags as `Flag1|Flag2`. Bits that have no name are appended as hex value.
+macro_rules! flags {
+    ($flags:expr) => {{
+        let mut parts = Vec::new();
+        let mut named = 0;
+        for (name, flag) in $flags.iter_names() {
+            parts.push(camel_case(name));
+            named |= flag.bits();
+        }
+        join_flags(parts, $flags.bits() & !named)
+    }};
+}
+
+///Native replacement for `spirv-dis`. Produces the standard SPIR-V assembly text that can be read back via
+/// [assemble](super::assemble).
+#[derive(Debug, Clone, Copy)]
+pub struct Disassembler {
+    ///Use names derived from `OpName` and types (`%uint`, `%_ptr_Function_float`, ...) instead of raw ids.
+    pub friendly_names: bool,
+    ///Print the `; SPIR-V` header block.
+    pub header: bool,
+    ///Align opcodes in a column.
+    pub indent: bool,
+    ///Add comments naming the module's sections and functions, like `spirv-dis --comment`.
+    pub comment: bool,
+}
+
+impl Default for Disassembler {
+    fn default() -> Self {
+        Disassembler {
+            friendly_names: true,
+            header: true,
+            indent: true,
+            comment: false,
+        }
+    }
+}
+
+impl Disassembler {
+    ///Disassembles `module` into SPIR-V assembly text.
+    pub fn disassemble(&self, module: &Module) -> String {
+        let ctx = Context::new(module, self.friendly_names);
+        let mut out = String::new();
+
+        if self.header {
+            if let Some(header) = &module.header {
+                let (major, minor) = header.version();
+                let (tool, tool_version) = header.generator();
+                let _ = writeln!(out, "; SPIR-V");
+                let _ = writeln!(out, "; Version: {}.{}", major, minor);
+                let _ = writeln!(out, "; Generator: {}; {}", tool, tool_version);
+                let _ = writeln!(out, "; Bound: {}", header.bound);
+                let _ = writeln!(out, "; Schema: {}", header.reserved_word);
+            }
+        }
+
+        let mode_section = module
+            .capabilities
+            .iter()
+            .chain(&module.extensions)
+            .chain(&module.ext_inst_imports)
+            .chain(&module.memory_model)
+            .chain(&module.entry_points)
+            .chain(&module.execution_modes);
+        for inst in mode_section {
+            self.write_instruction(&ctx, &mut out, inst);
+        }
+
+        let debug_section: Vec<&Instruction> = module
+            .debug_string_source
+            .iter()
+            .chain(&module.debug_names)
+            .chain(&module.debug_module_processed)
+            .collect();
+        self.write_section(&ctx, &mut out, "Debug Information", debug_section);
+        self.write_section(
+            &ctx,
+            &mut out,
+            "Annotations",
+            module.annotations.iter().collect(),
+        );
+        self.write_section(
+            &ctx,
+            &mut out,
+            "Types, variables and constants",
+            module.types_global_values.iter().collect(),
+        );
+
+        for function in &module.functions {
+            if self.comment {
+                let name = function
+                    .def_id()
+                    .map(|id| ctx.name(id))
+                    .unwrap_or_else(|| String::from("%<unknown>"));
+                let _ = writeln!(out, "\n; Function {}", &name[1..]);
+            }
+            for inst in function.all_inst_iter() {
+                self.write_instruction(&ctx, &mut out, inst);
+            }
+        }
+
+        out
+    }
+
+    fn write_section(
+        &self,
+        ctx: &Context,
+        out: &mut String,
+        name: &str,
+        section: Vec<&Instruction>,
+    ) {
+        if section.is_empty() {
+            return;
+        }
+        if self.comment {
+            let _ = writeln!(out, "\n; {}", name);
+        }
+        for inst in section {
+            self.write_instruction(ctx, out, inst);
+        }
+    }
+
+    fn write_instruction(&self, ctx: &Context, out: &mut String, inst: &Instruction) {
+        let line = ctx.instruction(inst);
+        match (inst.result_id, self.indent) {
+            (Some(id), true) => {
+                let _ = write!(
+                    out,
+                    "{:>width$} = ",
+                    ctx.name(id),
+                    width = OPCODE_COLUMN - 3
+                );
+            }
+            (Some(id), false) => {
+                let _ = write!(out, "{} = ", ctx.name(id));
+            }
+            (None, true) => {
+                let _ = write!(out, "{:width$}", "", width = OPCODE_COLUMN);
+            }
+            (None, false) => {}
+        }
+        let _ = writeln!(out, "{}", line);
+    }
+}
+
+///Disassembles `module` using the default [Disassembler] settings.
+pub fn disassemble(module: &Module) -> String {
+    Disassembler::default().disassemble(module)
+}
+
+//Module-wide information needed to print an instruction.
+struct Context {
+    names: AHashMap<u32, String>,
+    numeric_types: AHashMap<u32, NumericType>,
+    value_types: AHashMap<u32, u32>,
+    ext_sets: AHashMap<u32, String>,
+}
+
+impl Context {
+    fn new(module: &Module, friendly_names: bool) -> Self {
+        let mut ctx = Context {
+            names: AHashMap::default(),
+            numeric_types: AHashMap::default(),
+            value_types: AHashMap::default(),
+            ext_sets: AHashMap::default(),
+        };
+
+        for inst in module.all_inst_iter() {
+            if let (Some(id), Some(ty)) = (inst.result_id, inst.result_type) {
+                ctx.value_types.insert(id, ty);
+            }
+            if let Some(id) = inst.result_id {
+                if let Some(nt) = NumericType::from_instruction(inst) {
+                    ctx.numeric_types.insert(id, nt);
+                }
+                if inst.class.opcode == Op::ExtInstImport {
+                    if let Some(Operand::LiteralString(set)) = inst.operands.get(0) {
+                        ctx.ext_sets.insert(id, set.clone());
+                    }
+                }
+            }
+        }
+
+        if friendly_names {
+            ctx.assign_friendly_names(module);
+        }
+        ctx
+    }
+
+    //Assigns names similar to spirv-dis' friendly name mapper. Explicit `OpName`s take precedence, types and
+    // constants are named after their structure.
+    fn assign_friendly_names(&mut self, module: &Module) {
+        let mut used = AHashSet::default();
+
+        for inst in &module.debug_names {
+            if inst.class.opcode != Op::Name {
+                continue;
+            }
+            if let (Some(Operand::IdRef(id)), Some(Operand::LiteralString(name))) =
+                (inst.operands.get(0), inst.operands.get(1))
+            {
+                if !self.names.contains_key(id) {
+                    let name = unique_name(&mut used, sanitize(name));
+                    self.names.insert(*id, name);
+                }
+            }
+        }
+
+        for inst in &module.types_global_values {
+            let Some(id) = inst.result_id else {
+                continue;
+            };
+            if self.names.contains_key(&id) {
+                continue;
+            }
+            if let Some(name) = self.structural_name(inst) {
+                let name = unique_name(&mut used, name);
+                self.names.insert(id, name);
+            }
+        }
+    }
+
+    fn structural_name(&self, inst: &Instruction) -> Option<String> {
+        let id_name = |idx: usize| -> Option<String> {
+            inst.operands
+                .get(idx)
+                .and_then(|op| op.id_ref_any())
+                .and_then(|id| self.names.get(&id).cloned())
+        };
+        let literal = |idx: usize| -> Option<u32> {
+            match inst.operands.get(idx) {
+                Some(Operand::LiteralBit32(v)) => Some(*v),
+                _ => None,
+            }
+        };
+
+        let name = match inst.class.opcode {
+            Op::TypeVoid => String::from("void"),
+            Op::TypeBool => String::from("bool"),
+            Op::TypeInt => {
+                let width = literal(0)?;
+                let prefix = if literal(1)? == 1 { "int" } else { "uint" };
+                if width == 32 {
+                    prefix.to_owned()
+                } else {
+                    format!("{}{}", prefix, width)
+                }
+            }
+            Op::TypeFloat => match literal(0)? {
+                16 => String::from("half"),
+                32 => String::from("float"),
+                64 => String::from("double"),
+                w => format!("fp{}", w),
+            },
+            Op::TypeVector => format!("v{}{}", literal(1)?, id_name(0)?),
+            Op::TypeMatrix => format!("mat{}{}", literal(1)?, id_name(0)?),
+            Op::TypeArray => format!("_arr_{}_{}", id_name(0)?, id_name(1)?),
+            Op::TypeRuntimeArray => format!("_runtimearr_{}", id_name(0)?),
+            Op::TypePointer => match inst.operands.get(0) {
+                Some(Operand::StorageClass(sc)) => format!("_ptr_{:?}_{}", sc, id_name(1)?),
+                _ => return None,
+            },
+            Op::TypeStruct => format!("_struct_{}", inst.result_id?),
+            Op::TypeSampler => String::from("type_sampler"),
+            Op::TypeSampledImage => String::from("type_sampled_image"),
+            Op::TypeImage => String::from("type_image"),
+            Op::ConstantTrue => String::from("true"),
+            Op::ConstantFalse => String::from("false"),
+            Op::Constant => {
+                let ty = inst.result_type?;
+                let value = self.literal_number(ty, inst.operands.get(0)?);
+                format!(
+                    "{}_{}",
+                    self.names.get(&ty)?,
+                    value.replace('-', "n").replace(['.', '+'], "_")
+                )
+            }
+            _ => return None,
+        };
+        Some(name)
+    }
+
+    fn name(&self, id: u32) -> String {
+        match self.names.get(&id) {
+            Some(name) => format!("%{}", name),
+            None => format!("%{}", id),
+        }
+    }
+
+    fn instruction(&self, inst: &Instruction) -> String {
+        let mut line = format!("Op{}", inst.class.opname);
+        if let Some(ty) = inst.result_type {
+            line.push(' ');
+            line.push_str(&self.name(ty));
+        }
+
+        //Type used to print context dependent literals
+        let literal_type = match inst.class.opcode {
+            Op::Constant | Op::SpecConstant => inst.result_type,
+            Op::Switch => inst
+                .operands
+                .get(0)
+                .and_then(|op| op.id_ref_any())
+                .and_then(|selector| self.value_types.get(&selector).copied()),
+            _ => None,
+        };
+        let ext_set = if inst.class.opcode == Op::ExtInst {
+            inst.operands
+                .get(0)
+                .and_then(|op| op.id_ref_any())
+                .and_then(|set| self.ext_sets.get(&set))
+        } else {
+            None
+        };
+
+        for op in &inst.operands {
+            line.push(' ');
+            let formatted = match op {
+                Operand::LiteralBit32(_) | Operand::LiteralBit64(_) => match literal_type {
+                    Some(ty) => self.literal_number(ty, op),
+                    None => plain_literal(op),
+                },
+                Operand::LiteralExtInstInteger(n) => ext_inst_name(ext_set.map(|s| s.as_str()), *n),
+                _ => self.operand(op),
+            };
+            line.push_str(&formatted);
+        }
+        line
+    }
+
+    fn operand(&self, op: &Operand) -> String {
+        match op {
+            Operand::IdRef(id) | Operand::IdScope(id) | Operand::IdMemorySemantics(id) => {
+                self.name(*id)
+            }
+            Operand::LiteralString(s) => quote(s),
+            Operand::LiteralSpecConstantOpInteger(op) => {
+                CoreInstructionTable::get(*op).opname.to_owned()
+            }
+            Operand::LiteralBit32(_) | Operand::LiteralBit64(_) => plain_literal(op),
+            Operand::ImageOperands(f) => flags!(f),
+            Operand::FPFastMathMode(f) => flags!(f),
+            Operand::SelectionControl(f) => flags!(f),
+            Operand::LoopControl(f) => flags!(f),
+            Operand::FunctionControl(f) => flags!(f),
+            Operand::MemorySemantics(f) => flags!(f),
+            Operand::MemoryAccess(f) => flags!(f),
+            Operand::KernelProfilingInfo(f) => flags!(f),
+            Operand::RayFlags(f) => flags!(f),
+            Operand::FragmentShadingRate(f) => flags!(f),
+            //Value enums print their SPIR-V name.
+            other => format!("{}", other),
+        }
+    }
+
+    fn literal_number(&self, ty: u32, op: &Operand) -> String {
+        let bits = match op {
+            Operand::LiteralBit32(v) => *v as u64,
+            Operand::LiteralBit64(v) => *v,
+            _ => return plain_literal(op),
+        };
+        match self.numeric_types.get(&ty) {
+            Some(nt) => nt.format(bits),
+            None => plain_literal(op),
+        }
+    }
+}
+
+fn plain_literal(op: &Operand) -> String {
+    match op {
+        Operand::LiteralBit32(v) => v.to_string(),
+        Operand::LiteralBit64(v) => v.to_string(),
+        other => format!("{}", other),
+    }
+}
+
+fn ext_inst_name(set: Option<&str>, n: u32) -> String {
+    let found = match set {
+        Some(GLSL_STD_450) => GlslStd450InstructionTable::iter()
+            .find(|inst| inst.opcode == n)
+            .map(|inst| inst.opname),
+        Some(OPENCL_STD) => OpenCLStd100InstructionTable::iter()
+            .find(|inst| inst.opcode == n)
+            .map(|inst| inst.opname),
+        _ => None,
+    };
+    found.map(|n| n.to_owned()).unwrap_or_else(|| n.to_string())
+}
+
+fn join_flags(mut parts: Vec<String>, unnamed_bits: u32) -> String {
+    if unnamed_bits != 0 {
+        parts.push(format!("0x{:x}", unnamed_bits));
+    }
+    if parts.is_empty() {
+        String::from("None")
+    } else {
+        parts.join("|")
+    }
+}
+
+//`DONT_INLINE` -> `DontInline`
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_string() + &chars.as_str().to_lowercase(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
+
+//Replaces characters that can't be part of an id name. Names that are pure numbers are prefixed, since
+// they would collide with raw ids.
+fn sanitize(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if out.is_empty() || out.chars().all(|c| c.is_ascii_digit()) {
+        out.insert(0, '_');
+    }
+    out
+}
+
+fn unique_name(used: &mut AHashSet<String>, name: String) -> String {
+    let mut candidate = name.clone();
+    let mut suffix = 0;
+    while used.contains(&candidate) {
+        candidate = format!("{}_{}", name, suffix);
+        suffix += 1;
+    }
+    used.insert(candidate.clone());
+    candidate
+}
@@ -0,0 +1,650 @@
+//! # Module diff
+//!
+//! Structural comparison of two SPIR-V modules. Patches renumber ids freely, which makes diffing disassembly
+//! useless. [ModuleDiff] therefore identifies items by structure instead:
+//!
+//! - Ids of named items (`OpName`, entry point names) are replaced by that name.
+//! - Ids of unnamed types, constants and variables are replaced by their (recursively resolved) definition,
+//!   for instance `(OpTypePointer Function (OpTypeFloat 32))`.
+//! - Function local ids are numbered in order of definition. Blocks of matched functions are aligned by their shape,
+//!   and locals of aligned blocks are mapped onto each other before comparing, so inserting a block does not
+//!   report every following block as changed.
+//!
+//! The result can be printed (see [Display](std::fmt::Display)) or serialized via [ModuleDiff::to_json].
+
+use std::fmt::Display;
+
+use ahash::{AHashMap, AHashSet};
+use rspirv::{
+    dr::{Block, Function, Instruction, Module, Operand},
+    grammar::reflect,
+    spirv::Op,
+};
+use serde::Serialize;
+
+///Kind of a diffed item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemKind {
+    ///Capabilities, extensions, imports, memory model, entry points and execution modes.
+    ModeSetting,
+    Type,
+    Constant,
+    ///Global variable.
+    Variable,
+    Decoration,
+    ///A function's signature.
+    Function,
+    ///A single basic block of a function.
+    Block,
+}
+
+impl Display for ItemKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ItemKind::ModeSetting => "mode-setting",
+            ItemKind::Type => "type",
+            ItemKind::Constant => "constant",
+            ItemKind::Variable => "variable",
+            ItemKind::Decoration => "decoration",
+            ItemKind::Function => "function",
+            ItemKind::Block => "block",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+///Change of a single item. Texts are given in the id independent form described in the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    Added { after: String },
+    Removed { before: String },
+    Changed { before: String, after: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffItem {
+    pub kind: ItemKind,
+    ///Name the item was matched by.
+    pub name: String,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+///Structural difference between two modules, usually a template and its patched version.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ModuleDiff {
+    pub items: Vec<DiffItem>,
+}
+
+impl ModuleDiff {
+    ///Compares `before` against `after`.
+    pub fn new(before: &Module, after: &Module) -> Self {
+        let mut before = Side::new(before);
+        let mut after = Side::new(after);
+        let mut diff = ModuleDiff::default();
+
+        diff.keyed(
+            ItemKind::ModeSetting,
+            before.mode_settings(),
+            after.mode_settings(),
+        );
+        for kind in [ItemKind::Type, ItemKind::Constant, ItemKind::Variable] {
+            diff.keyed(kind, before.globals(kind), after.globals(kind));
+        }
+        diff.keyed(
+            ItemKind::Decoration,
+            before.decorations(),
+            after.decorations(),
+        );
+        diff.functions(&mut before, &mut after);
+        diff
+    }
+
+    ///True if both modules are structurally equal.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    ///All changes of the given kind.
+    pub fn of_kind(&self, kind: ItemKind) -> impl Iterator<Item = &DiffItem> {
+        self.items.iter().filter(move |item| item.kind == kind)
+    }
+
+    ///Serializes the diff as pretty printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize module diff")
+    }
+
+    //Matches items by identity (first) and compares their structure (second). Items without a match are
+    // reported as added or removed.
+    fn keyed(
+        &mut self,
+        kind: ItemKind,
+        before: Vec<(String, String)>,
+        after: Vec<(String, String)>,
+    ) {
+        let mut unmatched: AHashMap<&str, Vec<usize>> = AHashMap::default();
+        for (idx, (identity, _)) in after.iter().enumerate().rev() {
+            unmatched.entry(identity.as_str()).or_default().push(idx);
+        }
+
+        let mut matched = AHashSet::default();
+        for (identity, structure) in &before {
+            match unmatched.get_mut(identity.as_str()).and_then(|c| c.pop()) {
+                Some(idx) => {
+                    matched.insert(idx);
+                    if after[idx].1 != *structure {
+                        self.items.push(DiffItem {
+                            kind,
+                            name: identity.clone(),
+                            change: Change::Changed {
+                                before: structure.clone(),
+                                after: after[idx].1.clone(),
+                            },
+                        });
+                    }
+                }
+                None => self.items.push(DiffItem {
+                    kind,
+                    name: identity.clone(),
+                    change: Change::Removed {
+                        before: structure.clone(),
+                    },
+                }),
+            }
+        }
+        for (idx, (identity, structure)) in after.iter().enumerate() {
+            if !matched.contains(&idx) {
+                self.items.push(DiffItem {
+                    kind,
+                    name: identity.clone(),
+                    change: Change::Added {
+                        after: structure.clone(),
+                    },
+                });
+            }
+        }
+    }
+
+    fn functions(&mut self, before: &mut Side, after: &mut Side) {
+        let after_functions: AHashMap<String, usize> = after
+            .module
+            .functions
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, f)| Some((after.function_name(f.def_id()?), idx)))
+            .collect();
+
+        let before_module = before.module;
+        let after_module = after.module;
+        let mut matched = AHashSet::default();
+        for function in &before_module.functions {
+            let Some(id) = function.def_id() else {
+                continue;
+            };
+            let name = before.function_name(id);
+            match after_functions.get(&name) {
+                Some(idx) => {
+                    matched.insert(*idx);
+                    let other = &after_module.functions[*idx];
+                    self.function(&name, (&mut *before, function), (&mut *after, other));
+                }
+                None => self.items.push(DiffItem {
+                    kind: ItemKind::Function,
+                    name: name.clone(),
+                    change: Change::Removed {
+                        before: before.signature(function),
+                    },
+                }),
+            }
+        }
+
+        for (idx, function) in after_module.functions.iter().enumerate() {
+            if matched.contains(&idx) {
+                continue;
+            }
+            let Some(id) = function.def_id() else {
+                continue;
+            };
+            self.items.push(DiffItem {
+                kind: ItemKind::Function,
+                name: after.function_name(id),
+                change: Change::Added {
+                    after: after.signature(function),
+                },
+            });
+        }
+    }
+
+    //Diffs two matched functions block by block.
+    fn function(
+        &mut self,
+        name: &str,
+        before: (&mut Side, &Function),
+        after: (&mut Side, &Function),
+    ) {
+        let (before, before_fn) = before;
+        let (after, after_fn) = after;
+
+        let (before_sig, after_sig) = (before.signature(before_fn), after.signature(after_fn));
+        if before_sig != after_sig {
+            self.items.push(DiffItem {
+                kind: ItemKind::Function,
+                name: name.to_owned(),
+                change: Change::Changed {
+                    before: before_sig,
+                    after: after_sig,
+                },
+            });
+        }
+
+        //Align blocks by shape, i.e. ignoring which locals are referenced.
+        let before_locals = local_ordinals(before_fn);
+        let after_locals = local_ordinals(after_fn);
+        let before_shapes: Vec<String> = before_fn
+            .blocks
+            .iter()
+            .map(|b| before.block_text(b, &|id| before_locals.get(&id).map(|_| String::from("_"))))
+            .collect();
+        let after_shapes: Vec<String> = after_fn
+            .blocks
+            .iter()
+            .map(|b| after.block_text(b, &|id| after_locals.get(&id).map(|_| String::from("_"))))
+            .collect();
+        let aligned = lcs(&before_shapes, &after_shapes);
+
+        //Map locals of aligned blocks and parameters onto each other
+        let mut mapping: AHashMap<u32, u32> = AHashMap::default();
+        for (b, a) in before_fn.parameters.iter().zip(&after_fn.parameters) {
+            if let (Some(b), Some(a)) = (b.result_id, a.result_id) {
+                mapping.insert(b, a);
+            }
+        }
+        for (bi, ai) in &aligned {
+            let (bb, ab) = (&before_fn.blocks[*bi], &after_fn.blocks[*ai]);
+            let ids = block_inst_iter(bb).zip(block_inst_iter(ab));
+            for (b, a) in ids {
+                if let (Some(b), Some(a)) = (b.result_id, a.result_id) {
+                    mapping.insert(b, a);
+                }
+            }
+        }
+
+        let before_local = |id: u32| -> Option<String> {
+            let ordinal = before_locals.get(&id)?;
+            Some(match mapping.get(&id).and_then(|a| after_locals.get(a)) {
+                Some(after) => format!("%{}", after),
+                None => format!("%?{}", ordinal),
+            })
+        };
+        let after_local =
+            |id: u32| -> Option<String> { after_locals.get(&id).map(|o| format!("%{}", o)) };
+
+        let mut push_block = |change: Change, idx: usize| {
+            self.items.push(DiffItem {
+                kind: ItemKind::Block,
+                name: format!("{}: block {}", name, idx),
+                change,
+            })
+        };
+
+        //Walk the gaps between aligned blocks. Unaligned blocks within the same gap are paired as changed.
+        let mut bi = 0;
+        let mut ai = 0;
+        for (next_b, next_a) in aligned
+            .iter()
+            .copied()
+            .chain([(before_fn.blocks.len(), after_fn.blocks.len())])
+        {
+            let removed: Vec<usize> = (bi..next_b).collect();
+            let added: Vec<usize> = (ai..next_a).collect();
+            for pair in 0..removed.len().max(added.len()) {
+                match (removed.get(pair), added.get(pair)) {
+                    (Some(b), Some(a)) => push_block(
+                        Change::Changed {
+                            before: before.block_text(&before_fn.blocks[*b], &before_local),
+                            after: after.block_text(&after_fn.blocks[*a], &after_local),
+                        },
+                        *a,
+                    ),
+                    (Some(b), None) => push_block(
+                        Change::Removed {
+                            before: before.block_text(&before_fn.blocks[*b], &before_local),
+                        },
+                        *b,
+                    ),
+                    (None, Some(a)) => push_block(
+                        Change::Added {
+                            after: after.block_text(&after_fn.blocks[*a], &after_local),
+                        },
+                        *a,
+                    ),
+                    (None, None) => {}
+                }
+            }
+
+            //Aligned blocks have the same shape, but might reference different locals
+            if next_b < before_fn.blocks.len() {
+                let b = before.block_text(&before_fn.blocks[next_b], &before_local);
+                let a = after.block_text(&after_fn.blocks[next_a], &after_local);
+                if a != b {
+                    push_block(
+                        Change::Changed {
+                            before: b,
+                            after: a,
+                        },
+                        next_a,
+                    );
+                }
+            }
+            bi = next_b + 1;
+            ai = next_a + 1;
+        }
+    }
+}
+
+impl Display for ModuleDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for item in &self.items {
+            match &item.change {
+                Change::Added { after } => {
+                    writeln!(f, "+ {} {}", item.kind, item.name)?;
+                    write_lines(f, "  + ", after, &item.name)?;
+                }
+                Change::Removed { before } => {
+                    writeln!(f, "- {} {}", item.kind, item.name)?;
+                    write_lines(f, "  - ", before, &item.name)?;
+                }
+                Change::Changed { before, after } => {
+                    writeln!(f, "~ {} {}", item.kind, item.name)?;
+                    let before: Vec<&str> = before.lines().collect();
+                    let after: Vec<&str> = after.lines().collect();
+                    let common = lcs(&before, &after);
+                    let (mut bi, mut ai) = (0, 0);
+                    for (next_b, next_a) in common.into_iter().chain([(before.len(), after.len())])
+                    {
+                        for line in &before[bi..next_b] {
+                            writeln!(f, "  - {}", line)?;
+                        }
+                        for line in &after[ai..next_a] {
+                            writeln!(f, "  + {}", line)?;
+                        }
+                        if let Some(line) = after.get(next_a) {
+                            writeln!(f, "    {}", line)?;
+                        }
+                        bi = next_b + 1;
+                        ai = next_a + 1;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+//Writes `text` line by line, unless it is the same as the item's name.
+fn write_lines(
+    f: &mut std::fmt::Formatter<'_>,
+    prefix: &str,
+    text: &str,
+    name: &str,
+) -> std::fmt::Result {
+    if text == name {
+        return Ok(());
+    }
+    for line in text.lines() {
+        writeln!(f, "{}{}", prefix, line)?;
+    }
+    Ok(())
+}
+
+//Longest common subsequence of `a` and `b`, returned as aligned index pairs in ascending order.
+fn lcs<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let mut table = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            table[i][j] = if a[i] == b[j] {
+                table[i + 1][j + 1] + 1
+            } else {
+                table[i + 1][j].max(table[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if table[i + 1][j] >= table[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+fn block_inst_iter(block: &Block) -> impl Iterator<Item = &Instruction> {
+    block.label.iter().chain(&block.instructions)
+}
+
+//Numbers all ids defined in `function` in order of definition.
+fn local_ordinals(function: &Function) -> AHashMap<u32, usize> {
+    function
+        .parameters
+        .iter()
+        .chain(function.blocks.iter().flat_map(block_inst_iter))
+        .filter_map(|inst| inst.result_id)
+        .enumerate()
+        .map(|(ordinal, id)| (id, ordinal))
+        .collect()
+}
+
+//Id independent view of one module.
+struct Side<'m> {
+    module: &'m Module,
+    globals: AHashMap<u32, &'m Instruction>,
+    //Unique names of all named ids, including entry points.
+    names: AHashMap<u32, String>,
+    //`function/%ordinal` names of function locals, used if they are referenced from outside the function.
+    locals: AHashMap<u32, String>,
+    keys: AHashMap<u32, String>,
+    in_progress: AHashSet<u32>,
+}
+
+impl<'m> Side<'m> {
+    fn new(module: &'m Module) -> Self {
+        let mut used = AHashSet::default();
+        let mut names = AHashMap::default();
+        let mut assign = |id: u32, name: &str| {
+            if names.contains_key(&id) {
+                return;
+            }
+            let mut candidate = name.to_owned();
+            let mut suffix = 1;
+            while !used.insert(candidate.clone()) {
+                candidate = format!("{}#{}", name, suffix);
+                suffix += 1;
+            }
+            names.insert(id, candidate);
+        };
+
+        for inst in &module.debug_names {
+            if let (Op::Name, Some(Operand::IdRef(id)), Some(Operand::LiteralString(n))) = (
+                inst.class.opcode,
+                inst.operands.get(0),
+                inst.operands.get(1),
+            ) {
+                assign(*id, n);
+            }
+        }
+        for inst in &module.entry_points {
+            if let (Some(Operand::IdRef(id)), Some(Operand::LiteralString(n))) =
+                (inst.operands.get(1), inst.operands.get(2))
+            {
+                assign(*id, n);
+            }
+        }
+        //Unnamed functions are named by position
+        for (idx, function) in module.functions.iter().enumerate() {
+            if let Some(id) = function.def_id() {
+                assign(id, &format!("function{}", idx));
+            }
+        }
+
+        let globals = module
+            .global_inst_iter()
+            .filter_map(|inst| Some((inst.result_id?, inst)))
+            .collect();
+
+        let mut side = Side {
+            module,
+            globals,
+            names,
+            locals: AHashMap::default(),
+            keys: AHashMap::default(),
+            in_progress: AHashSet::default(),
+        };
+        for function in &module.functions {
+            let Some(id) = function.def_id() else {
+                continue;
+            };
+            let fname = side.function_name(id);
+            for (local, ordinal) in local_ordinals(function) {
+                side.locals.insert(local, format!("{}/%{}", fname, ordinal));
+            }
+        }
+        side
+    }
+
+    fn function_name(&self, id: u32) -> String {
+        self.names
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    //Id independent key used wherever `id` is referenced.
+    fn key(&mut self, id: u32) -> String {
+        if let Some(name) = self.names.get(&id) {
+            return format!("%{}", name);
+        }
+        if let Some(key) = self.keys.get(&id) {
+            return key.clone();
+        }
+        if let Some(local) = self.locals.get(&id) {
+            return format!("%{}", local);
+        }
+        let Some(inst) = self.globals.get(&id).copied() else {
+            return format!("%<undefined {}>", id);
+        };
+        //Only possible for forward declared pointers
+        if !self.in_progress.insert(id) {
+            return String::from("%<recursive>");
+        }
+        let key = format!("({})", self.render(inst, &|_| None));
+        self.in_progress.remove(&id);
+        self.keys.insert(id, key.clone());
+        key
+    }
+
+    //Renders `inst` without its result id. `local` names function local ids.
+    fn render(&mut self, inst: &Instruction, local: &dyn Fn(u32) -> Option<String>) -> String {
+        let mut text = format!("Op{}", inst.class.opname);
+        let resolve = |this: &mut Self, id: u32| local(id).unwrap_or_else(|| this.key(id));
+        if let Some(ty) = inst.result_type {
+            text.push(' ');
+            text.push_str(&resolve(self, ty));
+        }
+        for op in &inst.operands {
+            text.push(' ');
+            match op {
+                Operand::LiteralString(s) => text.push_str(&format!("{:?}", s)),
+                op => match op.id_ref_any() {
+                    Some(referenced) => text.push_str(&resolve(self, referenced)),
+                    None => text.push_str(&op.to_string()),
+                },
+            }
+        }
+        text
+    }
+
+    //Returns identity and structure of a global item. Named items are identified by their name, so a changed definition
+    // is reported as change instead of a removed and an added item.
+    fn structure(&mut self, inst: &Instruction) -> (String, String) {
+        let rendered = self.render(inst, &|_| None);
+        match inst.result_id.and_then(|id| self.names.get(&id)) {
+            Some(name) => (format!("%{}", name), rendered),
+            None => (rendered.clone(), rendered),
+        }
+    }
+
+    fn mode_settings(&mut self) -> Vec<(String, String)> {
+        let module = self.module;
+        module
+            .capabilities
+            .iter()
+            .chain(&module.extensions)
+            .chain(&module.ext_inst_imports)
+            .chain(&module.memory_model)
+            .chain(&module.entry_points)
+            .chain(&module.execution_modes)
+            .map(|inst| self.structure(inst))
+            .collect()
+    }
+
+    fn globals(&mut self, kind: ItemKind) -> Vec<(String, String)> {
+        self.module
+            .types_global_values
+            .iter()
+            .filter(|inst| {
+                let op = inst.class.opcode;
+                match kind {
+                    ItemKind::Type => reflect::is_type(op),
+                    ItemKind::Constant => reflect::is_constant(op) || op == Op::Undef,
+                    ItemKind::Variable => op == Op::Variable,
+                    _ => false,
+                }
+            })
+            .map(|inst| self.structure(inst))
+            .collect()
+    }
+
+    fn decorations(&mut self) -> Vec<(String, String)> {
+        self.module
+            .annotations
+            .iter()
+            .map(|inst| self.structure(inst))
+            .collect()
+    }
+
+    fn signature(&mut self, function: &Function) -> String {
+        let mut text = match &function.def {
+            Some(def) => self.render(def, &|_| None),
+            None => String::from("OpFunction <undefined>"),
+        };
+        for param in &function.parameters {
+            text.push('\n');
+            text.push_str(&self.render(param, &|_| None));
+        }
+        text
+    }
+
+    fn block_text(&mut self, block: &Block, local: &dyn Fn(u32) -> Option<String>) -> String {
+        block_inst_iter(block)
+            .map(|inst| {
+                let rendered = self.render(inst, local);
+                match inst.result_id.and_then(local) {
+                    Some(result) => format!("{} = {}", result, rendered),
+                    None => rendered,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
@@ -13,7 +13,7 @@
 //! - resource bindings
 //! - functions
 //! - function bodies
-//! - control-flow-graph
+//! - control-flow-graph, see [analysis::Cfg](crate::analysis::Cfg)
 //! - basic-blocks
 //!
 //! This list is non exhaustive and might change / be extended.
@@ -21,49 +21,151 @@
 //! Each of those points can be patched individually. Additionally patches can use a combination of those. For instance, you might want to patch the module with a constant (basically specialization constans).
 //! This touches not just BBs (basic blocks), but possibly CFG (control flow) or resource binding as well.
 
-use crate::{patch::Patcher, PatcherError};
+use rspirv::{
+    dr::Operand,
+    spirv::{ExecutionMode, ExecutionModel},
+};
+use smallvec::SmallVec;
+use std::rc::Rc;
+
+use crate::{
+    patch::{IrState, Patcher},
+    spirv_ext::SpirvExt,
+    PatcherError,
+};
+
+///A single `OpEntryPoint` of a module, including all execution modes that are declared for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryPoint {
+    ///Id of the `OpFunction` that is declared as entry point.
+    ///
+    /// Note that this id is only valid for the template module. Patches might renumber ids, use
+    /// [SpirvExt::find_entry_point] to resolve the function in a patched module.
+    pub function_id: u32,
+    pub name: String,
+    pub execution_model: ExecutionModel,
+    ///Ids of all interface variables listed on the `OpEntryPoint`.
+    pub interface: SmallVec<[u32; 8]>,
+    ///All `OpExecutionMode` and `OpExecutionModeId` declared for this entry point, together with their
+    /// additional operands.
+    pub execution_modes: SmallVec<[(ExecutionMode, SmallVec<[Operand; 3]>); 3]>,
+}
 
 ///Single loaded module.
 ///
-/// Note that we currently only support one entry-point per module.
+/// A module can declare any number of entry points. Use [Module::patch_entry_point] to scope a [Patcher]
+/// to one of them.
 pub struct Module {
     //Binary SPIR-V template data.
     module_binary: Vec<u8>,
     spv_mod: rspirv::dr::Module,
+    entry_points: Vec<EntryPoint>,
+    //SPIR-T context shared by all patching sessions of this module, so interned types and constants are reused.
+    spirt_ctx: Rc<spirt::Context>,
 }
 
 impl Module {
     pub fn new(spirv_binary: Vec<u8>) -> Result<Self, PatcherError> {
         let spv_mod = rspirv::dr::load_bytes(&spirv_binary)?;
-        /*
-                if spv_mod.entry_points.len() > 1 {
-                    return Err(PatcherError::MultipleEntryPoints);
-                }
-
-                if spv_mod.entry_points.len() == 0 {
-                    return Err(PatcherError::NoEntryPoint);
-                }
-        */
+        let entry_points = spv_mod.collect_entry_points();
         Ok(Module {
             module_binary: spirv_binary,
             spv_mod,
+            entry_points,
+            spirt_ctx: Rc::new(spirt::Context::new()),
         })
     }
 
+    ///Loads a module from SPIR-V assembly text, as produced by `spirv-dis` or [Disassembler](crate::asm::Disassembler).
+    pub fn from_assembly(text: &str) -> Result<Self, PatcherError> {
+        let words = rspirv::binary::Assemble::assemble(&crate::asm::assemble(text)?);
+        Self::new(bytemuck::cast_slice(&words).to_vec())
+    }
+
+    ///Returns all entry points declared in this module.
+    pub fn entry_points(&self) -> &[EntryPoint] {
+        &self.entry_points
+    }
+
+    ///Searches for an entry point with the given `name`. Since SPIR-V allows the same name for entry points of
+    /// different execution models (for instance a vertex and a fragment `main`), `model` can be used to disambiguate.
+    pub fn entry_point(
+        &self,
+        name: &str,
+        model: Option<ExecutionModel>,
+    ) -> Result<&EntryPoint, PatcherError> {
+        let mut candidates = self
+            .entry_points
+            .iter()
+            .filter(|ep| ep.name == name && model.map(|m| m == ep.execution_model).unwrap_or(true));
+
+        let found = candidates
+            .next()
+            .ok_or_else(|| PatcherError::UnknownEntryPoint(name.to_owned()))?;
+        if candidates.next().is_some() {
+            return Err(PatcherError::AmbiguousEntryPoint(name.to_owned()));
+        }
+        Ok(found)
+    }
+
     ///Returns the spirv template used by this module when patching
     pub fn spirv(&self) -> &rspirv::dr::Module {
         &self.spv_mod
     }
 
+    ///Returns the SPIR-T context that is used whenever a patching session of this module lowers to SPIR-T.
+    pub fn spirt_context(&self) -> Rc<spirt::Context> {
+        self.spirt_ctx.clone()
+    }
+
+    ///Structurally compares the template against `patched`, see [ModuleDiff](crate::diff::ModuleDiff).
+    pub fn diff(&self, patched: &rspirv::dr::Module) -> crate::diff::ModuleDiff {
+        crate::diff::ModuleDiff::new(&self.spv_mod, patched)
+    }
+
+    ///Reflects the resources used by the entry point `name` of the template, see [Reflection](crate::reflect::Reflection).
+    pub fn reflect(
+        &self,
+        name: &str,
+        model: Option<ExecutionModel>,
+    ) -> Result<crate::reflect::Reflection, PatcherError> {
+        let entry_point = self.entry_point(name, model)?;
+        Ok(crate::reflect::Reflection::from_entry_point(
+            &self.spv_mod,
+            entry_point,
+        ))
+    }
+
     ///Returns the template SPIR-V code. Note that this module might not be a valid SPIR-V module.
     pub fn template_code(&self) -> &[u8] {
         &self.module_binary
     }
 
+    ///Starts a patching session on the whole module.
     pub fn patch<'a>(&'a self) -> Patcher<'a> {
         Patcher {
             module: self,
-            ir_state: crate::patch::IrState::SpirV(self.spv_mod.clone()),
+            ir_state: IrState::from_spirv(self.spv_mod.clone(), self.spirt_ctx.clone()),
+            entry_point: None,
+            verify_patches: cfg!(debug_assertions),
+            reports: Vec::new(),
         }
     }
+
+    ///Starts a patching session that is scoped to a single entry point. Patches that respect the scope only
+    /// touch code that is reachable from that entry point.
+    pub fn patch_entry_point<'a>(
+        &'a self,
+        name: &str,
+        model: Option<ExecutionModel>,
+    ) -> Result<Patcher<'a>, PatcherError> {
+        let entry_point = self.entry_point(name, model)?.clone();
+        Ok(Patcher {
+            module: self,
+            ir_state: IrState::from_spirv(self.spv_mod.clone(), self.spirt_ctx.clone()),
+            entry_point: Some(entry_point),
+            verify_patches: cfg!(debug_assertions),
+            reports: Vec::new(),
+        })
+    }
 }
@@ -0,0 +1,274 @@
+//! Copies functions from one module into another.
+//!
+//! Function local ids are renamed, global declarations are imported on first use. Types and constants go through a
+//! [TypeImporter], so equal types of both modules are shared. `OpExtInstImport`s are shared with the destination as
+//! well, `OpString`s are copied, and `OpUndef`s use the destination's undef of the same type. Instructions of
+//! `NonSemantic.*` extended instruction sets are dropped.
+//!
+//! Global variables are not imported. Mapping one fails with [ImportError::Variable], so the caller can decide how to
+//! resolve it.
+
+use ahash::AHashMap;
+use rspirv::{
+    dr::{Block, Function, Instruction, Module, Operand},
+    spirv::{Op, Word},
+};
+use thiserror::Error;
+
+use crate::{
+    spirv_ext::SpirvExt,
+    type_tree::{TypeImporter, TypeTreeError},
+};
+
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("%{0} is not declared in the source module")]
+    UnknownId(Word),
+    #[error("%{0} is a global variable, which is not imported")]
+    Variable(Word),
+    #[error("Could not import types: {0}")]
+    TypeTree(#[from] TypeTreeError),
+}
+
+///Imports functions of `src` into another module, see the [module](self) documentation.
+pub struct FunctionImporter<'src> {
+    src: &'src Module,
+    types: TypeImporter<'src>,
+    //Function local ids of `src` to destination ids
+    locals: AHashMap<Word, Word>,
+    //Imported extended instruction sets and strings
+    globals: AHashMap<Word, Word>,
+}
+
+impl<'src> FunctionImporter<'src> {
+    pub fn new(src: &'src Module, dst: &Module) -> Result<Self, TypeTreeError> {
+        Ok(FunctionImporter {
+            src,
+            types: TypeImporter::new(src, dst)?,
+            locals: AHashMap::default(),
+            globals: AHashMap::default(),
+        })
+    }
+
+    ///The importer used for types and constants.
+    pub fn types(&mut self) -> &mut TypeImporter<'src> {
+        &mut self.types
+    }
+
+    ///Allocates a destination id for every id the functions at `functions` (indices into `src`) define.
+    pub fn reserve_locals(&mut self, dst: &mut Module, functions: &[usize]) {
+        for f in functions {
+            for inst in self.src.functions[*f].all_inst_iter() {
+                if let Some(id) = inst.result_id {
+                    self.locals.insert(id, dst.allocate_id());
+                }
+            }
+        }
+    }
+
+    ///Reserved function local ids of `src` to their destination ids.
+    pub fn locals(&self) -> &AHashMap<Word, Word> {
+        &self.locals
+    }
+
+    ///Maps the id `id` of `src` into `dst`, importing its declaration if needed.
+    pub fn map(&mut self, dst: &mut Module, id: Word) -> Result<Word, ImportError> {
+        if let Some(new) = self.locals.get(&id).or_else(|| self.globals.get(&id)) {
+            return Ok(*new);
+        }
+
+        let src = self.src;
+        if let Some(import) = src
+            .ext_inst_imports
+            .iter()
+            .find(|inst| inst.result_id == Some(id))
+        {
+            let existing = dst
+                .ext_inst_imports
+                .iter()
+                .find(|inst| inst.operands == import.operands)
+                .and_then(|inst| inst.result_id);
+            let new = match existing {
+                Some(new) => new,
+                None => {
+                    let new = dst.allocate_id();
+                    dst.ext_inst_imports.push(Instruction::new(
+                        Op::ExtInstImport,
+                        None,
+                        Some(new),
+                        import.operands.clone(),
+                    ));
+                    new
+                }
+            };
+            self.globals.insert(id, new);
+            return Ok(new);
+        }
+        if let Some(string) = src
+            .debug_string_source
+            .iter()
+            .find(|inst| inst.class.opcode == Op::String && inst.result_id == Some(id))
+        {
+            let new = dst.allocate_id();
+            dst.debug_string_source.push(Instruction::new(
+                Op::String,
+                None,
+                Some(new),
+                string.operands.clone(),
+            ));
+            self.globals.insert(id, new);
+            return Ok(new);
+        }
+
+        let decl = src
+            .types_global_values
+            .iter()
+            .find(|inst| inst.result_id == Some(id))
+            .ok_or(ImportError::UnknownId(id))?;
+        match decl.class.opcode {
+            Op::Variable => Err(ImportError::Variable(id)),
+            Op::Undef => {
+                let ty = self.types.import(dst, decl.result_type.unwrap())?;
+                Ok(dst.find_or_insert_undef(ty))
+            }
+            _ => Ok(self.types.import(dst, id)?),
+        }
+    }
+
+    ///Copies `inst`, mapping all of its ids into `dst`.
+    pub fn map_instruction(
+        &mut self,
+        dst: &mut Module,
+        inst: &Instruction,
+    ) -> Result<Instruction, ImportError> {
+        let mut inst = inst.clone();
+        if let Some(ty) = inst.result_type {
+            inst.result_type = Some(self.map(dst, ty)?);
+        }
+        if let Some(id) = inst.result_id {
+            inst.result_id = Some(self.map(dst, id)?);
+        }
+        for operand in inst.operands.iter_mut() {
+            if let Some(id) = operand.id_ref_any_mut() {
+                *id = self.map(dst, *id)?;
+            }
+        }
+        Ok(inst)
+    }
+
+    ///True for instructions of `NonSemantic.*` extended instruction sets, which are not imported.
+    pub fn is_non_semantic(&self, inst: &Instruction) -> bool {
+        inst.class.opcode == Op::ExtInst
+            && inst
+                .operands
+                .first()
+                .and_then(|op| op.id_ref_any())
+                .and_then(|set| {
+                    self.src
+                        .ext_inst_imports
+                        .iter()
+                        .find(|import| import.result_id == Some(set))
+                })
+                .map(|import| {
+                    matches!(import.operands.first(), Some(Operand::LiteralString(name)) if name.starts_with("NonSemantic."))
+                })
+                .unwrap_or(false)
+    }
+
+    ///Copies the names and decorations of all reserved locals into `dst`.
+    pub fn import_local_annotations(&mut self, dst: &mut Module) -> Result<(), ImportError> {
+        let src = self.src;
+        let targets_local = |inst: &&Instruction| {
+            inst.operands
+                .first()
+                .and_then(|op| op.id_ref_any())
+                .map(|id| self.locals.contains_key(&id))
+                .unwrap_or(false)
+        };
+        let names = src
+            .debug_names
+            .iter()
+            .filter(targets_local)
+            .collect::<Vec<_>>();
+        let decorations = src
+            .annotations
+            .iter()
+            .filter(targets_local)
+            .collect::<Vec<_>>();
+        for name in names {
+            let mut name = name.clone();
+            name.operands[0] = Operand::IdRef(self.locals[&name.operands[0].unwrap_id_ref()]);
+            dst.debug_names.push(name);
+        }
+        for decoration in decorations {
+            let decoration = self.map_instruction(dst, decoration)?;
+            dst.annotations.push(decoration);
+        }
+        Ok(())
+    }
+
+    ///Copies the function `root` of `src`, every function it calls, and their names and decorations into `dst`.
+    /// Returns the new id of `root`.
+    pub fn import_functions(&mut self, dst: &mut Module, root: Word) -> Result<Word, ImportError> {
+        let src = self.src;
+        let reachable = src.reachable_functions(root);
+        let functions = src
+            .functions
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| {
+                f.def_id()
+                    .map(|id| reachable.contains(&id))
+                    .unwrap_or(false)
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        self.reserve_locals(dst, &functions);
+
+        let mut copied = Vec::with_capacity(functions.len());
+        for function in functions.iter().map(|f| &src.functions[*f]) {
+            let mut blocks = Vec::with_capacity(function.blocks.len());
+            for block in &function.blocks {
+                let mut instructions = Vec::with_capacity(block.instructions.len());
+                for inst in &block.instructions {
+                    if !self.is_non_semantic(inst) {
+                        instructions.push(self.map_instruction(dst, inst)?);
+                    }
+                }
+                blocks.push(Block {
+                    label: block
+                        .label
+                        .as_ref()
+                        .map(|label| self.map_instruction(dst, label))
+                        .transpose()?,
+                    instructions,
+                });
+            }
+            copied.push(Function {
+                def: function
+                    .def
+                    .as_ref()
+                    .map(|def| self.map_instruction(dst, def))
+                    .transpose()?,
+                end: function
+                    .end
+                    .as_ref()
+                    .map(|end| self.map_instruction(dst, end))
+                    .transpose()?,
+                parameters: function
+                    .parameters
+                    .iter()
+                    .map(|param| self.map_instruction(dst, param))
+                    .collect::<Result<_, _>>()?,
+                blocks,
+            });
+        }
+
+        self.import_local_annotations(dst)?;
+        dst.functions.extend(copied);
+        self.locals
+            .get(&root)
+            .copied()
+            .ok_or(ImportError::UnknownId(root))
+    }
+}
@@ -8,6 +8,9 @@
 //! - Patch: defines the `Patch` API, as well as implemented patching passes.
 //! - Verify: Provides custom verification methods, as well as using `spirv-val` to verify a SpirV module.
 //!
+//! Additionally [reflect] reports the resource interface of an entry point, [diff] compares two modules, and [import]
+//! copies functions from one module into another.
+//!
 #![deny(warnings)]
 
 use std::error::Error;
@@ -17,11 +20,16 @@ use thiserror::Error;
 pub use rspirv;
 pub use spirt;
 
+pub mod analysis;
+pub mod asm;
+pub mod diff;
 mod dis_assamble;
-pub use dis_assamble::Module;
+pub use dis_assamble::{EntryPoint, Module};
+pub mod import;
 mod print;
 pub use print::DisassamblerPrinter;
 pub mod patch;
+pub mod reflect;
 pub mod spirv_ext;
 pub mod type_tree;
 mod validator;
@@ -34,12 +42,27 @@ pub enum PatcherError {
     LowerError(#[from] std::io::Error),
     #[error("Could not parse spirv binary code: {0}")]
     SpirVParseError(#[from] rspirv::binary::ParseState),
+    #[error("Could not assemble SPIR-V text: {0}")]
+    AssemblyError(#[from] asm::AssemblerError),
     #[error("SPIR-V build error")]
     SpirVBuildError(#[from] rspirv::dr::Error),
     #[error("Could not load SPIR-V binary, multiple entry-points exist")]
     MultipleEntryPoints,
     #[error("Could not load SPIR-V binary, no entry-point exists")]
     NoEntryPoint,
+    #[error("Module has no entry-point named \"{0}\"")]
+    UnknownEntryPoint(String),
+    #[error("Entry-point name \"{0}\" is ambiguous, specify an execution model")]
+    AmbiguousEntryPoint(String),
+    #[error("Patch \"{patch}\" violated its invariants: {reason}")]
+    VerificationFailed { patch: String, reason: String },
+    #[error("Pipeline step {step} (\"{patch}\") failed: {error}")]
+    PipelineStepFailed {
+        step: usize,
+        patch: String,
+        #[source]
+        error: Box<PatcherError>,
+    },
     #[error("Patch internal runtime error: {0}")]
     Internal(Box<dyn Error>),
 }
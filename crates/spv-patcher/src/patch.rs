@@ -1,9 +1,4 @@
 //TODO Patch ideas:
-//     make-var-const: Rewrite variable to constant. Possibly fold afterwards?
-//     link kernel-in-kernel: takes a kernel, and tries to embedded another kernel into it by linking the host's data to the former input
-//                            parameters of the to-be-embedded kernel.
-//     max-iteration-count: augments a selected loop with a counter that makes it quit after a constant amount of loops (good
-//                          for debugging gpu hangs I'd say)
 //     replace-function: takes a function by-id and replaces its content based on the supplied spirv. Verifies that
 //                       the function's interfaces match. Basically linking, but for already defined functions.
 //     link: Simple link pass, basically what `spv-link` does. Searches for imports and replaces them with export-marked
@@ -13,121 +8,359 @@
 
 //! Patch utilities and pre-implemented patches.
 
+mod invariant;
+mod make_var_const;
+mod pipeline;
 //Test patch
 // TODO: Remove in favor of *correct* patches
 mod memory_model;
 mod mutate_constant;
 
-use crate::PatcherError;
+use crate::{analysis::DefUse, spirv_ext::SpirvExt, EntryPoint, PatcherError};
+use ahash::AHashSet;
+pub use invariant::{Invariant, NoDanglingCalls, ReturnsDeclaredType, Verified};
+pub use make_var_const::{MakeVarConst, MakeVarConstError, VariableSelector};
 pub use memory_model::MemoryModel;
-pub use mutate_constant::MutateConstant;
+pub use mutate_constant::{ConstantSelector, MutateConstant, MutateConstantError};
+pub use pipeline::{
+    DynPatch, FailurePolicy, PatchPipeline, PipelineFailure, StepOutcome, StepReport,
+};
 use rspirv::binary::Assemble;
-use std::rc::Rc;
+use std::{any::Any, rc::Rc};
 
 pub trait Patch {
     fn apply<'a>(self, patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError>;
+
+    ///Name used to attribute errors to this patch. Defaults to the type's name.
+    fn name(&self) -> String {
+        std::any::type_name::<Self>().to_owned()
+    }
+
+    ///Invariants this patch guarantees to hold after [apply](Patch::apply). They are collected before the patch is applied
+    /// and checked by [Patcher::patch] if verification is enabled.
+    fn invariants(&self) -> Vec<Box<dyn Invariant>> {
+        Vec::new()
+    }
+}
+
+///Number of conversions between SPIR-V and SPIR-T an [IrState] performed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConversionStats {
+    ///SPIR-V to SPIR-T lowerings.
+    pub lowered: usize,
+    ///SPIR-T to SPIR-V liftings.
+    pub lifted: usize,
+}
+
+impl ConversionStats {
+    pub fn total(&self) -> usize {
+        self.lowered + self.lifted
+    }
+
+    ///Conversions that happened since `earlier` was taken.
+    pub fn since(&self, earlier: &ConversionStats) -> ConversionStats {
+        ConversionStats {
+            lowered: self.lowered - earlier.lowered,
+            lifted: self.lifted - earlier.lifted,
+        }
+    }
 }
 
 ///Represents current internal IR state.
-pub enum IrState {
-    SpirV(rspirv::dr::Module),
-    SpirT {
-        ctx: Rc<spirt::Context>,
-        module: spirt::Module,
-    },
+///
+/// Both representations are cached. Reading one via [spirv](IrState::spirv) or [spirt](IrState::spirt) converts only if
+/// no up-to-date version exists, and keeps the other one valid. Taking mutable access via [as_spirv](IrState::as_spirv) or
+/// [as_spirt](IrState::as_spirt) marks the other representation as dirty, so that it is rebuilt the next time it is requested.
+pub struct IrState {
+    //`None` if the representation is dirty. At least one of both is always up to date.
+    spirv: Option<rspirv::dr::Module>,
+    spirt: Option<spirt::Module>,
+    ctx: Rc<spirt::Context>,
+    stats: ConversionStats,
+    //Index of the SPIR-V representation. Dropped whenever SPIR-V is mutated without it.
+    def_use: Option<DefUse>,
 }
 
 impl IrState {
+    ///Creates a state from SPIR-V. SPIR-T is lowered within `ctx` once it is requested.
+    pub fn from_spirv(module: rspirv::dr::Module, ctx: Rc<spirt::Context>) -> Self {
+        IrState {
+            spirv: Some(module),
+            spirt: None,
+            ctx,
+            stats: ConversionStats::default(),
+            def_use: None,
+        }
+    }
+
+    ///True if an up-to-date SPIR-V representation is cached.
     pub fn is_spirv(&self) -> bool {
-        if let IrState::SpirV(_) = self {
-            true
-        } else {
-            false
+        self.spirv.is_some()
+    }
+
+    ///True if an up-to-date SPIR-T representation is cached.
+    pub fn is_spirt(&self) -> bool {
+        self.spirt.is_some()
+    }
+
+    ///The SPIR-T context all lowerings of this state use.
+    pub fn context(&self) -> Rc<spirt::Context> {
+        self.ctx.clone()
+    }
+
+    ///Conversions performed by this state so far.
+    pub fn stats(&self) -> ConversionStats {
+        self.stats
+    }
+
+    ///Copies the state without converting it. Only one representation is copied, SPIR-V if it is up to date.
+    pub fn snapshot(&mut self) -> IrState {
+        self.compact();
+        IrState {
+            spirv: self.spirv.clone(),
+            spirt: match self.spirv {
+                Some(_) => None,
+                None => self.spirt.clone(),
+            },
+            ctx: self.ctx.clone(),
+            stats: self.stats,
+            def_use: None,
         }
     }
 
-    ///Transforms the internal representation into SPIR-V.
+    ///Makes sure an up-to-date SPIR-V representation exists, lifting SPIR-T if needed.
     pub fn into_spirv(&mut self) {
-        if let IrState::SpirT { module, .. } = self {
+        if self.spirv.is_none() {
+            let module = self
+                .spirt
+                .as_ref()
+                .expect("IrState holds neither SPIR-V nor SPIR-T");
             let emitter = module
                 .lift_to_spv_module_emitter()
                 .expect("Could not lift SPIR-T to SPIR-V");
             let spv = rspirv::dr::load_words(&emitter.words).unwrap();
 
-            *self = IrState::SpirV(spv);
+            self.stats.lifted += 1;
+            self.spirv = Some(spv);
         };
+        self.compact();
     }
 
-    ///Returns current state as SpirV-IR. Might translate if needed.
+    //Removes instructions killed through the def-use index.
+    fn compact(&mut self) {
+        if let (Some(spv), Some(def_use)) = (&mut self.spirv, &mut self.def_use) {
+            if def_use.has_killed() {
+                def_use.compact(spv);
+            }
+        }
+    }
+
+    ///Returns current state as SpirV-IR without invalidating the SPIR-T representation. Might translate if needed.
+    pub fn spirv(&mut self) -> &rspirv::dr::Module {
+        self.into_spirv();
+        self.spirv.as_ref().unwrap()
+    }
+
+    ///Returns current state as mutable SpirV-IR. Might translate if needed. Marks the SPIR-T representation as dirty.
     pub fn as_spirv(&mut self) -> &mut rspirv::dr::Module {
-        //Return module
         self.into_spirv();
-        if let IrState::SpirV(s) = self {
-            s
-        } else {
-            panic!("Failed to convert to spirv")
-        }
+        self.spirt = None;
+        self.def_use = None;
+        self.spirv.as_mut().unwrap()
+    }
+
+    ///Returns current state as SpirV-IR together with its cached [DefUse] index. The index is built if needed.
+    pub fn def_use(&mut self) -> (&rspirv::dr::Module, &DefUse) {
+        self.into_spirv();
+        let spv = self.spirv.as_ref().unwrap();
+        let def_use = self.def_use.get_or_insert_with(|| DefUse::new(spv));
+        (spv, def_use)
     }
 
-    ///Transforms the internal state into a SPIR-T representation.
+    ///Like [as_spirv](IrState::as_spirv), but keeps the [DefUse] index. All mutations must go through the
+    /// returned index, so that it stays valid.
+    pub fn def_use_mut(&mut self) -> (&mut rspirv::dr::Module, &mut DefUse) {
+        self.into_spirv();
+        self.spirt = None;
+        let spv = self.spirv.as_mut().unwrap();
+        let def_use = self.def_use.get_or_insert_with(|| DefUse::new(spv));
+        (spv, def_use)
+    }
+
+    ///Makes sure an up-to-date SPIR-T representation exists, lowering SPIR-V if needed.
     pub fn into_spirt(&mut self) {
-        if let IrState::SpirV(spv) = self {
-            let ctx = Rc::new(spirt::Context::new());
+        if self.spirt.is_none() {
+            self.compact();
+            let spv = self
+                .spirv
+                .as_ref()
+                .expect("IrState holds neither SPIR-V nor SPIR-T");
             let spv_code = spv.assemble();
             let spv_bytes: Vec<u8> = bytemuck::cast_slice(&spv_code).to_vec();
-            let module = spirt::Module::lower_from_spv_bytes(ctx.clone(), spv_bytes).unwrap();
-            *self = IrState::SpirT { ctx, module }
+            let module = spirt::Module::lower_from_spv_bytes(self.ctx.clone(), spv_bytes).unwrap();
+
+            self.stats.lowered += 1;
+            self.spirt = Some(module);
         }
     }
 
+    ///Returns current state as SPIR-T without invalidating the SPIR-V representation. Might translate if needed.
+    pub fn spirt(&mut self) -> &spirt::Module {
+        self.into_spirt();
+        self.spirt.as_ref().unwrap()
+    }
+
+    ///Returns current state as mutable SPIR-T. Might translate if needed. Marks the SPIR-V representation as dirty.
     pub fn as_spirt(&mut self) -> (&mut spirt::Module, Rc<spirt::Context>) {
         self.into_spirt();
-        if let IrState::SpirT { ctx, module } = self {
-            (module, ctx.clone())
-        } else {
-            panic!("Failed to lower to spir-t")
-        }
+        self.spirv = None;
+        self.def_use = None;
+        (self.spirt.as_mut().unwrap(), self.ctx.clone())
     }
 }
 
 pub struct Patcher<'module> {
     pub module: &'module crate::Module,
     pub ir_state: IrState,
+    ///The entry point this patching session is scoped to. `None` if the whole module is patched.
+    pub entry_point: Option<EntryPoint>,
+    ///If set, each patch's [invariants](Patch::invariants) are checked after it was applied. Enabled by default in debug builds.
+    pub verify_patches: bool,
+    //Reports patches left behind, in the order they were pushed.
+    pub(crate) reports: Vec<Box<dyn Any>>,
 }
 
 impl<'module> Patcher<'module> {
-    pub fn print(self) -> Self {
-        match &self.ir_state {
-            IrState::SpirT { ctx: _, module } => {
+    ///Resolves the id of the selected entry point's function in the current IR state. Returns `None` if the session is not
+    /// scoped to an entry point.
+    pub fn entry_function(&mut self) -> Option<u32> {
+        let ep = self.entry_point.as_ref()?;
+        let (name, model) = (ep.name.clone(), ep.execution_model);
+        let id = self.ir_state.spirv().find_entry_point(&name, Some(model));
+        if id.is_none() {
+            log::error!("Selected entry point {} vanished while patching", name);
+        }
+        id
+    }
+
+    ///Returns the set of function ids that are reachable from the selected entry point, or `None` if
+    /// the session is not scoped to an entry point, in which case all functions should be considered.
+    pub fn scope(&mut self) -> Option<AHashSet<u32>> {
+        let root = self.entry_function()?;
+        Some(self.ir_state.spirv().reachable_functions(root))
+    }
+
+    ///Prints the current state. SPIR-V is preferred if it is up to date.
+    pub fn print(mut self) -> Self {
+        self.ir_state.compact();
+        match (&self.ir_state.spirv, &self.ir_state.spirt) {
+            (Some(spv), _) => {
+                println!("SPIR-V:\n{}", crate::asm::disassemble(spv));
+            }
+            (None, Some(module)) => {
                 print!(
                     "SPIR-T:\n{}",
                     spirt::print::Plan::for_module(module).pretty_print()
                 );
             }
-            IrState::SpirV(spv) => {
-                println!("SPIR-V\n{:#?}", spv);
+            (None, None) => {}
+        }
+        self
+    }
+
+    ///Stores the report of a patch, so that it can be retrieved via [report](Patcher::report) after patching.
+    pub fn push_report<R: Any>(&mut self, report: R) {
+        self.reports.push(Box::new(report));
+    }
+
+    ///Returns the report of type `R` that was pushed last, if any.
+    ///
+    ///Patches that report on their work push that report at the end of [Patch::apply], so it can be retrieved here by
+    /// type. Patches that can be run on a module directly return the same report from there.
+    pub fn report<R: Any>(&self) -> Option<&R> {
+        self.reports
+            .iter()
+            .rev()
+            .find_map(|report| report.downcast_ref::<R>())
+    }
+
+    ///Removes all reports of type `R` and returns them in the order they were pushed.
+    pub fn take_reports<R: Any>(&mut self) -> Vec<R> {
+        let mut taken = Vec::new();
+        let mut kept = Vec::with_capacity(self.reports.len());
+        for report in self.reports.drain(..) {
+            match report.downcast::<R>() {
+                Ok(report) => taken.push(*report),
+                Err(other) => kept.push(other),
             }
         }
+        self.reports = kept;
+        taken
+    }
+
+    ///Conversions between SPIR-V and SPIR-T performed in this session so far.
+    pub fn conversions(&self) -> ConversionStats {
+        self.ir_state.stats()
+    }
+
+    ///Enables or disables the post-patch verification of [Patch::invariants].
+    pub fn with_verification(mut self, verify: bool) -> Self {
+        self.verify_patches = verify;
         self
     }
 
+    ///Applies `to_apply`. If verification is enabled, checks the patch's invariants afterwards and fails with
+    /// [PatcherError::VerificationFailed] if any of them does not hold.
+    ///
+    ///Note that verifying lifts the module to SPIR-V if the patch left it in SPIR-T form. The SPIR-T form stays cached.
     pub fn patch(self, to_apply: impl crate::patch::Patch) -> Result<Self, PatcherError> {
-        to_apply.apply(self)
+        if !self.verify_patches {
+            return to_apply.apply(self);
+        }
+
+        let name = to_apply.name();
+        let invariants = to_apply.invariants();
+        let mut patched = to_apply.apply(self)?;
+        if !invariants.is_empty() {
+            let module = patched.ir_state.spirv();
+            for invariant in invariants {
+                invariant
+                    .check(module)
+                    .map_err(|reason| PatcherError::VerificationFailed {
+                        patch: name.clone(),
+                        reason,
+                    })?;
+            }
+        }
+        Ok(patched)
+    }
+
+    ///Structurally compares the template module against the current state of this session.
+    pub fn diff(&mut self) -> crate::diff::ModuleDiff {
+        self.module.diff(self.ir_state.spirv())
+    }
+
+    ///Runs the native [Verifier](crate::verify::Verifier) on the current state of the module.
+    pub fn verify(&mut self) -> Result<(), crate::verify::Diagnostics> {
+        crate::verify::Verifier::verify(self.ir_state.spirv())
     }
 
-    pub fn unwrap_module(mut self) -> rspirv::dr::Module{
-        self.ir_state.as_spirv().clone()
+    pub fn unwrap_module(mut self) -> rspirv::dr::Module {
+        self.ir_state.into_spirv();
+        self.ir_state.spirv.unwrap()
     }
 
     ///Assembles the patched spriv code. Can be directly loaded into a OpenCL or OpenGL pipeline.
-    pub fn assemble(self) -> Vec<u32> {
-        match self.ir_state {
-            IrState::SpirT { ctx: _, module } => module.lift_to_spv_module_emitter().unwrap().words,
-            IrState::SpirV(spv) => spv.assemble(),
+    pub fn assemble(mut self) -> Vec<u32> {
+        self.ir_state.compact();
+        match (self.ir_state.spirv, self.ir_state.spirt) {
+            (Some(spv), _) => spv.assemble(),
+            (None, Some(module)) => module.lift_to_spv_module_emitter().unwrap().words,
+            (None, None) => unreachable!("IrState holds neither SPIR-V nor SPIR-T"),
         }
     }
 
-    pub fn assemble_bytes(self) -> Vec<u8>{
+    pub fn assemble_bytes(self) -> Vec<u8> {
         let vecu32 = self.assemble();
         //NOTE: for some reason the cast_vec does not work
         bytemuck::cast_slice(&vecu32).to_vec()
@@ -0,0 +1,123 @@
+use ahash::AHashMap;
+use rspirv::{dr::Module, spirv::Op};
+
+use crate::verify::Verifier;
+
+///A property a [Patch](super::Patch) guarantees to hold after it was applied. Invariants are collected
+/// before the patch is applied, and checked against the patched module if verification is enabled on the [Patcher](super::Patcher).
+///
+///Closures of the form `Fn(&Module) -> Result<(), String>` are invariants as well.
+pub trait Invariant {
+    ///Checks the invariant on `module`. Returns a description of the violation if it does not hold.
+    fn check(&self, module: &Module) -> Result<(), String>;
+}
+
+impl<F> Invariant for F
+where
+    F: Fn(&Module) -> Result<(), String>,
+{
+    fn check(&self, module: &Module) -> Result<(), String> {
+        self(module)
+    }
+}
+
+///Every `OpFunctionCall` calls a function that is defined or declared in the module, with the
+/// callee's parameter count.
+pub struct NoDanglingCalls;
+
+impl Invariant for NoDanglingCalls {
+    fn check(&self, module: &Module) -> Result<(), String> {
+        let functions: AHashMap<u32, usize> = module
+            .functions
+            .iter()
+            .filter_map(|f| f.def_id().map(|id| (id, f.parameters.len())))
+            .collect();
+
+        for inst in module.all_inst_iter() {
+            if inst.class.opcode != Op::FunctionCall {
+                continue;
+            }
+            let Some(callee) = inst.operands.get(0).and_then(|op| op.id_ref_any()) else {
+                return Err(String::from("OpFunctionCall without callee"));
+            };
+            match functions.get(&callee) {
+                None => return Err(format!("Call to undefined function %{}", callee)),
+                Some(params) if *params != inst.operands.len() - 1 => {
+                    return Err(format!(
+                        "Call to %{} passes {} arguments, but the function takes {}",
+                        callee,
+                        inst.operands.len() - 1,
+                        params
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+///Every function with a body returns a value of its declared return type, or uses `OpReturn` if the
+/// return type is void.
+pub struct ReturnsDeclaredType;
+
+impl Invariant for ReturnsDeclaredType {
+    fn check(&self, module: &Module) -> Result<(), String> {
+        let types: AHashMap<u32, u32> = module
+            .all_inst_iter()
+            .filter_map(|inst| inst.result_id.zip(inst.result_type))
+            .collect();
+        let void_types: Vec<u32> = module
+            .types_global_values
+            .iter()
+            .filter(|inst| inst.class.opcode == Op::TypeVoid)
+            .filter_map(|inst| inst.result_id)
+            .collect();
+
+        for f in &module.functions {
+            let (Some(id), Some(return_type)) =
+                (f.def_id(), f.def.as_ref().and_then(|def| def.result_type))
+            else {
+                continue;
+            };
+            let is_void = void_types.contains(&return_type);
+
+            for inst in f.blocks.iter().filter_map(|b| b.instructions.last()) {
+                match inst.class.opcode {
+                    Op::Return if !is_void => {
+                        return Err(format!(
+                            "Function %{} uses OpReturn, but returns %{}",
+                            id, return_type
+                        ))
+                    }
+                    Op::ReturnValue => {
+                        let value_type = inst
+                            .operands
+                            .get(0)
+                            .and_then(|op| op.id_ref_any())
+                            .and_then(|value| types.get(&value));
+                        if value_type != Some(&return_type) {
+                            return Err(format!(
+                                "Function %{} returns a value of type {:?}, but declares %{}",
+                                id, value_type, return_type
+                            ));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+///The module passes the native [Verifier].
+pub struct Verified;
+
+impl Invariant for Verified {
+    fn check(&self, module: &Module) -> Result<(), String> {
+        Verifier::verify(module).map_err(|diagnostics| diagnostics.to_string())
+    }
+}
@@ -0,0 +1,272 @@
+//! Patch that rewrites a variable into a constant.
+
+use rspirv::{
+    dr::{Module, Operand},
+    spirv::{Op, StorageClass, Word},
+};
+use thiserror::Error;
+
+use crate::{
+    analysis::{DefUse, InstLocation, Section, UseSlot},
+    spirv_ext::{ConstantValue, SpirvExt},
+    PatcherError,
+};
+
+use super::{mutate_constant::type_matches, Patch};
+
+#[derive(Error, Debug)]
+pub enum MakeVarConstError {
+    #[error("No variable matches {0:?}")]
+    NoMatch(VariableSelector),
+    #[error("Name \"{0}\" is used by more than one variable")]
+    Ambiguous(String),
+    #[error("%{id} is a {op:?}, not a variable")]
+    NotAVariable { id: Word, op: Op },
+    #[error("Variable %{id} has storage class {class:?}, only Function and Private variables can be made constant")]
+    StorageClass { id: Word, class: StorageClass },
+    #[error("Variable %{id} is stored {stores} times, and no value was given")]
+    NotStoredOnce { id: Word, stores: usize },
+    #[error("Variable %{id} is assigned %{value}, which is not a constant")]
+    NotAConstant { id: Word, value: Word },
+    #[error("Variable %{id} is used by a {op:?}, only loads and stores can be rewritten")]
+    UnsupportedUse { id: Word, op: Op },
+    #[error("Can not assign {value:?} to variable %{id} of type %{ty}")]
+    TypeMismatch {
+        id: Word,
+        ty: Word,
+        value: ConstantValue,
+    },
+}
+
+///Selects the variable a [MakeVarConst] rewrites.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VariableSelector {
+    ///The variable with this `OpName`. Fails if several variables share the name.
+    Name(String),
+    ///The variable with this result id.
+    Id(Word),
+}
+
+///Rewrites a `Function` or `Private` variable into a constant. All loads of the variable are replaced by the constant,
+/// and the variable is removed together with its stores.
+///
+///Without a `value`, the variable must be assigned exactly once, either by its initializer or by a single `OpStore` of a
+/// constant. Loads that might execute before that store read an undefined value, so using the constant there is valid as
+/// well. With a `value`, all stores are dropped and loads see `value` instead.
+///
+///The variable may only be used by loads and stores, since access chains or function calls could write to it. Chain
+/// `ConstantFold` of `patch-constant-fold` afterwards to fold instructions that became constant.
+#[derive(Debug, Clone)]
+pub struct MakeVarConst {
+    pub variable: VariableSelector,
+    pub value: Option<ConstantValue>,
+}
+
+impl MakeVarConst {
+    pub fn new(variable: VariableSelector) -> Self {
+        MakeVarConst {
+            variable,
+            value: None,
+        }
+    }
+
+    ///Forces the variable to `value`, regardless of what is stored to it.
+    pub fn with_value(mut self, value: ConstantValue) -> Self {
+        self.value = Some(value);
+        self
+    }
+
+    ///Rewrites the variable in `module`. Returns the id of the constant that replaced it.
+    pub fn run(&self, module: &mut Module) -> Result<Word, MakeVarConstError> {
+        let mut def_use = DefUse::new(module);
+        let constant = self.make_const(module, &mut def_use)?;
+        def_use.compact(module);
+        Ok(constant)
+    }
+
+    fn select(&self, module: &Module, def_use: &DefUse) -> Result<Word, MakeVarConstError> {
+        let is_variable = |id: Word| {
+            def_use
+                .def_inst(module, id)
+                .map(|inst| inst.class.opcode == Op::Variable)
+                .unwrap_or(false)
+        };
+        match &self.variable {
+            VariableSelector::Id(id) => match def_use.def_inst(module, *id) {
+                Some(inst) if inst.class.opcode == Op::Variable => Ok(*id),
+                Some(inst) => Err(MakeVarConstError::NotAVariable {
+                    id: *id,
+                    op: inst.class.opcode,
+                }),
+                None => Err(MakeVarConstError::NoMatch(self.variable.clone())),
+            },
+            VariableSelector::Name(name) => {
+                let mut variables = module
+                    .debug_names
+                    .iter()
+                    .filter(|inst| {
+                        inst.class.opcode == Op::Name
+                            && inst.operands.get(1) == Some(&Operand::LiteralString(name.clone()))
+                    })
+                    .filter_map(|inst| inst.operands.first().and_then(|op| op.id_ref_any()))
+                    .filter(|id| is_variable(*id));
+                let id = variables
+                    .next()
+                    .ok_or_else(|| MakeVarConstError::NoMatch(self.variable.clone()))?;
+                if variables.next().is_some() {
+                    return Err(MakeVarConstError::Ambiguous(name.clone()));
+                }
+                Ok(id)
+            }
+        }
+    }
+
+    fn make_const(
+        &self,
+        module: &mut Module,
+        def_use: &mut DefUse,
+    ) -> Result<Word, MakeVarConstError> {
+        let id = self.select(module, def_use)?;
+        let variable = def_use.def_inst(module, id).unwrap();
+        let class = match variable.operands.first() {
+            Some(Operand::StorageClass(class)) => *class,
+            _ => StorageClass::Generic,
+        };
+        if !matches!(class, StorageClass::Function | StorageClass::Private) {
+            return Err(MakeVarConstError::StorageClass { id, class });
+        }
+        let initializer = variable.operands.get(1).and_then(|op| op.id_ref_any());
+        let pointee = variable
+            .result_type
+            .and_then(|ptr| def_use.def_inst(module, ptr))
+            .and_then(|ptr| ptr.operands.get(1))
+            .and_then(|op| op.id_ref_any())
+            .unwrap_or(0);
+
+        //Sort all uses into loads and stores
+        let mut loads = Vec::new();
+        let mut stores = Vec::new();
+        let mut in_interface = false;
+        for u in def_use.users_of(id).iter().copied() {
+            let Some(inst) = u.location.get(module) else {
+                continue;
+            };
+            //Names and decorations are removed together with the variable
+            if let InstLocation::Global {
+                section: Section::Annotations | Section::DebugNames,
+                ..
+            } = u.location
+            {
+                continue;
+            }
+            match (inst.class.opcode, u.slot) {
+                (Op::Load, UseSlot::Operand(0)) => loads.push(u.location),
+                (Op::Store, UseSlot::Operand(0)) => stores.push((
+                    u.location,
+                    inst.operands.get(1).and_then(|op| op.id_ref_any()),
+                )),
+                (Op::EntryPoint, _) => in_interface = true,
+                (op, _) => return Err(MakeVarConstError::UnsupportedUse { id, op }),
+            }
+        }
+
+        let constant = match &self.value {
+            Some(value) => {
+                if !type_matches(module, pointee, value) {
+                    return Err(MakeVarConstError::TypeMismatch {
+                        id,
+                        ty: pointee,
+                        value: value.clone(),
+                    });
+                }
+                let declared = module.types_global_values.len();
+                let constant = module.find_or_insert_constant(value.clone());
+                //Declarations are appended, so the locations collected above stay valid
+                if module.types_global_values.len() != declared {
+                    *def_use = DefUse::new(module);
+                }
+                constant
+            }
+            None => {
+                let mut assigned = initializer
+                    .into_iter()
+                    .chain(stores.iter().map(|(_, value)| value.unwrap_or(0)));
+                let value = match (assigned.next(), assigned.next()) {
+                    (Some(value), None) => value,
+                    _ => {
+                        return Err(MakeVarConstError::NotStoredOnce {
+                            id,
+                            stores: stores.len() + initializer.iter().count(),
+                        })
+                    }
+                };
+                let is_constant = def_use
+                    .def_inst(module, value)
+                    .map(|inst| is_constant(inst.class.opcode))
+                    .unwrap_or(false);
+                if !is_constant {
+                    return Err(MakeVarConstError::NotAConstant { id, value });
+                }
+                value
+            }
+        };
+
+        log::trace!(
+            "Replace {} loads of %{} with %{}, removing {} stores",
+            loads.len(),
+            id,
+            constant,
+            stores.len()
+        );
+        for location in loads {
+            if let Some(load) = location.get(module).and_then(|inst| inst.result_id) {
+                //Kill names and decorations first, so they don't end up on the constant
+                def_use.kill(module, load);
+                def_use.replace_all_uses(module, load, constant);
+            }
+            def_use.kill_at(module, location);
+        }
+        for (location, _) in stores {
+            def_use.kill_at(module, location);
+        }
+        def_use.kill(module, id);
+        //Interface lists of entry points may reference private variables since SPIR-V 1.4
+        if in_interface {
+            for entry_point in module.entry_points.iter_mut() {
+                entry_point.operands.retain(|op| op != &Operand::IdRef(id));
+            }
+            def_use.compact(module);
+        }
+        Ok(constant)
+    }
+}
+
+fn is_constant(op: Op) -> bool {
+    matches!(
+        op,
+        Op::Constant
+            | Op::ConstantTrue
+            | Op::ConstantFalse
+            | Op::ConstantNull
+            | Op::ConstantComposite
+            | Op::SpecConstant
+            | Op::SpecConstantTrue
+            | Op::SpecConstantFalse
+            | Op::SpecConstantComposite
+            | Op::SpecConstantOp
+    )
+}
+
+impl Patch for MakeVarConst {
+    fn apply<'a>(
+        self,
+        mut patcher: super::Patcher<'a>,
+    ) -> Result<super::Patcher<'a>, PatcherError> {
+        let (spv_mod, def_use) = patcher.ir_state.def_use_mut();
+        let constant = self
+            .make_const(spv_mod, def_use)
+            .map_err(|e| PatcherError::Internal(e.into()))?;
+        log::info!("Made {:?} constant %{}", self.variable, constant);
+        Ok(patcher)
+    }
+}
@@ -1,48 +1,233 @@
-//! Test patch that mutates a given constant.
+//! Patch that mutates selected constants.
 
-use rspirv::{dr::Operand, spirv::Op};
+use rspirv::{
+    dr::{Instruction, Module, Operand},
+    spirv::Op,
+};
+use thiserror::Error;
 
-use crate::{spirv_ext::SpirvExt, PatcherError};
+use crate::{
+    analysis::{Use, UseSlot},
+    spirv_ext::{ConstantValue, SpirvExt},
+    PatcherError,
+};
 
 use super::Patch;
 
-pub enum MutateConstant {
-    Integer { from: u32, to: u32 },
-    Float { from: f32, to: f32 },
+#[derive(Error, Debug)]
+pub enum MutateConstantError {
+    #[error("No constant matches {0:?}")]
+    NoMatch(ConstantSelector),
+    #[error("%{id} is a {op:?}, not a constant")]
+    NotAConstant { id: u32, op: Op },
+    #[error("Selected use does not exist, or does not use an id")]
+    InvalidUse,
+    #[error("Can not assign {value:?} to constant %{id} of type %{ty}")]
+    TypeMismatch {
+        id: u32,
+        ty: u32,
+        value: ConstantValue,
+    },
 }
 
-impl Patch for MutateConstant {
-    fn apply<'a>(
-        self,
-        mut patcher: super::Patcher<'a>,
-    ) -> Result<super::Patcher<'a>, PatcherError> {
-        let spv_mod = patcher.ir_state.as_spirv();
-
-        if spv_mod.has_extension("GL_EXT_nonuniform_qualifier") {
-            log::info!("Has extension!");
-        }
+///Selects the constants a [MutateConstant] changes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstantSelector {
+    ///All constants with this `OpName`.
+    Name(String),
+    ///The constant with this result id.
+    Id(u32),
+    ///All constants with this value and type.
+    Value(ConstantValue),
+    ///Only this use of a constant, for instance one of [DefUse::users_of](crate::analysis::DefUse::users_of). Other
+    /// uses of the same constant keep their value.
+    Use(Use),
+}
 
-        for c in spv_mod.types_global_values.iter_mut() {
-            if c.class.opcode == Op::Constant {
-                match (&mut c.operands[0], &self) {
-                    (Operand::LiteralBit32(lit), MutateConstant::Integer { from, to }) => {
-                        if *lit == *from {
-                            log::trace!("Mutatet Constant, found right const {} -> {}", from, to);
-                            *lit = *to;
-                        }
-                    }
-                    (Operand::LiteralBit32(lit), MutateConstant::Float { from, to }) => {
-                        let litf32 = f32::from_bits(*lit);
-                        if litf32 == *from {
-                            log::trace!("Mutatet Constant, found right const {} -> {}", from, to);
-                            *lit = to.to_bits();
+impl ConstantSelector {
+    ///Indices into `types_global_values` of the selected constants. A [Use](ConstantSelector::Use) selects the constant
+    /// it uses.
+    pub fn select(&self, spv: &Module) -> Result<Vec<usize>, MutateConstantError> {
+        let mut selected = Vec::new();
+        match self {
+            ConstantSelector::Name(name) => {
+                for inst in &spv.debug_names {
+                    if let (Op::Name, Some(id), Some(Operand::LiteralString(n))) = (
+                        inst.class.opcode,
+                        inst.operands.get(0).and_then(|op| op.id_ref_any()),
+                        inst.operands.get(1),
+                    ) {
+                        if n == name {
+                            selected.extend(constant_index(spv, id)?);
                         }
                     }
-                    _ => {}
                 }
             }
+            ConstantSelector::Id(id) => selected.extend(constant_index(spv, *id)?),
+            ConstantSelector::Value(value) => {
+                let (op, operands) = value.parts();
+                selected.extend(
+                    spv.types_global_values
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, inst)| {
+                            inst.class.opcode == op
+                                && inst.operands == operands
+                                && inst
+                                    .result_type
+                                    .map(|ty| type_matches(spv, ty, value))
+                                    .unwrap_or(false)
+                        })
+                        .map(|(idx, _)| idx),
+                );
+            }
+            ConstantSelector::Use(to_select) => {
+                let UseSlot::Operand(operand) = to_select.slot else {
+                    return Err(MutateConstantError::InvalidUse);
+                };
+                let used = to_select
+                    .location
+                    .get(spv)
+                    .and_then(|inst| inst.operands.get(operand))
+                    .and_then(|op| op.id_ref_any())
+                    .ok_or(MutateConstantError::InvalidUse)?;
+                selected.extend(constant_index(spv, used)?);
+            }
+        }
+        Ok(selected)
+    }
+}
+
+///Changes the value of all constants selected by `selector` to `to`. Fails if no constant is selected, or if `to` has a
+/// different type than a selected constant.
+///
+///Constants selected by name, id or value are changed in place, so all their uses change. Specialization constants are
+/// not changed.
+#[derive(Debug, Clone)]
+pub struct MutateConstant {
+    pub selector: ConstantSelector,
+    pub to: ConstantValue,
+}
+
+impl MutateConstant {
+    pub fn new(selector: ConstantSelector, to: ConstantValue) -> Self {
+        MutateConstant { selector, to }
+    }
+
+    fn mutate_use(&self, spv: &mut Module, to_mutate: Use) -> Result<(), MutateConstantError> {
+        let idx = *self
+            .selector
+            .select(spv)?
+            .first()
+            .ok_or(MutateConstantError::NoMatch(self.selector.clone()))?;
+        self.check_type(spv, &spv.types_global_values[idx])?;
+
+        let new = spv.find_or_insert_constant(self.to.clone());
+        if let (UseSlot::Operand(operand), Some(inst)) =
+            (to_mutate.slot, to_mutate.location.get_mut(spv))
+        {
+            if let Some(id) = inst
+                .operands
+                .get_mut(operand)
+                .and_then(|op| op.id_ref_any_mut())
+            {
+                *id = new;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_type(&self, spv: &Module, constant: &Instruction) -> Result<(), MutateConstantError> {
+        let id = constant.result_id.unwrap_or(0);
+        let ty = constant.result_type.unwrap_or(0);
+        if type_matches(spv, ty, &self.to) {
+            Ok(())
+        } else {
+            Err(MutateConstantError::TypeMismatch {
+                id,
+                ty,
+                value: self.to.clone(),
+            })
+        }
+    }
+
+    fn mutate(&self, spv: &mut Module) -> Result<(), MutateConstantError> {
+        if let ConstantSelector::Use(to_mutate) = &self.selector {
+            return self.mutate_use(spv, *to_mutate);
+        }
+
+        let selected = self.selector.select(spv)?;
+        if selected.is_empty() {
+            return Err(MutateConstantError::NoMatch(self.selector.clone()));
+        }
+        for idx in &selected {
+            self.check_type(spv, &spv.types_global_values[*idx])?;
         }
 
+        let (op, operands) = self.to.parts();
+        for idx in selected {
+            let constant = &mut spv.types_global_values[idx];
+            log::trace!(
+                "Mutate constant %{} to {:?}",
+                constant.result_id.unwrap_or(0),
+                self.to
+            );
+            let (ty, id) = (constant.result_type, constant.result_id);
+            *constant = Instruction::new(op, ty, id, operands.clone());
+        }
+        Ok(())
+    }
+}
+
+//Index of `id`'s declaration in `types_global_values`. Fails if it is not a (non-specialization) constant.
+fn constant_index(spv: &Module, id: u32) -> Result<Option<usize>, MutateConstantError> {
+    let Some(idx) = spv
+        .types_global_values
+        .iter()
+        .position(|inst| inst.result_id == Some(id))
+    else {
+        return Ok(None);
+    };
+    let op = spv.types_global_values[idx].class.opcode;
+    if is_constant(op) {
+        Ok(Some(idx))
+    } else {
+        Err(MutateConstantError::NotAConstant { id, op })
+    }
+}
+
+//True if `value` can be assigned to a constant of type `ty`.
+pub(super) fn type_matches(spv: &Module, ty: u32, value: &ConstantValue) -> bool {
+    match value.scalar_type() {
+        Ok((op, operands)) => spv
+            .types_global_values
+            .iter()
+            .find(|inst| inst.result_id == Some(ty))
+            .map(|inst| inst.class.opcode == op && inst.operands == operands)
+            .unwrap_or(false),
+        Err(value_ty) => value_ty == ty,
+    }
+}
+
+fn is_constant(op: Op) -> bool {
+    matches!(
+        op,
+        Op::Constant
+            | Op::ConstantTrue
+            | Op::ConstantFalse
+            | Op::ConstantNull
+            | Op::ConstantComposite
+    )
+}
+
+impl Patch for MutateConstant {
+    fn apply<'a>(
+        self,
+        mut patcher: super::Patcher<'a>,
+    ) -> Result<super::Patcher<'a>, PatcherError> {
+        let spv_mod = patcher.ir_state.as_spirv();
+        self.mutate(spv_mod)
+            .map_err(|e| PatcherError::Internal(e.into()))?;
         Ok(patcher)
     }
 }
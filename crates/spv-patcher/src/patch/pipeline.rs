@@ -0,0 +1,240 @@
+use std::time::{Duration, Instant};
+
+use crate::PatcherError;
+
+use super::{ConversionStats, IrState, Patch, Patcher};
+
+///Object safe version of [Patch]. Implemented for every patch, so any patch can be stored in a [PatchPipeline].
+pub trait DynPatch {
+    fn apply_boxed<'a>(self: Box<Self>, patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError>;
+    fn patch_name(&self) -> String;
+}
+
+impl<P: Patch> DynPatch for P {
+    fn apply_boxed<'a>(self: Box<Self>, patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError> {
+        //Go through the patcher, so invariants are checked as well.
+        patcher.patch(*self)
+    }
+
+    fn patch_name(&self) -> String {
+        Patch::name(self)
+    }
+}
+
+///Decides which state a [PatchPipeline] returns if one of its steps fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailurePolicy {
+    ///Restore the state the pipeline started with, i.e. the last known-good variant.
+    #[default]
+    Rollback,
+    ///Keep all steps that succeeded before the failing one.
+    KeepPartial,
+}
+
+///Outcome of a single pipeline step.
+#[derive(Debug)]
+pub enum StepOutcome {
+    Applied,
+    Failed(PatcherError),
+    ///Not executed, since an earlier step failed.
+    Skipped,
+}
+
+///Report of a single step of a [PatchPipeline].
+#[derive(Debug)]
+pub struct StepReport {
+    pub name: String,
+    pub outcome: StepOutcome,
+    pub duration: Duration,
+    ///SPIR-V / SPIR-T conversions the step caused. Useful to order patches so that steps working on the same
+    /// representation run back to back.
+    pub conversions: ConversionStats,
+}
+
+impl StepReport {
+    pub fn is_applied(&self) -> bool {
+        matches!(self.outcome, StepOutcome::Applied)
+    }
+}
+
+///Returned by [PatchPipeline::run] if a step failed.
+pub struct PipelineFailure<'a> {
+    ///Index of the failing step.
+    pub step: usize,
+    ///Patcher in the state selected by the pipeline's [FailurePolicy].
+    pub patcher: Patcher<'a>,
+    ///Report of every step. The failing step's report contains the error.
+    pub reports: Vec<StepReport>,
+}
+
+impl<'a> PipelineFailure<'a> {
+    ///Returns the error of the failing step.
+    pub fn error(&self) -> &PatcherError {
+        match &self.reports[self.step].outcome {
+            StepOutcome::Failed(e) => e,
+            _ => unreachable!("Failing step has no error"),
+        }
+    }
+
+    ///Name of the failing patch.
+    pub fn failed_patch(&self) -> &str {
+        &self.reports[self.step].name
+    }
+}
+
+///An ordered list of patches that is applied transactionally.
+///
+/// Since [Patch::apply] consumes the [Patcher], a failing patch would normally lose all prior work. The pipeline
+/// snapshots the module before each step, so that a failure returns a usable [Patcher] as defined by
+/// the [FailurePolicy], together with a report of each step.
+///
+///Reports pushed via [Patcher::push_report] are kept for every step whose state is kept, i.e. rolling back drops
+/// the reports of all steps.
+///
+///Snapshots copy whichever representation is up to date, see [IrState::snapshot], so taking them never converts
+/// the module. Under [FailurePolicy::Rollback] only the initial state is copied.
+pub struct PatchPipeline {
+    steps: Vec<Box<dyn DynPatch>>,
+    pub policy: FailurePolicy,
+}
+
+impl Default for PatchPipeline {
+    fn default() -> Self {
+        Self::new(FailurePolicy::default())
+    }
+}
+
+impl PatchPipeline {
+    pub fn new(policy: FailurePolicy) -> Self {
+        PatchPipeline {
+            steps: Vec::new(),
+            policy,
+        }
+    }
+
+    ///Appends `patch` as the last step.
+    pub fn push(&mut self, patch: impl Patch + 'static) {
+        self.steps.push(Box::new(patch));
+    }
+
+    ///Appends `patch` as the last step, builder style.
+    pub fn then(mut self, patch: impl Patch + 'static) -> Self {
+        self.push(patch);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    ///Applies all steps in order. Returns the patched [Patcher] and a report per step, or a [PipelineFailure]
+    /// if any step failed.
+    pub fn run<'a>(
+        self,
+        mut patcher: Patcher<'a>,
+    ) -> Result<(Patcher<'a>, Vec<StepReport>), PipelineFailure<'a>> {
+        let module = patcher.module;
+        let entry_point = patcher.entry_point.clone();
+        let verify_patches = patcher.verify_patches;
+        let restore = |mut ir_state: IrState,
+                       stats: ConversionStats,
+                       reports: Vec<Box<dyn std::any::Any>>| {
+            ir_state.stats = stats;
+            Patcher {
+                module,
+                ir_state,
+                entry_point: entry_point.clone(),
+                verify_patches,
+                reports,
+            }
+        };
+        //Patch reports pushed before the pipeline started. Those are the only ones kept on rollback.
+        let initial_reports = patcher.reports.len();
+
+        //State a failing step restores. Under KeepPartial it is replaced before each step.
+        let mut last_good = match self.policy {
+            FailurePolicy::Rollback => Some(patcher.ir_state.snapshot()),
+            FailurePolicy::KeepPartial => None,
+        };
+        let mut reports: Vec<StepReport> = self
+            .steps
+            .iter()
+            .map(|s| StepReport {
+                name: s.patch_name(),
+                outcome: StepOutcome::Skipped,
+                duration: Duration::ZERO,
+                conversions: ConversionStats::default(),
+            })
+            .collect();
+
+        for (idx, step) in self.steps.into_iter().enumerate() {
+            if self.policy == FailurePolicy::KeepPartial {
+                last_good = Some(patcher.ir_state.snapshot());
+            }
+
+            let stats_before = patcher.conversions();
+            //Taken out, since a failing step consumes the patcher
+            let mut patch_reports = std::mem::take(&mut patcher.reports);
+            let start = Instant::now();
+            let result = step.apply_boxed(patcher);
+            reports[idx].duration = start.elapsed();
+
+            match result {
+                Ok(mut p) => {
+                    reports[idx].outcome = StepOutcome::Applied;
+                    reports[idx].conversions = p.conversions().since(&stats_before);
+                    patch_reports.append(&mut p.reports);
+                    p.reports = patch_reports;
+                    patcher = p;
+                }
+                Err(e) => {
+                    log::error!(
+                        "Pipeline step {} ({}) failed: {}",
+                        idx,
+                        reports[idx].name,
+                        e
+                    );
+                    reports[idx].outcome = StepOutcome::Failed(e);
+                    if self.policy == FailurePolicy::Rollback {
+                        patch_reports.truncate(initial_reports);
+                    }
+                    let state = last_good.expect("Pipeline took no snapshot");
+                    return Err(PipelineFailure {
+                        step: idx,
+                        patcher: restore(state, stats_before, patch_reports),
+                        reports,
+                    });
+                }
+            }
+        }
+
+        Ok((patcher, reports))
+    }
+}
+
+impl Patch for PatchPipeline {
+    fn apply<'a>(self, patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError> {
+        match self.run(patcher) {
+            Ok((patcher, _reports)) => Ok(patcher),
+            Err(mut failure) => {
+                let patch = failure.failed_patch().to_owned();
+                let error = match std::mem::replace(
+                    &mut failure.reports[failure.step].outcome,
+                    StepOutcome::Skipped,
+                ) {
+                    StepOutcome::Failed(e) => e,
+                    _ => unreachable!("Failing step has no error"),
+                };
+                Err(PatcherError::PipelineStepFailed {
+                    step: failure.step,
+                    patch,
+                    error: Box::new(error),
+                })
+            }
+        }
+    }
+}
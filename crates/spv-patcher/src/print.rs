@@ -1,5 +1,8 @@
-use std::{fmt::Display, io::Write, process::Stdio};
-///Takes the SPIR-V words and prints it using spv-dis if installed.
+use std::fmt::Display;
+
+use crate::asm::Disassembler;
+
+///Takes the SPIR-V words and prints them as SPIR-V assembly, similar to `spirv-dis --comment`.
 pub struct DisassamblerPrinter {
     to_print: String,
 }
@@ -7,47 +10,31 @@ pub struct DisassamblerPrinter {
 impl DisassamblerPrinter {
     #[allow(dead_code)]
     pub fn from_words(code: &[u32]) -> Self {
-        let mut child = match std::process::Command::new("spirv-dis")
-            .arg("--comment")
-            .stdin(Stdio::piped())
-            .spawn()
-        {
-            Ok(c) => c,
-            Err(e) => {
-                log::error!("Failed to spawn spirv-dis: {}", e);
-                return DisassamblerPrinter {
-                    to_print: String::from("No spirv-dis"),
-                };
-            }
-        };
-
-        //configure std-in to take our bytecode
-        let code: Vec<u8> = bytemuck::cast_slice(code).to_vec();
-
-        let mut stdin = child.stdin.take().expect("Failed to open stdin");
-        std::thread::spawn(move || {
-            stdin.write_all(&code).expect("Failed to write to stdin");
-        });
-
-        let output = match child
-            .wait_with_output()
-            .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
-        {
-            Ok(res) => res,
+        match rspirv::dr::load_words(code) {
+            Ok(module) => Self::from_module(&module),
             Err(e) => {
-                log::error!("Failed to run spv-dis: {}", e);
-                return DisassamblerPrinter {
-                    to_print: String::from("Disassambling failed"),
-                };
+                log::error!("Failed to parse SPIR-V for disassembling: {}", e);
+                DisassamblerPrinter {
+                    to_print: format!("Disassambling failed: {}", e),
+                }
             }
-        };
-        DisassamblerPrinter { to_print: output }
+        }
     }
 
     #[allow(dead_code)]
     pub fn from_bytecode(code: &[u8]) -> Self {
         Self::from_words(bytemuck::cast_slice(code))
     }
+
+    pub fn from_module(module: &rspirv::dr::Module) -> Self {
+        let disassembler = Disassembler {
+            comment: true,
+            ..Default::default()
+        };
+        DisassamblerPrinter {
+            to_print: disassembler.disassemble(module),
+        }
+    }
 }
 
 impl Display for DisassamblerPrinter {
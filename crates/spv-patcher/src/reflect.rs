@@ -0,0 +1,827 @@
+//! # Resource reflection
+//!
+//! Reports the resource interface of a single entry point: descriptor bindings, the push constant block, stage inputs
+//! and outputs, specialization constants and the workgroup size. This is what a host needs to build a pipeline layout
+//! for a module it did not compile itself, for instance after patching.
+//!
+//! The result can be serialized via [Reflection::to_json]. [descriptors] lists the descriptors of the whole module instead.
+//!
+//! ## Implementation details
+//!
+//! Descriptors and push constants are reported if they are referenced by any function reachable from the entry point.
+//! Stage variables are taken from the entry point's interface list, which always contains all `Input` and `Output`
+//! variables.
+//!
+//! Access of a descriptor is derived by following its pointer through access chains, copies and function calls to the
+//! memory operations using it. A loaded image counts as written if it is used by `OpImageWrite`, and as read by any
+//! other use. Atomics count as both.
+//!
+//! The workgroup size is taken, in this order of precedence, from the constant decorated as `WorkgroupSize` built-in,
+//! from a `LocalSizeId` or from a `LocalSize` execution mode.
+
+use ahash::{AHashMap, AHashSet};
+use rspirv::{
+    dr::{Instruction, Module, Operand},
+    spirv::{BuiltIn, Decoration, Dim, ExecutionMode, ExecutionModel, Op, StorageClass, Word},
+};
+use serde::{Serialize, Serializer};
+
+use crate::{
+    analysis::{ConstEvaluator, ConstValue, DefUse, UseSlot},
+    spirv_ext::SpirvExt,
+    EntryPoint, PatcherError,
+};
+
+///Vulkan descriptor type of a [DescriptorBinding].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DescriptorType {
+    Sampler,
+    CombinedImageSampler,
+    SampledImage,
+    StorageImage,
+    UniformTexelBuffer,
+    StorageTexelBuffer,
+    UniformBuffer,
+    StorageBuffer,
+    InputAttachment,
+    AccelerationStructure,
+    ///A type that does not map to a descriptor type.
+    Unknown,
+}
+
+///How the entry point accesses a resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize)]
+pub struct Access {
+    pub read: bool,
+    pub write: bool,
+}
+
+///A descriptor used by the entry point.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DescriptorBinding {
+    pub variable: Word,
+    pub name: Option<String>,
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: DescriptorType,
+    ///Number of descriptors, 1 if the variable is not an array. 0 for runtime arrays.
+    pub count: u32,
+    pub access: Access,
+    ///`InputAttachmentIndex` of input attachments.
+    pub input_attachment_index: Option<u32>,
+}
+
+///A top level member of a [PushConstantBlock].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlockMember {
+    pub name: Option<String>,
+    pub offset: u32,
+    ///Size in bytes, 0 for runtime arrays.
+    pub size: u32,
+    ///Readable type, for instance `vec4<f32>` or `[u32; 4]`.
+    pub ty: String,
+}
+
+///Layout of the push constant block used by the entry point.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PushConstantBlock {
+    pub variable: Word,
+    pub name: Option<String>,
+    ///Size in bytes, which is the end of the last member.
+    pub size: u32,
+    pub members: Vec<BlockMember>,
+}
+
+///An `Input` or `Output` variable of the entry point.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StageVariable {
+    pub variable: Word,
+    pub name: Option<String>,
+    pub location: Option<u32>,
+    pub component: Option<u32>,
+    ///Built-in, if the variable itself is decorated as one.
+    #[serde(serialize_with = "serialize_debug_opt")]
+    pub builtin: Option<BuiltIn>,
+    pub ty: String,
+}
+
+///Default value of a [SpecConstant].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum SpecDefault {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+}
+
+///A specialization constant of the module.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpecConstant {
+    pub constant: Word,
+    pub name: Option<String>,
+    pub spec_id: u32,
+    pub ty: String,
+    ///Default value, `None` if it could not be interpreted (for instance half floats).
+    pub default: Option<SpecDefault>,
+}
+
+///Workgroup size of a compute-like entry point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct WorkgroupSize {
+    ///Size in x, y and z. Specializable dimensions report their default.
+    pub size: [u32; 3],
+    ///SpecId of each dimension that can be specialized.
+    pub spec_ids: [Option<u32>; 3],
+}
+
+///Resource interface of a single entry point, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Reflection {
+    pub entry_point: String,
+    #[serde(serialize_with = "serialize_debug")]
+    pub execution_model: ExecutionModel,
+    pub descriptors: Vec<DescriptorBinding>,
+    pub push_constants: Option<PushConstantBlock>,
+    pub inputs: Vec<StageVariable>,
+    pub outputs: Vec<StageVariable>,
+    pub spec_constants: Vec<SpecConstant>,
+    pub workgroup_size: Option<WorkgroupSize>,
+}
+
+impl Reflection {
+    ///Reflects the entry point `name` of `module`. `model` disambiguates entry points of the same name, see
+    /// [Module::entry_point](crate::Module::entry_point).
+    pub fn new(
+        module: &Module,
+        name: &str,
+        model: Option<ExecutionModel>,
+    ) -> Result<Self, PatcherError> {
+        let entry_points = module.collect_entry_points();
+        let mut candidates = entry_points
+            .iter()
+            .filter(|ep| ep.name == name && model.map(|m| m == ep.execution_model).unwrap_or(true));
+        let found = candidates
+            .next()
+            .ok_or_else(|| PatcherError::UnknownEntryPoint(name.to_owned()))?;
+        if candidates.next().is_some() {
+            return Err(PatcherError::AmbiguousEntryPoint(name.to_owned()));
+        }
+        Ok(Self::from_entry_point(module, found))
+    }
+
+    ///Reflects `entry_point`, which must be declared by `module`.
+    pub fn from_entry_point(module: &Module, entry_point: &EntryPoint) -> Self {
+        let reflector = Reflector::new(module, Some(entry_point));
+        let (inputs, outputs) = reflector.stage_variables();
+        Reflection {
+            entry_point: entry_point.name.clone(),
+            execution_model: entry_point.execution_model,
+            descriptors: reflector.descriptors(),
+            push_constants: reflector.push_constants(),
+            inputs,
+            outputs,
+            spec_constants: reflector.spec_constants(),
+            workgroup_size: reflector.workgroup_size(),
+        }
+    }
+
+    ///Serializes the reflection as pretty printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Reflection is always serializable")
+    }
+}
+
+///Collects all descriptors of `module` in declaration order, including those no entry point uses. Access is derived
+/// from all functions of the module.
+pub fn descriptors(module: &Module) -> Vec<DescriptorBinding> {
+    Reflector::new(module, None).descriptors()
+}
+
+fn serialize_debug<T: std::fmt::Debug, S: Serializer>(value: &T, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&format!("{:?}", value))
+}
+
+fn serialize_debug_opt<T: std::fmt::Debug, S: Serializer>(
+    value: &Option<T>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serialize_debug(value, s),
+        None => s.serialize_none(),
+    }
+}
+
+struct Reflector<'a> {
+    module: &'a Module,
+    //`None` if the whole module is reflected
+    entry_point: Option<&'a EntryPoint>,
+    globals: AHashMap<Word, &'a Instruction>,
+    evaluator: ConstEvaluator,
+    //Indices of all functions reachable from the entry point
+    functions: AHashSet<usize>,
+    //All ids referenced by those functions
+    used: AHashSet<Word>,
+    def_use: DefUse,
+}
+
+impl<'a> Reflector<'a> {
+    fn new(module: &'a Module, entry_point: Option<&'a EntryPoint>) -> Self {
+        let reachable = entry_point.map(|ep| module.reachable_functions(ep.function_id));
+        let functions = module
+            .functions
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| match (&reachable, f.def_id()) {
+                (Some(reachable), Some(id)) => reachable.contains(&id),
+                (Some(_), None) => false,
+                (None, _) => true,
+            })
+            .map(|(idx, _)| idx)
+            .collect::<AHashSet<_>>();
+        let used = functions
+            .iter()
+            .flat_map(|idx| module.functions[*idx].all_inst_iter())
+            .flat_map(|inst| inst.operands.iter().filter_map(|op| op.id_ref_any()))
+            .collect();
+        Reflector {
+            module,
+            entry_point,
+            globals: module
+                .types_global_values
+                .iter()
+                .filter_map(|inst| Some((inst.result_id?, inst)))
+                .collect(),
+            evaluator: ConstEvaluator::new(module),
+            functions,
+            used,
+            def_use: DefUse::new(module),
+        }
+    }
+
+    //True if the reflected entry point references the global `variable`. Always true if the whole module is reflected.
+    fn is_used(&self, variable: Word) -> bool {
+        match self.entry_point {
+            Some(entry_point) => {
+                self.used.contains(&variable) || entry_point.interface.contains(&variable)
+            }
+            None => true,
+        }
+    }
+
+    fn def(&self, id: Word) -> Option<&'a Instruction> {
+        self.globals.get(&id).copied()
+    }
+
+    ///Additional operands of the decoration `decoration` of `id`, or of its `member`.
+    fn decoration(
+        &self,
+        id: Word,
+        member: Option<u32>,
+        decoration: Decoration,
+    ) -> Option<&'a [Operand]> {
+        self.module.annotations.iter().find_map(|inst| {
+            let rest = match (inst.class.opcode, member, inst.operands.as_slice()) {
+                (Op::Decorate, None, [Operand::IdRef(target), Operand::Decoration(dec), rest @ ..])
+                    if *target == id && *dec == decoration =>
+                {
+                    rest
+                }
+                (
+                    Op::MemberDecorate,
+                    Some(member),
+                    [Operand::IdRef(target), Operand::LiteralBit32(m), Operand::Decoration(dec), rest @ ..],
+                ) if *target == id && *m == member && *dec == decoration => rest,
+                _ => return None,
+            };
+            Some(rest)
+        })
+    }
+
+    fn literal(&self, id: Word, member: Option<u32>, decoration: Decoration) -> Option<u32> {
+        match self.decoration(id, member, decoration)? {
+            [Operand::LiteralBit32(value), ..] => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn member_name(&self, id: Word, member: u32) -> Option<String> {
+        self.module.debug_names.iter().find_map(|inst| {
+            match (inst.class.opcode, inst.operands.as_slice()) {
+                (
+                    Op::MemberName,
+                    [Operand::IdRef(target), Operand::LiteralBit32(m), Operand::LiteralString(name)],
+                ) if *target == id && *m == member => Some(name.clone()),
+                _ => None,
+            }
+        })
+    }
+
+    ///Value of an integer constant, including the default of specialization constants.
+    fn constant_u32(&self, id: Word) -> Option<u32> {
+        let inst = self.def(id)?;
+        match (inst.class.opcode, inst.operands.as_slice()) {
+            (Op::Constant | Op::SpecConstant, [Operand::LiteralBit32(value)]) => Some(*value),
+            _ => self.evaluator.value(id)?.as_u64().map(|v| v as u32),
+        }
+    }
+
+    ///Global variables of `class`, in declaration order, together with their pointee type.
+    fn variables(&self, class: StorageClass) -> impl Iterator<Item = (Word, Word)> + '_ {
+        self.module
+            .types_global_values
+            .iter()
+            .filter_map(move |inst| {
+                match (
+                    inst.class.opcode,
+                    inst.result_id,
+                    inst.result_type,
+                    inst.operands.first(),
+                ) {
+                    (Op::Variable, Some(variable), Some(ptr), Some(Operand::StorageClass(c)))
+                        if *c == class =>
+                    {
+                        let pointee = self.def(ptr)?.operands.get(1)?.id_ref_any()?;
+                        Some((variable, pointee))
+                    }
+                    _ => None,
+                }
+            })
+    }
+
+    fn descriptors(&self) -> Vec<DescriptorBinding> {
+        let mut descriptors = Vec::new();
+        for inst in &self.module.types_global_values {
+            let (Op::Variable, Some(variable), Some(Operand::StorageClass(class))) =
+                (inst.class.opcode, inst.result_id, inst.operands.first())
+            else {
+                continue;
+            };
+            if !matches!(
+                class,
+                StorageClass::Uniform | StorageClass::UniformConstant | StorageClass::StorageBuffer
+            ) || !self.is_used(variable)
+            {
+                continue;
+            }
+            let (Some(set), Some(binding)) = (
+                self.literal(variable, None, Decoration::DescriptorSet),
+                self.literal(variable, None, Decoration::Binding),
+            ) else {
+                continue;
+            };
+
+            //Unwrap descriptor arrays, the count of nested arrays is the product of their lengths
+            let mut count = 1;
+            let mut ty = inst
+                .result_type
+                .and_then(|ptr| self.def(ptr)?.operands.get(1)?.id_ref_any());
+            while let Some(array) = ty.and_then(|ty| self.def(ty)) {
+                match array.class.opcode {
+                    Op::TypeArray => {
+                        let len = array
+                            .operands
+                            .get(1)
+                            .and_then(|op| self.constant_u32(op.id_ref_any()?))
+                            .unwrap_or(1);
+                        count = count.saturating_mul(len);
+                    }
+                    Op::TypeRuntimeArray => count = 0,
+                    _ => break,
+                }
+                ty = array.operands.first().and_then(|op| op.id_ref_any());
+            }
+
+            descriptors.push(DescriptorBinding {
+                variable,
+                name: self.module.get_name(variable),
+                set,
+                binding,
+                descriptor_type: ty
+                    .map(|ty| self.descriptor_type(ty, *class))
+                    .unwrap_or(DescriptorType::Unknown),
+                count,
+                access: self.access(variable),
+                input_attachment_index: self.literal(
+                    variable,
+                    None,
+                    Decoration::InputAttachmentIndex,
+                ),
+            });
+        }
+        descriptors
+    }
+
+    fn descriptor_type(&self, ty: Word, class: StorageClass) -> DescriptorType {
+        let Some(inst) = self.def(ty) else {
+            return DescriptorType::Unknown;
+        };
+        match inst.class.opcode {
+            Op::TypeSampler => DescriptorType::Sampler,
+            Op::TypeSampledImage => DescriptorType::CombinedImageSampler,
+            Op::TypeAccelerationStructureKHR => DescriptorType::AccelerationStructure,
+            Op::TypeImage => {
+                let storage = inst.operands.get(5) == Some(&Operand::LiteralBit32(2));
+                match (inst.operands.get(1), storage) {
+                    (Some(Operand::Dim(Dim::DimSubpassData)), _) => DescriptorType::InputAttachment,
+                    (Some(Operand::Dim(Dim::DimBuffer)), true) => {
+                        DescriptorType::StorageTexelBuffer
+                    }
+                    (Some(Operand::Dim(Dim::DimBuffer)), false) => {
+                        DescriptorType::UniformTexelBuffer
+                    }
+                    (_, true) => DescriptorType::StorageImage,
+                    (_, false) => DescriptorType::SampledImage,
+                }
+            }
+            Op::TypeStruct => match class {
+                StorageClass::StorageBuffer => DescriptorType::StorageBuffer,
+                StorageClass::Uniform
+                    if self.decoration(ty, None, Decoration::BufferBlock).is_some() =>
+                {
+                    DescriptorType::StorageBuffer
+                }
+                StorageClass::Uniform => DescriptorType::UniformBuffer,
+                _ => DescriptorType::Unknown,
+            },
+            _ => DescriptorType::Unknown,
+        }
+    }
+
+    ///Follows the pointer `variable` to all memory operations of reachable functions.
+    fn access(&self, variable: Word) -> Access {
+        let def_use = &self.def_use;
+        let mut access = Access::default();
+        let mut visited = AHashSet::new();
+        let mut pointers = vec![variable];
+        while let Some(pointer) = pointers.pop() {
+            if !visited.insert(pointer) {
+                continue;
+            }
+            for usage in def_use.users_of(pointer) {
+                if !usage
+                    .location
+                    .function()
+                    .map(|f| self.functions.contains(&f))
+                    .unwrap_or(false)
+                {
+                    continue;
+                }
+                let (Some(inst), UseSlot::Operand(slot)) =
+                    (usage.location.get(self.module), usage.slot)
+                else {
+                    continue;
+                };
+                match inst.class.opcode {
+                    Op::AccessChain
+                    | Op::InBoundsAccessChain
+                    | Op::PtrAccessChain
+                    | Op::InBoundsPtrAccessChain
+                    | Op::CopyObject
+                    | Op::ImageTexelPointer
+                        if slot == 0 =>
+                    {
+                        pointers.extend(inst.result_id)
+                    }
+                    Op::Load => {
+                        //Image handles are written through the loaded value, anything else reads it
+                        let users = inst.result_id.map(|id| def_use.users_of(id)).unwrap_or(&[]);
+                        let mut used = false;
+                        for user in users
+                            .iter()
+                            .filter(|user| user.location.function().is_some())
+                        {
+                            used = true;
+                            match user.location.get(self.module) {
+                                Some(user_inst)
+                                    if user_inst.class.opcode == Op::ImageWrite
+                                        && user.slot == UseSlot::Operand(0) =>
+                                {
+                                    access.write = true
+                                }
+                                _ => access.read = true,
+                            }
+                        }
+                        access.read |= !used;
+                    }
+                    Op::Store => access.write = true,
+                    Op::CopyMemory | Op::CopyMemorySized => {
+                        if slot == 0 {
+                            access.write = true;
+                        } else {
+                            access.read = true;
+                        }
+                    }
+                    Op::AtomicLoad => access.read = true,
+                    Op::AtomicStore => access.write = true,
+                    Op::AtomicExchange
+                    | Op::AtomicCompareExchange
+                    | Op::AtomicCompareExchangeWeak
+                    | Op::AtomicIIncrement
+                    | Op::AtomicIDecrement
+                    | Op::AtomicIAdd
+                    | Op::AtomicISub
+                    | Op::AtomicSMin
+                    | Op::AtomicUMin
+                    | Op::AtomicSMax
+                    | Op::AtomicUMax
+                    | Op::AtomicAnd
+                    | Op::AtomicOr
+                    | Op::AtomicXor
+                    | Op::AtomicFAddEXT
+                    | Op::AtomicFMinEXT
+                    | Op::AtomicFMaxEXT => {
+                        access.read = true;
+                        access.write = true;
+                    }
+                    Op::FunctionCall if slot > 0 => {
+                        //Continue with the callee's parameter
+                        let callee = inst.operands.first().and_then(|op| op.id_ref_any());
+                        let parameter = self
+                            .module
+                            .functions
+                            .iter()
+                            .find(|f| f.def_id() == callee && callee.is_some())
+                            .and_then(|f| f.parameters.get(slot - 1))
+                            .and_then(|p| p.result_id);
+                        pointers.extend(parameter);
+                    }
+                    //Only queries the size
+                    Op::ArrayLength => {}
+                    _ => access.read = true,
+                }
+            }
+        }
+        access
+    }
+
+    fn push_constants(&self) -> Option<PushConstantBlock> {
+        let (variable, block) = self
+            .variables(StorageClass::PushConstant)
+            .find(|(variable, _)| self.is_used(*variable))?;
+        let block_inst = self.def(block)?;
+        let members = block_inst
+            .operands
+            .iter()
+            .filter_map(|op| op.id_ref_any())
+            .enumerate()
+            .map(|(idx, ty)| BlockMember {
+                name: self.member_name(block, idx as u32),
+                offset: self
+                    .literal(block, Some(idx as u32), Decoration::Offset)
+                    .unwrap_or(0),
+                size: self.member_size(block, idx as u32, ty).unwrap_or(0),
+                ty: self.type_name(ty),
+            })
+            .collect::<Vec<_>>();
+        Some(PushConstantBlock {
+            variable,
+            name: self
+                .module
+                .get_name(variable)
+                .or_else(|| self.module.get_name(block)),
+            size: members.iter().map(|m| m.offset + m.size).max().unwrap_or(0),
+            members,
+        })
+    }
+
+    ///Size of member `member` of type `ty` within `parent`. Matrices use the stride declared on the member.
+    fn member_size(&self, parent: Word, member: u32, ty: Word) -> Option<u32> {
+        let inst = self.def(ty)?;
+        if inst.class.opcode == Op::TypeMatrix {
+            let stride = self.literal(parent, Some(member), Decoration::MatrixStride)?;
+            let columns = match inst.operands.get(1) {
+                Some(Operand::LiteralBit32(columns)) => *columns,
+                _ => return None,
+            };
+            let rows = match self
+                .def(inst.operands.first()?.id_ref_any()?)?
+                .operands
+                .get(1)
+            {
+                Some(Operand::LiteralBit32(rows)) => *rows,
+                _ => return None,
+            };
+            let row_major = self
+                .decoration(parent, Some(member), Decoration::RowMajor)
+                .is_some();
+            return Some(stride * if row_major { rows } else { columns });
+        }
+        self.size_of(ty)
+    }
+
+    ///Size of `ty` in bytes, according to its explicit layout decorations.
+    fn size_of(&self, ty: Word) -> Option<u32> {
+        let inst = self.def(ty)?;
+        let literal = |idx: usize| match inst.operands.get(idx) {
+            Some(Operand::LiteralBit32(v)) => Some(*v),
+            _ => None,
+        };
+        match inst.class.opcode {
+            Op::TypeInt | Op::TypeFloat => Some(literal(0)? / 8),
+            Op::TypeVector => {
+                Some(self.size_of(inst.operands.first()?.id_ref_any()?)? * literal(1)?)
+            }
+            Op::TypeArray => {
+                let length = self.constant_u32(inst.operands.get(1)?.id_ref_any()?)?;
+                let stride = self.literal(ty, None, Decoration::ArrayStride)?;
+                Some(stride * length)
+            }
+            Op::TypeRuntimeArray => Some(0),
+            Op::TypeStruct => inst
+                .operands
+                .iter()
+                .filter_map(|op| op.id_ref_any())
+                .enumerate()
+                .map(|(idx, member)| {
+                    Some(
+                        self.literal(ty, Some(idx as u32), Decoration::Offset)?
+                            + self.member_size(ty, idx as u32, member)?,
+                    )
+                })
+                .try_fold(0, |size, end| end.map(|end| size.max(end))),
+            _ => None,
+        }
+    }
+
+    ///Readable name of `ty`, for instance `vec4<f32>`.
+    fn type_name(&self, ty: Word) -> String {
+        let Some(inst) = self.def(ty) else {
+            return format!("%{}", ty);
+        };
+        let literal = |idx: usize| match inst.operands.get(idx) {
+            Some(Operand::LiteralBit32(v)) => *v,
+            _ => 0,
+        };
+        let operand_type = |idx: usize| {
+            inst.operands
+                .get(idx)
+                .and_then(|op| op.id_ref_any())
+                .map(|id| self.type_name(id))
+                .unwrap_or_default()
+        };
+        match inst.class.opcode {
+            Op::TypeVoid => "void".to_owned(),
+            Op::TypeBool => "bool".to_owned(),
+            Op::TypeInt if literal(1) == 1 => format!("i{}", literal(0)),
+            Op::TypeInt => format!("u{}", literal(0)),
+            Op::TypeFloat => format!("f{}", literal(0)),
+            Op::TypeVector => format!("vec{}<{}>", literal(1), operand_type(0)),
+            Op::TypeMatrix => {
+                let column = inst
+                    .operands
+                    .first()
+                    .and_then(|op| self.def(op.id_ref_any()?));
+                let (rows, elem) = match column.map(|c| (c.operands.first(), c.operands.get(1))) {
+                    Some((Some(elem), Some(Operand::LiteralBit32(rows)))) => (
+                        *rows,
+                        elem.id_ref_any()
+                            .map(|e| self.type_name(e))
+                            .unwrap_or_default(),
+                    ),
+                    _ => (0, String::new()),
+                };
+                format!("mat{}x{}<{}>", literal(1), rows, elem)
+            }
+            Op::TypeArray => match inst
+                .operands
+                .get(1)
+                .and_then(|op| self.constant_u32(op.id_ref_any()?))
+            {
+                Some(length) => format!("[{}; {}]", operand_type(0), length),
+                None => format!("[{}; ?]", operand_type(0)),
+            },
+            Op::TypeRuntimeArray => format!("[{}]", operand_type(0)),
+            Op::TypeStruct => self
+                .module
+                .get_name(ty)
+                .unwrap_or_else(|| "struct".to_owned()),
+            Op::TypePointer => format!("ptr<{}>", operand_type(1)),
+            Op::TypeImage => "image".to_owned(),
+            Op::TypeSampledImage => "sampled_image".to_owned(),
+            Op::TypeSampler => "sampler".to_owned(),
+            Op::TypeAccelerationStructureKHR => "acceleration_structure".to_owned(),
+            op => format!("{:?}", op),
+        }
+    }
+
+    fn stage_variables(&self) -> (Vec<StageVariable>, Vec<StageVariable>) {
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        let interface = self.entry_point.map(|ep| &ep.interface[..]).unwrap_or(&[]);
+        for variable in interface {
+            let Some(inst) = self.def(*variable) else {
+                continue;
+            };
+            let list = match inst.operands.first() {
+                Some(Operand::StorageClass(StorageClass::Input)) => &mut inputs,
+                Some(Operand::StorageClass(StorageClass::Output)) => &mut outputs,
+                _ => continue,
+            };
+            let pointee = inst
+                .result_type
+                .and_then(|ptr| self.def(ptr)?.operands.get(1)?.id_ref_any());
+            list.push(StageVariable {
+                variable: *variable,
+                name: self.module.get_name(*variable),
+                location: self.literal(*variable, None, Decoration::Location),
+                component: self.literal(*variable, None, Decoration::Component),
+                builtin: match self.decoration(*variable, None, Decoration::BuiltIn) {
+                    Some([Operand::BuiltIn(builtin), ..]) => Some(*builtin),
+                    _ => None,
+                },
+                ty: pointee.map(|ty| self.type_name(ty)).unwrap_or_default(),
+            });
+        }
+        (inputs, outputs)
+    }
+
+    fn spec_constants(&self) -> Vec<SpecConstant> {
+        self.module
+            .types_global_values
+            .iter()
+            .filter_map(|inst| {
+                let (Some(constant), Some(ty)) = (inst.result_id, inst.result_type) else {
+                    return None;
+                };
+                let default = match inst.class.opcode {
+                    Op::SpecConstantTrue => Some(SpecDefault::Bool(true)),
+                    Op::SpecConstantFalse => Some(SpecDefault::Bool(false)),
+                    Op::SpecConstant => match self.evaluator.literal_value(ty, &inst.operands) {
+                        Some(value @ ConstValue::Int { signed: true, .. }) => {
+                            value.as_i64().map(SpecDefault::Int)
+                        }
+                        Some(value @ ConstValue::Int { .. }) => {
+                            value.as_u64().map(SpecDefault::UInt)
+                        }
+                        Some(value) => value.as_f64().map(SpecDefault::Float),
+                        None => None,
+                    },
+                    _ => return None,
+                };
+                Some(SpecConstant {
+                    constant,
+                    name: self.module.get_name(constant),
+                    spec_id: self.literal(constant, None, Decoration::SpecId)?,
+                    ty: self.type_name(ty),
+                    default,
+                })
+            })
+            .collect()
+    }
+
+    fn workgroup_size(&self) -> Option<WorkgroupSize> {
+        let from_ids = |ids: &[Operand]| {
+            let mut size = WorkgroupSize {
+                size: [1; 3],
+                spec_ids: [None; 3],
+            };
+            for (idx, id) in ids.iter().take(3).enumerate() {
+                let id = id.id_ref_any()?;
+                size.size[idx] = self.constant_u32(id)?;
+                size.spec_ids[idx] = self.literal(id, None, Decoration::SpecId);
+            }
+            Some(size)
+        };
+
+        let builtin = self.globals.iter().find_map(|(id, inst)| {
+            match (
+                inst.class.opcode,
+                self.decoration(*id, None, Decoration::BuiltIn),
+            ) {
+                (
+                    Op::ConstantComposite | Op::SpecConstantComposite,
+                    Some([Operand::BuiltIn(BuiltIn::WorkgroupSize), ..]),
+                ) => Some(*inst),
+                _ => None,
+            }
+        });
+        if let Some(inst) = builtin {
+            return from_ids(&inst.operands);
+        }
+
+        let entry_point = self.entry_point?;
+        entry_point
+            .execution_modes
+            .iter()
+            .find(|(mode, _)| *mode == ExecutionMode::LocalSizeId)
+            .and_then(|(_, operands)| from_ids(&operands[..]))
+            .or_else(|| {
+                let (_, operands) = entry_point
+                    .execution_modes
+                    .iter()
+                    .find(|(mode, _)| *mode == ExecutionMode::LocalSize)?;
+                let mut size = [1; 3];
+                for (idx, op) in operands.iter().take(3).enumerate() {
+                    if let Operand::LiteralBit32(value) = op {
+                        size[idx] = *value;
+                    }
+                }
+                Some(WorkgroupSize {
+                    size,
+                    spec_ids: [None; 3],
+                })
+            })
+    }
+}
@@ -1,10 +1,198 @@
 //! Extensions to SPIR-V module. Adds querying capability and analysis.
+use ahash::AHashSet;
 use rspirv::{
-    dr::{Instruction, Operand},
+    dr::{Instruction, ModuleHeader, Operand},
     spirv::{Capability, Decoration, ExecutionModel, Op},
 };
+use smallvec::SmallVec;
 
-use crate::type_tree::TypeTree;
+use crate::{
+    analysis::CallGraph,
+    type_tree::{TTDecoration, TTypeOperand, TypeTree, TypeTreeError},
+    EntryPoint,
+};
+
+///Decoration of a type, without its target. Used to find or declare decorated types, see
+/// [SpirvExt::find_or_insert_type].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeDecoration {
+    ///Member index for `OpMemberDecorate`, `None` for `OpDecorate`.
+    pub member: Option<u32>,
+    pub decoration: Decoration,
+    ///Extra operands, for instance the offset of `Offset`.
+    pub operands: Vec<Operand>,
+}
+
+impl TypeDecoration {
+    pub fn new(decoration: Decoration, operands: Vec<Operand>) -> Self {
+        TypeDecoration {
+            member: None,
+            decoration,
+            operands,
+        }
+    }
+
+    pub fn member(member: u32, decoration: Decoration, operands: Vec<Operand>) -> Self {
+        TypeDecoration {
+            member: Some(member),
+            decoration,
+            operands,
+        }
+    }
+
+    fn to_tt(&self) -> TTDecoration {
+        let operands = self
+            .operands
+            .iter()
+            .cloned()
+            .map(TTypeOperand::from)
+            .collect();
+        match self.member {
+            None => TTDecoration::Decorate {
+                decoration: self.decoration,
+                operands,
+            },
+            Some(member) => TTDecoration::MemberDecorate {
+                member,
+                decoration: self.decoration,
+                operands,
+            },
+        }
+    }
+
+    fn to_instruction(&self, target: u32) -> Instruction {
+        let has_string = self
+            .operands
+            .iter()
+            .any(|op| matches!(op, Operand::LiteralString(_)));
+        let has_id = self.operands.iter().any(|op| op.id_ref_any().is_some());
+        let mut operands = vec![Operand::IdRef(target)];
+        let op = match self.member {
+            Some(member) => {
+                operands.push(Operand::LiteralBit32(member));
+                if has_string {
+                    Op::MemberDecorateString
+                } else {
+                    Op::MemberDecorate
+                }
+            }
+            None if has_string => Op::DecorateString,
+            None if has_id => Op::DecorateId,
+            None => Op::Decorate,
+        };
+        operands.push(Operand::Decoration(self.decoration));
+        operands.extend(self.operands.iter().cloned());
+        Instruction::new(op, None, None, operands)
+    }
+}
+
+///Matches the `OpLine`s of a source line, in a file whose name ends with a given suffix.
+#[derive(Debug, Clone)]
+pub struct LineMatcher {
+    //OpStrings naming the selected file
+    files: AHashSet<u32>,
+    line: u32,
+}
+
+impl LineMatcher {
+    ///Matches `line` of all files of `module` whose name ends with `file`.
+    pub fn new(module: &rspirv::dr::Module, file: &str, line: u32) -> Self {
+        let files = module
+            .debug_string_source
+            .iter()
+            .filter(|inst| inst.class.opcode == Op::String)
+            .filter(|inst| match inst.operands.first() {
+                Some(Operand::LiteralString(name)) => name.ends_with(file),
+                _ => false,
+            })
+            .filter_map(|inst| inst.result_id)
+            .collect();
+        LineMatcher { files, line }
+    }
+
+    ///True if `inst` is an `OpLine` of the selected line.
+    pub fn matches(&self, inst: &Instruction) -> bool {
+        inst.class.opcode == Op::Line
+            && inst
+                .operands
+                .first()
+                .and_then(|op| op.id_ref_any())
+                .map(|file| self.files.contains(&file))
+                .unwrap_or(false)
+            && inst.operands.get(1) == Some(&Operand::LiteralBit32(self.line))
+    }
+}
+
+///A constant for [SpirvExt::find_or_insert_constant]. Scalar types are declared as needed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstantValue {
+    Bool(bool),
+    U32(u32),
+    I32(i32),
+    F32(f32),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    ///Half precision float, given by its bits.
+    F16(u16),
+    ///`OpConstantNull` of the given type.
+    Null(u32),
+    ///`OpConstantComposite` of the given type, built from the given constants.
+    Composite {
+        ty: u32,
+        constituents: Vec<u32>,
+    },
+}
+
+impl ConstantValue {
+    ///Opcode and operands of the scalar type declaration of this value. Null and composite values carry their type
+    /// id instead, which is returned as error.
+    pub(crate) fn scalar_type(&self) -> Result<(Op, Vec<Operand>), u32> {
+        let int = |width: u32, signed: u32| {
+            Ok((
+                Op::TypeInt,
+                vec![Operand::LiteralBit32(width), Operand::LiteralBit32(signed)],
+            ))
+        };
+        let float = |width: u32| Ok((Op::TypeFloat, vec![Operand::LiteralBit32(width)]));
+        match self {
+            ConstantValue::Bool(_) => Ok((Op::TypeBool, Vec::new())),
+            ConstantValue::U32(_) => int(32, 0),
+            ConstantValue::I32(_) => int(32, 1),
+            ConstantValue::U64(_) => int(64, 0),
+            ConstantValue::I64(_) => int(64, 1),
+            ConstantValue::F16(_) => float(16),
+            ConstantValue::F32(_) => float(32),
+            ConstantValue::F64(_) => float(64),
+            ConstantValue::Null(ty) | ConstantValue::Composite { ty, .. } => Err(*ty),
+        }
+    }
+
+    ///Opcode and operands of the constant declaring this value.
+    pub(crate) fn parts(&self) -> (Op, Vec<Operand>) {
+        match self {
+            ConstantValue::Bool(true) => (Op::ConstantTrue, Vec::new()),
+            ConstantValue::Bool(false) => (Op::ConstantFalse, Vec::new()),
+            ConstantValue::U32(value) => (Op::Constant, vec![Operand::LiteralBit32(*value)]),
+            ConstantValue::I32(value) => (Op::Constant, vec![Operand::LiteralBit32(*value as u32)]),
+            //Literals narrower than 32 bit occupy the low-order bits of a single word
+            ConstantValue::F16(bits) => (Op::Constant, vec![Operand::LiteralBit32(*bits as u32)]),
+            ConstantValue::F32(value) => {
+                (Op::Constant, vec![Operand::LiteralBit32(value.to_bits())])
+            }
+            ConstantValue::U64(value) => (Op::Constant, vec![Operand::LiteralBit64(*value)]),
+            ConstantValue::I64(value) => (Op::Constant, vec![Operand::LiteralBit64(*value as u64)]),
+            ConstantValue::F64(value) => {
+                (Op::Constant, vec![Operand::LiteralBit64(value.to_bits())])
+            }
+            ConstantValue::Null(_) => (Op::ConstantNull, Vec::new()),
+            ConstantValue::Composite { constituents, .. } => (
+                Op::ConstantComposite,
+                constituents.iter().map(|id| Operand::IdRef(*id)).collect(),
+            ),
+        }
+    }
+}
 
 pub trait SpirvExt {
     ///Returns true if the extension is loaded in that module
@@ -23,10 +211,55 @@ pub trait SpirvExt {
     ///Tries to find a debug name (`OpName`) for the given instruction id.
     fn get_name(&self, id: u32) -> Option<String>;
 
-    ///Returns the execution model of this Module. Note that we, per-definition, only have one entry point per [Module](crate::Module).
-    fn get_execution_model(&self) -> ExecutionModel;
+    ///Returns the execution model of this module's entry point. Returns `None` if the module declares no, or more than one entry point.
+    /// In that case use [collect_entry_points](SpirvExt::collect_entry_points) instead.
+    fn get_execution_model(&self) -> Option<ExecutionModel>;
+
+    ///Collects all `OpEntryPoint`s of the module together with their execution modes.
+    fn collect_entry_points(&self) -> Vec<EntryPoint>;
 
-    fn build_type_tree(&self) -> TypeTree;
+    ///Returns the function id of the entry point with the given `name` and (if supplied) `model`.
+    fn find_entry_point(&self, name: &str, model: Option<ExecutionModel>) -> Option<u32>;
+
+    ///Returns the ids of all functions that can be reached via `OpFunctionCall` from the function `root`. The set includes `root` itself.
+    fn reachable_functions(&self, root: u32) -> AHashSet<u32>;
+
+    ///Builds the [TypeTree] of all types declared in this module.
+    fn build_type_tree(&self) -> Result<TypeTree, TypeTreeError>;
+
+    ///Returns a fresh id and bumps the module's id bound. Creates the header if there is none.
+    fn allocate_id(&mut self) -> u32;
+
+    ///Returns the first type declared as `op operands` that has exactly the given `decorations`, regardless of their
+    /// order.
+    fn find_type_by_structure(
+        &self,
+        op: Op,
+        operands: &[Operand],
+        decorations: &[TypeDecoration],
+    ) -> Option<u32>;
+
+    ///Returns the pointee type of the pointer type `ptr`.
+    fn pointee(&self, ptr: u32) -> Option<u32>;
+
+    ///Returns the type declared as `op operands` with exactly the given `decorations`, or declares it.
+    ///
+    ///Non-aggregate types (everything except structs, arrays and pointers) may only be declared once, so those are
+    /// reused regardless of their decorations, and `decorations` are only applied when a new type is declared.
+    ///
+    ///Declaring an integer or float type of a width other than 32 bit adds the capability it requires, e.g. `Int64`.
+    fn find_or_insert_type(
+        &mut self,
+        op: Op,
+        operands: &[Operand],
+        decorations: &[TypeDecoration],
+    ) -> u32;
+
+    ///Returns an undecorated, non-specialization constant of the given value, or declares it.
+    fn find_or_insert_constant(&mut self, constant: ConstantValue) -> u32;
+
+    ///Returns a global `OpUndef` of type `ty`, or declares it.
+    fn find_or_insert_undef(&mut self, ty: u32) -> u32;
 }
 
 impl SpirvExt for rspirv::dr::Module {
@@ -51,13 +284,85 @@ impl SpirvExt for rspirv::dr::Module {
         false
     }
 
-    fn get_execution_model(&self) -> ExecutionModel {
-        let exmodel = match self.entry_points[0].operands[0] {
-            Operand::ExecutionModel(m) => m,
-            _ => panic!("Entry point had no execution mode"),
-        };
+    fn get_execution_model(&self) -> Option<ExecutionModel> {
+        if self.entry_points.len() != 1 {
+            return None;
+        }
 
-        exmodel
+        match self.entry_points[0].operands.get(0) {
+            Some(Operand::ExecutionModel(m)) => Some(*m),
+            _ => {
+                log::error!("Entry point had no execution model");
+                None
+            }
+        }
+    }
+
+    fn collect_entry_points(&self) -> Vec<EntryPoint> {
+        let mut entry_points = Vec::with_capacity(self.entry_points.len());
+        for inst in &self.entry_points {
+            match (
+                inst.operands.get(0),
+                inst.operands.get(1),
+                inst.operands.get(2),
+            ) {
+                (
+                    Some(Operand::ExecutionModel(execution_model)),
+                    Some(Operand::IdRef(function_id)),
+                    Some(Operand::LiteralString(name)),
+                ) => entry_points.push(EntryPoint {
+                    function_id: *function_id,
+                    name: name.clone(),
+                    execution_model: *execution_model,
+                    interface: inst.operands[3..]
+                        .iter()
+                        .filter_map(|op| op.id_ref_any())
+                        .collect(),
+                    execution_modes: smallvec::SmallVec::new(),
+                }),
+                _ => log::error!("Malformed OpEntryPoint: {:?}", inst),
+            }
+        }
+
+        //Now attach all execution modes to their entry point
+        for mode in &self.execution_modes {
+            if let (Some(Operand::IdRef(target)), Some(Operand::ExecutionMode(exmode))) =
+                (mode.operands.get(0), mode.operands.get(1))
+            {
+                for ep in entry_points
+                    .iter_mut()
+                    .filter(|ep| ep.function_id == *target)
+                {
+                    ep.execution_modes
+                        .push((*exmode, mode.operands[2..].iter().cloned().collect()));
+                }
+            }
+        }
+
+        entry_points
+    }
+
+    fn find_entry_point(&self, name: &str, model: Option<ExecutionModel>) -> Option<u32> {
+        self.entry_points.iter().find_map(|inst| {
+            match (
+                inst.operands.get(0),
+                inst.operands.get(1),
+                inst.operands.get(2),
+            ) {
+                (
+                    Some(Operand::ExecutionModel(m)),
+                    Some(Operand::IdRef(function_id)),
+                    Some(Operand::LiteralString(n)),
+                ) if n == name && model.map(|model| model == *m).unwrap_or(true) => {
+                    Some(*function_id)
+                }
+                _ => None,
+            }
+        })
+    }
+
+    fn reachable_functions(&self, root: u32) -> AHashSet<u32> {
+        CallGraph::new(self).reachable_from(root)
     }
 
     fn decorate(&mut self, id: u32, decoration: Decoration) {
@@ -66,9 +371,9 @@ impl SpirvExt for rspirv::dr::Module {
 
         self.annotations.push(Instruction::new(
             Op::Decorate,
-            Some(id),
             None,
-            vec![Operand::Decoration(decoration)],
+            None,
+            vec![Operand::IdRef(id), Operand::Decoration(decoration)],
         ))
     }
 
@@ -142,7 +447,150 @@ impl SpirvExt for rspirv::dr::Module {
         None
     }
 
-    fn build_type_tree(&self) -> TypeTree {
+    fn build_type_tree(&self) -> Result<TypeTree, TypeTreeError> {
         TypeTree::from_module(self)
     }
+
+    fn allocate_id(&mut self) -> u32 {
+        if self.header.is_none() {
+            let bound = self
+                .all_inst_iter()
+                .filter_map(|inst| inst.result_id)
+                .max()
+                .map(|max| max + 1)
+                .unwrap_or(1);
+            self.header = Some(ModuleHeader::new(bound));
+        }
+        let header = self.header.as_mut().unwrap();
+        let id = header.bound;
+        header.bound += 1;
+        id
+    }
+
+    fn find_type_by_structure(
+        &self,
+        op: Op,
+        operands: &[Operand],
+        decorations: &[TypeDecoration],
+    ) -> Option<u32> {
+        let mut expected: SmallVec<[TTDecoration; 3]> =
+            decorations.iter().map(TypeDecoration::to_tt).collect();
+        expected.sort();
+
+        let all_decorations = match TypeTree::collect_decorations(self) {
+            Ok(decorations) => decorations,
+            Err(e) => {
+                log::error!("Could not collect decorations: {}", e);
+                return None;
+            }
+        };
+        self.types_global_values
+            .iter()
+            .filter(|inst| inst.class.opcode == op && inst.operands == operands)
+            .filter_map(|inst| inst.result_id)
+            .find(|id| {
+                let mut found = all_decorations.get(id).cloned().unwrap_or_default();
+                found.sort();
+                found == expected
+            })
+    }
+
+    fn pointee(&self, ptr: u32) -> Option<u32> {
+        self.types_global_values
+            .iter()
+            .find(|inst| inst.result_id == Some(ptr) && inst.class.opcode == Op::TypePointer)
+            .and_then(|inst| inst.operands.get(1))
+            .and_then(|op| op.id_ref_any())
+    }
+
+    fn find_or_insert_type(
+        &mut self,
+        op: Op,
+        operands: &[Operand],
+        decorations: &[TypeDecoration],
+    ) -> u32 {
+        let is_unique = !matches!(
+            op,
+            Op::TypeStruct | Op::TypeArray | Op::TypeRuntimeArray | Op::TypePointer
+        );
+        let existing = if is_unique {
+            self.types_global_values
+                .iter()
+                .find(|inst| inst.class.opcode == op && inst.operands == operands)
+                .and_then(|inst| inst.result_id)
+        } else {
+            self.find_type_by_structure(op, operands, decorations)
+        };
+        if let Some(id) = existing {
+            return id;
+        }
+
+        if let Some(capability) = width_capability(op, operands) {
+            self.add_capability(capability);
+        }
+        let id = self.allocate_id();
+        self.types_global_values
+            .push(Instruction::new(op, None, Some(id), operands.to_vec()));
+        for decoration in decorations {
+            self.annotations.push(decoration.to_instruction(id));
+        }
+        id
+    }
+
+    fn find_or_insert_constant(&mut self, constant: ConstantValue) -> u32 {
+        let ty = match constant.scalar_type() {
+            Ok((op, operands)) => self.find_or_insert_type(op, &operands, &[]),
+            Err(ty) => ty,
+        };
+        let (op, operands) = constant.parts();
+
+        let decorated: AHashSet<u32> = self
+            .annotations
+            .iter()
+            .filter_map(|inst| inst.operands.get(0).and_then(|op| op.id_ref_any()))
+            .collect();
+        let existing = self
+            .types_global_values
+            .iter()
+            .filter(|inst| {
+                inst.class.opcode == op && inst.result_type == Some(ty) && inst.operands == operands
+            })
+            .filter_map(|inst| inst.result_id)
+            .find(|id| !decorated.contains(id));
+        if let Some(id) = existing {
+            return id;
+        }
+
+        let id = self.allocate_id();
+        self.types_global_values
+            .push(Instruction::new(op, Some(ty), Some(id), operands));
+        id
+    }
+
+    fn find_or_insert_undef(&mut self, ty: u32) -> u32 {
+        let existing = self
+            .types_global_values
+            .iter()
+            .find(|inst| inst.class.opcode == Op::Undef && inst.result_type == Some(ty))
+            .and_then(|inst| inst.result_id);
+        if let Some(id) = existing {
+            return id;
+        }
+        let id = self.allocate_id();
+        self.types_global_values
+            .push(Instruction::new(Op::Undef, Some(ty), Some(id), Vec::new()));
+        id
+    }
+}
+
+//Capability a scalar type of a width other than 32 bit needs.
+fn width_capability(op: Op, operands: &[Operand]) -> Option<Capability> {
+    match (op, operands.first()?) {
+        (Op::TypeInt, Operand::LiteralBit32(8)) => Some(Capability::Int8),
+        (Op::TypeInt, Operand::LiteralBit32(16)) => Some(Capability::Int16),
+        (Op::TypeInt, Operand::LiteralBit32(64)) => Some(Capability::Int64),
+        (Op::TypeFloat, Operand::LiteralBit32(16)) => Some(Capability::Float16),
+        (Op::TypeFloat, Operand::LiteralBit32(64)) => Some(Capability::Float64),
+        _ => None,
+    }
 }
@@ -1,36 +1,82 @@
-use std::collections::VecDeque;
-
 use ahash::{AHashMap, AHashSet};
 use rspirv::{
-    dr::{Instruction, Operand},
+    dr::{Instruction, Module, Operand},
+    grammar::reflect,
     spirv::{Decoration, Op},
 };
 use smallvec::SmallVec;
+use thiserror::Error;
 
-#[derive(Hash, PartialEq, Eq, Debug)]
+use crate::spirv_ext::SpirvExt;
+
+#[derive(Error, Debug)]
+pub enum TypeTreeError {
+    #[error("Operand {index} of {op:?} is not a {expected}")]
+    MalformedOperand {
+        op: Op,
+        index: usize,
+        expected: &'static str,
+    },
+    #[error("Type %{ty} references %{operand}, which is not declared before")]
+    UndeclaredOperand { ty: u32, operand: u32 },
+    #[error("Decoration group %{0} is applied, but never declared")]
+    UnknownGroup(u32),
+    #[error("%{0} is not declared as a global value")]
+    UnknownId(u32),
+    #[error("%{id} is a {op:?}, only types and constants can be imported")]
+    NotImportable { id: u32, op: Op },
+    #[error("Type %{0} references itself, which can not be imported (yet)")]
+    CyclicType(u32),
+}
+
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
 pub enum TTypeOperand {
     LiteralBit32(u32),
     LiteralBit64(u64),
     LiteralString(String),
     IdRef(u32),
+    ///Any other operand, i.e. enumerants and bit masks, identified by its debug representation. For instance
+    /// `StorageClass(Uniform)`.
+    Enumerant(String),
 }
 
 impl From<Operand> for TTypeOperand {
     fn from(value: Operand) -> Self {
+        if let Some(id) = value.id_ref_any() {
+            return TTypeOperand::IdRef(id);
+        }
         match value {
-            Operand::IdRef(r) => TTypeOperand::IdRef(r),
             Operand::LiteralBit32(i) => TTypeOperand::LiteralBit32(i),
             Operand::LiteralBit64(i) => TTypeOperand::LiteralBit64(i),
+            Operand::LiteralExtInstInteger(i) => TTypeOperand::LiteralBit32(i),
             Operand::LiteralString(s) => TTypeOperand::LiteralString(s),
-            _ => panic!("Unparseabel operand {}", value),
+            other => TTypeOperand::Enumerant(format!("{:?}", other)),
         }
     }
 }
 
-#[derive(Hash, PartialEq, Eq, Debug)]
+///A single decoration including its extra operands, for instance the `16` of `Offset 16`, or the string of
+/// `UserSemantic`. Decorations applied through decoration groups are resolved to their targets.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
 pub enum TTDecoration {
-    Decorate(Decoration),
-    MemberDecorate { member: u32, decoration: Decoration },
+    Decorate {
+        decoration: Decoration,
+        operands: SmallVec<[TTypeOperand; 1]>,
+    },
+    MemberDecorate {
+        member: u32,
+        decoration: Decoration,
+        operands: SmallVec<[TTypeOperand; 1]>,
+    },
+}
+
+impl TTDecoration {
+    pub fn decoration(&self) -> Decoration {
+        match self {
+            TTDecoration::Decorate { decoration, .. }
+            | TTDecoration::MemberDecorate { decoration, .. } => *decoration,
+        }
+    }
 }
 
 #[derive(Hash, PartialEq, Eq, Debug)]
@@ -44,10 +90,9 @@ pub struct TTypeDef {
 #[derive(Hash, PartialEq, Eq, Debug)]
 pub struct TType {
     ///The type ID this type is known under, in the module the type tree was created from.
-    ///
-    /// Must be the same as `self.type_def.result_type.unwrap()`
     pub src_type_id: u32,
     type_def: TTypeDef,
+    ///All decorations of the type, sorted, so that the order of the annotations does not matter.
     pub decorations: SmallVec<[TTDecoration; 3]>,
 }
 
@@ -56,156 +101,526 @@ pub struct TypeTree {
     pub known_types: AHashSet<TType>,
 }
 
+fn id_at(inst: &Instruction, index: usize) -> Result<u32, TypeTreeError> {
+    inst.operands
+        .get(index)
+        .and_then(|op| op.id_ref_any())
+        .ok_or(TypeTreeError::MalformedOperand {
+            op: inst.class.opcode,
+            index,
+            expected: "id",
+        })
+}
+
+fn literal_at(inst: &Instruction, index: usize) -> Result<u32, TypeTreeError> {
+    match inst.operands.get(index) {
+        Some(Operand::LiteralBit32(i)) => Ok(*i),
+        _ => Err(TypeTreeError::MalformedOperand {
+            op: inst.class.opcode,
+            index,
+            expected: "32bit literal",
+        }),
+    }
+}
+
+//Parses the decoration at `index` and all its extra operands.
+fn decoration_at(
+    inst: &Instruction,
+    index: usize,
+) -> Result<(Decoration, SmallVec<[TTypeOperand; 1]>), TypeTreeError> {
+    match inst.operands.get(index) {
+        Some(Operand::Decoration(d)) => Ok((
+            *d,
+            inst.operands[index + 1..]
+                .iter()
+                .cloned()
+                .map(TTypeOperand::from)
+                .collect(),
+        )),
+        _ => Err(TypeTreeError::MalformedOperand {
+            op: inst.class.opcode,
+            index,
+            expected: "decoration",
+        }),
+    }
+}
+
 impl TypeTree {
-    ///Parses all types into a type tree
-    pub fn from_module(module: &rspirv::dr::Module) -> Self {
-        //iterate over all types. Always checking:
-        // 1. Are all dependencies already in the tree:
-        // 2. what decoration do we have for that type
-
-        //Pre build decoration lookup
-        let mut type_decorations = {
-            let mut decorations: AHashMap<u32, SmallVec<[TTDecoration; 3]>> = AHashMap::default();
-
-            for inst in &module.annotations {
-                match inst.class.opcode {
-                    Op::Decorate => {
-                        let id = inst.result_id.unwrap();
-                        let decoration = if let Operand::Decoration(d) = inst.operands[1] {
-                            d
-                        } else {
-                            panic!("Decoration operand was not a decoration!");
-                        };
-
-                        //Insert or add decoration for that type
-                        if let Some(ty_decorations) = decorations.get_mut(&id) {
-                            ty_decorations.push(TTDecoration::Decorate(decoration))
-                        } else {
-                            decorations.insert(
-                                id,
-                                smallvec::smallvec![TTDecoration::Decorate(decoration)],
-                            );
-                        }
-                    }
-                    Op::MemberDecorate => {
-                        let id = inst.result_id.unwrap();
-                        let member = if let Operand::LiteralBit32(i) = inst.operands[1] {
-                            i
-                        } else {
-                            panic!("MemberDecorate operand[1] was not a literal int32");
-                        };
-                        let decoration = if let Operand::Decoration(d) = inst.operands[2] {
-                            d
-                        } else {
-                            panic!("Decoration operand was not a decoration!");
-                        };
-
-                        //Insert or add decoration for that type
-                        if let Some(ty_decorations) = decorations.get_mut(&id) {
-                            ty_decorations.push(TTDecoration::MemberDecorate { member, decoration })
-                        } else {
-                            decorations.insert(
-                                id,
-                                smallvec::smallvec![TTDecoration::MemberDecorate {
-                                    member,
-                                    decoration
-                                }],
-                            );
+    ///Imports `types` of `src` into `dst` and returns the id mapping of all imported types and constants, including
+    /// dependencies. See [TypeImporter] for details.
+    pub fn import(
+        src: &Module,
+        dst: &mut Module,
+        types: &[u32],
+    ) -> Result<AHashMap<u32, u32>, TypeTreeError> {
+        let mut importer = TypeImporter::new(src, dst)?;
+        for ty in types {
+            importer.import(dst, *ty)?;
+        }
+        Ok(importer.into_mapping())
+    }
+
+    ///Parses all types into a type tree. Fails if the module's annotations or types are malformed.
+    pub fn from_module(module: &Module) -> Result<Self, TypeTreeError> {
+        let mut type_decorations = Self::collect_decorations(module)?;
+
+        //Ids that might be referenced by a type. Since SPIR-V requires declaration before use, checking in module order
+        // is enough. The only exception are forward declared pointers.
+        let mut declared = AHashSet::default();
+        let mut known_types = AHashSet::default();
+        for inst in &module.types_global_values {
+            if inst.class.opcode == Op::TypeForwardPointer {
+                declared.insert(id_at(inst, 0)?);
+                continue;
+            }
+            let Some(id) = inst.result_id else {
+                continue;
+            };
+            if reflect::is_type(inst.class.opcode) {
+                if let Some(operand) = inst
+                    .operands
+                    .iter()
+                    .filter_map(|op| op.id_ref_any())
+                    .find(|operand| !declared.contains(operand))
+                {
+                    return Err(TypeTreeError::UndeclaredOperand { ty: id, operand });
+                }
+
+                let mut decorations = type_decorations.remove(&id).unwrap_or_default();
+                decorations.sort();
+                known_types.insert(TType {
+                    src_type_id: id,
+                    type_def: TTypeDef {
+                        op: inst.class.opcode,
+                        operands: inst
+                            .operands
+                            .iter()
+                            .cloned()
+                            .map(TTypeOperand::from)
+                            .collect(),
+                    },
+                    decorations,
+                });
+            }
+            declared.insert(id);
+        }
+
+        Ok(TypeTree { known_types })
+    }
+
+    //Builds the decoration lookup for all ids. Group decorations are resolved to their targets.
+    pub(crate) fn collect_decorations(
+        module: &Module,
+    ) -> Result<AHashMap<u32, SmallVec<[TTDecoration; 3]>>, TypeTreeError> {
+        let mut decorations: AHashMap<u32, SmallVec<[TTDecoration; 3]>> = AHashMap::default();
+        let mut groups = AHashSet::default();
+
+        for inst in &module.annotations {
+            match inst.class.opcode {
+                Op::Decorate | Op::DecorateId | Op::DecorateString => {
+                    let (decoration, operands) = decoration_at(inst, 1)?;
+                    decorations
+                        .entry(id_at(inst, 0)?)
+                        .or_default()
+                        .push(TTDecoration::Decorate {
+                            decoration,
+                            operands,
+                        });
+                }
+                Op::MemberDecorate | Op::MemberDecorateString => {
+                    let member = literal_at(inst, 1)?;
+                    let (decoration, operands) = decoration_at(inst, 2)?;
+                    decorations.entry(id_at(inst, 0)?).or_default().push(
+                        TTDecoration::MemberDecorate {
+                            member,
+                            decoration,
+                            operands,
+                        },
+                    );
+                }
+                Op::DecorationGroup => {
+                    groups.extend(inst.result_id);
+                }
+                _ => {}
+            }
+        }
+
+        //Groups are declared after their decorations, but before being applied. So resolve them in a second pass.
+        for inst in &module.annotations {
+            if !matches!(
+                inst.class.opcode,
+                Op::GroupDecorate | Op::GroupMemberDecorate
+            ) {
+                continue;
+            }
+            let group = id_at(inst, 0)?;
+            if !groups.contains(&group) {
+                return Err(TypeTreeError::UnknownGroup(group));
+            }
+            let applied = decorations.get(&group).cloned().unwrap_or_default();
+
+            if inst.class.opcode == Op::GroupDecorate {
+                for index in 1..inst.operands.len() {
+                    decorations
+                        .entry(id_at(inst, index)?)
+                        .or_default()
+                        .extend(applied.iter().cloned());
+                }
+            } else {
+                //(target, member) pairs following the group
+                for index in (1..inst.operands.len()).step_by(2) {
+                    let target = id_at(inst, index)?;
+                    let member = literal_at(inst, index + 1)?;
+                    let as_member = applied.iter().map(|dec| match dec {
+                        TTDecoration::Decorate {
+                            decoration,
+                            operands,
                         }
-                    }
-                    Op::DecorateId
-                    | Op::DecorateString
-                    | Op::MemberDecorateString
-                    | Op::GroupMemberDecorate
-                    | Op::GroupDecorate => {
-                        panic!(
-                            "{:?} decoration type not supported (yet)!",
-                            inst.class.opcode
-                        );
-                    }
-                    _ => {}
+                        | TTDecoration::MemberDecorate {
+                            decoration,
+                            operands,
+                            ..
+                        } => TTDecoration::MemberDecorate {
+                            member,
+                            decoration: *decoration,
+                            operands: operands.clone(),
+                        },
+                    });
+                    decorations.entry(target).or_default().extend(as_member);
                 }
             }
+        }
+
+        Ok(decorations)
+    }
+}
+
+fn is_importable(op: Op) -> bool {
+    reflect::is_type(op) || reflect::is_constant(op)
+}
+
+//Non-aggregate types, which SPIR-V allows to be declared only once. Decorations can't tell two of those apart.
+// Pointers may be declared multiple times, for instance with different `ArrayStride`s, so they are not unique.
+fn is_unique_type(op: Op) -> bool {
+    reflect::is_type(op)
+        && !matches!(
+            op,
+            Op::TypeStruct
+                | Op::TypeArray
+                | Op::TypeRuntimeArray
+                | Op::TypePointer
+                | Op::TypeForwardPointer
+        )
+}
+
+//Structural keys of all types and constants of a module. Two ids of different modules have the same key, if their
+// definitions, all their dependencies, and all decorations are equal. Decorations of unique types are ignored.
+struct StructuralKeys {
+    //Index into `types_global_values` for each id.
+    defs: AHashMap<u32, usize>,
+    decorations: AHashMap<u32, SmallVec<[TTDecoration; 3]>>,
+    keys: AHashMap<u32, String>,
+}
+
+impl StructuralKeys {
+    fn new(module: &Module) -> Result<Self, TypeTreeError> {
+        Ok(StructuralKeys {
+            defs: module
+                .types_global_values
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, inst)| inst.result_id.map(|id| (id, idx)))
+                .collect(),
+            decorations: TypeTree::collect_decorations(module)?,
+            keys: AHashMap::default(),
+        })
+    }
+
+    fn key(&mut self, module: &Module, id: u32) -> Result<String, TypeTreeError> {
+        self.key_inner(module, id, &mut Vec::new())
+    }
 
-            decorations
+    fn key_inner(
+        &mut self,
+        module: &Module,
+        id: u32,
+        stack: &mut Vec<u32>,
+    ) -> Result<String, TypeTreeError> {
+        if let Some(key) = self.keys.get(&id) {
+            return Ok(key.clone());
+        }
+        if stack.contains(&id) {
+            return Ok(String::from("(cycle)"));
+        }
+        let inst = self
+            .defs
+            .get(&id)
+            .map(|idx| &module.types_global_values[*idx])
+            .ok_or(TypeTreeError::UnknownId(id))?;
+        if !is_importable(inst.class.opcode) {
+            return Err(TypeTreeError::NotImportable {
+                id,
+                op: inst.class.opcode,
+            });
+        }
+
+        stack.push(id);
+        let mut key = format!("({:?}", inst.class.opcode);
+        if let Some(ty) = inst.result_type {
+            key.push(' ');
+            key.push_str(&self.key_inner(module, ty, stack)?);
+        }
+        for operand in &inst.operands {
+            key.push(' ');
+            key.push_str(&self.operand_key(module, operand.clone().into(), stack)?);
+        }
+
+        //Sort by key, since ids in operands differ between modules.
+        let mut decorations = Vec::new();
+        let own_decorations = if is_unique_type(inst.class.opcode) {
+            SmallVec::new()
+        } else {
+            self.decorations.get(&id).cloned().unwrap_or_default()
         };
+        for dec in own_decorations {
+            let (member, decoration, operands) = match dec {
+                TTDecoration::Decorate {
+                    decoration,
+                    operands,
+                } => (None, decoration, operands),
+                TTDecoration::MemberDecorate {
+                    member,
+                    decoration,
+                    operands,
+                } => (Some(member), decoration, operands),
+            };
+            let mut dec_key = format!("[{:?} {:?}", member, decoration);
+            for operand in operands {
+                dec_key.push(' ');
+                dec_key.push_str(&self.operand_key(module, operand, stack)?);
+            }
+            dec_key.push(']');
+            decorations.push(dec_key);
+        }
+        decorations.sort();
+        for dec_key in decorations {
+            key.push(' ');
+            key.push_str(&dec_key);
+        }
+        key.push(')');
+        stack.pop();
 
-        //parse tree for all types
-        let type_stack = module.types_global_values.iter().filter(|inst| {
-            //Small hack. Instead of figuring out all kinds of OpTypeX (there are many),
-            // instead filter out all constants
-            ![
-                Op::Constant,
-                Op::ConstantComposite,
-                Op::ConstantFalse,
-                Op::ConstantNull,
-                Op::ConstantPipeStorage,
-                Op::ConstantSampler,
-                Op::ConstantTrue,
-                Op::SpecConstant,
-                Op::SpecConstantComposite,
-                Op::SpecConstantFalse,
-                Op::SpecConstantOp,
-                Op::SpecConstantTrue,
-            ]
-            .contains(&inst.class.opcode)
-        });
-
-        //At first, cache type key as well, later drop that for the final tree
-        let mut tmp_tree = AHashMap::default();
-
-        //Post stack that allows us to *deffere* a type to a later stage
-        let mut type_stack: VecDeque<&Instruction> = type_stack.collect();
-        loop {
-            let stack_len = type_stack.len();
-            if stack_len == 0 {
-                break;
+        //Keys within a cycle depend on where the cycle was entered, so don't cache those.
+        if !key.contains("(cycle)") {
+            self.keys.insert(id, key.clone());
+        }
+        Ok(key)
+    }
+
+    fn operand_key(
+        &mut self,
+        module: &Module,
+        operand: TTypeOperand,
+        stack: &mut Vec<u32>,
+    ) -> Result<String, TypeTreeError> {
+        match operand {
+            TTypeOperand::IdRef(id) => self.key_inner(module, id, stack),
+            other => Ok(format!("{:?}", other)),
+        }
+    }
+}
+
+///Maps types and constants of a source module into a destination module.
+///
+///A type is mapped to a structurally equal type of the destination, i.e. same definition, same dependencies and same
+/// decorations, regardless of the ids. If there is none, the type is inserted into the destination together with all
+/// its dependencies, decorations and debug names. Decoration groups are resolved while doing so.
+///
+///Non-aggregate types (scalars, vectors, matrices, images, ...) may only be declared once, so those are matched on their
+/// definition alone, and their decorations are dropped when an existing declaration is reused. Pointers may be
+/// declared multiple times, so their decorations (e.g. `ArrayStride`) are matched like those of aggregates.
+///
+///All mappings are cached, so reuse one importer for all types of a module.
+pub struct TypeImporter<'src> {
+    src: &'src Module,
+    src_keys: StructuralKeys,
+    //First id of each structural key in the destination.
+    dst_ids: AHashMap<String, u32>,
+    mapping: AHashMap<u32, u32>,
+    //Types that are currently being imported.
+    importing: Vec<u32>,
+}
+
+impl<'src> TypeImporter<'src> {
+    pub fn new(src: &'src Module, dst: &Module) -> Result<Self, TypeTreeError> {
+        let mut dst_keys = StructuralKeys::new(dst)?;
+        let mut dst_ids = AHashMap::default();
+        for inst in &dst.types_global_values {
+            if let (Some(id), true) = (inst.result_id, is_importable(inst.class.opcode)) {
+                let key = dst_keys.key(dst, id)?;
+                dst_ids.entry(key).or_insert(id);
             }
-            //Swap into tmp for working on the stack
-            let mut tmp_stack = VecDeque::new();
-            std::mem::swap(&mut type_stack, &mut tmp_stack);
-
-            for ty in tmp_stack {
-                for operand in &ty.operands {
-                    if let Operand::IdRef(r) = &operand {
-                        if !tmp_tree.contains_key(r) {
-                            log::info!("Deferring operand with id {:?}", r);
-                            type_stack.push_back(ty);
+        }
+
+        Ok(TypeImporter {
+            src,
+            src_keys: StructuralKeys::new(src)?,
+            dst_ids,
+            mapping: AHashMap::default(),
+            importing: Vec::new(),
+        })
+    }
+
+    ///Returns the id of the destination's type (or constant) that is structurally equal to `src_id`, without importing
+    /// anything.
+    pub fn find(&mut self, src_id: u32) -> Result<Option<u32>, TypeTreeError> {
+        if let Some(id) = self.mapping.get(&src_id) {
+            return Ok(Some(*id));
+        }
+        let key = self.src_keys.key(self.src, src_id)?;
+        Ok(self.dst_ids.get(&key).copied())
+    }
+
+    ///Maps `src_id` into `dst`, inserting it (and its dependencies) if needed. Returns the id in `dst`.
+    ///
+    ///`dst` must be the module the importer was created for.
+    pub fn import(&mut self, dst: &mut Module, src_id: u32) -> Result<u32, TypeTreeError> {
+        if let Some(id) = self.find(src_id)? {
+            self.mapping.insert(src_id, id);
+            return Ok(id);
+        }
+        if self.importing.contains(&src_id) {
+            return Err(TypeTreeError::CyclicType(src_id));
+        }
+        self.importing.push(src_id);
+
+        let src = self.src;
+        let mut inst = self
+            .src_keys
+            .defs
+            .get(&src_id)
+            .map(|idx| src.types_global_values[*idx].clone())
+            .ok_or(TypeTreeError::UnknownId(src_id))?;
+        if let Some(ty) = inst.result_type {
+            inst.result_type = Some(self.import(dst, ty)?);
+        }
+        for operand in inst.operands.iter_mut() {
+            if let Some(id) = operand.id_ref_any_mut() {
+                *id = self.import(dst, *id)?;
+            }
+        }
+        let dst_id = dst.allocate_id();
+        inst.result_id = Some(dst_id);
+        dst.types_global_values.push(inst);
+
+        let key = self.src_keys.key(src, src_id)?;
+        self.dst_ids.entry(key).or_insert(dst_id);
+        self.mapping.insert(src_id, dst_id);
+        self.import_annotations(dst, src_id, dst_id)?;
+
+        self.importing.pop();
+        Ok(dst_id)
+    }
+
+    ///All ids mapped so far, from source to destination.
+    pub fn mapping(&self) -> &AHashMap<u32, u32> {
+        &self.mapping
+    }
+
+    pub fn into_mapping(self) -> AHashMap<u32, u32> {
+        self.mapping
+    }
+
+    //Copies all decorations and names of `src_id` to `dst_id`.
+    fn import_annotations(
+        &mut self,
+        dst: &mut Module,
+        src_id: u32,
+        dst_id: u32,
+    ) -> Result<(), TypeTreeError> {
+        let src = self.src;
+        let group_decorations = |group: u32| {
+            src.annotations.iter().filter(move |inst| {
+                matches!(
+                    inst.class.opcode,
+                    Op::Decorate | Op::DecorateId | Op::DecorateString
+                ) && inst.operands.get(0).and_then(|op| op.id_ref_any()) == Some(group)
+            })
+        };
+
+        let mut decorations = Vec::new();
+        for inst in &src.annotations {
+            let target = inst.operands.get(0).and_then(|op| op.id_ref_any());
+            match inst.class.opcode {
+                Op::Decorate
+                | Op::DecorateId
+                | Op::DecorateString
+                | Op::MemberDecorate
+                | Op::MemberDecorateString
+                    if target == Some(src_id) =>
+                {
+                    decorations.push(inst.clone());
+                }
+                Op::GroupDecorate
+                    if inst.operands[1..]
+                        .iter()
+                        .any(|op| op.id_ref_any() == Some(src_id)) =>
+                {
+                    decorations.extend(group_decorations(id_at(inst, 0)?).cloned());
+                }
+                Op::GroupMemberDecorate => {
+                    let group = id_at(inst, 0)?;
+                    for index in (1..inst.operands.len()).step_by(2) {
+                        if inst.operands[index].id_ref_any() != Some(src_id) {
+                            continue;
+                        }
+                        let member = inst.operands.get(index + 1).cloned().ok_or(
+                            TypeTreeError::MalformedOperand {
+                                op: inst.class.opcode,
+                                index: index + 1,
+                                expected: "32bit literal",
+                            },
+                        )?;
+                        for dec in group_decorations(group) {
+                            let op = match dec.class.opcode {
+                                Op::Decorate => Op::MemberDecorate,
+                                Op::DecorateString => Op::MemberDecorateString,
+                                _ => {
+                                    log::warn!(
+                                        "{:?} can not be applied to a member, ignoring it",
+                                        dec.class.opcode
+                                    );
+                                    continue;
+                                }
+                            };
+                            let mut operands = dec.operands.clone();
+                            operands.insert(1, member.clone());
+                            decorations.push(Instruction::new(op, None, None, operands));
                         }
                     }
                 }
-
-                //passed operand check, therefore collect all
-                tmp_tree.insert(
-                    ty.result_id.as_ref().unwrap(),
-                    TType {
-                        type_def: TTypeDef {
-                            op: ty.class.opcode,
-                            operands: ty.operands[1..]
-                                .iter()
-                                .map(|o| TTypeOperand::from(o.clone()))
-                                .collect(),
-                        },
-                        src_type_id: *ty.result_id.as_ref().unwrap(),
-                        decorations: if let Some(dec) =
-                            type_decorations.remove(ty.result_id.as_ref().unwrap())
-                        {
-                            dec.into()
-                        } else {
-                            SmallVec::new()
-                        },
-                    },
-                );
+                _ => {}
             }
+        }
 
-            if stack_len == type_stack.len() {
-                panic!("stack could not be reduced!");
+        for mut dec in decorations {
+            dec.operands[0] = Operand::IdRef(dst_id);
+            for operand in dec.operands[1..].iter_mut() {
+                if let Some(id) = operand.id_ref_any_mut() {
+                    *id = self.import(dst, *id)?;
+                }
             }
+            dst.annotations.push(dec);
         }
 
-        //now build the actual *TreeSet*
-        TypeTree {
-            known_types: tmp_tree.into_iter().map(|(_key, value)| value).collect(),
+        for name in &src.debug_names {
+            if name.operands.get(0).and_then(|op| op.id_ref_any()) == Some(src_id) {
+                let mut name = name.clone();
+                name.operands[0] = Operand::IdRef(dst_id);
+                dst.debug_names.push(name);
+            }
         }
+
+        Ok(())
     }
 }
@@ -1,7 +1,6 @@
 use std::io::Write;
 use std::process::{Command, Stdio};
-///`spirv-val` based validator.
-//TODO: Might add custom validation passes later.
+///`spirv-val` based validator. See [verify](crate::verify) for the in-process verifier.
 #[allow(dead_code)]
 pub struct Validator;
 
@@ -10,7 +9,12 @@ impl Validator {
     ///Tries to run validator. Returns `Ok` if validated successfully
     /// or `Err` containing `spirv-val`'s error if not.
     pub fn validate_code(spirv: &[u8]) -> Result<(), String> {
-        let mut child = match Command::new("spirv-val").stdin(Stdio::piped()).spawn() {
+        let mut child = match Command::new("spirv-val")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
             Ok(c) => c,
             Err(e) => {
                 log::error!("Failed to spawn spirv-val: {}", e);
@@ -28,13 +32,19 @@ impl Validator {
             stdin.write_all(&code).expect("Failed to write to stdin");
         });
 
-        match child
-            .wait_with_output()
-            .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
-        {
-            Ok(_res) => Ok(()),
+        match child.wait_with_output() {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => {
+                //spirv-val reports errors on stderr, but older versions used stdout.
+                let mut msg = String::from_utf8_lossy(&output.stderr).to_string();
+                msg.push_str(&String::from_utf8_lossy(&output.stdout));
+                if msg.trim().is_empty() {
+                    msg = format!("spirv-val failed with {}", output.status);
+                }
+                Err(msg)
+            }
             Err(e) => {
-                log::error!("Failed to run spv-dis: {}", e);
+                log::error!("Failed to run spirv-val: {}", e);
                 Err(format!("{}", e))
             }
         }
@@ -1 +1,1190 @@
+//! # Native verification
+//!
+//! In-process SPIR-V verification that does not depend on `spirv-val` being installed. It does not replace a full validator,
+//! but covers the rules patches usually break:
+//!
+//! - ids are defined exactly once, and before they are used
+//! - operand types are consistent for memory access, calls, returns and arithmetic
+//! - instructions are placed in the section they belong to
+//! - blocks are terminated correctly
+//! - structured merge and continue targets are valid
+//! - decorations and debug names target existing ids
+//!
+//! Use [Verifier::verify] to get a list of [Diagnostic]s, each naming the offending instruction.
 
+use std::fmt::Display;
+
+use ahash::{AHashMap, AHashSet};
+use rspirv::{
+    dr::{Function, Instruction, Module, Operand},
+    grammar::reflect,
+    spirv::{Decoration, Op, StorageClass},
+};
+use thiserror::Error;
+
+///Rule that was violated by an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    ///Id is not defined, defined twice or used before its definition.
+    IdDefinition,
+    ///Operand types do not match the instruction's expectation.
+    TypeConsistency,
+    ///Instruction is placed in the wrong section of the module or function.
+    SectionOrder,
+    ///Block is not terminated, or has a terminator in its middle.
+    BlockTermination,
+    ///Merge or continue targets violate the structured control flow rules.
+    StructuredControlFlow,
+    ///Decoration or debug name targets an invalid id.
+    DecorationTarget,
+}
+
+///A single verification error.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub rule: Rule,
+    pub message: String,
+    ///The offending instruction, if the error can be attributed to a single instruction.
+    pub instruction: Option<Instruction>,
+    ///Function in which the error occurred, if any.
+    pub function: Option<u32>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{:?}] {}", self.rule, self.message)?;
+        if let Some(function) = self.function {
+            write!(f, " (in function %{})", function)?;
+        }
+        if let Some(inst) = &self.instruction {
+            write!(f, "\n    {}", format_instruction(inst))?;
+        }
+        Ok(())
+    }
+}
+
+///Collection of all diagnostics found while verifying a module.
+#[derive(Error, Debug, Clone)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "SPIR-V verification failed with {} error(s):",
+            self.0.len()
+        )?;
+        for d in &self.0 {
+            writeln!(f, "{}", d)?;
+        }
+        Ok(())
+    }
+}
+
+impl Diagnostics {
+    pub fn has_rule(&self, rule: Rule) -> bool {
+        self.0.iter().any(|d| d.rule == rule)
+    }
+}
+
+//Simple `%id = OpName %type operands` formatting used in diagnostics.
+fn format_instruction(inst: &Instruction) -> String {
+    let mut s = String::new();
+    if let Some(id) = inst.result_id {
+        s.push_str(&format!("%{} = ", id));
+    }
+    s.push_str("Op");
+    s.push_str(inst.class.opname);
+    if let Some(ty) = inst.result_type {
+        s.push_str(&format!(" %{}", ty));
+    }
+    for op in &inst.operands {
+        s.push_str(&format!(" {}", op));
+    }
+    s
+}
+
+///Native SPIR-V verifier. See the [module documentation](self) for the covered rules.
+pub struct Verifier<'a> {
+    module: &'a Module,
+    diagnostics: Vec<Diagnostic>,
+    //All defined ids and the instruction that defines them.
+    defs: AHashMap<u32, &'a Instruction>,
+    //Function ids, since they are allowed to be referenced before their definition.
+    functions: AHashMap<u32, &'a Function>,
+    //Ids defined outside of function bodies.
+    globals: AHashSet<u32>,
+}
+
+impl<'a> Verifier<'a> {
+    ///Verifies `module`. Returns all found [Diagnostic]s as error.
+    pub fn verify(module: &'a Module) -> Result<(), Diagnostics> {
+        let diagnostics = Self::diagnose(module);
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(Diagnostics(diagnostics))
+        }
+    }
+
+    ///Runs all rules and returns every diagnostic that was found.
+    pub fn diagnose(module: &'a Module) -> Vec<Diagnostic> {
+        let mut verifier = Verifier {
+            module,
+            diagnostics: Vec::new(),
+            defs: AHashMap::default(),
+            functions: AHashMap::default(),
+            globals: AHashSet::default(),
+        };
+
+        verifier.collect_definitions();
+        verifier.check_sections();
+        verifier.check_global_uses();
+        for function in &module.functions {
+            verifier.check_function(function);
+        }
+        verifier.check_annotations();
+        verifier.check_entry_points();
+
+        verifier.diagnostics
+    }
+
+    fn report(
+        &mut self,
+        rule: Rule,
+        message: impl Into<String>,
+        instruction: Option<&Instruction>,
+        function: Option<u32>,
+    ) {
+        self.diagnostics.push(Diagnostic {
+            rule,
+            message: message.into(),
+            instruction: instruction.cloned(),
+            function,
+        });
+    }
+
+    fn collect_definitions(&mut self) {
+        let module = self.module;
+        let bound = module.header.as_ref().map(|h| h.bound);
+        for inst in module.all_inst_iter() {
+            if let Some(id) = inst.result_id {
+                if let Some(bound) = bound {
+                    if id >= bound {
+                        self.report(
+                            Rule::IdDefinition,
+                            format!("Id %{} exceeds the module's id bound {}", id, bound),
+                            Some(inst),
+                            None,
+                        );
+                    }
+                }
+                if self.defs.insert(id, inst).is_some() {
+                    self.report(
+                        Rule::IdDefinition,
+                        format!("Id %{} is defined more than once", id),
+                        Some(inst),
+                        None,
+                    );
+                }
+            }
+        }
+
+        for f in &module.functions {
+            if let Some(id) = f.def_id() {
+                self.functions.insert(id, f);
+            }
+        }
+        self.globals = module
+            .global_inst_iter()
+            .filter_map(|inst| inst.result_id)
+            .collect();
+    }
+
+    fn is_type(&self, id: u32) -> bool {
+        self.defs
+            .get(&id)
+            .map(|inst| reflect::is_type(inst.class.opcode))
+            .unwrap_or(false)
+    }
+
+    fn opcode_of(&self, id: u32) -> Option<Op> {
+        self.defs.get(&id).map(|inst| inst.class.opcode)
+    }
+
+    ///Returns the type id of the value `id`.
+    fn type_of(&self, id: u32) -> Option<u32> {
+        self.defs.get(&id).and_then(|inst| inst.result_type)
+    }
+
+    ///If `ty` is a pointer, returns `(storage_class, pointee)`.
+    fn pointer_info(&self, ty: u32) -> Option<(StorageClass, u32)> {
+        let inst = self.defs.get(&ty)?;
+        if inst.class.opcode != Op::TypePointer {
+            return None;
+        }
+        match (inst.operands.get(0), inst.operands.get(1)) {
+            (Some(Operand::StorageClass(sc)), Some(Operand::IdRef(pointee))) => {
+                Some((*sc, *pointee))
+            }
+            _ => None,
+        }
+    }
+
+    ///Returns `(width, components)` for scalar or vector integer/float types.
+    fn numeric_shape(&self, ty: u32) -> Option<(Op, u32, u32)> {
+        let inst = self.defs.get(&ty)?;
+        match inst.class.opcode {
+            Op::TypeInt | Op::TypeFloat => match inst.operands.get(0) {
+                Some(Operand::LiteralBit32(w)) => Some((inst.class.opcode, *w, 1)),
+                _ => None,
+            },
+            Op::TypeVector => match (inst.operands.get(0), inst.operands.get(1)) {
+                (Some(Operand::IdRef(component)), Some(Operand::LiteralBit32(count))) => {
+                    let (op, width, _) = self.numeric_shape(*component)?;
+                    Some((op, width, *count))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn check_sections(&mut self) {
+        let module = self.module;
+        let sections: [(&str, &Vec<Instruction>, fn(Op) -> bool); 10] = [
+            ("capability", &module.capabilities, |op| {
+                op == Op::Capability
+            }),
+            ("extension", &module.extensions, |op| op == Op::Extension),
+            ("ext-inst-import", &module.ext_inst_imports, |op| {
+                op == Op::ExtInstImport
+            }),
+            ("entry-point", &module.entry_points, |op| {
+                op == Op::EntryPoint
+            }),
+            ("execution-mode", &module.execution_modes, |op| {
+                op == Op::ExecutionMode || op == Op::ExecutionModeId
+            }),
+            ("debug-source", &module.debug_string_source, |op| {
+                matches!(
+                    op,
+                    Op::String | Op::Source | Op::SourceContinued | Op::SourceExtension
+                )
+            }),
+            ("debug-name", &module.debug_names, |op| {
+                op == Op::Name || op == Op::MemberName
+            }),
+            ("module-processed", &module.debug_module_processed, |op| {
+                op == Op::ModuleProcessed
+            }),
+            ("annotation", &module.annotations, |op| {
+                reflect::is_annotation(op) || op == Op::DecorateId
+            }),
+            ("types-global-values", &module.types_global_values, |op| {
+                reflect::is_type(op)
+                    || reflect::is_constant(op)
+                    || reflect::is_location_debug(op)
+                    || matches!(op, Op::Variable | Op::Undef | Op::ExtInst)
+            }),
+        ];
+
+        for (name, section, allowed) in sections {
+            for inst in section {
+                if !allowed(inst.class.opcode) {
+                    self.report(
+                        Rule::SectionOrder,
+                        format!(
+                            "Op{} is not allowed in the {} section",
+                            inst.class.opname, name
+                        ),
+                        Some(inst),
+                        None,
+                    );
+                }
+            }
+        }
+
+        match &module.memory_model {
+            Some(mm) if mm.class.opcode == Op::MemoryModel => {}
+            Some(mm) => self.report(
+                Rule::SectionOrder,
+                "Memory model section contains no OpMemoryModel",
+                Some(mm),
+                None,
+            ),
+            None => self.report(
+                Rule::SectionOrder,
+                "Module has no OpMemoryModel",
+                None,
+                None,
+            ),
+        }
+
+        for inst in &module.types_global_values {
+            if inst.class.opcode == Op::Variable {
+                if let Some(Operand::StorageClass(StorageClass::Function)) = inst.operands.get(0) {
+                    self.report(
+                        Rule::SectionOrder,
+                        "Global OpVariable must not use the Function storage class",
+                        Some(inst),
+                        None,
+                    );
+                }
+            }
+        }
+    }
+
+    //Checks that every id used in the global section is defined. Types, constants and global variables must be
+    // declared before they are used, everything else may forward reference.
+    fn check_global_uses(&mut self) {
+        let module = self.module;
+        let mut forward_pointers = AHashSet::default();
+        let mut seen = AHashSet::default();
+        for inst in &module.types_global_values {
+            if inst.class.opcode == Op::TypeForwardPointer {
+                if let Some(Operand::IdRef(ptr)) = inst.operands.get(0) {
+                    forward_pointers.insert(*ptr);
+                }
+                continue;
+            }
+
+            let uses: Vec<u32> = inst
+                .result_type
+                .into_iter()
+                .chain(inst.operands.iter().filter_map(|op| op.id_ref_any()))
+                .collect();
+            for id in &uses {
+                if !self.defs.contains_key(id) {
+                    self.report(
+                        Rule::IdDefinition,
+                        format!("Id %{} is used, but never defined", id),
+                        Some(inst),
+                        None,
+                    );
+                } else if !seen.contains(id)
+                    && !forward_pointers.contains(id)
+                    && !self.functions.contains_key(id)
+                    && !matches!(
+                        self.opcode_of(*id),
+                        Some(Op::String) | Some(Op::ExtInstImport)
+                    )
+                {
+                    self.report(
+                        Rule::IdDefinition,
+                        format!("Id %{} is used before its definition", id),
+                        Some(inst),
+                        None,
+                    );
+                }
+            }
+
+            if let Some(ty) = inst.result_type {
+                if self.defs.contains_key(&ty) && !self.is_type(ty) {
+                    self.report(
+                        Rule::TypeConsistency,
+                        format!("Result type %{} is not a type", ty),
+                        Some(inst),
+                        None,
+                    );
+                }
+            }
+
+            self.check_global_types(inst);
+
+            if let Some(id) = inst.result_id {
+                seen.insert(id);
+            }
+        }
+    }
+
+    fn check_global_types(&mut self, inst: &Instruction) {
+        let Some(ty) = inst.result_type else {
+            return;
+        };
+        let ty_op = self.opcode_of(ty);
+        match inst.class.opcode {
+            Op::Constant | Op::SpecConstant => {
+                if !matches!(ty_op, Some(Op::TypeInt) | Some(Op::TypeFloat)) {
+                    self.report(
+                        Rule::TypeConsistency,
+                        "Scalar constant must have integer or float type",
+                        Some(inst),
+                        None,
+                    );
+                }
+            }
+            Op::ConstantTrue | Op::ConstantFalse | Op::SpecConstantTrue | Op::SpecConstantFalse => {
+                if ty_op != Some(Op::TypeBool) {
+                    self.report(
+                        Rule::TypeConsistency,
+                        "Boolean constant must have bool type",
+                        Some(inst),
+                        None,
+                    );
+                }
+            }
+            Op::Variable => self.check_variable(inst, None),
+            _ => {}
+        }
+    }
+
+    fn check_variable(&mut self, inst: &Instruction, function: Option<u32>) {
+        let Some(ty) = inst.result_type else {
+            return;
+        };
+        match (self.pointer_info(ty), inst.operands.get(0)) {
+            (Some((ptr_class, pointee)), Some(Operand::StorageClass(class))) => {
+                if ptr_class != *class {
+                    self.report(
+                        Rule::TypeConsistency,
+                        format!(
+                            "Variable storage class {:?} does not match its pointer type's storage class {:?}",
+                            class, ptr_class
+                        ),
+                        Some(inst),
+                        function,
+                    );
+                }
+                if let Some(Operand::IdRef(init)) = inst.operands.get(1) {
+                    if let Some(init_ty) = self.type_of(*init) {
+                        if init_ty != pointee {
+                            self.report(
+                                Rule::TypeConsistency,
+                                "Variable initializer type does not match the pointee type",
+                                Some(inst),
+                                function,
+                            );
+                        }
+                    }
+                }
+            }
+            _ => self.report(
+                Rule::TypeConsistency,
+                "OpVariable's result type must be a pointer",
+                Some(inst),
+                function,
+            ),
+        }
+    }
+
+    fn check_function(&mut self, function: &'a Function) {
+        let Some(def) = &function.def else {
+            self.report(Rule::SectionOrder, "Function has no OpFunction", None, None);
+            return;
+        };
+        let fid = def.result_id;
+        if function.end.is_none() {
+            self.report(
+                Rule::SectionOrder,
+                "Function has no OpFunctionEnd",
+                Some(def),
+                fid,
+            );
+        }
+
+        self.check_function_type(function, def);
+
+        //Function-local id order. Ids of this function that are already defined at a point in the linear block order.
+        let mut local_defs: AHashSet<u32> = function
+            .parameters
+            .iter()
+            .filter_map(|p| p.result_id)
+            .chain(fid)
+            .collect();
+        let labels: AHashMap<u32, usize> = function
+            .blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, b)| b.label_id().map(|l| (l, idx)))
+            .collect();
+        let function_ids: AHashSet<u32> = function
+            .all_inst_iter()
+            .filter_map(|inst| inst.result_id)
+            .collect();
+
+        let mut merge_targets: AHashMap<u32, u32> = AHashMap::default();
+        for (block_idx, block) in function.blocks.iter().enumerate() {
+            if block.label.is_none() {
+                self.report(Rule::BlockTermination, "Block has no OpLabel", None, fid);
+            }
+            if block.instructions.is_empty() {
+                self.report(
+                    Rule::BlockTermination,
+                    "Block is empty and therefore not terminated",
+                    block.label.as_ref(),
+                    fid,
+                );
+                continue;
+            }
+
+            let mut phi_allowed = true;
+            let mut variable_allowed = block_idx == 0;
+            let last = block.instructions.len() - 1;
+            for (inst_idx, inst) in block.instructions.iter().enumerate() {
+                let op = inst.class.opcode;
+                //Placement inside the block
+                match op {
+                    Op::Line | Op::NoLine => {}
+                    Op::Phi if !phi_allowed => self.report(
+                        Rule::SectionOrder,
+                        "OpPhi must be placed at the start of a block",
+                        Some(inst),
+                        fid,
+                    ),
+                    Op::Variable if !variable_allowed => self.report(
+                        Rule::SectionOrder,
+                        "Function-local OpVariable must be placed at the start of the first block",
+                        Some(inst),
+                        fid,
+                    ),
+                    Op::Variable => {
+                        phi_allowed = false;
+                        if let Some(Operand::StorageClass(sc)) = inst.operands.get(0) {
+                            if *sc != StorageClass::Function {
+                                self.report(
+                                    Rule::SectionOrder,
+                                    "Function-local OpVariable must use the Function storage class",
+                                    Some(inst),
+                                    fid,
+                                );
+                            }
+                        }
+                        self.check_variable(inst, fid);
+                    }
+                    Op::Phi => {}
+                    _ => {
+                        phi_allowed = false;
+                        variable_allowed = false;
+                        if reflect::is_type(op)
+                            || reflect::is_constant(op)
+                            || reflect::is_annotation(op)
+                            || reflect::is_nonlocation_debug(op)
+                        {
+                            self.report(
+                                Rule::SectionOrder,
+                                format!("Op{} is not allowed inside a function", inst.class.opname),
+                                Some(inst),
+                                fid,
+                            );
+                        }
+                    }
+                }
+
+                //Termination
+                let is_terminator = reflect::is_block_terminator(op);
+                if inst_idx == last && !is_terminator {
+                    self.report(
+                        Rule::BlockTermination,
+                        "Last instruction of a block must be a terminator",
+                        Some(inst),
+                        fid,
+                    );
+                }
+                if inst_idx != last && is_terminator {
+                    self.report(
+                        Rule::BlockTermination,
+                        "Terminator in the middle of a block",
+                        Some(inst),
+                        fid,
+                    );
+                }
+
+                //Id uses
+                for (operand_idx, operand) in inst.operands.iter().enumerate() {
+                    let Some(id) = operand.id_ref_any() else {
+                        continue;
+                    };
+                    if !self.defs.contains_key(&id) {
+                        self.report(
+                            Rule::IdDefinition,
+                            format!("Id %{} is used, but never defined", id),
+                            Some(inst),
+                            fid,
+                        );
+                        continue;
+                    }
+
+                    let is_label = labels.contains_key(&id);
+                    if function_ids.contains(&id) {
+                        //Phi operands and branch targets may reference later blocks
+                        let forward_allowed = is_label || (op == Op::Phi && operand_idx % 2 == 0);
+                        if !forward_allowed && !local_defs.contains(&id) {
+                            self.report(
+                                Rule::IdDefinition,
+                                format!("Id %{} is used before its definition", id),
+                                Some(inst),
+                                fid,
+                            );
+                        }
+                    } else if !self.globals.contains(&id) && !self.functions.contains_key(&id) {
+                        self.report(
+                            Rule::IdDefinition,
+                            format!("Id %{} is defined in another function", id),
+                            Some(inst),
+                            fid,
+                        );
+                    }
+                }
+                if let Some(ty) = inst.result_type {
+                    if !self.is_type(ty) {
+                        self.report(
+                            Rule::TypeConsistency,
+                            format!("Result type %{} is not a type", ty),
+                            Some(inst),
+                            fid,
+                        );
+                    }
+                }
+
+                self.check_instruction_types(function, inst, fid);
+
+                if let Some(id) = inst.result_id {
+                    local_defs.insert(id);
+                }
+            }
+
+            self.check_structure(function, block_idx, &labels, &mut merge_targets, fid);
+        }
+    }
+
+    fn check_function_type(&mut self, function: &Function, def: &Instruction) {
+        let fid = def.result_id;
+        let Some(Operand::IdRef(fn_ty)) = def.operands.get(1) else {
+            self.report(
+                Rule::TypeConsistency,
+                "OpFunction has no function type",
+                Some(def),
+                fid,
+            );
+            return;
+        };
+        let Some(fn_ty_inst) = self.defs.get(fn_ty).copied() else {
+            return;
+        };
+        if fn_ty_inst.class.opcode != Op::TypeFunction {
+            self.report(
+                Rule::TypeConsistency,
+                "OpFunction's type operand is not an OpTypeFunction",
+                Some(def),
+                fid,
+            );
+            return;
+        }
+
+        if fn_ty_inst.operands.get(0).and_then(|o| o.id_ref_any()) != def.result_type {
+            self.report(
+                Rule::TypeConsistency,
+                "Function result type does not match the return type of its function type",
+                Some(def),
+                fid,
+            );
+        }
+        let param_types: Vec<u32> = fn_ty_inst
+            .operands
+            .iter()
+            .skip(1)
+            .filter_map(|o| o.id_ref_any())
+            .collect();
+        if param_types.len() != function.parameters.len() {
+            self.report(
+                Rule::TypeConsistency,
+                format!(
+                    "Function declares {} parameters, but its type has {}",
+                    function.parameters.len(),
+                    param_types.len()
+                ),
+                Some(def),
+                fid,
+            );
+        } else {
+            for (param, ty) in function.parameters.iter().zip(param_types.iter()) {
+                if param.result_type != Some(*ty) {
+                    self.report(
+                        Rule::TypeConsistency,
+                        "Parameter type does not match the function type",
+                        Some(param),
+                        fid,
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_instruction_types(
+        &mut self,
+        function: &Function,
+        inst: &Instruction,
+        fid: Option<u32>,
+    ) {
+        let id_operand = |idx: usize| inst.operands.get(idx).and_then(|o| o.id_ref_any());
+        match inst.class.opcode {
+            Op::Load => {
+                let pointee = id_operand(0)
+                    .and_then(|ptr| self.type_of(ptr))
+                    .and_then(|ty| self.pointer_info(ty));
+                match pointee {
+                    Some((_, pointee)) if Some(pointee) != inst.result_type => self.report(
+                        Rule::TypeConsistency,
+                        "OpLoad result type does not match the pointee type",
+                        Some(inst),
+                        fid,
+                    ),
+                    None => self.report(
+                        Rule::TypeConsistency,
+                        "OpLoad pointer operand is not a pointer",
+                        Some(inst),
+                        fid,
+                    ),
+                    _ => {}
+                }
+            }
+            Op::Store => {
+                let pointee = id_operand(0)
+                    .and_then(|ptr| self.type_of(ptr))
+                    .and_then(|ty| self.pointer_info(ty));
+                let object_ty = id_operand(1).and_then(|obj| self.type_of(obj));
+                match pointee {
+                    Some((_, pointee)) if Some(pointee) != object_ty => self.report(
+                        Rule::TypeConsistency,
+                        "OpStore object type does not match the pointee type",
+                        Some(inst),
+                        fid,
+                    ),
+                    None => self.report(
+                        Rule::TypeConsistency,
+                        "OpStore pointer operand is not a pointer",
+                        Some(inst),
+                        fid,
+                    ),
+                    _ => {}
+                }
+            }
+            Op::ReturnValue => {
+                let return_type = function.def.as_ref().and_then(|d| d.result_type);
+                if id_operand(0).and_then(|v| self.type_of(v)) != return_type {
+                    self.report(
+                        Rule::TypeConsistency,
+                        "OpReturnValue type does not match the function's return type",
+                        Some(inst),
+                        fid,
+                    );
+                }
+            }
+            Op::Return => {
+                let return_type = function.def.as_ref().and_then(|d| d.result_type);
+                if return_type.and_then(|rt| self.opcode_of(rt)) != Some(Op::TypeVoid) {
+                    self.report(
+                        Rule::TypeConsistency,
+                        "OpReturn in a function with non-void return type",
+                        Some(inst),
+                        fid,
+                    );
+                }
+            }
+            Op::FunctionCall => {
+                let Some(callee) = id_operand(0) else {
+                    return;
+                };
+                let Some(callee_fn) = self.functions.get(&callee).copied() else {
+                    self.report(
+                        Rule::IdDefinition,
+                        format!("OpFunctionCall calls %{}, which is not a function", callee),
+                        Some(inst),
+                        fid,
+                    );
+                    return;
+                };
+                if callee_fn.def.as_ref().and_then(|d| d.result_type) != inst.result_type {
+                    self.report(
+                        Rule::TypeConsistency,
+                        "OpFunctionCall result type does not match the callee's return type",
+                        Some(inst),
+                        fid,
+                    );
+                }
+                let args = &inst.operands[1..];
+                if args.len() != callee_fn.parameters.len() {
+                    self.report(
+                        Rule::TypeConsistency,
+                        format!(
+                            "OpFunctionCall passes {} arguments, but the callee takes {}",
+                            args.len(),
+                            callee_fn.parameters.len()
+                        ),
+                        Some(inst),
+                        fid,
+                    );
+                } else {
+                    for (arg, param) in args.iter().zip(callee_fn.parameters.iter()) {
+                        if arg.id_ref_any().and_then(|a| self.type_of(a)) != param.result_type {
+                            self.report(
+                                Rule::TypeConsistency,
+                                format!("Argument {} does not match the parameter type", arg),
+                                Some(inst),
+                                fid,
+                            );
+                        }
+                    }
+                }
+            }
+            Op::BranchConditional => {
+                let cond_ty = id_operand(0).and_then(|c| self.type_of(c));
+                if cond_ty.and_then(|ty| self.opcode_of(ty)) != Some(Op::TypeBool) {
+                    self.report(
+                        Rule::TypeConsistency,
+                        "OpBranchConditional condition must be a bool",
+                        Some(inst),
+                        fid,
+                    );
+                }
+            }
+            Op::FAdd | Op::FSub | Op::FMul | Op::FDiv | Op::FRem | Op::FMod | Op::FNegate => {
+                for idx in 0..inst.operands.len() {
+                    if id_operand(idx).and_then(|v| self.type_of(v)) != inst.result_type {
+                        self.report(
+                            Rule::TypeConsistency,
+                            "Float arithmetic operand type does not match the result type",
+                            Some(inst),
+                            fid,
+                        );
+                        break;
+                    }
+                }
+            }
+            Op::IAdd
+            | Op::ISub
+            | Op::IMul
+            | Op::UDiv
+            | Op::SDiv
+            | Op::UMod
+            | Op::SRem
+            | Op::SMod
+            | Op::SNegate
+            | Op::BitwiseAnd
+            | Op::BitwiseOr
+            | Op::BitwiseXor
+            | Op::Not => {
+                //Integer arithmetic allows mixing signedness, but not widths or component counts
+                let expected = inst.result_type.and_then(|ty| self.numeric_shape(ty));
+                for idx in 0..inst.operands.len() {
+                    let found = id_operand(idx)
+                        .and_then(|v| self.type_of(v))
+                        .and_then(|ty| self.numeric_shape(ty));
+                    if expected.is_none() || found != expected {
+                        self.report(
+                            Rule::TypeConsistency,
+                            "Integer arithmetic operand type does not match the result type",
+                            Some(inst),
+                            fid,
+                        );
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_structure(
+        &mut self,
+        function: &Function,
+        block_idx: usize,
+        labels: &AHashMap<u32, usize>,
+        merge_targets: &mut AHashMap<u32, u32>,
+        fid: Option<u32>,
+    ) {
+        let block = &function.blocks[block_idx];
+        let Some(terminator) = block.instructions.last() else {
+            return;
+        };
+        let header = block.label_id().unwrap_or(0);
+
+        //Branch targets must be blocks of the same function
+        let targets: Vec<u32> = match terminator.class.opcode {
+            Op::Branch => terminator
+                .operands
+                .iter()
+                .filter_map(|o| o.id_ref_any())
+                .collect(),
+            Op::BranchConditional => terminator
+                .operands
+                .iter()
+                .skip(1)
+                .take(2)
+                .filter_map(|o| o.id_ref_any())
+                .collect(),
+            Op::Switch => terminator
+                .operands
+                .iter()
+                .skip(1)
+                .filter_map(|o| o.id_ref_any())
+                .collect(),
+            _ => Vec::new(),
+        };
+        for target in targets {
+            if !labels.contains_key(&target) {
+                self.report(
+                    Rule::StructuredControlFlow,
+                    format!("Branch target %{} is not a block of this function", target),
+                    Some(terminator),
+                    fid,
+                );
+            }
+        }
+
+        //Merge instructions must be directly before the terminator
+        for (idx, inst) in block.instructions.iter().enumerate() {
+            let op = inst.class.opcode;
+            if op != Op::SelectionMerge && op != Op::LoopMerge {
+                continue;
+            }
+            if idx + 2 != block.instructions.len() {
+                self.report(
+                    Rule::StructuredControlFlow,
+                    format!(
+                        "Op{} must directly precede the block's terminator",
+                        inst.class.opname
+                    ),
+                    Some(inst),
+                    fid,
+                );
+                continue;
+            }
+
+            let allowed_terminator = match op {
+                Op::LoopMerge => {
+                    matches!(terminator.class.opcode, Op::Branch | Op::BranchConditional)
+                }
+                _ => matches!(terminator.class.opcode, Op::BranchConditional | Op::Switch),
+            };
+            if !allowed_terminator {
+                self.report(
+                    Rule::StructuredControlFlow,
+                    format!(
+                        "Op{} can't be followed by Op{}",
+                        inst.class.opname, terminator.class.opname
+                    ),
+                    Some(inst),
+                    fid,
+                );
+            }
+
+            let merge = inst.operands.get(0).and_then(|o| o.id_ref_any());
+            let continue_target = if op == Op::LoopMerge {
+                inst.operands.get(1).and_then(|o| o.id_ref_any())
+            } else {
+                None
+            };
+
+            if let Some(merge) = merge {
+                match labels.get(&merge) {
+                    None => self.report(
+                        Rule::StructuredControlFlow,
+                        format!("Merge target %{} is not a block of this function", merge),
+                        Some(inst),
+                        fid,
+                    ),
+                    Some(merge_idx) => {
+                        if *merge_idx <= block_idx {
+                            self.report(
+                                Rule::StructuredControlFlow,
+                                "Merge block must appear after its header block",
+                                Some(inst),
+                                fid,
+                            );
+                        }
+                        if let Some(other) = merge_targets.insert(merge, header) {
+                            self.report(
+                                Rule::StructuredControlFlow,
+                                format!(
+                                    "Block %{} is the merge block of both %{} and %{}",
+                                    merge, other, header
+                                ),
+                                Some(inst),
+                                fid,
+                            );
+                        }
+                    }
+                }
+                if merge == header {
+                    self.report(
+                        Rule::StructuredControlFlow,
+                        "Header block can't be its own merge block",
+                        Some(inst),
+                        fid,
+                    );
+                }
+            }
+
+            if let Some(continue_target) = continue_target {
+                if !labels.contains_key(&continue_target) {
+                    self.report(
+                        Rule::StructuredControlFlow,
+                        format!(
+                            "Continue target %{} is not a block of this function",
+                            continue_target
+                        ),
+                        Some(inst),
+                        fid,
+                    );
+                }
+                if Some(continue_target) == merge {
+                    self.report(
+                        Rule::StructuredControlFlow,
+                        "Loop merge block and continue target must differ",
+                        Some(inst),
+                        fid,
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_annotations(&mut self) {
+        let module = self.module;
+        for inst in module.annotations.iter().chain(module.debug_names.iter()) {
+            let Some(target) = inst.operands.get(0).and_then(|o| o.id_ref_any()) else {
+                continue;
+            };
+            let Some(target_inst) = self.defs.get(&target).copied() else {
+                self.report(
+                    Rule::DecorationTarget,
+                    format!("Op{} targets undefined id %{}", inst.class.opname, target),
+                    Some(inst),
+                    None,
+                );
+                continue;
+            };
+
+            match inst.class.opcode {
+                Op::MemberDecorate | Op::MemberName | Op::MemberDecorateString => {
+                    let member = match inst.operands.get(1) {
+                        Some(Operand::LiteralBit32(m)) => *m as usize,
+                        _ => continue,
+                    };
+                    if target_inst.class.opcode != Op::TypeStruct {
+                        self.report(
+                            Rule::DecorationTarget,
+                            format!("Op{} must target a struct type", inst.class.opname),
+                            Some(inst),
+                            None,
+                        );
+                    } else if member >= target_inst.operands.len() {
+                        self.report(
+                            Rule::DecorationTarget,
+                            format!(
+                                "Member index {} is out of range for a struct with {} members",
+                                member,
+                                target_inst.operands.len()
+                            ),
+                            Some(inst),
+                            None,
+                        );
+                    }
+                }
+                Op::Decorate => {
+                    if let Some(Operand::Decoration(dec)) = inst.operands.get(1) {
+                        self.check_decoration(inst, *dec, target_inst);
+                    }
+                }
+                Op::GroupDecorate | Op::GroupMemberDecorate => {
+                    if target_inst.class.opcode != Op::DecorationGroup {
+                        self.report(
+                            Rule::DecorationTarget,
+                            format!("Op{} must use a decoration group", inst.class.opname),
+                            Some(inst),
+                            None,
+                        );
+                    }
+                    for decorated in inst.operands[1..].iter().filter_map(|o| o.id_ref_any()) {
+                        if !self.defs.contains_key(&decorated) {
+                            self.report(
+                                Rule::DecorationTarget,
+                                format!("Decoration group applied to undefined id %{}", decorated),
+                                Some(inst),
+                                None,
+                            );
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn check_decoration(
+        &mut self,
+        inst: &Instruction,
+        decoration: Decoration,
+        target: &Instruction,
+    ) {
+        let target_op = target.class.opcode;
+        let valid = match decoration {
+            Decoration::Block | Decoration::BufferBlock => target_op == Op::TypeStruct,
+            Decoration::ArrayStride => matches!(
+                target_op,
+                Op::TypeArray | Op::TypeRuntimeArray | Op::TypePointer
+            ),
+            Decoration::SpecId => matches!(
+                target_op,
+                Op::SpecConstant | Op::SpecConstantTrue | Op::SpecConstantFalse
+            ),
+            Decoration::NonUniform => !reflect::is_type(target_op) && target_op != Op::Label,
+            Decoration::DescriptorSet | Decoration::Binding | Decoration::Location => {
+                target_op == Op::Variable
+            }
+            _ => true,
+        };
+
+        if !valid {
+            self.report(
+                Rule::DecorationTarget,
+                format!(
+                    "{:?} decoration can't be applied to Op{}",
+                    decoration, target.class.opname
+                ),
+                Some(inst),
+                None,
+            );
+        }
+    }
+
+    fn check_entry_points(&mut self) {
+        let module = self.module;
+        for inst in &module.entry_points {
+            if let Some(Operand::IdRef(f)) = inst.operands.get(1) {
+                if !self.functions.contains_key(f) {
+                    self.report(
+                        Rule::IdDefinition,
+                        format!("Entry point function %{} is not defined", f),
+                        Some(inst),
+                        None,
+                    );
+                }
+            }
+            for iface in inst.operands.iter().skip(3).filter_map(|o| o.id_ref_any()) {
+                if self.opcode_of(iface) != Some(Op::Variable) {
+                    self.report(
+                        Rule::IdDefinition,
+                        format!("Entry point interface %{} is not a global variable", iface),
+                        Some(inst),
+                        None,
+                    );
+                }
+            }
+        }
+        for inst in &module.execution_modes {
+            if let Some(Operand::IdRef(f)) = inst.operands.get(0) {
+                if !self.functions.contains_key(f) {
+                    self.report(
+                        Rule::IdDefinition,
+                        format!("Execution mode targets %{}, which is not a function", f),
+                        Some(inst),
+                        None,
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use spv_patcher::{
+    asm::{assemble, disassemble, Disassembler},
+    rspirv::{
+        dr::Operand,
+        spirv::{ExecutionModel, Op},
+    },
+    Module,
+};
+
+const COMPUTE: &str = r#"
+; SPIR-V
+; Version: 1.3
+               OpCapability Shader
+          %1 = OpExtInstImport "GLSL.std.450"
+               OpMemoryModel Logical GLSL450
+               OpEntryPoint GLCompute %main "main"
+               OpExecutionMode %main LocalSize 64 1 1
+               OpName %main "main"
+               OpName %v "v"
+       %void = OpTypeVoid
+          %3 = OpTypeFunction %void
+      %float = OpTypeFloat 32
+        %int = OpTypeInt 32 1
+%_ptr_Function_float = OpTypePointer Function %float
+  %float_0_5 = OpConstant %float 0.5
+     %int_n2 = OpConstant %int -2
+  %float_nan = OpConstant %float 0x1.800000p+128
+       %main = OpFunction %void None %3
+          %5 = OpLabel
+          %v = OpVariable %_ptr_Function_float Function
+               OpStore %v %float_0_5 Aligned 4
+         %10 = OpLoad %float %v
+         %11 = OpExtInst %float %1 Sqrt %10
+               OpSelectionMerge %merge None
+               OpSwitch %int_n2 %merge -2 %case
+       %case = OpLabel
+               OpBranch %merge
+      %merge = OpLabel
+               OpReturn
+               OpFunctionEnd
+"#;
+
+#[test]
+fn assemble_text() {
+    let module = Module::from_assembly(COMPUTE).unwrap();
+    let ep = module.entry_point("main", None).unwrap();
+    assert_eq!(ep.execution_model, ExecutionModel::GLCompute);
+
+    let spv = module.spirv();
+    assert_eq!(spv.header.as_ref().unwrap().version(), (1, 3));
+
+    let constants: Vec<&Operand> = spv
+        .types_global_values
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::Constant)
+        .map(|inst| &inst.operands[0])
+        .collect();
+    assert_eq!(
+        constants,
+        vec![
+            &Operand::LiteralBit32(0.5f32.to_bits()),
+            &Operand::LiteralBit32(-2i32 as u32),
+            &Operand::LiteralBit32(0x7fc0_0000),
+        ]
+    );
+}
+
+#[test]
+fn disassemble_friendly_names() {
+    let module = Module::from_assembly(COMPUTE).unwrap();
+    let text = Disassembler::default().disassemble(module.spirv());
+
+    assert!(
+        text.contains("OpEntryPoint GLCompute %main \"main\""),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("%float_0_5 = OpConstant %float 0.5"),
+        "{}",
+        text
+    );
+    assert!(text.contains("%int_n2 = OpConstant %int -2"), "{}", text);
+    assert!(
+        text.contains("OpConstant %float 0x1.800000p+128"),
+        "{}",
+        text
+    );
+    assert!(text.contains("OpExtInst %float %1 Sqrt"), "{}", text);
+    assert!(text.contains("OpSwitch %int_n2 %"), "{}", text);
+    assert!(text.contains("OpStore %v %float_0_5 Aligned 4"), "{}", text);
+}
+
+#[test]
+fn roundtrip_glslang_shader() {
+    let module = Module::new(
+        include_bytes!("../../patch-strip-debug/tests/big_debug_shader.spirv").to_vec(),
+    )
+    .unwrap();
+
+    //Raw ids are kept by the assembler, so the text has to be reproduced exactly. The header is skipped, since
+    // generator and bound are not preserved.
+    let raw = Disassembler {
+        friendly_names: false,
+        header: false,
+        ..Default::default()
+    };
+    let text = raw.disassemble(module.spirv());
+    assert_eq!(text, raw.disassemble(&assemble(&text).unwrap()));
+
+    //Friendly names are renumbered, but must describe the same module.
+    let friendly = disassemble(module.spirv());
+    let reassembled = assemble(&friendly).unwrap();
+    assert_eq!(
+        module.spirv().all_inst_iter().count(),
+        reassembled.all_inst_iter().count()
+    );
+}
+
+#[test]
+fn assembler_errors() {
+    let err = assemble("OpCapability NotACapability").unwrap_err();
+    assert!(err.to_string().contains("line 1"), "{}", err);
+
+    let err = assemble("%x = OpTypeVoid\n%y = OpTypePointer Function %missing").unwrap_err();
+    assert!(err.to_string().contains("%missing"), "{}", err);
+}
@@ -0,0 +1,98 @@
+use spv_patcher::{analysis::CallGraph, spirv_ext::SpirvExt, Module};
+
+//Two entry points sharing a helper, plus an (invalid, but well-formed) recursive pair only reachable from `b`.
+const SHADER: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %a "a"
+OpEntryPoint GLCompute %b "b"
+OpExecutionMode %a LocalSize 1 1 1
+OpExecutionMode %b LocalSize 1 1 1
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%shared = OpFunction %void None %fn
+%l0 = OpLabel
+OpReturn
+OpFunctionEnd
+%ping = OpFunction %void None %fn
+%l1 = OpLabel
+%c0 = OpFunctionCall %void %pong
+OpReturn
+OpFunctionEnd
+%pong = OpFunction %void None %fn
+%l2 = OpLabel
+%c1 = OpFunctionCall %void %ping
+OpReturn
+OpFunctionEnd
+%a = OpFunction %void None %fn
+%l3 = OpLabel
+%c2 = OpFunctionCall %void %shared
+%c3 = OpFunctionCall %void %shared
+OpReturn
+OpFunctionEnd
+%b = OpFunction %void None %fn
+%l4 = OpLabel
+%c4 = OpFunctionCall %void %shared
+%c5 = OpFunctionCall %void %ping
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn callers_callees_and_reachability() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let spv = module.spirv();
+    let graph = CallGraph::new(spv);
+    let [shared, ping, pong, a, b] = [0, 1, 2, 3, 4].map(|i| spv.functions[i].def_id().unwrap());
+
+    assert_eq!(graph.functions(), &[shared, ping, pong, a, b]);
+    assert_eq!(graph.entry_points(), &[a, b]);
+    //Repeated calls are only listed once
+    assert_eq!(graph.callees(a), &[shared]);
+    assert_eq!(graph.callees(b), &[shared, ping]);
+    assert_eq!(graph.callers(shared), &[a, b]);
+    assert_eq!(graph.callers(ping), &[pong, b]);
+    assert!(graph.callers(a).is_empty());
+
+    assert_eq!(graph.entry_points_reaching(shared).as_slice(), &[a, b]);
+    assert_eq!(graph.entry_points_reaching(pong).as_slice(), &[b]);
+    assert!(graph.reachable_from(b).contains(&pong));
+    assert!(!graph.reachable_from(a).contains(&ping));
+    assert_eq!(spv.reachable_functions(a), graph.reachable_from(a));
+
+    assert!(graph.is_recursive(ping));
+    assert!(graph.is_recursive(pong));
+    assert!(!graph.is_recursive(b));
+    assert!(graph.has_recursion());
+}
+
+//A long call chain `f0 -> f1 -> ...` whose last function calls itself.
+fn chain(length: usize) -> String {
+    let mut asm = String::from(
+        "OpCapability Shader\nOpMemoryModel Logical GLSL450\nOpEntryPoint GLCompute %f0 \"main\"\nOpExecutionMode %f0 LocalSize 1 1 1\n%void = OpTypeVoid\n%fn = OpTypeFunction %void\n",
+    );
+    for idx in 0..length {
+        let callee = (idx + 1).min(length - 1);
+        asm.push_str(&format!(
+            "%f{idx} = OpFunction %void None %fn\n%l{idx} = OpLabel\n%c{idx} = OpFunctionCall %void %f{callee}\nOpReturn\nOpFunctionEnd\n"
+        ));
+    }
+    asm
+}
+
+#[test]
+fn recursion_in_deep_chain() {
+    let module = Module::from_assembly(&chain(10_000)).unwrap();
+    let spv = module.spirv();
+    let graph = CallGraph::new(spv);
+    let ids = spv
+        .functions
+        .iter()
+        .map(|f| f.def_id().unwrap())
+        .collect::<Vec<_>>();
+
+    assert!(graph.has_recursion());
+    assert!(graph.is_recursive(ids[9_999]));
+    assert!(ids[..9_999].iter().all(|f| !graph.is_recursive(*f)));
+    assert_eq!(graph.reachable_from(ids[0]).len(), 10_000);
+}
@@ -0,0 +1,79 @@
+use spv_patcher::{
+    analysis::{Cfg, ConstructKind, DominatorTree, LoopForest},
+    Module,
+};
+
+//Loop with a selection in its body. Block indices are noted behind the labels.
+const LOOP: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%bool = OpTypeBool
+%true = OpConstantTrue %bool
+%main = OpFunction %void None %fn
+%entry = OpLabel            ; 0
+OpBranch %header
+%header = OpLabel           ; 1
+OpLoopMerge %merge %continue None
+OpBranchConditional %true %body %merge
+%body = OpLabel             ; 2
+OpSelectionMerge %join None
+OpBranchConditional %true %then %join
+%then = OpLabel             ; 3
+OpBranch %join
+%join = OpLabel             ; 4
+OpBranch %continue
+%continue = OpLabel         ; 5
+OpBranch %header
+%merge = OpLabel            ; 6
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn loop_with_selection() {
+    let module = Module::from_assembly(LOOP).unwrap();
+    let cfg = Cfg::new(&module.spirv().functions[0]);
+
+    assert_eq!(cfg.len(), 7);
+    assert_eq!(cfg.successors(1), &[2, 6]);
+    assert_eq!(cfg.predecessors(1), &[0, 5]);
+    assert_eq!(cfg.exits().collect::<Vec<_>>(), vec![6]);
+    assert_eq!(cfg.reverse_post_order()[0], 0);
+
+    let header = cfg.construct_of_header(1).unwrap();
+    assert_eq!(header.merge, 6);
+    assert_eq!(header.kind, ConstructKind::Loop { continue_target: 5 });
+    let selection = cfg.construct_of_header(2).unwrap();
+    assert_eq!(selection.kind, ConstructKind::Selection);
+
+    let dom = DominatorTree::dominators(&cfg);
+    assert_eq!(dom.idom(0), None);
+    assert_eq!(dom.idom(4), Some(2));
+    assert_eq!(dom.idom(6), Some(1));
+    assert!(dom.dominates(1, 5));
+    assert!(!dom.dominates(2, 6));
+    assert!(selection.contains(3, &dom));
+    assert!(!selection.contains(4, &dom));
+
+    let post_dom = DominatorTree::post_dominators(&cfg);
+    assert_eq!(post_dom.roots(), &[6]);
+    assert_eq!(post_dom.idom(0), Some(1));
+    assert_eq!(post_dom.idom(2), Some(4));
+    assert_eq!(post_dom.idom(1), Some(6));
+    assert!(post_dom.dominates(5, 3));
+
+    let loops = LoopForest::new(&cfg, &dom);
+    assert_eq!(loops.loops().len(), 1);
+    let l = loops.loop_of_header(1).unwrap();
+    assert_eq!(l.blocks, vec![1, 2, 3, 4, 5]);
+    assert_eq!(l.latches.as_slice(), &[5]);
+    assert_eq!(l.exits.as_slice(), &[6]);
+    assert_eq!(l.merge, Some(6));
+    assert_eq!(l.continue_target, Some(5));
+    assert_eq!(loops.depth(3), 1);
+    assert_eq!(loops.depth(6), 0);
+}
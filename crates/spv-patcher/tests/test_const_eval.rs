@@ -0,0 +1,82 @@
+use spv_patcher::{
+    analysis::{ConstEvaluator, ConstValue},
+    spirv_ext::SpirvExt,
+    Module,
+};
+
+//Arrays whose zero values are too large to be expanded, next to a small one.
+const SHADER: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %uint "uint"
+OpName %huge "huge"
+OpName %nested "nested"
+OpName %small "small"
+OpName %null_huge "null_huge"
+OpName %null_nested "null_nested"
+OpName %null_small "null_small"
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%uint_2 = OpConstant %uint 2
+%uint_65536 = OpConstant %uint 65536
+%uint_max = OpConstant %uint 4294967295
+%huge = OpTypeArray %uint %uint_max
+%inner = OpTypeArray %uint %uint_65536
+%nested = OpTypeArray %inner %uint_65536
+%small = OpTypeArray %uint %uint_2
+%null_huge = OpConstantNull %huge
+%null_nested = OpConstantNull %nested
+%null_small = OpConstantNull %small
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn huge_arrays() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let mut evaluator = ConstEvaluator::new(&spv);
+    let [uint, huge, nested, small, null_huge, null_nested, null_small] = [
+        "uint",
+        "huge",
+        "nested",
+        "small",
+        "null_huge",
+        "null_nested",
+        "null_small",
+    ]
+    .map(|name| spv.get_by_name(name).unwrap().result_id.unwrap());
+
+    //The array types are known without being expanded
+    assert_eq!(evaluator.member_count(huge), Some(u32::MAX as u64));
+    assert_eq!(evaluator.member_type(huge, 1 << 20), Some(uint));
+    assert_eq!(evaluator.member_count(nested), Some(65536));
+
+    //Zero values are only built for small types
+    assert!(evaluator.value(null_huge).is_none());
+    assert!(evaluator.value(null_nested).is_none());
+    assert!(evaluator.zero(nested).is_none());
+    let zero = evaluator.zero(uint).unwrap();
+    assert_eq!(
+        evaluator.value(null_small),
+        Some(&ConstValue::Composite(vec![zero.clone(), zero.clone()]))
+    );
+
+    //A composite of the wrong length is refused instead of declared
+    let count = spv.types_global_values.len();
+    assert_eq!(
+        evaluator.find_or_insert(&mut spv, huge, &ConstValue::Composite(vec![zero; 2])),
+        None
+    );
+    assert_eq!(spv.types_global_values.len(), count);
+    let zero_small = evaluator.zero(small).unwrap();
+    assert_eq!(
+        evaluator.find_or_insert(&mut spv, small, &zero_small),
+        Some(null_small)
+    );
+}
@@ -0,0 +1,190 @@
+use spv_patcher::{
+    analysis::{InstLocation, Section, UseSlot},
+    rspirv::spirv::Op,
+    spirv_ext::SpirvExt,
+    Module,
+};
+
+const SHADER: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %unused "unused"
+OpDecorate %unused RelaxedPrecision
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%uint_1 = OpConstant %uint 1
+%uint_2 = OpConstant %uint 2
+%unused = OpConstant %uint 3
+%main = OpFunction %void None %fn
+%entry = OpLabel
+%x = OpIAdd %uint %uint_1 %uint_1
+%y = OpIMul %uint %x %uint_1
+OpReturn
+OpFunctionEnd
+"#;
+
+fn id_of(module: &spv_patcher::rspirv::dr::Module, op: Op) -> u32 {
+    module
+        .all_inst_iter()
+        .find(|inst| inst.class.opcode == op)
+        .and_then(|inst| inst.result_id)
+        .unwrap()
+}
+
+#[test]
+fn users_and_definitions() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut patcher = module.patch();
+    let (spv, def_use) = patcher.ir_state.def_use();
+
+    let x = id_of(spv, Op::IAdd);
+    let def = def_use.definition_of(x).unwrap();
+    assert!(matches!(
+        def,
+        InstLocation::Block {
+            function: 0,
+            block: 0,
+            index: 0
+        }
+    ));
+    assert_eq!(def.get(spv).unwrap().class.opcode, Op::IAdd);
+
+    let users = def_use.users_of(x);
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].slot, UseSlot::Operand(0));
+
+    let uint_1 = spv.types_global_values[3].result_id.unwrap();
+    assert_eq!(def_use.users_of(uint_1).len(), 3);
+}
+
+#[test]
+fn replace_and_kill() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut patcher = module.patch();
+    let (spv, def_use) = patcher.ir_state.def_use_mut();
+
+    let uint_1 = spv.types_global_values[3].result_id.unwrap();
+    let uint_2 = spv.types_global_values[4].result_id.unwrap();
+    let unused = spv.types_global_values[5].result_id.unwrap();
+
+    assert_eq!(def_use.replace_all_uses(spv, uint_1, uint_2), 3);
+    assert!(def_use.users_of(uint_1).is_empty());
+    assert_eq!(def_use.users_of(uint_2).len(), 3);
+
+    def_use.kill(spv, unused);
+    assert!(def_use.definition_of(unused).is_none());
+    assert!(def_use.has_killed());
+    //Killed instructions are kept as OpNop until the module is handed out
+    assert_eq!(spv.annotations[0].class.opcode, Op::Nop);
+
+    //Reading the module compacts it
+    let spv = patcher.ir_state.spirv();
+    assert!(spv.annotations.is_empty());
+    assert!(spv.debug_names.is_empty());
+    assert!(spv
+        .all_inst_iter()
+        .all(|inst| inst.result_id != Some(unused)));
+    assert!(spv.all_inst_iter().all(|inst| inst.class.opcode != Op::Nop));
+
+    //The index was rebuilt and is still cached
+    let (spv, def_use) = patcher.ir_state.def_use();
+    let def = def_use.definition_of(uint_2).unwrap();
+    assert_eq!(
+        def,
+        InstLocation::Global {
+            section: Section::TypesGlobalValues,
+            index: 4
+        }
+    );
+    assert_eq!(def.get(spv).unwrap().result_id, Some(uint_2));
+}
+
+//`%dead` is never branched to, `%helper` is never called and `%add` ignores its second parameter.
+const FUNCTIONS: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %helper "helper"
+OpName %dead "dead"
+OpName %b "b"
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%add_fn = OpTypeFunction %uint %uint %uint
+%uint_1 = OpConstant %uint 1
+%helper = OpFunction %void None %fn
+%helper_entry = OpLabel
+%h = OpIAdd %uint %uint_1 %uint_1
+OpReturn
+OpFunctionEnd
+%add = OpFunction %uint None %add_fn
+%a = OpFunctionParameter %uint
+%b = OpFunctionParameter %uint
+%add_entry = OpLabel
+OpReturnValue %a
+OpFunctionEnd
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+%dead = OpLabel
+%d = OpIMul %uint %uint_1 %uint_1
+OpReturn
+OpFunctionEnd
+"#;
+
+fn killed(name: &str) -> spv_patcher::rspirv::dr::Module {
+    let module = Module::from_assembly(FUNCTIONS).unwrap();
+    let mut patcher = module.patch();
+    let (spv, def_use) = patcher.ir_state.def_use_mut();
+    let id = spv.get_by_name(name).unwrap().result_id.unwrap();
+    def_use.kill(spv, id);
+    patcher.ir_state.spirv().clone()
+}
+
+fn has_nop(spv: &spv_patcher::rspirv::dr::Module) -> bool {
+    spv.all_inst_iter().any(|inst| inst.class.opcode == Op::Nop)
+        || spv.functions.iter().any(|f| {
+            f.def
+                .as_ref()
+                .map(|d| d.class.opcode == Op::Nop)
+                .unwrap_or(false)
+                || f.blocks.iter().any(|b| {
+                    b.label
+                        .as_ref()
+                        .map(|l| l.class.opcode == Op::Nop)
+                        .unwrap_or(false)
+                })
+        })
+}
+
+#[test]
+fn kill_function() {
+    let spv = killed("helper");
+    assert_eq!(spv.functions.len(), 2);
+    assert!(spv
+        .all_inst_iter()
+        .all(|inst| inst.class.opcode != Op::IAdd));
+    assert!(!has_nop(&spv));
+}
+
+#[test]
+fn kill_parameter() {
+    let spv = killed("b");
+    assert_eq!(spv.functions[1].parameters.len(), 1);
+    assert_eq!(spv.functions[1].blocks.len(), 1);
+    assert!(!has_nop(&spv));
+}
+
+#[test]
+fn kill_block() {
+    let spv = killed("dead");
+    assert_eq!(spv.functions[2].blocks.len(), 1);
+    assert!(spv
+        .all_inst_iter()
+        .all(|inst| inst.class.opcode != Op::IMul));
+    assert!(!has_nop(&spv));
+}
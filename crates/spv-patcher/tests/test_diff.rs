@@ -0,0 +1,110 @@
+use spv_patcher::{
+    asm::assemble,
+    diff::{Change, ItemKind},
+    Module,
+};
+
+const TEMPLATE: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%uint_1 = OpConstant %uint 1
+%main = OpFunction %void None %fn
+%entry = OpLabel
+%x = OpIAdd %uint %uint_1 %uint_1
+%y = OpIMul %uint %x %x
+OpReturn
+OpFunctionEnd
+"#;
+
+//Same module, but with different ids.
+const RENUMBERED: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %10 "main"
+OpExecutionMode %10 LocalSize 1 1 1
+%7 = OpTypeVoid
+%3 = OpTypeFunction %7
+%20 = OpTypeInt 32 0
+%4 = OpConstant %20 1
+%10 = OpFunction %7 None %3
+%2 = OpLabel
+%9 = OpIAdd %20 %4 %4
+%8 = OpIMul %20 %9 %9
+OpReturn
+OpFunctionEnd
+"#;
+
+const PATCHED: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+%float = OpTypeFloat 32
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%uint_2 = OpConstant %uint 2
+%main = OpFunction %void None %fn
+%entry = OpLabel
+%x = OpIAdd %uint %uint_2 %uint_2
+%y = OpIMul %uint %x %x
+OpReturn
+OpFunctionEnd
+"#;
+
+fn template() -> Module {
+    Module::from_assembly(TEMPLATE).unwrap()
+}
+
+#[test]
+fn renumbering_is_no_change() {
+    let module = template();
+    assert!(module.diff(module.spirv()).is_empty());
+
+    let diff = module.diff(&assemble(RENUMBERED).unwrap());
+    assert!(diff.is_empty(), "{}", diff);
+}
+
+#[test]
+fn structural_changes() {
+    let module = template();
+    let diff = module.diff(&assemble(PATCHED).unwrap());
+
+    let types: Vec<_> = diff.of_kind(ItemKind::Type).collect();
+    assert_eq!(types.len(), 1, "{}", diff);
+    assert_eq!(
+        types[0].change,
+        Change::Added {
+            after: String::from("OpTypeFloat 32")
+        }
+    );
+
+    let constants: Vec<_> = diff.of_kind(ItemKind::Constant).collect();
+    assert_eq!(constants.len(), 2, "{}", diff);
+    assert!(matches!(constants[0].change, Change::Removed { .. }));
+    assert!(matches!(constants[1].change, Change::Added { .. }));
+
+    let blocks: Vec<_> = diff.of_kind(ItemKind::Block).collect();
+    assert_eq!(blocks.len(), 1, "{}", diff);
+    assert_eq!(blocks[0].name, "main: block 0");
+
+    assert_eq!(diff.of_kind(ItemKind::ModeSetting).count(), 0, "{}", diff);
+    assert_eq!(diff.of_kind(ItemKind::Function).count(), 0, "{}", diff);
+
+    let text = diff.to_string();
+    assert!(text.contains("~ block main: block 0"), "{}", text);
+    assert!(
+        text.contains("+ %1 = OpIAdd (OpTypeInt 32 0) (OpConstant (OpTypeInt 32 0) 2)"),
+        "{}",
+        text
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&diff.to_json()).unwrap();
+    assert_eq!(json["items"][0]["kind"], "type");
+    assert_eq!(json["items"][0]["change"], "added");
+}
@@ -0,0 +1,214 @@
+use spv_patcher::{
+    rspirv::{
+        binary::Assemble,
+        dr::{Builder, Operand},
+        spirv::{
+            self, AddressingModel, BuiltIn, Capability, Decoration, ExecutionMode, ExecutionModel,
+            FunctionControl, StorageClass,
+        },
+    },
+    Module, PatcherError,
+};
+
+//Both stages are called `main`. `shared` is called by both, `helper` only by the fragment stage.
+struct Stages {
+    module: Module,
+    vert: u32,
+    frag: u32,
+    comp: u32,
+    shared: u32,
+    helper: u32,
+    position: u32,
+    color: u32,
+}
+
+fn stages() -> Stages {
+    let mut b = Builder::new();
+    b.capability(Capability::Shader);
+    b.memory_model(AddressingModel::Logical, spirv::MemoryModel::GLSL450);
+    let void = b.type_void();
+    let fn_ty = b.type_function(void, vec![]);
+    let float = b.type_float(32);
+    let v4float = b.type_vector(float, 4);
+    let ptr_out = b.type_pointer(None, StorageClass::Output, v4float);
+    let position = b.variable(ptr_out, None, StorageClass::Output, None);
+    let color = b.variable(ptr_out, None, StorageClass::Output, None);
+    b.decorate(
+        position,
+        Decoration::BuiltIn,
+        [Operand::BuiltIn(BuiltIn::Position)],
+    );
+    b.decorate(color, Decoration::Location, [Operand::LiteralInt32(0)]);
+
+    //Function that calls `callees`
+    let function = |b: &mut Builder, callees: &[u32]| {
+        let f = b
+            .begin_function(void, None, FunctionControl::NONE, fn_ty)
+            .unwrap();
+        b.begin_block(None).unwrap();
+        for callee in callees {
+            b.function_call(void, None, *callee, []).unwrap();
+        }
+        b.ret().unwrap();
+        b.end_function().unwrap();
+        f
+    };
+    let shared = function(&mut b, &[]);
+    let helper = function(&mut b, &[shared]);
+    let vert = function(&mut b, &[shared]);
+    let frag = function(&mut b, &[helper]);
+    let comp = function(&mut b, &[]);
+
+    b.entry_point(ExecutionModel::Vertex, vert, "main", [position]);
+    b.entry_point(ExecutionModel::Fragment, frag, "main", [color]);
+    let interface: [u32; 0] = [];
+    b.entry_point(ExecutionModel::GLCompute, comp, "compute", interface);
+    b.execution_mode(frag, ExecutionMode::OriginUpperLeft, []);
+    b.execution_mode(comp, ExecutionMode::LocalSize, [4, 2, 1]);
+
+    let bytes = b
+        .module()
+        .assemble()
+        .into_iter()
+        .flat_map(|w| w.to_le_bytes())
+        .collect();
+    Stages {
+        module: Module::new(bytes).unwrap(),
+        vert,
+        frag,
+        comp,
+        shared,
+        helper,
+        position,
+        color,
+    }
+}
+
+#[test]
+fn entry_points() {
+    let stages = stages();
+    let module = &stages.module;
+    let entry_points = module
+        .entry_points()
+        .iter()
+        .map(|ep| {
+            (
+                ep.name.as_str(),
+                ep.execution_model,
+                ep.function_id,
+                ep.interface.to_vec(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        entry_points,
+        [
+            (
+                "main",
+                ExecutionModel::Vertex,
+                stages.vert,
+                vec![stages.position]
+            ),
+            (
+                "main",
+                ExecutionModel::Fragment,
+                stages.frag,
+                vec![stages.color]
+            ),
+            ("compute", ExecutionModel::GLCompute, stages.comp, vec![]),
+        ]
+    );
+
+    let modes = |index: usize| {
+        module.entry_points()[index]
+            .execution_modes
+            .iter()
+            .map(|(mode, operands)| (*mode, operands.len()))
+            .collect::<Vec<_>>()
+    };
+    assert!(modes(0).is_empty());
+    assert_eq!(modes(1), [(ExecutionMode::OriginUpperLeft, 0)]);
+    assert_eq!(modes(2), [(ExecutionMode::LocalSize, 3)]);
+}
+
+#[test]
+fn entry_point_ambiguity() {
+    let stages = stages();
+    let module = &stages.module;
+    assert!(matches!(
+        module.entry_point("main", None),
+        Err(PatcherError::AmbiguousEntryPoint(name)) if name == "main"
+    ));
+    assert!(matches!(
+        module.entry_point("main", Some(ExecutionModel::GLCompute)),
+        Err(PatcherError::UnknownEntryPoint(_))
+    ));
+    assert!(matches!(
+        module.entry_point("missing", None),
+        Err(PatcherError::UnknownEntryPoint(name)) if name == "missing"
+    ));
+
+    let fragment = module
+        .entry_point("main", Some(ExecutionModel::Fragment))
+        .unwrap();
+    assert_eq!(fragment.function_id, stages.frag);
+    //Unique names don't need an execution model
+    let compute = module.entry_point("compute", None).unwrap();
+    assert_eq!(compute.execution_model, ExecutionModel::GLCompute);
+}
+
+#[test]
+fn patch_entry_point() {
+    let stages = stages();
+    let module = &stages.module;
+    assert!(matches!(
+        module.patch_entry_point("main", None),
+        Err(PatcherError::AmbiguousEntryPoint(_))
+    ));
+    assert!(matches!(
+        module.patch_entry_point("missing", None),
+        Err(PatcherError::UnknownEntryPoint(_))
+    ));
+
+    let mut patcher = module
+        .patch_entry_point("main", Some(ExecutionModel::Fragment))
+        .unwrap();
+    let entry_point = patcher.entry_point.as_ref().unwrap();
+    assert_eq!(entry_point.execution_model, ExecutionModel::Fragment);
+    assert_eq!(patcher.entry_function(), Some(stages.frag));
+
+    let mut unscoped = module.patch();
+    assert!(unscoped.entry_point.is_none());
+    assert_eq!(unscoped.entry_function(), None);
+}
+
+#[test]
+fn patcher_scope() {
+    let stages = stages();
+    let module = &stages.module;
+    let scope = |model: ExecutionModel| {
+        let mut scope = module
+            .patch_entry_point("main", Some(model))
+            .unwrap()
+            .scope()
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>();
+        scope.sort();
+        scope
+    };
+    let sorted = |mut ids: Vec<u32>| {
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(
+        scope(ExecutionModel::Vertex),
+        sorted(vec![stages.vert, stages.shared])
+    );
+    assert_eq!(
+        scope(ExecutionModel::Fragment),
+        sorted(vec![stages.frag, stages.helper, stages.shared])
+    );
+    assert!(module.patch().scope().is_none());
+}
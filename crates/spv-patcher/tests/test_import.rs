@@ -0,0 +1,107 @@
+use spv_patcher::{
+    import::{FunctionImporter, ImportError},
+    rspirv::{
+        dr::{Module as SpvModule, Operand},
+        spirv::Op,
+    },
+    spirv_ext::SpirvExt,
+    verify::Verifier,
+    Module,
+};
+
+const HOST: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%undef = OpUndef %uint
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+const SRC: &str = r#"
+OpCapability Shader
+OpCapability Linkage
+OpMemoryModel Logical GLSL450
+OpName %sum "sum"
+OpName %counter "counter"
+OpDecorate %sum NoContraction
+%uint = OpTypeInt 32 0
+%ptr_priv = OpTypePointer Private %uint
+%add_fn = OpTypeFunction %uint %uint
+%undef = OpUndef %uint
+%counter = OpVariable %ptr_priv Private
+%add = OpFunction %uint None %add_fn
+%a = OpFunctionParameter %uint
+%add_entry = OpLabel
+%sum = OpIAdd %uint %a %undef
+OpReturnValue %sum
+OpFunctionEnd
+%twice = OpFunction %uint None %add_fn
+%b = OpFunctionParameter %uint
+%twice_entry = OpLabel
+%once = OpFunctionCall %uint %add %b
+%again = OpFunctionCall %uint %add %once
+OpReturnValue %again
+OpFunctionEnd
+%count = OpFunction %uint None %add_fn
+%c = OpFunctionParameter %uint
+%count_entry = OpLabel
+%value = OpLoad %uint %counter
+OpReturnValue %value
+OpFunctionEnd
+"#;
+
+#[test]
+fn import_functions() {
+    let mut host = Module::from_assembly(HOST).unwrap().spirv().clone();
+    let src = Module::from_assembly(SRC).unwrap().spirv().clone();
+    let twice = src.functions[1].def_id().unwrap();
+    let undefs = |spv: &SpvModule| {
+        spv.types_global_values
+            .iter()
+            .filter(|inst| inst.class.opcode == Op::Undef)
+            .count()
+    };
+    let host_undefs = undefs(&host);
+
+    let new = FunctionImporter::new(&src, &host)
+        .unwrap()
+        .import_functions(&mut host, twice)
+        .unwrap();
+
+    //`twice` and the `add` it calls are copied, `count` is not
+    assert_eq!(host.functions.len(), 3);
+    assert_eq!(host.functions[2].def_id(), Some(new));
+    let add = host.functions[1].def_id().unwrap();
+    assert!(host.functions[2]
+        .all_inst_iter()
+        .filter(|inst| inst.class.opcode == Op::FunctionCall)
+        .all(|inst| inst.operands[0] == Operand::IdRef(add)));
+    //The host's undef is reused, and names and decorations of locals are copied
+    assert_eq!(undefs(&host), host_undefs);
+    let sum = host.get_by_name("sum").unwrap().result_id.unwrap();
+    assert!(host
+        .annotations
+        .iter()
+        .any(|inst| inst.operands.first() == Some(&Operand::IdRef(sum))));
+
+    Verifier::verify(&host).unwrap();
+}
+
+#[test]
+fn variables_are_not_imported() {
+    let mut host = Module::from_assembly(HOST).unwrap().spirv().clone();
+    let src = Module::from_assembly(SRC).unwrap().spirv().clone();
+    let counter = src.get_by_name("counter").unwrap().result_id.unwrap();
+    let count = src.functions[2].def_id().unwrap();
+    let err = FunctionImporter::new(&src, &host)
+        .unwrap()
+        .import_functions(&mut host, count);
+    assert!(matches!(err, Err(ImportError::Variable(id)) if id == counter));
+}
@@ -0,0 +1,115 @@
+use spv_patcher::{
+    patch::{Invariant, NoDanglingCalls, Patch, Patcher},
+    rspirv::{
+        binary::Assemble,
+        dr::{Builder, Module as SpvModule, Operand},
+        spirv::{AddressingModel, Capability, ExecutionModel, FunctionControl, MemoryModel},
+    },
+    Module, PatcherError,
+};
+
+fn module() -> Module {
+    let mut b = Builder::new();
+    b.capability(Capability::Shader);
+    b.memory_model(AddressingModel::Logical, MemoryModel::GLSL450);
+    let void = b.type_void();
+    let fn_ty = b.type_function(void, vec![]);
+    let f = b
+        .begin_function(void, None, FunctionControl::NONE, fn_ty)
+        .unwrap();
+    b.begin_block(None).unwrap();
+    b.ret().unwrap();
+    b.end_function().unwrap();
+    let interface: [u32; 0] = [];
+    b.entry_point(ExecutionModel::GLCompute, f, "main", interface);
+
+    let bytes = b
+        .module()
+        .assemble()
+        .into_iter()
+        .flat_map(|w| w.to_le_bytes())
+        .collect();
+    Module::new(bytes).unwrap()
+}
+
+//Switches the memory model to Vulkan, and claims it is `claimed` afterwards.
+struct SwitchMemoryModel {
+    claimed: MemoryModel,
+}
+
+impl Patch for SwitchMemoryModel {
+    fn apply<'a>(self, mut patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError> {
+        let spv = patcher.ir_state.as_spirv();
+        spv.memory_model.as_mut().unwrap().operands[1] = Operand::MemoryModel(MemoryModel::Vulkan);
+        Ok(patcher)
+    }
+
+    fn name(&self) -> String {
+        "switch-memory-model".to_owned()
+    }
+
+    fn invariants(&self) -> Vec<Box<dyn Invariant>> {
+        let claimed = self.claimed;
+        let mut invariants: Vec<Box<dyn Invariant>> = vec![Box::new(NoDanglingCalls)];
+        invariants.push(Box::new(move |module: &SpvModule| {
+            let model = memory_model(module);
+            if model == Operand::MemoryModel(claimed) {
+                Ok(())
+            } else {
+                Err(format!("Memory model is {:?}", model))
+            }
+        }));
+        invariants
+    }
+}
+
+fn memory_model(module: &SpvModule) -> Operand {
+    module.memory_model.as_ref().unwrap().operands[1].clone()
+}
+
+#[test]
+fn invariant_holds() {
+    let module = module();
+    let patched = module
+        .patch()
+        .with_verification(true)
+        .patch(SwitchMemoryModel {
+            claimed: MemoryModel::Vulkan,
+        })
+        .unwrap()
+        .unwrap_module();
+    assert_eq!(
+        memory_model(&patched),
+        Operand::MemoryModel(MemoryModel::Vulkan)
+    );
+}
+
+#[test]
+fn invariant_violated() {
+    let module = module();
+    let err = module
+        .patch()
+        .with_verification(true)
+        .patch(SwitchMemoryModel {
+            claimed: MemoryModel::GLSL450,
+        });
+    let Err(PatcherError::VerificationFailed { patch, reason }) = err else {
+        panic!("Patch should violate its invariant");
+    };
+    assert_eq!(patch, "switch-memory-model");
+    assert!(reason.contains("Vulkan"));
+
+    //Not checked if verification is disabled
+    let patched = module
+        .patch()
+        .with_verification(false)
+        .patch(SwitchMemoryModel {
+            claimed: MemoryModel::GLSL450,
+        })
+        .unwrap()
+        .unwrap_module();
+    assert_eq!(
+        memory_model(&patched),
+        Operand::MemoryModel(MemoryModel::Vulkan)
+    );
+}
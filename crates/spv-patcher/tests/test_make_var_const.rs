@@ -0,0 +1,174 @@
+use spv_patcher::{
+    patch::{MakeVarConst, MakeVarConstError, VariableSelector},
+    rspirv::{
+        dr::{Module as SpvModule, Operand},
+        spirv::Op,
+    },
+    spirv_ext::{ConstantValue, SpirvExt},
+    verify::Verifier,
+    Module,
+};
+
+const SHADER: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %count "count"
+OpName %scale "scale"
+OpName %acc "acc"
+OpName %param "param"
+OpName %sum "sum"
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%float = OpTypeFloat 32
+%ptr_fn_uint = OpTypePointer Function %uint
+%ptr_priv_float = OpTypePointer Private %float
+%ptr_priv_uint = OpTypePointer Private %uint
+%uint_1 = OpConstant %uint 1
+%uint_4 = OpConstant %uint 4
+%float_2 = OpConstant %float 2
+%scale = OpVariable %ptr_priv_float Private %float_2
+%param = OpVariable %ptr_priv_uint Private
+%main = OpFunction %void None %fn
+%entry = OpLabel
+%count = OpVariable %ptr_fn_uint Function
+%acc = OpVariable %ptr_fn_uint Function
+OpStore %count %uint_4
+OpStore %acc %uint_1
+%c = OpLoad %uint %count
+%sum = OpIAdd %uint %c %c
+OpStore %acc %sum
+%s = OpLoad %float %scale
+%t = OpFMul %float %s %s
+%p = OpLoad %uint %param
+OpStore %param %p
+OpReturn
+OpFunctionEnd
+"#;
+
+fn count(spv: &SpvModule, op: Op) -> usize {
+    spv.all_inst_iter()
+        .filter(|inst| inst.class.opcode == op)
+        .count()
+}
+
+#[test]
+fn single_store() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let uint_4 = spv
+        .types_global_values
+        .iter()
+        .find(|inst| inst.operands == [Operand::LiteralBit32(4)])
+        .and_then(|inst| inst.result_id)
+        .unwrap();
+    let stores = count(&spv, Op::Store);
+
+    let constant = MakeVarConst::new(VariableSelector::Name("count".to_owned()))
+        .run(&mut spv)
+        .unwrap();
+    assert_eq!(constant, uint_4);
+    assert!(spv.get_by_name("count").is_none());
+    assert_eq!(count(&spv, Op::Store), stores - 1);
+
+    let sum = spv.get_by_name("sum").unwrap();
+    assert_eq!(
+        sum.operands,
+        [Operand::IdRef(constant), Operand::IdRef(constant)]
+    );
+    Verifier::verify(&spv).unwrap();
+}
+
+#[test]
+fn drops_names_and_decorations_of_loads() {
+    let shader = SHADER.replace(
+        "OpName %sum \"sum\"",
+        "OpName %sum \"sum\"\nOpName %c \"loaded\"\nOpDecorate %c RelaxedPrecision",
+    );
+    let module = Module::from_assembly(&shader).unwrap();
+    let mut spv = module.spirv().clone();
+
+    let constant = MakeVarConst::new(VariableSelector::Name("count".to_owned()))
+        .run(&mut spv)
+        .unwrap();
+    assert!(spv.get_by_name("loaded").is_none());
+    assert!(spv.get_name(constant).is_none());
+    assert!(spv.annotations.is_empty());
+    Verifier::verify(&spv).unwrap();
+}
+
+#[test]
+fn initializer() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let scale = spv.get_by_name("scale").unwrap().result_id.unwrap();
+    let constant = MakeVarConst::new(VariableSelector::Id(scale))
+        .run(&mut spv)
+        .unwrap();
+
+    let float_2 = spv
+        .types_global_values
+        .iter()
+        .find(|inst| inst.operands == [Operand::LiteralBit32(2.0f32.to_bits())])
+        .and_then(|inst| inst.result_id);
+    assert_eq!(Some(constant), float_2);
+    let mul = spv
+        .all_inst_iter()
+        .find(|inst| inst.class.opcode == Op::FMul)
+        .unwrap();
+    assert_eq!(
+        mul.operands,
+        [Operand::IdRef(constant), Operand::IdRef(constant)]
+    );
+    Verifier::verify(&spv).unwrap();
+}
+
+#[test]
+fn forced_value() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let loads = count(&spv, Op::Load);
+    let stores = count(&spv, Op::Store);
+
+    //acc is stored twice, and param with a non-constant
+    let err = MakeVarConst::new(VariableSelector::Name("acc".to_owned())).run(&mut spv);
+    assert!(matches!(
+        err,
+        Err(MakeVarConstError::NotStoredOnce { stores: 2, .. })
+    ));
+    let err = MakeVarConst::new(VariableSelector::Name("param".to_owned())).run(&mut spv);
+    assert!(matches!(err, Err(MakeVarConstError::NotAConstant { .. })));
+
+    let constant = MakeVarConst::new(VariableSelector::Name("param".to_owned()))
+        .with_value(ConstantValue::U32(9))
+        .run(&mut spv)
+        .unwrap();
+    assert_eq!(count(&spv, Op::Load), loads - 1);
+    assert_eq!(count(&spv, Op::Store), stores - 1);
+    //The loaded value is stored back, which is removed as well
+    assert!(spv
+        .all_inst_iter()
+        .all(|inst| inst.operands.first() != Some(&Operand::IdRef(constant))));
+    Verifier::verify(&spv).unwrap();
+
+    let err = MakeVarConst::new(VariableSelector::Name("acc".to_owned()))
+        .with_value(ConstantValue::F32(1.0))
+        .run(&mut spv);
+    assert!(matches!(err, Err(MakeVarConstError::TypeMismatch { .. })));
+}
+
+#[test]
+fn rejects_non_variables() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let sum = spv.get_by_name("sum").unwrap().result_id.unwrap();
+    let err = MakeVarConst::new(VariableSelector::Id(sum)).run(&mut spv);
+    assert!(matches!(
+        err,
+        Err(MakeVarConstError::NotAVariable { op: Op::IAdd, .. })
+    ));
+    let err = MakeVarConst::new(VariableSelector::Name("nothing".to_owned())).run(&mut spv);
+    assert!(matches!(err, Err(MakeVarConstError::NoMatch(_))));
+}
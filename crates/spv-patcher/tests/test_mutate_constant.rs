@@ -0,0 +1,192 @@
+use spv_patcher::{
+    analysis::UseSlot,
+    patch::{ConstantSelector, MutateConstant},
+    rspirv::{
+        dr::Operand,
+        spirv::{Op, Word},
+    },
+    spirv_ext::{ConstantValue, SpirvExt},
+    verify::Verifier,
+    Module,
+};
+
+const SHADER: &str = r#"
+OpCapability Shader
+OpCapability Float16
+OpCapability Float64
+OpCapability Int64
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %uint_2 "uint_2"
+OpName %int_2 "int_2"
+OpName %ulong_7 "ulong_7"
+OpName %scale "scale"
+OpName %half_1 "half_1"
+OpName %flag "flag"
+OpName %x "x"
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%int = OpTypeInt 32 1
+%ulong = OpTypeInt 64 0
+%half = OpTypeFloat 16
+%double = OpTypeFloat 64
+%bool = OpTypeBool
+%uint_2 = OpConstant %uint 2
+%int_2 = OpConstant %int 2
+%ulong_7 = OpConstant %ulong 7
+%scale = OpConstant %double 0.5
+%half_1 = OpConstant %half 0x3c00
+%flag = OpConstantTrue %bool
+%main = OpFunction %void None %fn
+%entry = OpLabel
+%x = OpIAdd %uint %uint_2 %uint_2
+%y = OpIMul %int %int_2 %int_2
+%z = OpFMul %double %scale %scale
+%w = OpFAdd %half %half_1 %half_1
+%v = OpLogicalNot %bool %flag
+%l = OpIAdd %ulong %ulong_7 %ulong_7
+OpReturn
+OpFunctionEnd
+"#;
+
+fn value_of(spv: &spv_patcher::rspirv::dr::Module, id: Word) -> (Op, Vec<Operand>) {
+    let inst = spv
+        .types_global_values
+        .iter()
+        .find(|inst| inst.result_id == Some(id))
+        .unwrap();
+    (inst.class.opcode, inst.operands.clone())
+}
+
+#[test]
+fn select_by_value_name_and_id() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let spv = module.spirv();
+    let ulong_7 = spv.get_by_name("ulong_7").unwrap().result_id.unwrap();
+    let flag = spv.get_by_name("flag").unwrap().result_id.unwrap();
+
+    let mut patched = module
+        .patch()
+        .patch(MutateConstant::new(
+            ConstantSelector::Value(ConstantValue::U32(2)),
+            ConstantValue::U32(3),
+        ))
+        .unwrap()
+        .patch(MutateConstant::new(
+            ConstantSelector::Name("scale".to_string()),
+            ConstantValue::F64(2.0),
+        ))
+        .unwrap()
+        .patch(MutateConstant::new(
+            ConstantSelector::Id(ulong_7),
+            ConstantValue::U64(1 << 40),
+        ))
+        .unwrap()
+        .patch(MutateConstant::new(
+            ConstantSelector::Name("half_1".to_string()),
+            ConstantValue::F16(0x4000),
+        ))
+        .unwrap()
+        .patch(MutateConstant::new(
+            ConstantSelector::Id(flag),
+            ConstantValue::Bool(false),
+        ))
+        .unwrap();
+    let spv = patched.ir_state.spirv();
+
+    assert_eq!(
+        value_of(spv, spv.get_by_name("uint_2").unwrap().result_id.unwrap()),
+        (Op::Constant, vec![Operand::LiteralBit32(3)])
+    );
+    //The signed constant has a different type, so it keeps its value
+    assert_eq!(
+        value_of(spv, spv.get_by_name("int_2").unwrap().result_id.unwrap()),
+        (Op::Constant, vec![Operand::LiteralBit32(2)])
+    );
+    assert_eq!(
+        value_of(spv, spv.get_by_name("scale").unwrap().result_id.unwrap()),
+        (Op::Constant, vec![Operand::LiteralBit64(2.0f64.to_bits())])
+    );
+    assert_eq!(
+        value_of(spv, ulong_7),
+        (Op::Constant, vec![Operand::LiteralBit64(1 << 40)])
+    );
+    assert_eq!(
+        value_of(spv, spv.get_by_name("half_1").unwrap().result_id.unwrap()),
+        (Op::Constant, vec![Operand::LiteralBit32(0x4000)])
+    );
+    assert_eq!(value_of(spv, flag), (Op::ConstantFalse, vec![]));
+    Verifier::verify(spv).unwrap();
+}
+
+#[test]
+fn select_single_use() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut patcher = module.patch();
+    let (spv, def_use) = patcher.ir_state.def_use();
+    let uint_2 = spv.get_by_name("uint_2").unwrap().result_id.unwrap();
+    let x = spv.get_by_name("x").unwrap().result_id.unwrap();
+    //The second operand of `%x = OpIAdd %uint %uint_2 %uint_2`
+    let to_mutate = *def_use
+        .users_of(uint_2)
+        .iter()
+        .find(|u| {
+            u.slot == UseSlot::Operand(1)
+                && u.location.get(spv).and_then(|i| i.result_id) == Some(x)
+        })
+        .unwrap();
+
+    let mut patched = patcher
+        .patch(MutateConstant::new(
+            ConstantSelector::Use(to_mutate),
+            ConstantValue::U32(5),
+        ))
+        .unwrap();
+    let spv = patched.ir_state.spirv();
+    let add = to_mutate.location.get(spv).unwrap();
+    let new = add.operands[1].unwrap_id_ref();
+    //Only the selected use changes, the constant itself is untouched
+    assert_eq!(add.operands[0], Operand::IdRef(uint_2));
+    assert_ne!(new, uint_2);
+    assert_eq!(
+        value_of(spv, uint_2),
+        (Op::Constant, vec![Operand::LiteralBit32(2)])
+    );
+    assert_eq!(
+        value_of(spv, new),
+        (Op::Constant, vec![Operand::LiteralBit32(5)])
+    );
+    Verifier::verify(spv).unwrap();
+}
+
+#[test]
+fn mismatches_are_errors() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    //No such constant
+    assert!(module
+        .patch()
+        .patch(MutateConstant::new(
+            ConstantSelector::Value(ConstantValue::U32(42)),
+            ConstantValue::U32(3),
+        ))
+        .is_err());
+    //Wrong type for the selected constant
+    assert!(module
+        .patch()
+        .patch(MutateConstant::new(
+            ConstantSelector::Name("scale".to_string()),
+            ConstantValue::F32(1.0),
+        ))
+        .is_err());
+    //Selected id is not a constant
+    let uint = module.spirv().types_global_values[2].result_id.unwrap();
+    assert!(module
+        .patch()
+        .patch(MutateConstant::new(
+            ConstantSelector::Id(uint),
+            ConstantValue::U32(3),
+        ))
+        .is_err());
+}
@@ -0,0 +1,241 @@
+use spv_patcher::{
+    patch::{FailurePolicy, MemoryModel, Patch, PatchPipeline, Patcher, StepOutcome},
+    rspirv::{
+        binary::Assemble,
+        dr::{Builder, Operand},
+        spirv::{self, AddressingModel, Capability, ExecutionModel, FunctionControl},
+    },
+    Module, PatcherError,
+};
+
+fn module() -> Module {
+    let mut b = Builder::new();
+    b.capability(Capability::Shader);
+    b.memory_model(AddressingModel::Logical, spirv::MemoryModel::GLSL450);
+    let void = b.type_void();
+    let fn_ty = b.type_function(void, vec![]);
+    let f = b
+        .begin_function(void, None, FunctionControl::NONE, fn_ty)
+        .unwrap();
+    b.begin_block(None).unwrap();
+    b.ret().unwrap();
+    b.end_function().unwrap();
+    let interface: [u32; 0] = [];
+    b.entry_point(ExecutionModel::GLCompute, f, "main", interface);
+
+    let bytes = b
+        .module()
+        .assemble()
+        .into_iter()
+        .flat_map(|w| w.to_le_bytes())
+        .collect();
+    Module::new(bytes).unwrap()
+}
+
+fn memory_model(from: spirv::MemoryModel, to: spirv::MemoryModel) -> MemoryModel {
+    MemoryModel {
+        from: (AddressingModel::Logical, from),
+        to: (AddressingModel::Logical, to),
+    }
+}
+
+//First step succeeds, second one expects the wrong source memory model.
+fn failing_pipeline(policy: FailurePolicy) -> PatchPipeline {
+    PatchPipeline::new(policy)
+        .then(memory_model(
+            spirv::MemoryModel::GLSL450,
+            spirv::MemoryModel::Vulkan,
+        ))
+        .then(memory_model(
+            spirv::MemoryModel::GLSL450,
+            spirv::MemoryModel::Simple,
+        ))
+        .then(memory_model(
+            spirv::MemoryModel::Vulkan,
+            spirv::MemoryModel::Simple,
+        ))
+}
+
+fn current_memory_model(module: &spv_patcher::rspirv::dr::Module) -> Operand {
+    module.memory_model.as_ref().unwrap().operands[1].clone()
+}
+
+#[test]
+fn pipeline_rollback() {
+    let module = module();
+    let Err(failure) = failing_pipeline(FailurePolicy::Rollback).run(module.patch()) else {
+        panic!("Pipeline should fail");
+    };
+
+    assert_eq!(failure.step, 1);
+    assert!(failure.reports[0].is_applied());
+    assert!(matches!(failure.reports[2].outcome, StepOutcome::Skipped));
+
+    let patched = failure.patcher.unwrap_module();
+    assert_eq!(
+        current_memory_model(&patched),
+        Operand::MemoryModel(spirv::MemoryModel::GLSL450)
+    );
+}
+
+#[test]
+fn pipeline_keep_partial() {
+    let module = module();
+    let Err(failure) = failing_pipeline(FailurePolicy::KeepPartial).run(module.patch()) else {
+        panic!("Pipeline should fail");
+    };
+
+    assert_eq!(failure.step, 1);
+    assert!(failure.failed_patch().contains("MemoryModel"));
+
+    let patched = failure.patcher.unwrap_module();
+    assert_eq!(
+        current_memory_model(&patched),
+        Operand::MemoryModel(spirv::MemoryModel::Vulkan)
+    );
+}
+
+#[test]
+fn pipeline_success() {
+    let module = module();
+    let pipeline = PatchPipeline::default().then(memory_model(
+        spirv::MemoryModel::GLSL450,
+        spirv::MemoryModel::Vulkan,
+    ));
+    let Ok((patcher, reports)) = pipeline.run(module.patch()) else {
+        panic!("Pipeline should succeed");
+    };
+
+    assert!(reports.iter().all(|r| r.is_applied()));
+    assert_eq!(
+        current_memory_model(&patcher.unwrap_module()),
+        Operand::MemoryModel(spirv::MemoryModel::Vulkan)
+    );
+}
+
+#[test]
+fn ir_state_caching() {
+    let module = module();
+    let mut patcher = module.patch();
+
+    //Reading both representations converts once and keeps both valid.
+    patcher.ir_state.spirt();
+    patcher.ir_state.spirv();
+    patcher.ir_state.spirt();
+    assert!(patcher.ir_state.is_spirv() && patcher.ir_state.is_spirt());
+    assert_eq!(patcher.conversions().lowered, 1);
+    assert_eq!(patcher.conversions().lifted, 0);
+
+    //Mutating SPIR-T dirties SPIR-V, the following read lifts once.
+    patcher.ir_state.as_spirt();
+    assert!(!patcher.ir_state.is_spirv());
+    patcher.ir_state.spirv();
+    patcher.ir_state.spirv();
+    assert_eq!(patcher.conversions().lifted, 1);
+
+    //Sessions share the module's SPIR-T context.
+    assert!(std::rc::Rc::ptr_eq(
+        &patcher.ir_state.context(),
+        &module.patch().ir_state.context()
+    ));
+
+    let (_patcher, reports) = PatchPipeline::default()
+        .then(memory_model(
+            spirv::MemoryModel::GLSL450,
+            spirv::MemoryModel::Simple,
+        ))
+        .run(patcher)
+        .unwrap_or_else(|f| panic!("{}", f.error()));
+    assert_eq!(reports[0].conversions.total(), 0);
+}
+
+//Pushes its value as report.
+struct Reporting(u32);
+
+impl Patch for Reporting {
+    fn apply<'a>(self, mut patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError> {
+        patcher.push_report(self.0);
+        Ok(patcher)
+    }
+}
+
+fn reporting_pipeline(policy: FailurePolicy) -> PatchPipeline {
+    PatchPipeline::new(policy)
+        .then(Reporting(1))
+        .then(memory_model(
+            spirv::MemoryModel::Vulkan,
+            spirv::MemoryModel::Simple,
+        ))
+}
+
+#[test]
+fn pipeline_reports() {
+    let module = module();
+    let mut patcher = module.patch().patch(Reporting(0)).unwrap();
+    patcher.push_report(String::from("other"));
+    let (mut patcher, _) = PatchPipeline::default()
+        .then(Reporting(1))
+        .then(Reporting(2))
+        .run(patcher)
+        .unwrap_or_else(|f| panic!("{}", f.error()));
+    assert_eq!(patcher.report::<u32>(), Some(&2));
+    assert_eq!(patcher.take_reports::<u32>(), [0, 1, 2]);
+    assert_eq!(patcher.report::<u32>(), None);
+    assert_eq!(
+        patcher.report::<String>().map(String::as_str),
+        Some("other")
+    );
+
+    //Rolling back drops the reports of all steps, keeping partial results keeps those of the applied steps
+    let Err(failure) = reporting_pipeline(FailurePolicy::Rollback)
+        .run(module.patch().patch(Reporting(0)).unwrap())
+    else {
+        panic!("Pipeline should fail");
+    };
+    let mut patcher = failure.patcher;
+    assert_eq!(patcher.take_reports::<u32>(), [0]);
+
+    let Err(failure) = reporting_pipeline(FailurePolicy::KeepPartial)
+        .run(module.patch().patch(Reporting(0)).unwrap())
+    else {
+        panic!("Pipeline should fail");
+    };
+    let mut patcher = failure.patcher;
+    assert_eq!(patcher.take_reports::<u32>(), [0, 1]);
+}
+
+//Mutates the module in its SPIR-T form.
+struct SpirtPatch;
+
+impl Patch for SpirtPatch {
+    fn apply<'a>(self, mut patcher: Patcher<'a>) -> Result<Patcher<'a>, PatcherError> {
+        patcher.ir_state.as_spirt();
+        Ok(patcher)
+    }
+}
+
+#[test]
+fn snapshots_do_not_convert() {
+    let module = module();
+    for policy in [FailurePolicy::Rollback, FailurePolicy::KeepPartial] {
+        let Err(failure) = PatchPipeline::new(policy)
+            .then(SpirtPatch)
+            .then(SpirtPatch)
+            .then(memory_model(
+                spirv::MemoryModel::Vulkan,
+                spirv::MemoryModel::Simple,
+            ))
+            .run(module.patch())
+        else {
+            panic!("Pipeline should fail");
+        };
+        assert_eq!(failure.step, 2);
+        //Only the first step lowers, snapshotting the SPIR-T steps never lifts
+        assert_eq!(failure.patcher.conversions().lowered, 1);
+        assert_eq!(failure.patcher.conversions().lifted, 0);
+        assert_eq!(
+            failure.patcher.ir_state.is_spirv(),
+            policy == FailurePolicy::Rollback
+        );
+    }
+}
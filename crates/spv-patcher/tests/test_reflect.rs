@@ -0,0 +1,342 @@
+use spv_patcher::{
+    reflect::{descriptors, Access, DescriptorType, SpecDefault, WorkgroupSize},
+    rspirv::spirv::{BuiltIn, ExecutionModel},
+    Module, PatcherError,
+};
+
+//`unused` is declared but never referenced by the entry point, `store` is written through a helper function.
+const COMPUTE: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main" %gid
+OpExecutionMode %main LocalSize 8 8 1
+OpName %params "params"
+OpName %store "store"
+OpName %image "image"
+OpName %unused "unused"
+OpName %consts "consts"
+OpMemberName %consts_block 0 "scale"
+OpMemberName %consts_block 1 "transform"
+OpMemberName %consts_block 2 "weights"
+OpName %gid "gid"
+OpName %scale "scale"
+OpName %enable "enable"
+OpDecorate %gid BuiltIn GlobalInvocationId
+OpMemberDecorate %params_block 0 Offset 0
+OpDecorate %params_block Block
+OpDecorate %params DescriptorSet 0
+OpDecorate %params Binding 0
+OpDecorate %float_arr ArrayStride 4
+OpMemberDecorate %store_block 0 Offset 0
+OpDecorate %store_block Block
+OpDecorate %store DescriptorSet 0
+OpDecorate %store Binding 1
+OpDecorate %image DescriptorSet 1
+OpDecorate %image Binding 0
+OpDecorate %unused DescriptorSet 2
+OpDecorate %unused Binding 0
+OpDecorate %weights_arr ArrayStride 4
+OpMemberDecorate %consts_block 0 Offset 0
+OpMemberDecorate %consts_block 1 Offset 16
+OpMemberDecorate %consts_block 1 ColMajor
+OpMemberDecorate %consts_block 1 MatrixStride 16
+OpMemberDecorate %consts_block 2 Offset 80
+OpDecorate %consts_block Block
+OpDecorate %scale SpecId 3
+OpDecorate %enable SpecId 7
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%float = OpTypeFloat 32
+%uint = OpTypeInt 32 0
+%int = OpTypeInt 32 1
+%bool = OpTypeBool
+%int_0 = OpConstant %int 0
+%uint_0 = OpConstant %uint 0
+%uint_4 = OpConstant %uint 4
+%float_1 = OpConstant %float 1
+%scale = OpSpecConstant %float 2
+%enable = OpSpecConstantTrue %bool
+%v3uint = OpTypeVector %uint 3
+%v4float = OpTypeVector %float 4
+%mat4 = OpTypeMatrix %v4float 4
+%v2int = OpTypeVector %int 2
+%params_block = OpTypeStruct %v4float
+%ptr_params = OpTypePointer Uniform %params_block
+%float_arr = OpTypeRuntimeArray %float
+%store_block = OpTypeStruct %float_arr
+%ptr_store = OpTypePointer StorageBuffer %store_block
+%ptr_store_float = OpTypePointer StorageBuffer %float
+%ptr_uniform_v4 = OpTypePointer Uniform %v4float
+%img = OpTypeImage %float 2D 0 0 0 2 Rgba8
+%ptr_img = OpTypePointer UniformConstant %img
+%weights_arr = OpTypeArray %float %uint_4
+%consts_block = OpTypeStruct %float %mat4 %weights_arr
+%ptr_consts = OpTypePointer PushConstant %consts_block
+%ptr_consts_float = OpTypePointer PushConstant %float
+%ptr_gid = OpTypePointer Input %v3uint
+%helper_fn = OpTypeFunction %void %ptr_store_float
+%coord = OpConstantNull %v2int
+%params = OpVariable %ptr_params Uniform
+%store = OpVariable %ptr_store StorageBuffer
+%image = OpVariable %ptr_img UniformConstant
+%unused = OpVariable %ptr_img UniformConstant
+%consts = OpVariable %ptr_consts PushConstant
+%gid = OpVariable %ptr_gid Input
+%helper = OpFunction %void None %helper_fn
+%target = OpFunctionParameter %ptr_store_float
+%helper_entry = OpLabel
+OpStore %target %float_1
+OpReturn
+OpFunctionEnd
+%main = OpFunction %void None %fn
+%entry = OpLabel
+%color_ptr = OpAccessChain %ptr_uniform_v4 %params %int_0
+%color = OpLoad %v4float %color_ptr
+%scale_ptr = OpAccessChain %ptr_consts_float %consts %int_0
+%s = OpLoad %float %scale_ptr
+%element = OpAccessChain %ptr_store_float %store %int_0 %uint_0
+%call = OpFunctionCall %void %helper %element
+%handle = OpLoad %img %image
+OpImageWrite %handle %coord %color
+OpReturn
+OpFunctionEnd
+"#;
+
+const FRAGMENT: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint Fragment %main "main" %uv %color %coord
+OpExecutionMode %main OriginUpperLeft
+OpName %uv "uv"
+OpName %color "color"
+OpDecorate %uv Location 1
+OpDecorate %uv Component 2
+OpDecorate %color Location 0
+OpDecorate %coord BuiltIn FragCoord
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%float = OpTypeFloat 32
+%v2float = OpTypeVector %float 2
+%v4float = OpTypeVector %float 4
+%ptr_in_v2 = OpTypePointer Input %v2float
+%ptr_in_v4 = OpTypePointer Input %v4float
+%ptr_out_v4 = OpTypePointer Output %v4float
+%uv = OpVariable %ptr_in_v2 Input
+%coord = OpVariable %ptr_in_v4 Input
+%color = OpVariable %ptr_out_v4 Output
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn reflect_compute() {
+    let module = Module::from_assembly(COMPUTE).unwrap();
+    let reflection = module.reflect("main", None).unwrap();
+    assert_eq!(reflection.execution_model, ExecutionModel::GLCompute);
+
+    let descriptors = reflection
+        .descriptors
+        .iter()
+        .map(|d| {
+            (
+                d.name.as_deref().unwrap(),
+                d.set,
+                d.binding,
+                d.descriptor_type,
+                d.count,
+                d.access,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        descriptors,
+        [
+            (
+                "params",
+                0,
+                0,
+                DescriptorType::UniformBuffer,
+                1,
+                Access {
+                    read: true,
+                    write: false
+                }
+            ),
+            (
+                "store",
+                0,
+                1,
+                DescriptorType::StorageBuffer,
+                1,
+                Access {
+                    read: false,
+                    write: true
+                }
+            ),
+            (
+                "image",
+                1,
+                0,
+                DescriptorType::StorageImage,
+                1,
+                Access {
+                    read: false,
+                    write: true
+                }
+            ),
+        ]
+    );
+
+    let push = reflection.push_constants.as_ref().unwrap();
+    assert_eq!(push.name.as_deref(), Some("consts"));
+    assert_eq!(push.size, 96);
+    let members = push
+        .members
+        .iter()
+        .map(|m| (m.name.as_deref().unwrap(), m.offset, m.size, m.ty.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        members,
+        [
+            ("scale", 0, 4, "f32"),
+            ("transform", 16, 64, "mat4x4<f32>"),
+            ("weights", 80, 16, "[f32; 4]"),
+        ]
+    );
+
+    assert_eq!(reflection.inputs.len(), 1);
+    assert_eq!(
+        reflection.inputs[0].builtin,
+        Some(BuiltIn::GlobalInvocationId)
+    );
+    assert_eq!(reflection.inputs[0].ty, "vec3<u32>");
+    assert!(reflection.outputs.is_empty());
+
+    let specs = reflection
+        .spec_constants
+        .iter()
+        .map(|s| (s.spec_id, s.default))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        specs,
+        [
+            (3, Some(SpecDefault::Float(2.0))),
+            (7, Some(SpecDefault::Bool(true)))
+        ]
+    );
+
+    assert_eq!(
+        reflection.workgroup_size,
+        Some(WorkgroupSize {
+            size: [8, 8, 1],
+            spec_ids: [None; 3]
+        })
+    );
+
+    let json = reflection.to_json();
+    assert!(json.contains("\"descriptor_type\": \"storage_image\""));
+    assert!(json.contains("\"execution_model\": \"GLCompute\""));
+    assert!(json.contains("\"builtin\": \"GlobalInvocationId\""));
+}
+
+#[test]
+fn reflect_stage_interface() {
+    let module = Module::from_assembly(FRAGMENT).unwrap();
+    let reflection = module
+        .reflect("main", Some(ExecutionModel::Fragment))
+        .unwrap();
+    assert!(reflection.descriptors.is_empty());
+    assert!(reflection.push_constants.is_none());
+    assert!(reflection.workgroup_size.is_none());
+
+    let inputs = reflection
+        .inputs
+        .iter()
+        .map(|v| (v.location, v.component, v.builtin, v.ty.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        inputs,
+        [
+            (Some(1), Some(2), None, "vec2<f32>"),
+            (None, None, Some(BuiltIn::FragCoord), "vec4<f32>"),
+        ]
+    );
+    assert_eq!(reflection.outputs.len(), 1);
+    assert_eq!(reflection.outputs[0].name.as_deref(), Some("color"));
+    assert_eq!(reflection.outputs[0].location, Some(0));
+
+    assert!(matches!(
+        module.reflect("main", Some(ExecutionModel::Vertex)),
+        Err(PatcherError::UnknownEntryPoint(_))
+    ));
+}
+
+#[test]
+fn reflect_module_descriptors() {
+    let module = Module::from_assembly(COMPUTE).unwrap();
+    let found = descriptors(module.spirv())
+        .iter()
+        .map(|d| (d.name.clone().unwrap(), d.set, d.binding, d.descriptor_type))
+        .collect::<Vec<_>>();
+    //`unused` is listed as well, push constants are no descriptors
+    assert_eq!(
+        found,
+        [
+            ("params".to_owned(), 0, 0, DescriptorType::UniformBuffer),
+            ("store".to_owned(), 0, 1, DescriptorType::StorageBuffer),
+            ("image".to_owned(), 1, 0, DescriptorType::StorageImage),
+            ("unused".to_owned(), 2, 0, DescriptorType::StorageImage),
+        ]
+    );
+}
+
+#[test]
+fn descriptor_array_counts() {
+    let module = Module::from_assembly(
+        r#"
+OpCapability Shader
+OpCapability RuntimeDescriptorArray
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %grid "grid"
+OpName %bindless "bindless"
+OpDecorate %grid DescriptorSet 0
+OpDecorate %grid Binding 0
+OpDecorate %bindless DescriptorSet 0
+OpDecorate %bindless Binding 1
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%uint_3 = OpConstant %uint 3
+%uint_4 = OpConstant %uint 4
+%sampler = OpTypeSampler
+%row = OpTypeArray %sampler %uint_3
+%rows = OpTypeArray %row %uint_4
+%unbounded = OpTypeRuntimeArray %row
+%ptr_rows = OpTypePointer UniformConstant %rows
+%ptr_unbounded = OpTypePointer UniformConstant %unbounded
+%grid = OpVariable %ptr_rows UniformConstant
+%bindless = OpVariable %ptr_unbounded UniformConstant
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#,
+    )
+    .unwrap();
+    let found = descriptors(module.spirv())
+        .iter()
+        .map(|d| (d.name.clone().unwrap(), d.descriptor_type, d.count))
+        .collect::<Vec<_>>();
+    //A `[4][3]` array holds 12 descriptors, runtime arrays stay unbounded
+    assert_eq!(
+        found,
+        [
+            ("grid".to_owned(), DescriptorType::Sampler, 12),
+            ("bindless".to_owned(), DescriptorType::Sampler, 0),
+        ]
+    );
+}
@@ -0,0 +1,233 @@
+use spv_patcher::{
+    rspirv::{
+        dr::Operand,
+        spirv::{Capability, Decoration, Op, StorageClass},
+    },
+    spirv_ext::{ConstantValue, LineMatcher, SpirvExt, TypeDecoration},
+    verify::Verifier,
+    Module,
+};
+
+const SHADER: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpDecorate %arr_stride ArrayStride 4
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%float = OpTypeFloat 32
+%uint = OpTypeInt 32 0
+%uint_4 = OpConstant %uint 4
+%float_1 = OpConstant %float 1
+%arr_stride = OpTypeArray %float %uint_4
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn find_or_insert_reuses_declarations() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let id_of = |spv: &spv_patcher::rspirv::dr::Module, idx: usize| {
+        spv.types_global_values[idx].result_id.unwrap()
+    };
+    let (float, uint, uint_4, float_1, arr_stride) = (
+        id_of(&spv, 2),
+        id_of(&spv, 3),
+        id_of(&spv, 4),
+        id_of(&spv, 5),
+        id_of(&spv, 6),
+    );
+    let declared = spv.types_global_values.len();
+
+    let f32_operands = [Operand::LiteralBit32(32)];
+    assert_eq!(
+        spv.find_or_insert_type(Op::TypeFloat, &f32_operands, &[]),
+        float
+    );
+    assert_eq!(spv.find_or_insert_constant(ConstantValue::U32(4)), uint_4);
+    assert_eq!(
+        spv.find_or_insert_constant(ConstantValue::F32(1.0)),
+        float_1
+    );
+    assert_eq!(spv.types_global_values.len(), declared);
+
+    //Arrays are only reused if their decorations match
+    let arr_operands = [Operand::IdRef(float), Operand::IdRef(uint_4)];
+    let stride = [TypeDecoration::new(
+        Decoration::ArrayStride,
+        vec![Operand::LiteralBit32(4)],
+    )];
+    assert_eq!(
+        spv.find_type_by_structure(Op::TypeArray, &arr_operands, &stride),
+        Some(arr_stride)
+    );
+    assert_eq!(
+        spv.find_type_by_structure(Op::TypeArray, &arr_operands, &[]),
+        None
+    );
+    let arr = spv.find_or_insert_type(Op::TypeArray, &arr_operands, &[]);
+    assert_ne!(arr, arr_stride);
+    assert_eq!(
+        spv.find_or_insert_type(Op::TypeArray, &arr_operands, &[]),
+        arr
+    );
+
+    //New scalar types are declared once
+    let int = spv.find_or_insert_constant(ConstantValue::I32(-1));
+    let zero = spv.find_or_insert_constant(ConstantValue::I32(0));
+    let int_ty = |id: u32| {
+        spv.types_global_values
+            .iter()
+            .find(|inst| inst.result_id == Some(id))
+            .and_then(|inst| inst.result_type)
+    };
+    assert_eq!(int_ty(int), int_ty(zero));
+    assert_ne!(int_ty(int), Some(uint));
+
+    let t = spv.find_or_insert_constant(ConstantValue::Bool(true));
+    assert_eq!(spv.find_or_insert_constant(ConstantValue::Bool(true)), t);
+    let vec2 = spv.find_or_insert_type(
+        Op::TypeVector,
+        &[Operand::IdRef(float), Operand::LiteralBit32(2)],
+        &[],
+    );
+    let composite = ConstantValue::Composite {
+        ty: vec2,
+        constituents: vec![float_1, float_1],
+    };
+    let c = spv.find_or_insert_constant(composite.clone());
+    assert_eq!(spv.find_or_insert_constant(composite), c);
+    let null = spv.find_or_insert_constant(ConstantValue::Null(vec2));
+    assert_ne!(null, c);
+
+    //arr, int, -1, 0, bool, true, vec2, the composite and null
+    assert_eq!(spv.types_global_values.len(), declared + 9);
+    assert!(spv.header.as_ref().unwrap().bound > null);
+    Verifier::verify(&spv).unwrap();
+}
+
+#[test]
+fn find_or_insert_undef() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let float = spv.types_global_values[2].result_id.unwrap();
+    let uint = spv.types_global_values[3].result_id.unwrap();
+
+    let undef = spv.find_or_insert_undef(float);
+    assert_eq!(spv.find_or_insert_undef(float), undef);
+    assert_ne!(spv.find_or_insert_undef(uint), undef);
+    let undefs = spv
+        .types_global_values
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::Undef)
+        .count();
+    assert_eq!(undefs, 2);
+    Verifier::verify(&spv).unwrap();
+}
+
+#[test]
+fn line_matcher() {
+    let module = Module::from_assembly(
+        r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+%shader = OpString "shaders/guard.comp"
+%other = OpString "shaders/other.comp"
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpLine %shader 7 0
+OpLine %shader 8 0
+OpLine %other 7 0
+OpNoLine
+OpReturn
+OpFunctionEnd
+"#,
+    )
+    .unwrap();
+    let spv = module.spirv();
+    let matched = |file: &str, line: u32| {
+        let matcher = LineMatcher::new(spv, file, line);
+        spv.functions[0].blocks[0]
+            .instructions
+            .iter()
+            .map(|inst| matcher.matches(inst))
+            .collect::<Vec<_>>()
+    };
+
+    //Files are matched on their suffix
+    assert_eq!(matched("guard.comp", 7), [true, false, false, false]);
+    assert_eq!(
+        matched("shaders/guard.comp", 8),
+        [false, true, false, false]
+    );
+    assert_eq!(matched("other.comp", 7), [false, false, true, false]);
+    assert_eq!(matched("missing.comp", 7), [false; 4]);
+}
+
+#[test]
+fn find_or_insert_adds_width_capabilities() {
+    //`SHADER` only declares the `Shader` capability
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let capabilities = |spv: &spv_patcher::rspirv::dr::Module| {
+        spv.capabilities
+            .iter()
+            .map(|inst| inst.operands[0].unwrap_capability())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(capabilities(&spv), [Capability::Shader]);
+
+    spv.find_or_insert_constant(ConstantValue::U64(1));
+    spv.find_or_insert_constant(ConstantValue::I64(-1));
+    spv.find_or_insert_constant(ConstantValue::F64(1.0));
+    spv.find_or_insert_constant(ConstantValue::F16(0x3c00));
+    spv.find_or_insert_type(
+        Op::TypeInt,
+        &[Operand::LiteralBit32(8), Operand::LiteralBit32(0)],
+        &[],
+    );
+    spv.find_or_insert_type(
+        Op::TypeInt,
+        &[Operand::LiteralBit32(16), Operand::LiteralBit32(1)],
+        &[],
+    );
+    //32 bit types need nothing beyond `Shader`
+    spv.find_or_insert_constant(ConstantValue::I32(-1));
+    assert_eq!(
+        capabilities(&spv),
+        [
+            Capability::Shader,
+            Capability::Int64,
+            Capability::Float64,
+            Capability::Float16,
+            Capability::Int8,
+            Capability::Int16,
+        ]
+    );
+}
+
+#[test]
+fn pointee() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    let float = spv.types_global_values[2].result_id.unwrap();
+    let ptr = spv.find_or_insert_type(
+        Op::TypePointer,
+        &[
+            Operand::StorageClass(StorageClass::Function),
+            Operand::IdRef(float),
+        ],
+        &[],
+    );
+    assert_eq!(spv.pointee(ptr), Some(float));
+    //Only pointer types have a pointee
+    assert_eq!(spv.pointee(float), None);
+}
@@ -0,0 +1,330 @@
+use spv_patcher::{
+    rspirv::{
+        dr::Operand,
+        spirv::{Decoration, Op},
+    },
+    spirv_ext::SpirvExt,
+    type_tree::{TTDecoration, TTypeOperand, TypeImporter, TypeTree, TypeTreeError},
+    Module,
+};
+
+const SHADER: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %data "data"
+OpName %arr "arr"
+OpDecorateString %data UserSemantic "payload"
+OpMemberDecorateString %data 0 UserSemantic "first"
+OpMemberDecorate %data 0 Offset 0
+OpDecorateId %data AlignmentId %uint_16
+OpDecorate %grp RelaxedPrecision
+%grp = OpDecorationGroup
+OpGroupDecorate %grp %arr
+OpGroupMemberDecorate %grp %data 1
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%uint_4 = OpConstant %uint 4
+%uint_16 = OpConstant %uint 16
+%arr = OpTypeArray %uint %uint_4
+%data = OpTypeStruct %uint %arr
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+fn decorations_of<'a>(
+    tree: &'a TypeTree,
+    module: &spv_patcher::rspirv::dr::Module,
+    name: &str,
+) -> &'a [TTDecoration] {
+    let id = module.get_by_name(name).unwrap().result_id.unwrap();
+    &tree
+        .known_types
+        .iter()
+        .find(|ty| ty.src_type_id == id)
+        .unwrap()
+        .decorations
+}
+
+fn has(
+    decorations: &[TTDecoration],
+    member: Option<u32>,
+    decoration: Decoration,
+    operands: &[TTypeOperand],
+) -> bool {
+    decorations.iter().any(|dec| match dec {
+        TTDecoration::Decorate {
+            decoration: d,
+            operands: o,
+        } => member.is_none() && *d == decoration && o.as_slice() == operands,
+        TTDecoration::MemberDecorate {
+            member: m,
+            decoration: d,
+            operands: o,
+        } => member == Some(*m) && *d == decoration && o.as_slice() == operands,
+    })
+}
+
+#[test]
+fn all_decoration_forms() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let spv = module.spirv();
+    let tree = spv.build_type_tree().unwrap();
+    //void, fn, uint, arr and data
+    assert_eq!(tree.known_types.len(), 5);
+
+    let data = decorations_of(&tree, spv, "data");
+    assert_eq!(data.len(), 5);
+    let string = |s: &str| TTypeOperand::LiteralString(s.to_string());
+    assert!(has(
+        data,
+        None,
+        Decoration::UserSemantic,
+        &[string("payload")]
+    ));
+    assert!(has(
+        data,
+        Some(0),
+        Decoration::UserSemantic,
+        &[string("first")]
+    ));
+    assert!(has(
+        data,
+        Some(0),
+        Decoration::Offset,
+        &[TTypeOperand::LiteralBit32(0)]
+    ));
+    assert!(data
+        .iter()
+        .any(|dec| dec.decoration() == Decoration::AlignmentId));
+    //Group decorations are resolved to their targets
+    assert!(has(data, Some(1), Decoration::RelaxedPrecision, &[]));
+    let arr = decorations_of(&tree, spv, "arr");
+    assert_eq!(arr.len(), 1);
+    assert!(has(arr, None, Decoration::RelaxedPrecision, &[]));
+}
+
+#[test]
+fn undeclared_operand_is_an_error() {
+    let module = Module::from_assembly(SHADER).unwrap();
+    let mut spv = module.spirv().clone();
+    //Move the array type in front of its element type
+    let arr = spv
+        .types_global_values
+        .iter()
+        .position(|inst| inst.class.opcode == spv_patcher::rspirv::spirv::Op::TypeArray)
+        .unwrap();
+    let inst = spv.types_global_values.remove(arr);
+    spv.types_global_values.insert(0, inst);
+
+    assert!(matches!(
+        spv.build_type_tree(),
+        Err(TypeTreeError::UndeclaredOperand { .. })
+    ));
+}
+
+const DST: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+%uint = OpTypeInt 32 0
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn import_into_other_module() {
+    let src_module = Module::from_assembly(SHADER).unwrap();
+    let src = src_module.spirv();
+    let mut dst = Module::from_assembly(DST).unwrap().spirv().clone();
+    let dst_uint = dst.types_global_values[0].result_id.unwrap();
+    let types_before = dst.types_global_values.len();
+
+    let src_data = src.get_by_name("data").unwrap().result_id.unwrap();
+    let src_uint = src
+        .types_global_values
+        .iter()
+        .find(|inst| inst.class.opcode == Op::TypeInt)
+        .unwrap()
+        .result_id
+        .unwrap();
+
+    let mut importer = TypeImporter::new(src, &dst).unwrap();
+    //Already declared types are found, regardless of their id
+    assert_eq!(importer.find(src_uint).unwrap(), Some(dst_uint));
+    assert_eq!(importer.find(src_data).unwrap(), None);
+
+    let dst_data = importer.import(&mut dst, src_data).unwrap();
+    //Both constants, the array and the struct. The integer type is reused.
+    assert_eq!(dst.types_global_values.len(), types_before + 4);
+    assert_eq!(importer.mapping().get(&src_uint), Some(&dst_uint));
+    assert_eq!(importer.import(&mut dst, src_data).unwrap(), dst_data);
+    assert_eq!(dst.get_name(dst_data).as_deref(), Some("data"));
+
+    //The imported type is structurally equal to the source, including all decorations
+    let src_tree = src.build_type_tree().unwrap();
+    let dst_tree = dst.build_type_tree().unwrap();
+    let decorations = |tree: &TypeTree, id: u32| {
+        tree.known_types
+            .iter()
+            .find(|ty| ty.src_type_id == id)
+            .unwrap()
+            .decorations
+            .len()
+    };
+    assert_eq!(
+        decorations(&src_tree, src_data),
+        decorations(&dst_tree, dst_data)
+    );
+
+    //A fresh importer finds the imported type
+    let mut importer = TypeImporter::new(src, &dst).unwrap();
+    assert_eq!(importer.find(src_data).unwrap(), Some(dst_data));
+}
+
+//`float` and `v4float` are decorated, which must not lead to a second declaration of either.
+const DECORATED_SCALARS: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %block "block"
+OpDecorate %float RelaxedPrecision
+OpDecorate %v4float RelaxedPrecision
+OpMemberDecorate %block 0 Offset 0
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%float = OpTypeFloat 32
+%v4float = OpTypeVector %float 4
+%block = OpTypeStruct %v4float
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+const PLAIN_SCALARS: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%float = OpTypeFloat 32
+%v4float = OpTypeVector %float 4
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn reuse_decorated_non_aggregates() {
+    let src_module = Module::from_assembly(DECORATED_SCALARS).unwrap();
+    let src = src_module.spirv();
+    let mut dst = Module::from_assembly(PLAIN_SCALARS)
+        .unwrap()
+        .spirv()
+        .clone();
+    let count = |module: &spv_patcher::rspirv::dr::Module, op: Op| {
+        module
+            .types_global_values
+            .iter()
+            .filter(|inst| inst.class.opcode == op)
+            .count()
+    };
+
+    let src_block = src.get_by_name("block").unwrap().result_id.unwrap();
+    let mut importer = TypeImporter::new(src, &dst).unwrap();
+    importer.import(&mut dst, src_block).unwrap();
+    assert_eq!(count(&dst, Op::TypeFloat), 1);
+    assert_eq!(count(&dst, Op::TypeVector), 1);
+    assert_eq!(count(&dst, Op::TypeStruct), 1);
+    //Only the struct's own decoration is imported
+    assert_eq!(dst.annotations.len(), 1);
+
+    //A fresh importer finds the imported struct, although its member type is decorated in the source only
+    let mut importer = TypeImporter::new(src, &dst).unwrap();
+    assert!(importer.find(src_block).unwrap().is_some());
+}
+
+const STRIDE_16: &str = r#"
+OpCapability Shader
+OpCapability PhysicalStorageBufferAddresses
+OpExtension "SPV_KHR_physical_storage_buffer"
+OpMemoryModel PhysicalStorageBuffer64 GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %ptr "ptr"
+OpDecorate %ptr ArrayStride 16
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%float = OpTypeFloat 32
+%ptr = OpTypePointer PhysicalStorageBuffer %float
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+const STRIDE_4: &str = r#"
+OpCapability Shader
+OpCapability PhysicalStorageBufferAddresses
+OpExtension "SPV_KHR_physical_storage_buffer"
+OpMemoryModel PhysicalStorageBuffer64 GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %ptr "ptr"
+OpDecorate %ptr ArrayStride 4
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%float = OpTypeFloat 32
+%ptr = OpTypePointer PhysicalStorageBuffer %float
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+#[test]
+fn import_pointer_with_different_stride() {
+    let src_module = Module::from_assembly(STRIDE_16).unwrap();
+    let src = src_module.spirv();
+    let mut dst = Module::from_assembly(STRIDE_4).unwrap().spirv().clone();
+    let dst_ptr = dst.get_by_name("ptr").unwrap().result_id.unwrap();
+
+    let src_ptr = src.get_by_name("ptr").unwrap().result_id.unwrap();
+    let mut importer = TypeImporter::new(src, &dst).unwrap();
+    assert_eq!(importer.find(src_ptr).unwrap(), None);
+    let imported = importer.import(&mut dst, src_ptr).unwrap();
+    assert_ne!(imported, dst_ptr);
+
+    //The pointee is reused, the pointer is declared a second time with its own stride
+    let pointers = dst
+        .types_global_values
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::TypePointer)
+        .count();
+    assert_eq!(pointers, 2);
+    let floats = dst
+        .types_global_values
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::TypeFloat)
+        .count();
+    assert_eq!(floats, 1);
+    assert!(dst.annotations.iter().any(|dec| dec.operands
+        == [
+            Operand::IdRef(imported),
+            Operand::Decoration(Decoration::ArrayStride),
+            Operand::LiteralBit32(16),
+        ]));
+}
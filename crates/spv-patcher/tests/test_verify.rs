@@ -0,0 +1,197 @@
+use spv_patcher::{
+    asm::assemble,
+    rspirv::{
+        dr::{Builder, Module},
+        spirv::{
+            AddressingModel, Capability, ExecutionMode, ExecutionModel, FunctionControl,
+            MemoryModel, StorageClass,
+        },
+    },
+    verify::{Rule, Verifier},
+};
+
+//A loop containing a selection, reading from a decorated storage buffer.
+const STRUCTURED: &str = r#"
+OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint GLCompute %main "main"
+OpExecutionMode %main LocalSize 1 1 1
+OpName %data "data"
+OpMemberName %block 0 "values"
+OpDecorate %block Block
+OpMemberDecorate %block 0 Offset 0
+OpDecorate %data DescriptorSet 0
+OpDecorate %data Binding 0
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%uint = OpTypeInt 32 0
+%bool = OpTypeBool
+%uint_0 = OpConstant %uint 0
+%uint_1 = OpConstant %uint 1
+%uint_8 = OpConstant %uint 8
+%block = OpTypeStruct %uint
+%ptr_block = OpTypePointer Uniform %block
+%ptr_uint = OpTypePointer Uniform %uint
+%data = OpVariable %ptr_block Uniform
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpBranch %header
+%header = OpLabel
+%i = OpPhi %uint %uint_0 %entry %next %continue
+%cond = OpULessThan %bool %i %uint_8
+OpLoopMerge %exit %continue None
+OpBranchConditional %cond %body %exit
+%body = OpLabel
+%ptr = OpAccessChain %ptr_uint %data %uint_0
+%value = OpLoad %uint %ptr
+%odd = OpIEqual %bool %value %uint_1
+OpSelectionMerge %continue None
+OpBranchConditional %odd %then %continue
+%then = OpLabel
+OpBranch %continue
+%continue = OpLabel
+%next = OpIAdd %uint %i %uint_1
+OpBranch %header
+%exit = OpLabel
+OpReturn
+OpFunctionEnd
+"#;
+
+//Builds a compute shader with a single function whose body is emitted by `body`.
+fn compute_shader(body: impl FnOnce(&mut Builder)) -> Module {
+    let mut b = Builder::new();
+    b.set_version(1, 3);
+    b.capability(Capability::Shader);
+    b.memory_model(AddressingModel::Logical, MemoryModel::GLSL450);
+    let void = b.type_void();
+    let fn_ty = b.type_function(void, vec![]);
+    let f = b
+        .begin_function(void, None, FunctionControl::NONE, fn_ty)
+        .unwrap();
+    b.begin_block(None).unwrap();
+    body(&mut b);
+    b.end_function().unwrap();
+    let interface: [u32; 0] = [];
+    b.entry_point(ExecutionModel::GLCompute, f, "main", interface);
+    b.execution_mode(f, ExecutionMode::LocalSize, [1, 1, 1]);
+    b.module()
+}
+
+#[test]
+fn valid_module() {
+    let module = compute_shader(|b| {
+        let uint = b.type_int(32, 0);
+        let ptr = b.type_pointer(None, StorageClass::Function, uint);
+        let one = b.constant_u32(uint, 1);
+        let var = b.variable(ptr, None, StorageClass::Function, None);
+        b.store(var, one, None, []).unwrap();
+        let loaded = b.load(uint, None, var, None, []).unwrap();
+        b.i_add(uint, None, loaded, one).unwrap();
+        b.ret().unwrap();
+    });
+
+    if let Err(e) = Verifier::verify(&module) {
+        panic!("{}", e);
+    }
+}
+
+#[test]
+fn unterminated_block() {
+    let module = compute_shader(|b| {
+        let uint = b.type_int(32, 0);
+        let one = b.constant_u32(uint, 1);
+        b.i_add(uint, None, one, one).unwrap();
+    });
+
+    let err = Verifier::verify(&module).unwrap_err();
+    assert!(err.has_rule(Rule::BlockTermination), "{}", err);
+}
+
+#[test]
+fn undefined_id_and_type_mismatch() {
+    let module = compute_shader(|b| {
+        let uint = b.type_int(32, 0);
+        let float = b.type_float(32);
+        let ptr = b.type_pointer(None, StorageClass::Function, uint);
+        let var = b.variable(ptr, None, StorageClass::Function, None);
+        //Loads a float from an uint pointer
+        b.load(float, None, var, None, []).unwrap();
+        //Uses an id that is never defined
+        b.i_add(uint, None, 1000, 1000).unwrap();
+        b.ret().unwrap();
+    });
+
+    let err = Verifier::verify(&module).unwrap_err();
+    assert!(err.has_rule(Rule::TypeConsistency), "{}", err);
+    assert!(err.has_rule(Rule::IdDefinition), "{}", err);
+    //The offending instruction must be reported
+    assert!(err
+        .0
+        .iter()
+        .all(|d| d.instruction.is_some() && d.function.is_some()));
+}
+
+#[test]
+fn section_order() {
+    let module = assemble(STRUCTURED).unwrap();
+    Verifier::verify(&module).unwrap();
+
+    //An OpName among the decorations, and a global Function variable
+    let mut misplaced = module.clone();
+    misplaced.annotations.push(misplaced.debug_names[0].clone());
+    let err = Verifier::verify(&misplaced).unwrap_err();
+    assert!(err.has_rule(Rule::SectionOrder), "{}", err);
+
+    let function_global = assemble(&STRUCTURED.replace(
+        "%ptr_block = OpTypePointer Uniform %block\n%ptr_uint = OpTypePointer Uniform %uint\n%data = OpVariable %ptr_block Uniform",
+        "%ptr_block = OpTypePointer Function %block\n%ptr_uint = OpTypePointer Function %uint\n%data = OpVariable %ptr_block Function",
+    ))
+    .unwrap();
+    let err = Verifier::verify(&function_global).unwrap_err();
+    assert!(err.has_rule(Rule::SectionOrder), "{}", err);
+}
+
+#[test]
+fn structured_control_flow() {
+    Verifier::verify(&assemble(STRUCTURED).unwrap()).unwrap();
+
+    //The loop's merge block is also its continue target
+    let merge_is_continue = assemble(&STRUCTURED.replace(
+        "OpLoopMerge %exit %continue None",
+        "OpLoopMerge %exit %exit None",
+    ))
+    .unwrap();
+    let err = Verifier::verify(&merge_is_continue).unwrap_err();
+    assert!(err.has_rule(Rule::StructuredControlFlow), "{}", err);
+
+    //The selection merges into the loop's merge block
+    let shared_merge = assemble(&STRUCTURED.replace(
+        "OpSelectionMerge %continue None",
+        "OpSelectionMerge %exit None",
+    ))
+    .unwrap();
+    let err = Verifier::verify(&shared_merge).unwrap_err();
+    assert!(err.has_rule(Rule::StructuredControlFlow), "{}", err);
+}
+
+#[test]
+fn decoration_target() {
+    Verifier::verify(&assemble(STRUCTURED).unwrap()).unwrap();
+
+    //Member decorations on a scalar, and on a member the struct does not have
+    let not_a_struct = assemble(&STRUCTURED.replace(
+        "OpMemberDecorate %block 0 Offset 0",
+        "OpMemberDecorate %block 0 Offset 0\nOpMemberDecorate %uint 0 Offset 0",
+    ))
+    .unwrap();
+    let err = Verifier::verify(&not_a_struct).unwrap_err();
+    assert!(err.has_rule(Rule::DecorationTarget), "{}", err);
+
+    let out_of_range = assemble(&STRUCTURED.replace(
+        "OpMemberName %block 0 \"values\"",
+        "OpMemberName %block 3 \"values\"",
+    ))
+    .unwrap();
+    let err = Verifier::verify(&out_of_range).unwrap_err();
+    assert!(err.has_rule(Rule::DecorationTarget), "{}", err);
+}
@@ -2,7 +2,10 @@ use marpii::resources::PushConstant;
 use marpii_rmg_shared::ResourceHandle;
 use marpii_rmg_tasks::{DownloadBuffer, UploadBuffer};
 use spv_patcher::{
-    patch::MutateConstant, rspirv::spirv::ExecutionModel, spirv_ext::SpirvExt, PatcherError,
+    patch::{ConstantSelector, MutateConstant},
+    rspirv::spirv::ExecutionModel,
+    spirv_ext::{ConstantValue, SpirvExt},
+    PatcherError,
 };
 
 use crate::{compute_task::ComputeTask, test_runs::TestRun};
@@ -78,13 +81,16 @@ impl StaticMutateTest {
                 .get_module()
                 .spirv()
                 .get_execution_model()
-                == ExecutionModel::GLCompute
+                == Some(ExecutionModel::GLCompute)
         );
 
         self.test_task.pipeline.patch_pipeline(rmg, |patch| {
             patch
                 //.print()
-                .patch(MutateConstant::Integer { from, to })
+                .patch(MutateConstant::new(
+                    ConstantSelector::Value(ConstantValue::U32(from)),
+                    ConstantValue::U32(to),
+                ))
         })
     }
 
@@ -99,7 +105,10 @@ impl StaticMutateTest {
         self.test_task.pipeline.patch_pipeline(rmg, |patch| {
             patch
                 //.print()
-                .patch(MutateConstant::Float { from, to })
+                .patch(MutateConstant::new(
+                    ConstantSelector::Value(ConstantValue::F32(from)),
+                    ConstantValue::F32(to),
+                ))
         })
     }
 }
@@ -9,10 +9,12 @@ use marpii::{
 };
 use marpii_rmg::Rmg;
 use spv_patcher::{
-    patch::Patcher, rspirv::spirv::ExecutionModel, spirv_ext::SpirvExt, Module, PatcherError,
+    patch::Patcher, reflect::Reflection, rspirv::spirv::ExecutionModel, Module, PatcherError,
 };
 pub struct PatchablePipeline {
     module: Module,
+    ///Name of the compute entry point the pipeline is build for.
+    entry_point: String,
     pipeline: Arc<ComputePipeline>,
     ///Code on which the current `pipeline` is based on.
     patched: Vec<u8>,
@@ -20,17 +22,44 @@ pub struct PatchablePipeline {
 use std::sync::Arc;
 
 impl PatchablePipeline {
+    ///Loads the module's only `GLCompute` entry point. Use [from_spirv_entry_point](Self::from_spirv_entry_point) if the module
+    /// declares several.
     pub fn from_spirv(spirv_binary: Vec<u8>, rmg: &mut Rmg) -> Result<Self, PatcherError> {
         let module = Module::new(spirv_binary)?;
+        let mut compute_entry_points = module
+            .entry_points()
+            .iter()
+            .filter(|ep| ep.execution_model == ExecutionModel::GLCompute);
+        let entry_point = match (compute_entry_points.next(), compute_entry_points.next()) {
+            (Some(ep), None) => ep.name.clone(),
+            (None, _) => return Err(PatcherError::NoEntryPoint),
+            (Some(_), Some(_)) => return Err(PatcherError::MultipleEntryPoints),
+        };
 
-        assert!(
-            module.spirv().get_execution_model() == ExecutionModel::GLCompute,
-            "Test Pipeline was not GLCompute ExecutionModel, was {:?}",
-            module.spirv().get_execution_model()
-        );
+        Self::from_module(module, entry_point, rmg)
+    }
+
+    ///Loads the `GLCompute` entry point named `entry_point` of a module.
+    pub fn from_spirv_entry_point(
+        spirv_binary: Vec<u8>,
+        entry_point: &str,
+        rmg: &mut Rmg,
+    ) -> Result<Self, PatcherError> {
+        let module = Module::new(spirv_binary)?;
+        Self::from_module(module, entry_point.to_owned(), rmg)
+    }
+
+    fn from_module(
+        module: Module,
+        entry_point: String,
+        rmg: &mut Rmg,
+    ) -> Result<Self, PatcherError> {
+        //Makes sure the entry point exists
+        let _ep = module.entry_point(&entry_point, Some(ExecutionModel::GLCompute))?;
 
         //Load the pipeline assuming its using the bindless layout of RMG.
-        // NOTE: Later on this would actually be patchabel with the interface matching pass.
+        // NOTE: Later on this would actually be patchabel with the interface matching pass. Use
+        //       `reflection()` to check which bindings the code actually expects.
         //No additional descriptors for us
         let layout = rmg.resources.bindless_layout();
         let shader_module =
@@ -38,7 +67,7 @@ impl PatchablePipeline {
         let shader_stage = ShaderStage::from_module(
             shader_module.into(),
             vk::ShaderStageFlags::COMPUTE,
-            "main".to_owned(),
+            entry_point.clone(),
         );
 
         let pipeline = Arc::new(
@@ -50,6 +79,7 @@ impl PatchablePipeline {
         let patched = module.template_code().to_vec();
         Ok(PatchablePipeline {
             module,
+            entry_point,
             pipeline,
             patched,
         })
@@ -60,12 +90,17 @@ impl PatchablePipeline {
     }
 
     ///Applies `patching` to the template code and builds a new pipeline based on that. Panics if the resulting code is invalid.
+    ///
+    /// The supplied [Patcher] is scoped to the pipeline's entry point.
     pub fn patch_pipeline(
         &mut self,
         rmg: &mut Rmg,
         patching: impl FnOnce(Patcher) -> Result<Patcher, PatcherError>,
     ) -> Result<(), PatcherError> {
-        let patched = patching(self.module.patch())?.assemble();
+        let patcher = self
+            .module
+            .patch_entry_point(&self.entry_point, Some(ExecutionModel::GLCompute))?;
+        let patched = patching(patcher)?.assemble();
 
         /*
         println!(
@@ -81,7 +116,7 @@ impl PatchablePipeline {
         let shader_stage = ShaderStage::from_module(
             patched_module.into(),
             vk::ShaderStageFlags::COMPUTE,
-            "main".to_owned(),
+            self.entry_point.clone(),
         );
 
         let pipeline = Arc::new(
@@ -100,7 +135,18 @@ impl PatchablePipeline {
         &self.patched
     }
 
+    ///Reflects the resources used by the pipeline's current (possibly patched) code.
+    pub fn reflection(&self) -> Result<Reflection, PatcherError> {
+        let module = spv_patcher::rspirv::dr::load_bytes(&self.patched)?;
+        Reflection::new(&module, &self.entry_point, Some(ExecutionModel::GLCompute))
+    }
+
     pub fn get_module(&self) -> &Module {
         &self.module
     }
+
+    ///Name of the entry point this pipeline is build for.
+    pub fn entry_point(&self) -> &str {
+        &self.entry_point
+    }
 }